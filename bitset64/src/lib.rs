@@ -4,9 +4,17 @@ A simple bitset stored in a single u64.
 The downside of such a basic representation is, of course, that sets can only store the numbers
 0..64.  The major upside it that it implements `Copy`.  There's also less bookkeeping.  If need to
 store elements from 0..64 in a set, `BitSet64` is a very fast way to do it.
+
+This crate is `no_std`.
 */
 
+#![no_std]
+#[cfg(test)] #[macro_use] extern crate std;
+
+#[cfg(feature = "serde")] #[macro_use] extern crate serde_derive;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BitSet64(pub u64);
 impl BitSet64 {
     const MAX_IDX: u64 = 63;
@@ -101,7 +109,7 @@ impl BitSet64 {
             (Some(min), Some(max)) => Elements {
                 set: *self,
                 cur: min,
-                max: max,
+                max,
                 done: false,
             },
             _ => Elements {
@@ -122,9 +130,24 @@ impl BitSet64 {
             done: false,
         }
     }
+    /// The `j`th subset, in the order that `subsets()` yields them.  (The bits of `j` are spread
+    /// out over the elements of the set.)  Panics unless `j < 2 ** self.size()`.
+    pub fn nth_subset(&self, j: u64) -> BitSet64 {
+        assert!(self.size() == 64 || j >> self.size() == 0);
+        let mut ret = 0;
+        let mut set = self.0;
+        let mut j = j;
+        while set != 0 {
+            let lowest = set & set.wrapping_neg();
+            if j & 1 != 0 { ret |= lowest; }
+            j >>= 1;
+            set &= set - 1;
+        }
+        BitSet64(ret)
+    }
 }
 
-pub type Sets = std::iter::Map<std::ops::Range<u64>, fn(u64) -> BitSet64>;
+pub type Sets = core::iter::Map<core::ops::Range<u64>, fn(u64) -> BitSet64>;
 
 pub struct Elements {
     set: BitSet64,
@@ -165,8 +188,8 @@ impl Iterator for Subsets {
 }
 // impl FusedIterator for Subsets {}
 
-impl ::std::fmt::Display for BitSet64 {
-    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+impl ::core::fmt::Display for BitSet64 {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
         write!(f, "{:b}", self.0)
     }
 }
@@ -192,6 +215,14 @@ fn test_bitset64() {
     }
 }
 
+#[test]
+fn test_nth_subset() {
+    let set = BitSet64::empty_set().insert(2).insert(5).insert(6).insert(8);
+    for (j, i) in set.subsets().enumerate() {
+        assert_eq!(set.nth_subset(j as u64), i);
+    }
+}
+
 #[test]
 fn test_bitset64_empty() {
     let set = BitSet64::empty_set();
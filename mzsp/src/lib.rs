@@ -4,89 +4,627 @@ The "maximal zero-sum partitioning" problem may be stated thus:
 > Given a multiset of numbers *X*, such that ∑(*X*) =0, partition *X* into the maximum number of
 > subsets so that each subset sums to zero
 
-This crate implements a dynamic programming-based solution to this problem.
+This crate implements a dynamic programming-based solution to this problem.  The search is
+top-down and memoized, so only the subsets which can actually be left over after removing some
+zero-sum parts get solved.  When few subsets sum to zero (the usual case) that's far fewer than
+all 2ⁿ of them.
 
 ```
 # use mzsp::*;
 let partitionable   = vec![10, -10, 15, -15];
 let unpartitionable = vec![10, 20, -15, -15];
 
-assert_eq!(mzsp(&partitionable),   vec![vec![15, -15], vec![10, -10]]);
+assert_eq!(mzsp(&partitionable),   vec![vec![10, -10], vec![15, -15]]);
 assert_eq!(mzsp(&unpartitionable), vec![vec![10, 20, -15, -15]]);
 ```
 
-The most flexible and efficient way to use this crate is to use the `MZSP` iterator.  The `mzsp`
-function is a convenience function.
+If you want to inspect the result (perhaps more than once), use `Partitioning`.  If you just want
+to stream the partitions out, the `MZSP` iterator avoids an allocation.  The `mzsp` function is a
+convenience function.  To share one solution between threads, or to query it more than once, use
+a `Solver`.  If you only need to know whether there are at least k parts,
+`exists_partitioning` can answer that without finding the maximum.
+
+## Determinism
+
+The result depends only on the values (and their order), never on the platform: ties between
+equally good partitionings are always broken the same way.  `Partitioning` lists its parts in
+increasing order of their smallest index; `MZSP` yields them in decreasing order of their largest
+index.  Either way, the indices within a part are in increasing order.  If you'd rather have the
+parts by size, use `Partitioning::parts_by_size` or `MZSP::by_size`; ties keep the order above.
+
+With the `serde` feature enabled, `Partitioning` and `MZSP` can be serialized (the latter memo
+tables and all), so a solved instance can be cached or sent elsewhere.
+
+The `std` feature is on by default.  Without it, this crate is `no_std` and only needs `alloc`.
+
+With the `rayon` feature, the search scans big sets for parts on all cores, and `Partitioning`
+implements `IntoParallelIterator`.  The result is exactly the same as without it.
+
+With the `introspect` feature, you can look inside an `MZSP`'s memo tables, to see how the search
+arrived at its answer (see `MZSP::memo_entry`).  The tables are an implementation detail, so this
+is for debugging and visualization, not something to build on.
 */
 
+#![cfg_attr(not(feature = "std"), no_std)]
+#[cfg(not(feature = "std"))] #[macro_use] extern crate alloc;
+#[cfg(not(feature = "std"))] extern crate core as std;
+#[cfg(not(feature = "std"))] use alloc::vec::Vec;
+#[cfg(not(feature = "std"))] use alloc::sync::Arc;
+#[cfg(feature = "std")] use std::sync::Arc;
+#[cfg(not(feature = "std"))] type Map<K, V> = alloc::collections::BTreeMap<K, V>;
+#[cfg(feature = "std")] type Map<K, V> = std::collections::HashMap<K, V>;
+
 extern crate bitset64;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde_derive;
+#[cfg(all(test, feature = "serde"))] extern crate serde_json;
+#[cfg(feature = "rayon")] extern crate rayon;
+#[cfg(feature = "rayon")] use rayon::prelude::*;
 use bitset64::*;
+use std::ops::Add;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-/// Maximal zero-sum partitioning of a multiset.  This is a handy wrapper around `MZSP`.
+/// Maximal zero-sum partitioning of a multiset.  This is a handy wrapper around `Partitioning`.
+/// Panics if `Partitioning::compute` would fail.
 pub fn mzsp(values: &[isize]) -> Vec<Vec<isize>> {
-    MZSP::compute(values).map(|partition|
-        partition.elements().map(|idx|
-            values[idx as usize]
-        ).collect()
+    Partitioning::compute(values).expect("mzsp").parts().map(|part|
+        part.iter().map(|&idx| values[idx]).collect()
     ).collect()
 }
 
+/// Bounds on the number of parts in a maximal zero-sum partitioning, without solving the problem.
+/// Both are cheap to compute (roughly quadratic in the number of values).
+///
+/// The lower bound comes from greedily picking out zeros, then pairs `x, -x`, then triples, and
+/// finally whatever's left over (if it sums to zero).  The upper bound is the number of zeros plus
+/// the smaller of the number of positive and negative values, since every other part needs one of
+/// each.
+///
+/// ```
+/// # use mzsp::*;
+/// assert_eq!(bounds(&[10, -10, 15, -15]), (2, 2));
+/// assert_eq!(bounds(&[10, 20, -15, -15]), (1, 2));
+/// ```
+pub fn bounds(values: &[isize]) -> (usize, usize) {
+    let zeros = values.iter().filter(|&&x| x == 0).count();
+    let positive = values.iter().filter(|&&x| x > 0).count();
+    let upper = zeros + positive.min(values.len() - zeros - positive);
+
+    let mut lower = zeros;
+    let mut rest: Vec<isize> = values.iter().cloned().filter(|&x| x != 0).collect();
+    rest.sort_unstable();
+    // Pairs
+    let mut unpaired = vec![];
+    while let Some(x) = rest.pop() {
+        match x.checked_neg().map(|y| rest.binary_search(&y)) {
+            Some(Ok(i)) => { rest.remove(i); lower += 1; }
+            _ => unpaired.push(x),
+        }
+    }
+    unpaired.reverse();
+    let mut rest = unpaired;
+    // Triples: rest[i] + rest[j] + rest[k] = 0, with i < j < k
+    let mut i = 0;
+    'outer: while i < rest.len() {
+        for j in i + 1..rest.len() {
+            let c = match rest[i].checked_add(rest[j]).and_then(|x| x.checked_neg()) {
+                Some(c) => c,
+                None => continue,
+            };
+            if let Ok(k) = rest[j + 1..].binary_search(&c) {
+                rest.remove(j + 1 + k);
+                rest.remove(j);
+                rest.remove(i);
+                lower += 1;
+                continue 'outer;
+            }
+        }
+        i += 1;
+    }
+    // Everything else
+    if !rest.is_empty() && rest.iter().fold(0isize, |acc, &x| acc.wrapping_add(x)) == 0 {
+        lower += 1;
+    }
+    (lower, upper)
+}
+
+/// Whether the values can be partitioned into at least `k` zero-sum parts; ie. whether
+/// `Partitioning::compute` would find `k` or more.  The search stops as soon as it's found `k`
+/// parts (and `bounds` often settles the question without searching at all), so this is usually
+/// much cheaper than computing the maximum.  Like `compute`, it fails for more than `MAX_LEN`
+/// values.
+///
+/// ```
+/// # use mzsp::*;
+/// assert_eq!(exists_partitioning(&[10, -10, 15, -15], 2), Ok(true));
+/// assert_eq!(exists_partitioning(&[10, 20, -15, -15], 2), Ok(false));
+/// ```
+pub fn exists_partitioning(values: &[isize], k: usize) -> Result<bool, Error> {
+    if values.len() > MAX_LEN {
+        return Err(Error::TooManyElements { len: values.len() });
+    }
+    if values.iter().map(|&x| x as i128).sum::<i128>() != 0 { return Ok(k == 0); }
+    let (lower, upper) = bounds(values);
+    if k <= lower { return Ok(true); }
+    if k > upper { return Ok(false); }
+    check_isize_range(values)?;
+    let problem = Problem { values, is_zero: &|sum| sum == 0, options: Options::default(), weight: None, cancel: None, progress: None };
+    let mut search = Search::new(&problem);
+    Ok(search.has_parts(BitSet64::full_set(values.len() as u64), k, &mut Map::new()))
+}
+
+/// A maximal zero-sum partitioning of a multiset, with each part given as a list of indices into
+/// the original multiset.  The parts are sorted by their smallest index, and each part is sorted.
+///
+/// ```
+/// # use mzsp::*;
+/// let values = vec![10, -10, 15, -15];
+/// let partitioning = Partitioning::compute(&values).unwrap();
+/// assert_eq!(partitioning.num_parts(), 2);
+/// assert_eq!(partitioning.part_of(0), partitioning.part_of(1));
+/// for part in partitioning.parts() {
+///     assert_eq!(part.iter().map(|&idx| values[idx]).sum::<isize>(), 0);
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Partitioning {
+    /// The indices of every part, concatenated.  Each part is sorted.
+    elements: Vec<usize>,
+    /// `elements[ends[i-1]..ends[i]]` is the ith part.
+    ends: Vec<usize>,
+    /// For each index, the part which contains it.
+    part_of: Vec<Option<usize>>,
+}
+impl Partitioning {
+    /// Find a maximum zero-sum partitioning of the given values.  See `MZSP::compute`.
+    pub fn compute(values: &[isize]) -> Result<Partitioning, Error> {
+        MZSP::compute(values).map(|x| Partitioning::from_sets(values.len(), x))
+    }
+
+    /// Like `compute`, but subject to some constraints.  See `MZSP::compute_with`.
+    pub fn compute_with(values: &[isize], options: Options) -> Result<Partitioning, Error> {
+        MZSP::compute_with(values, options).map(|x| Partitioning::from_sets(values.len(), x))
+    }
+
+    /// Like `compute`, but for values which don't necessarily sum to zero: find as many zero-sum
+    /// parts as possible, and leave whatever's left over as the `residual`.  The residual never
+    /// contains a zero-sum subset.  If the values do sum to zero, this is the same as `compute`.
+    ///
+    /// There can only be `MAX_LEN - 1` values, since the search needs one more.
+    ///
+    /// ```
+    /// # use mzsp::*;
+    /// let p = Partitioning::compute_with_residual(&[10, -10, 3, 15, -15, 1]).unwrap();
+    /// assert_eq!(p.num_parts(), 2);
+    /// assert_eq!(p.residual(), vec![2, 5]);
+    /// ```
+    pub fn compute_with_residual(values: &[isize]) -> Result<Partitioning, Error> {
+        // Add an element which balances out the rest.  In a maximal partitioning, whatever shares
+        // a part with it is the residual.  Any zero-sum subset of that would be a part of its own.
+        let total: i128 = values.iter().map(|&x| x as i128).sum();
+        if -total < isize::MIN as i128 || -total > isize::MAX as i128 { return Err(Error::Overflow); }
+        let mut extended = values.to_vec();
+        extended.push(-total as isize);
+        let n = values.len();
+        let parts = MZSP::compute(&extended)?.filter(|set| !set.contains(n as u64));
+        Ok(Partitioning::from_sets(n, parts))
+    }
+
+    /// Like `compute_with`, but giving up if `cancel` gets set.  See `MZSP::compute_cancellable`.
+    pub fn compute_cancellable(values: &[isize], options: Options, cancel: &AtomicBool) -> Result<Partitioning, Error> {
+        MZSP::compute_cancellable(values, options, cancel).map(|x| Partitioning::from_sets(values.len(), x))
+    }
+
+    /// Like `compute_with`, but reporting progress.  See `MZSP::compute_monitored`.
+    pub fn compute_monitored(values: &[isize], options: Options, cancel: Option<&AtomicBool>,
+        progress: &dyn Fn(Progress)) -> Result<Partitioning, Error>
+    {
+        MZSP::compute_monitored(values, options, cancel, progress).map(|x| Partitioning::from_sets(values.len(), x))
+    }
+
+    /// Like `compute_with`, but maximizing the total weight of the parts.  See
+    /// `MZSP::compute_weighted`.
+    pub fn compute_weighted<W>(values: &[isize], options: Options, weight: W) -> Result<Partitioning, Error>
+        where W: Fn(BitSet64) -> f64
+    {
+        MZSP::compute_weighted(values, options, weight).map(|x| Partitioning::from_sets(values.len(), x))
+    }
+
+    /// Like `compute`, but parts only need to sum to within `epsilon` of zero.  See
+    /// `MZSP::compute_with_tolerance`.
+    pub fn compute_with_tolerance(values: &[f64], epsilon: f64) -> Result<Partitioning, Error> {
+        MZSP::compute_with_tolerance(values, epsilon).map(|x| Partitioning::from_sets(values.len(), x))
+    }
+
+    /// Build a partitioning of `0..n` from a list of (disjoint, non-empty) parts.  The parts may be
+    /// given in any order.
+    pub fn from_sets<I: IntoIterator<Item=BitSet64>>(n: usize, sets: I) -> Partitioning {
+        let mut ret = Partitioning { elements: vec![], ends: vec![], part_of: vec![None; n] };
+        let mut sets: Vec<BitSet64> = sets.into_iter().collect();
+        sets.sort_unstable_by_key(|set| set.min());
+        for set in sets {
+            for idx in set.elements() {
+                ret.part_of[idx as usize] = Some(ret.ends.len());
+                ret.elements.push(idx as usize);
+            }
+            ret.ends.push(ret.elements.len());
+        }
+        ret
+    }
+
+    /// The number of zero-sum parts.
+    pub fn num_parts(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// The indices in the ith part.  Panics if `i >= self.num_parts()`.
+    pub fn part(&self, i: usize) -> &[usize] {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        &self.elements[start..self.ends[i]]
+    }
+
+    /// Iterate over the parts.
+    pub fn parts<'a>(&'a self) -> impl Iterator<Item=&'a [usize]> + 'a {
+        (0..self.num_parts()).map(move |i| self.part(i))
+    }
+
+    /// Iterate over the parts, ordered by their size.  Parts of the same size are in the same order
+    /// as in `parts()`.
+    ///
+    /// ```
+    /// # use mzsp::*;
+    /// let partitioning = Partitioning::compute(&[3, -1, -2, 10, -10]).unwrap();
+    /// let sizes: Vec<_> = partitioning.parts_by_size(SizeOrder::SmallestFirst).map(|x| x.len()).collect();
+    /// assert_eq!(sizes, vec![2, 3]);
+    /// ```
+    pub fn parts_by_size<'a>(&'a self, order: SizeOrder) -> impl Iterator<Item=&'a [usize]> + 'a {
+        let mut parts: Vec<&[usize]> = self.parts().collect();
+        order.sort(&mut parts, |x| x.len());
+        parts.into_iter()
+    }
+
+    /// The part which contains the given index, or `None` if it's in the residual.
+    pub fn part_of(&self, idx: usize) -> Option<usize> {
+        self.part_of[idx]
+    }
+
+    /// The indices which aren't in any zero-sum part.  This is empty if the values sum to zero.
+    /// Otherwise, it's everything for `compute`, and whatever couldn't be partitioned for
+    /// `compute_with_residual`.
+    pub fn residual(&self) -> Vec<usize> {
+        (0..self.part_of.len()).filter(|&idx| self.part_of[idx].is_none()).collect()
+    }
+}
+
+/// Iterate over the parts in parallel.  They're yielded in the same order as `parts()`.
+#[cfg(feature = "rayon")]
+impl IntoParallelIterator for Partitioning {
+    type Item = Vec<usize>;
+    type Iter = rayon::vec::IntoIter<Vec<usize>>;
+    fn into_par_iter(self) -> Self::Iter {
+        self.parts().map(|part| part.to_vec()).collect::<Vec<_>>().into_par_iter()
+    }
+}
+
+/// Which parts to list first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeOrder {
+    SmallestFirst,
+    LargestFirst,
+}
+impl SizeOrder {
+    /// A stable sort.
+    fn sort<T, F: Fn(&T) -> usize>(self, xs: &mut [T], size: F) {
+        match self {
+            SizeOrder::SmallestFirst => xs.sort_by_key(|x| size(x)),
+            SizeOrder::LargestFirst => xs.sort_by_key(|x| usize::MAX - size(x)),
+        }
+    }
+}
+
+/// Constraints on the partitioning.  The default is unconstrained.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Every part must have at most this many elements.
+    pub max_part_size: Option<usize>,
+    /// Among the partitionings with the most parts, pick one whose largest part is as small as
+    /// possible.  (This isn't a constraint, so it never makes a problem infeasible.  It's ignored
+    /// by `compute_weighted`.)
+    pub minimize_largest_part: bool,
+}
+impl Options {
+    fn is_constrained(&self) -> bool {
+        self.max_part_size.is_some()
+    }
+}
+
+/// With the `rayon` feature, the values and the zero test are shared between threads.
+#[cfg(feature = "rayon")] trait Shared: Send + Sync {}
+#[cfg(feature = "rayon")] impl<T: Send + Sync> Shared for T {}
+#[cfg(not(feature = "rayon"))] trait Shared {}
+#[cfg(not(feature = "rayon"))] impl<T> Shared for T {}
+
+/// The most values which can be partitioned.  (Sets of indices are stored in a `BitSet64`.)
+pub const MAX_LEN: usize = 63;
+
+/// Why a partitioning couldn't be computed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There were more than `MAX_LEN` values.
+    TooManyElements { len: usize },
+    /// The values didn't sum to zero.  (Only `try_compute` insists on this.)
+    NotZeroSum { sum: i128 },
+    /// No partitioning satisfies the constraints.
+    Infeasible,
+    /// The values are so big that some subset of them would overflow when summed.
+    Overflow,
+    /// The search was cancelled before it finished.  See `MZSP::compute_cancellable`.
+    Cancelled,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Error::TooManyElements { len } =>
+                write!(f, "Can't partition {} values (the maximum is {})", len, MAX_LEN),
+            Error::NotZeroSum { sum } => write!(f, "The values sum to {}, not zero", sum),
+            Error::Infeasible => write!(f, "No partitioning satisfies the constraints"),
+            Error::Overflow => write!(f, "The values are too big to sum without overflowing"),
+            Error::Cancelled => write!(f, "The search was cancelled"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Check that no subset of `values` sums to something outside `min..=max`.  The partial sums in
+/// the search are all subset sums, so it's enough to check the total of the positive values and
+/// the total of the negative ones.
+fn check_range<I: Iterator<Item=i128> + Clone>(values: I, min: i128, max: i128) -> Result<(), Error> {
+    let positive: i128 = values.clone().filter(|&x| x > 0).sum();
+    let negative: i128 = values.filter(|&x| x < 0).sum();
+    if positive > max || negative < min { Err(Error::Overflow) } else { Ok(()) }
+}
+
+fn check_isize_range(values: &[isize]) -> Result<(), Error> {
+    check_range(values.iter().map(|&x| x as i128), isize::MIN as i128, isize::MAX as i128)
+}
+
+/// Everything the recurrence needs to know, besides the memo tables.
+struct Problem<'a, T: 'a, F: 'a> {
+    values: &'a [T],
+    is_zero: &'a F,
+    options: Options,
+    /// If set, maximize the total weight of the parts instead of their number.
+    weight: Option<&'a dyn Fn(BitSet64) -> f64>,
+    /// If this gets set, the search gives up.
+    cancel: Option<&'a AtomicBool>,
+    /// Told how the search is getting on, every so often.
+    progress: Option<&'a dyn Fn(Progress)>,
+}
+
+/// How far a search has got.  At the top level, the search looks at `total` subsets of the
+/// values (each of which might be the part containing the largest value), and it's looked at
+/// `done` of them so far.  (This doesn't count the time spent summing the subsets beforehand.)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub done: u64,
+    pub total: u64,
+}
+
+/// Progress is reported every time the search looks at this many more subsets at the top level.
+const PROGRESS_EVERY: u64 = 1 << 10;
+
 /// A partitioning of a multiset of integers, such that every partition sums to zero.
 ///
 /// A partitioning given by `MZSP::compute` is guaranteed to be maximal, in the sense that is no
 /// zero-sum partitioning with more partitions.
 ///
 /// `MZSP` allows you to iterate over the partitions, which are represented by guaranteed-non-empty
-/// `BitSet64`s.  The elements of the bitsets are indices into the original multiset.  Use it like
-/// this:
+/// `BitSet64`s.  The elements of the bitsets are indices into the original multiset.  Partitions
+/// are yielded in decreasing order of their largest element.  Use it like this:
 ///
 /// ```
 /// # use mzsp::*;
 /// # let values = vec![];
-/// for partition in MZSP::compute(&values) {
+/// for partition in MZSP::compute(&values).unwrap() {
 ///     for idx in partition.elements() {
 ///         let x = values[idx as usize];
 ///         /* do something with x */
 ///     }
 /// }
 /// ```
+///
+/// The memo tables are immutable once built, so cloning an `MZSP` is cheap: the clones share them.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MZSP {
-    memo: MemoTables,
+    memo: Arc<MemoTables>,
     remainder: BitSet64,
     next: BitSet64,
     len: usize,
 }
 impl MZSP {
-    /// Find a maximum zero-sum partitioning of the given values.
-    pub fn compute(values: &[isize]) -> MZSP {
-        let memo = MemoTables::new(values);
-        let mut set = BitSet64::full_set(values.len() as u64);
-        match set.take_max() {
-            None => {
-                MZSP {
-                    memo: memo,
-                    remainder: BitSet64::empty_set(),
-                    next: BitSet64::empty_set(),
-                    len: 0,
-                }
-            }
-            Some(max) => {
-                let (n, first_part) = max_zero_sum_partitions(&memo, values, set, max);
-                MZSP {
-                    memo: memo,
-                    remainder: set.minus(first_part),
-                    next: first_part,
-                    len: n,
-                }
-            }
+    /// Find a maximum zero-sum partitioning of the given values.  Fails if there are more than
+    /// `MAX_LEN` of them, or if they're big enough that their sums might overflow.
+    pub fn compute(values: &[isize]) -> Result<MZSP, Error> {
+        MZSP::compute_with(values, Options::default())
+    }
+
+    /// Like `compute`, but for any iterator of `i64`s, and insisting that the values sum to zero.
+    ///
+    /// ```
+    /// # use mzsp::*;
+    /// assert_eq!(MZSP::try_compute(vec![10, -10, 15, -15]).unwrap().len(), 2);
+    /// assert_eq!(MZSP::try_compute(vec![10, -5]).err(), Some(Error::NotZeroSum { sum: 5 }));
+    /// ```
+    pub fn try_compute<I: IntoIterator<Item=i64>>(values: I) -> Result<MZSP, Error> {
+        MZSP::try_compute_with(values, Options::default())
+    }
+
+    /// Like `try_compute`, but subject to some constraints (see `compute_with`).
+    pub fn try_compute_with<I: IntoIterator<Item=i64>>(values: I, options: Options) -> Result<MZSP, Error> {
+        let values: Vec<i64> = values.into_iter().collect();
+        if values.len() > MAX_LEN {
+            return Err(Error::TooManyElements { len: values.len() });
+        }
+        let sum: i128 = values.iter().map(|&x| i128::from(x)).sum();
+        if sum != 0 {
+            return Err(Error::NotZeroSum { sum });
+        }
+        check_range(values.iter().map(|&x| i128::from(x)), i64::MIN.into(), i64::MAX.into())?;
+        MZSP::compute_by(&values, &|sum| sum == 0, options, None, None)
+    }
+
+    /// Find a maximum zero-sum partitioning of the given values, subject to some constraints.
+    /// Fails with `Error::Infeasible` if there's no partitioning which satisfies them.
+    ///
+    /// When there are constraints, the values must sum to zero.
+    pub fn compute_with(values: &[isize], options: Options) -> Result<MZSP, Error> {
+        check_isize_range(values)?;
+        MZSP::compute_by(values, &|sum| sum == 0, options, None, None)
+    }
+
+    /// Like `compute_with`, but the search checks `cancel` every so often, and fails with
+    /// `Error::Cancelled` once it's set.  Set it from another thread (or a signal handler) to
+    /// stop a search which is taking too long.
+    ///
+    /// ```
+    /// # use mzsp::*;
+    /// # use std::sync::atomic::AtomicBool;
+    /// let cancel = AtomicBool::new(true);
+    /// let result = MZSP::compute_cancellable(&[10, -10, 15, -15], Options::default(), &cancel);
+    /// assert_eq!(result.err(), Some(Error::Cancelled));
+    /// ```
+    pub fn compute_cancellable(values: &[isize], options: Options, cancel: &AtomicBool) -> Result<MZSP, Error> {
+        check_isize_range(values)?;
+        MZSP::compute_by(values, &|sum| sum == 0, options, None, Some(cancel))
+    }
+
+    /// Like `compute_cancellable` (if `cancel` is given), but telling `progress` how the search is
+    /// getting on, every so often.  It's called from the thread doing the search, so it should be
+    /// quick.
+    ///
+    /// ```
+    /// # use mzsp::*;
+    /// # use std::cell::Cell;
+    /// let last = Cell::new(None);
+    /// MZSP::compute_monitored(&[10, -10, 15, -15], Options::default(), None, &|p| last.set(Some(p))).unwrap();
+    /// assert_eq!(last.get(), Some(Progress { done: 8, total: 8 }));
+    /// ```
+    pub fn compute_monitored(values: &[isize], options: Options, cancel: Option<&AtomicBool>,
+        progress: &dyn Fn(Progress)) -> Result<MZSP, Error>
+    {
+        check_isize_range(values)?;
+        if values.len() > MAX_LEN {
+            return Err(Error::TooManyElements { len: values.len() });
+        }
+        let problem = Problem { values, is_zero: &|sum| sum == 0, options, weight: None, cancel, progress: Some(progress) };
+        MZSP::search(&problem)
+    }
+
+    /// Find a zero-sum partitioning of the given values which maximizes the total weight of its
+    /// parts, rather than their number.  `weight` is called on every candidate part (as a set of
+    /// indices into `values`).  Fails with `Error::Infeasible` if there's no partitioning which
+    /// satisfies the constraints.
+    ///
+    /// A constant weight of 1 gives the same result as `compute_with`.  The iterator still yields
+    /// parts; it's up to you to total their weights if you need to.
+    pub fn compute_weighted<W>(values: &[isize], options: Options, weight: W) -> Result<MZSP, Error>
+        where W: Fn(BitSet64) -> f64
+    {
+        check_isize_range(values)?;
+        MZSP::compute_by(values, &|sum| sum == 0, options, Some(&weight), None)
+    }
+
+    /// Find a maximum partitioning of the given values, where a part counts as zero-sum if its
+    /// total is within `epsilon` of zero.  This is useful for real-world amounts with rounding
+    /// noise in them.
+    ///
+    /// "Close to zero" isn't transitive, so the usual guarantees are a bit weaker: every part sums
+    /// to within `epsilon` of zero, but the union of two parts might not.  If the values as a whole
+    /// don't sum to within `epsilon` of zero, there are no parts at all.
+    pub fn compute_with_tolerance(values: &[f64], epsilon: f64) -> Result<MZSP, Error> {
+        MZSP::compute_by(values, &|sum: f64| -epsilon <= sum && sum <= epsilon, Options::default(), None, None)
+    }
+
+    fn compute_by<T, F>(values: &[T], is_zero: &F, options: Options,
+        weight: Option<&dyn Fn(BitSet64) -> f64>, cancel: Option<&AtomicBool>) -> Result<MZSP, Error>
+        where T: Copy + Default + Add<Output=T> + Shared, F: Fn(T) -> bool + Shared
+    {
+        if values.len() > MAX_LEN {
+            return Err(Error::TooManyElements { len: values.len() });
         }
+        let problem = Problem { values, is_zero, options, weight, cancel, progress: None };
+        MZSP::search(&problem)
+    }
+
+    fn search<T, F>(problem: &Problem<T, F>) -> Result<MZSP, Error>
+        where T: Copy + Default + Add<Output=T> + Shared, F: Fn(T) -> bool + Shared
+    {
+        let (values, options) = (problem.values, problem.options);
+        let mut search = Search::new(problem);
+        let set = BitSet64::full_set(values.len() as u64);
+        let (n, first_part) = search.mzsp(set);
+        if search.cancelled { return Err(Error::Cancelled); }
+        if n == 0 && set.size() != 0 && options.is_constrained() { return Err(Error::Infeasible); }
+        Ok(MZSP {
+            memo: Arc::new(search.memo),
+            remainder: set.minus(first_part),
+            next: first_part,
+            len: n,
+        })
+    }
+}
+impl MZSP {
+    /// The next partition, without consuming it.
+    pub fn peek(&self) -> Option<BitSet64> {
+        if self.len == 0 { None } else { Some(self.next) }
+    }
+
+    /// What the search found out about `set`, if it visited it.  The whole set is always there,
+    /// and so are the remainders left after taking out each witness in turn.
+    ///
+    /// ```
+    /// # use mzsp::*;
+    /// let solution = MZSP::compute(&[10, -10, 15, -15]).unwrap();
+    /// let top = *solution.memo_entries().last().unwrap();  // The whole set
+    /// assert_eq!(top.parts, 2);
+    /// assert_eq!(Some(top.witness), solution.peek());
+    /// let rest = solution.memo_entry(top.set.minus(top.witness)).unwrap();
+    /// assert_eq!(rest.parts, 1);
+    /// ```
+    #[cfg(feature = "introspect")]
+    pub fn memo_entry(&self, set: BitSet64) -> Option<MemoEntry> {
+        self.memo.get_mzsp(set).map(|(parts, witness)| MemoEntry { set, parts, witness })
+    }
+
+    /// Every set which the search visited, in increasing order (as bitsets).
+    #[cfg(feature = "introspect")]
+    pub fn memo_entries(&self) -> Vec<MemoEntry> {
+        let mut entries: Vec<MemoEntry> = self.memo.mzsp_table.iter()
+            .map(|(&set, &(parts, witness))| MemoEntry { set: BitSet64(set), parts, witness })
+            .collect();
+        entries.sort_unstable_by_key(|x| x.set.0);
+        entries
+    }
+
+    /// The remaining partitions, ordered by their size.  Partitions of the same size are in the
+    /// usual order.  (This has to collect them all before yielding the first.)
+    pub fn by_size(self, order: SizeOrder) -> impl ExactSizeIterator<Item=BitSet64> {
+        let mut parts: Vec<BitSet64> = self.collect();
+        order.sort(&mut parts, |x| x.size() as usize);
+        parts.into_iter()
     }
 }
 impl Iterator for MZSP {
     type Item = BitSet64;
     fn next(&mut self) -> Option<BitSet64> {
         if self.len == 0 { return None; }
-        let (n, part) = self.memo.get_mzsp(self.remainder);
+        // The memo tables are only incomplete if they were deserialized from something bogus, in
+        // which case we just stop early.
+        let (n, part) = self.memo.get_mzsp(self.remainder).unwrap_or((0, BitSet64::empty_set()));
         self.len = n;
         self.remainder = self.remainder.minus(part);
         let ret = self.next;
@@ -99,70 +637,349 @@ impl Iterator for MZSP {
 }
 impl ExactSizeIterator for MZSP {}
 
+/// A solved problem, which can be queried any number of times.  It's immutable, `Send + Sync`, and
+/// cheap to clone (the clones share the memo tables), so one solver can serve many threads.  The
+/// queries return `MZSP`s, which are lightweight handles onto the same tables.
+///
+/// ```
+/// # use mzsp::*;
+/// let solver = Solver::compute(&[10, -10, 15, -15, 3, -1, -2]).unwrap();
+/// let handles: Vec<_> = (0..4).map(|_| {
+///     let solver = solver.clone();
+///     std::thread::spawn(move || solver.parts().count())
+/// }).collect();
+/// for h in handles { assert_eq!(h.join().unwrap(), 3); }
+/// ```
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Solver {
+    memo: Arc<MemoTables>,
+    set: BitSet64,
+}
+impl Solver {
+    /// Solve the problem.  See `MZSP::compute`.
+    pub fn compute(values: &[isize]) -> Result<Solver, Error> {
+        MZSP::compute(values).map(Solver::from)
+    }
+
+    /// Solve the problem, subject to some constraints.  See `MZSP::compute_with`.
+    pub fn compute_with(values: &[isize], options: Options) -> Result<Solver, Error> {
+        MZSP::compute_with(values, options).map(Solver::from)
+    }
+
+    /// The number of parts in the solution.
+    pub fn num_parts(&self) -> usize {
+        self.memo.get_mzsp(self.set).map_or(0, |x| x.0)
+    }
+
+    /// The parts of the solution.
+    pub fn parts(&self) -> MZSP {
+        // The whole set is only missing if the tables were deserialized from something bogus
+        self.parts_of(self.set).unwrap_or_else(|| MZSP {
+            memo: self.memo.clone(), remainder: BitSet64::empty_set(), next: BitSet64::empty_set(), len: 0,
+        })
+    }
+
+    /// The best partitioning of `subset`, if the search happened to solve it.  It always solves
+    /// whatever's left after taking out each of the solution's parts in turn, and usually a lot
+    /// more besides.
+    pub fn parts_of(&self, subset: BitSet64) -> Option<MZSP> {
+        let (len, first) = self.memo.get_mzsp(subset)?;
+        Some(MZSP { memo: self.memo.clone(), remainder: subset.minus(first), next: first, len })
+    }
+}
+/// The solver for the partitions which `MZSP` hasn't yielded yet.
+impl From<MZSP> for Solver {
+    fn from(x: MZSP) -> Solver {
+        Solver { set: BitSet64(x.remainder.0 | x.next.0), memo: x.memo }
+    }
+}
+
+/// A set which the search visited, and the best partitioning it found for it.  See
+/// `MZSP::memo_entry`.
+#[cfg(feature = "introspect")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoEntry {
+    pub set: BitSet64,
+    /// The number of parts in the best partitioning of `set`.  Under constraints, 0 means `set`
+    /// can't be partitioned at all.
+    pub parts: usize,
+    /// The part of that partitioning which contains the largest element of `set`.  The rest of
+    /// the partitioning is the best partitioning of `set.minus(witness)`.
+    pub witness: BitSet64,
+}
+
+/// The best partitioning of every set which the search visited, keyed by bitset.  Each entry is
+/// the number of parts, and the part which contains the set's largest element.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct MemoTables {
-    mzsp_table: Vec<(usize, BitSet64)>,
-    sum_table: Vec<isize>,
+    mzsp_table: Map<u64, (usize, BitSet64)>,
 }
 
 impl MemoTables {
-    fn new(values: &[isize]) -> MemoTables {
-        let mut tables = MemoTables {
-            mzsp_table: vec![],
-            sum_table: vec![],
-        };
+    /// `None` if `subset` wasn't visited by the search.
+    fn get_mzsp(&self, subset: BitSet64) -> Option<(usize, BitSet64)> {
+        if subset.size() == 0 { return Some((0, BitSet64::empty_set())); }
+        self.mzsp_table.get(&subset.0).cloned()
+    }
+}
 
-        if values.len() != 0 {
-            for mut set in BitSet64::enumerate(values.len() as u64 - 1) {
-                // Remove the max. element from `set`
-                let max = match set.take_max() { Some(x) => x, None => {
-                    // Oh... `set` is empty.  Never mind!
-                    tables.sum_table.push(0);
-                    tables.mzsp_table.push((0, BitSet64::empty_set()));
-                    continue;
-                }};
-                // Compute the sum of `set ∪ {max}` (we'll need this later)
-                let sum = values[max as usize] + tables.get_sum(set);
-                tables.sum_table.push(sum);
-                // Compute the mzsp of `set ∪ {max}`
-                let mzsp = max_zero_sum_partitions(&tables, values, set, max);
-                tables.mzsp_table.push(mzsp);
-            }
+/// A top-down search for the best partitioning.  Only the sets which are actually reachable from
+/// the top (ie. the remainders left after removing some zero-sum part) are ever solved, which is
+/// usually a small fraction of all of them.
+struct Search<'a, T: 'a, F: 'a> {
+    problem: &'a Problem<'a, T, F>,
+    parts: PartTest<'a, T, F>,
+    memo: MemoTables,
+    /// When maximizing weight, the best total weight of each set in `memo`.
+    weights: Map<u64, f64>,
+    /// With `minimize_largest_part`, the size of the largest part of each set in `memo`.
+    largest: Map<u64, usize>,
+    /// Whether the search noticed `problem.cancel`.  Once it has, every set is treated as having
+    /// no parts, so that the search unwinds quickly; none of them are memoized.
+    cancelled: bool,
+}
+
+/// Decides which sets can be parts.  This is the only part of the search which touches every
+/// subset, so (with the `rayon` feature) it's the part which runs in parallel.
+struct PartTest<'a, T: 'a, F: 'a> {
+    values: &'a [T],
+    is_zero: &'a F,
+    max_part_size: usize,
+    /// The sum of every subset of `values[..n-1]`, indexed by bitset.  Working these out is cheap
+    /// compared to the search, so we do them all up-front.
+    sums: Vec<T>,
+}
+
+/// With the `rayon` feature, sets with at least this many elements have their subsets scanned in
+/// parallel.  For smaller sets it's not worth the overhead.
+#[cfg(feature = "rayon")]
+const PAR_MIN_SIZE: u32 = 16;
+
+impl<'a, T, F> PartTest<'a, T, F>
+    where T: Copy + Default + Add<Output=T> + Shared, F: Fn(T) -> bool + Shared
+{
+    fn new(problem: &Problem<'a, T, F>) -> PartTest<'a, T, F> {
+        // The sums of the subsets of `values[..k+1]` are those of `values[..k]`, followed by the
+        // same again with `values[k]` added.
+        let m = problem.values.len().saturating_sub(1);
+        let mut sums = Vec::with_capacity(1 << m);
+        sums.push(T::default());
+        for &v in &problem.values[..m] {
+            let len = sums.len();
+            sums.extend_from_within(..);
+            add_to_all(&mut sums[len..], v);
+        }
+        PartTest {
+            values: problem.values,
+            is_zero: problem.is_zero,
+            max_part_size: problem.options.max_part_size.unwrap_or(usize::MAX),
+            sums,
         }
+    }
+
+    /// Can `i ∪ {x}` be a part?
+    fn is_part(&self, i: BitSet64, x: u64) -> bool {
+        // Check the size first, since the sum lookup is a likely cache miss
+        (i.size() as usize) < self.max_part_size
+            && (self.is_zero)(self.sums[i.0 as usize] + self.values[x as usize])
+    }
 
-        tables
+    /// The subsets of `set` which might form a part with `x`, in the same order as
+    /// `set.subsets()`.  Some of them might not (so check them with `is_part`), but all the ones
+    /// which do are included.
+    fn scan(&self, set: BitSet64, _x: u64) -> Scan {
+        #[cfg(feature = "rayon")]
+        {
+            if set.size() >= PAR_MIN_SIZE {
+                // Each task walks a run of consecutive subsets, starting from the nth
+                const RUN: u32 = 12;
+                let found: Vec<BitSet64> = (0..1u64 << (set.size() - RUN)).into_par_iter()
+                    .flat_map_iter(|run| {
+                        let first = set.nth_subset(run << RUN);
+                        ::std::iter::successors(Some(first), move |i| Some(BitSet64(set.0 & i.0.wrapping_sub(set.0))))
+                            .take(1 << RUN)
+                            .filter(move |&i| self.is_part(i, _x))
+                    })
+                    .collect();
+                return Scan::Found(found.into_iter());
+            }
+        }
+        Scan::All(set.subsets(), 1 << set.size())
     }
+}
 
-    /// Panics if `subset.max() > values.len()`.
-    fn get_mzsp(&self, subset: BitSet64) -> (usize, BitSet64) {
-        self.mzsp_table[subset.0 as usize]
+/// Add `v` to every element of `xs`.  This is where the time goes when building the sum table, so
+/// it's written to make LLVM's job easy: the main loop works on fixed-size blocks with no
+/// dependencies between lanes, which get compiled to SIMD adds.
+fn add_to_all<T: Copy + Add<Output=T> + Shared>(xs: &mut [T], v: T) {
+    const BLOCK: usize = 8;
+    #[cfg(feature = "rayon")]
+    {
+        if xs.len() >= 1 << 14 {
+            xs.par_chunks_mut(1 << 12).for_each(|chunk| add_to_all(chunk, v));
+            return;
+        }
+    }
+    let mut blocks = xs.chunks_exact_mut(BLOCK);
+    for block in &mut blocks {
+        for x in block.iter_mut() { *x = *x + v; }
     }
+    for x in blocks.into_remainder() { *x = *x + v; }
+}
 
-    /// Panics if `subset.max() > values.len()`.
-    fn get_sum(&self, subset: BitSet64) -> isize {
-        self.sum_table[subset.0 as usize]
+/// The subsets to try.
+enum Scan {
+    /// Every subset of the set, of which there are this many.
+    All(Subsets, u64),
+    #[cfg(feature = "rayon")]
+    Found(::std::vec::IntoIter<BitSet64>),
+}
+impl Scan {
+    /// How many subsets there are to try, in all.
+    fn total(&self) -> u64 {
+        match *self {
+            Scan::All(_, n) => n,
+            #[cfg(feature = "rayon")]
+            Scan::Found(ref x) => x.len() as u64,
+        }
+    }
+}
+impl Iterator for Scan {
+    type Item = BitSet64;
+    fn next(&mut self) -> Option<BitSet64> {
+        match *self {
+            Scan::All(ref mut x, _) => x.next(),
+            #[cfg(feature = "rayon")]
+            Scan::Found(ref mut x) => x.next(),
+        }
     }
 }
 
-/// The maximum number of zero-sum partitions of `set ∪ {x}`, and a bitset representing the
-/// partition which contains x.
-fn max_zero_sum_partitions(memo: &MemoTables, values: &[isize], set: BitSet64, x: u64) -> (usize, BitSet64) {
-    let mut best = (0, BitSet64::empty_set());
-    // For all subsets i of `set`, check whether i ∪ {x} forms a zero-sum partition.  If it does,
-    // check how many zero-sum partitions can be formed from set \ i.
-    let neg_val = -(values[x as usize]);
-    for i in set.subsets() {
-        if memo.get_sum(i) == neg_val {
+impl<'a, T, F> Search<'a, T, F>
+    where T: Copy + Default + Add<Output=T> + Shared, F: Fn(T) -> bool + Shared
+{
+    fn new(problem: &'a Problem<'a, T, F>) -> Search<'a, T, F> {
+        let parts = PartTest::new(problem);
+        Search { problem, parts, memo: MemoTables { mzsp_table: Map::new() },
+            weights: Map::new(), largest: Map::new(), cancelled: false }
+    }
+
+    /// The best partitioning of `set`: the number of parts, and the part which contains the
+    /// largest element.
+    fn mzsp(&mut self, set: BitSet64) -> (usize, BitSet64) {
+        if set.size() == 0 { return (0, BitSet64::empty_set()); }
+        if let Some(&x) = self.memo.mzsp_table.get(&set.0) { return x; }
+        if self.cancelled || self.problem.cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            self.cancelled = true;
+            return (0, BitSet64::empty_set());
+        }
+        let mut rest = set;
+        let x = rest.take_max().unwrap();
+        let mzsp = match self.problem.weight {
+            None => self.max_zero_sum_partitions(rest, x),
+            Some(w) => {
+                let (mzsp, weight) = self.max_weight_partitions(w, rest, x);
+                self.weights.insert(set.0, weight);
+                mzsp
+            }
+        };
+        if !self.cancelled { self.memo.mzsp_table.insert(set.0, mzsp); }
+        mzsp
+    }
+
+    /// Under constraints, a remainder might not be partitionable at all, in which case the part
+    /// which left it is no good.
+    fn is_feasible(&self, remainder: BitSet64, rem_mzsp: (usize, BitSet64)) -> bool {
+        !self.problem.options.is_constrained() || rem_mzsp.0 != 0 || remainder.size() == 0
+    }
+
+    /// The maximum number of zero-sum partitions of `set ∪ {x}`, and a bitset representing the
+    /// partition which contains x.
+    fn max_zero_sum_partitions(&mut self, set: BitSet64, x: u64) -> (usize, BitSet64) {
+        let minimize_largest = self.problem.options.minimize_largest_part;
+        let mut best = (0, BitSet64::empty_set());
+        let mut best_largest = usize::MAX;
+        // Only the top level reports progress: the one looking at every element
+        let progress = self.problem.progress.filter(|_| set.size() as usize + 1 == self.problem.values.len());
+        let scan = self.parts.scan(set, x);
+        let total = scan.total();
+        // For all subsets i of `set`, check whether i ∪ {x} forms a zero-sum partition.  If it
+        // does, check how many zero-sum partitions can be formed from set \ i.
+        for (done, i) in scan.enumerate() {
+            // The answer doesn't matter any more, so there's no point looking at the rest
+            if self.cancelled { break; }
+            if let Some(report) = progress {
+                if (done as u64).is_multiple_of(PROGRESS_EVERY) { report(Progress { done: done as u64, total }); }
+            }
+            if !self.parts.is_part(i, x) { continue; }
             // This subset cancels out our element exactly!  i ∪ {x} forms a zsp.
             let remainder = set.minus(i);
-            let rem_mzsp = memo.get_mzsp(remainder);
+            let rem_mzsp = self.mzsp(remainder);
+            if self.cancelled { break; }
+            if !self.is_feasible(remainder, rem_mzsp) { continue; }
             // rem_mzsp is the maximum number of partitions which the remainder can form.
-            if rem_mzsp.0 >= best.0 {
-                best = (rem_mzsp.0 + 1, i);
+            let n = rem_mzsp.0 + 1;
+            if !minimize_largest {
+                if n > best.0 { best = (n, i); }
+                continue;
+            }
+            let rem_largest = if remainder.size() == 0 { 0 } else { self.largest[&remainder.0] };
+            let largest = rem_largest.max(i.size() as usize + 1);
+            if n > best.0 || (n == best.0 && largest < best_largest) {
+                best = (n, i);
+                best_largest = largest;
+            }
+        }
+
+        if let Some(report) = progress.filter(|_| !self.cancelled) { report(Progress { done: total, total }); }
+        if minimize_largest && !self.cancelled { self.largest.insert(set.insert(x).0, best_largest); }
+        (best.0, best.1.insert(x))
+    }
+
+    /// Whether `set` (which sums to zero) can be split into at least `k` parts.  `failed` records
+    /// the smallest `k` for which each set has been found wanting.
+    fn has_parts(&mut self, set: BitSet64, k: usize, failed: &mut Map<u64, usize>) -> bool {
+        if k <= 1 { return k == 0 || set.size() != 0; }
+        if (set.size() as usize) < k { return false; }
+        if failed.get(&set.0).is_some_and(|&f| k >= f) { return false; }
+        let mut rest = set;
+        let x = rest.take_max().unwrap();
+        for i in self.parts.scan(rest, x) {
+            if self.parts.is_part(i, x) && self.has_parts(rest.minus(i), k - 1, failed) {
+                return true;
             }
         }
+        failed.insert(set.0, k);
+        false
     }
 
-    (best.0, best.1.insert(x))
+    /// Like `max_zero_sum_partitions`, but maximizing the total weight of the parts (given by
+    /// `weight`).  Also returns the total weight.
+    fn max_weight_partitions(&mut self, weight: &dyn Fn(BitSet64) -> f64, set: BitSet64, x: u64)
+        -> ((usize, BitSet64), f64)
+    {
+        let mut best: Option<(usize, BitSet64, f64)> = None;
+        for i in self.parts.scan(set, x) {
+            if self.cancelled { break; }
+            if !self.parts.is_part(i, x) { continue; }
+            let remainder = set.minus(i);
+            let rem_mzsp = self.mzsp(remainder);
+            if self.cancelled { break; }
+            if !self.is_feasible(remainder, rem_mzsp) { continue; }
+            let rem_weight = if remainder.size() == 0 { 0.0 } else { self.weights[&remainder.0] };
+            let total = weight(i.insert(x)) + rem_weight;
+            // Ties go to the first, as in `max_zero_sum_partitions`
+            if best.is_none_or(|b| total > b.2) {
+                best = Some((rem_mzsp.0 + 1, i, total));
+            }
+        }
+
+        match best {
+            Some((n, i, total)) => ((n, i.insert(x)), total),
+            None => ((0, BitSet64::singleton(x)), 0.0),
+        }
+    }
 }
 
 #[test]
@@ -170,9 +987,235 @@ fn test() {
     let partitionable   = vec![10, -10, 15, -15];
     let unpartitionable = vec![10, 20, -15, -15];
 
-    assert_eq!(MZSP::compute(&partitionable).len(),   2);
-    assert_eq!(MZSP::compute(&unpartitionable).len(), 1);
+    assert_eq!(MZSP::compute(&partitionable).unwrap().len(),   2);
+    assert_eq!(MZSP::compute(&unpartitionable).unwrap().len(), 1);
 
-    assert_eq!(mzsp(&partitionable),   vec![vec![15, -15], vec![10, -10]]);
+    assert_eq!(mzsp(&partitionable),   vec![vec![10, -10], vec![15, -15]]);
     assert_eq!(mzsp(&unpartitionable), vec![vec![10, 20, -15, -15]]);
 }
+
+#[test]
+fn test_partitioning() {
+    let p = Partitioning::compute(&[10, -10, 15, -15, 3, -1, -2]).unwrap();
+    assert_eq!(p.num_parts(), 3);
+    assert_eq!(p.parts().collect::<Vec<_>>(), vec![&[0, 1][..], &[2, 3], &[4, 5, 6]]);
+    assert_eq!(p.part_of(0), Some(0));
+    assert_eq!(p.part_of(6), Some(2));
+    assert!(p.residual().is_empty());
+
+    let empty = Partitioning::compute(&[]).unwrap();
+    assert_eq!(empty.num_parts(), 0);
+    assert_eq!(empty.parts().count(), 0);
+}
+
+#[test]
+fn test_residual() {
+    let same = |values: &[isize]| Partitioning::compute_with_residual(values).unwrap() == Partitioning::compute(values).unwrap();
+    assert!(same(&[10, -10, 15, -15, 3, -1, -2]));
+    assert!(same(&[]));
+
+    // A rounding error
+    let p = Partitioning::compute_with_residual(&[333, 333, -1000, 333, 7, -7]).unwrap();
+    assert_eq!(p.parts().collect::<Vec<_>>(), vec![&[4, 5][..]]);
+    assert_eq!(p.residual(), vec![0, 1, 2, 3]);
+    let p = Partitioning::compute_with_residual(&[5, 1, -1, 2, -5]).unwrap();
+    assert_eq!(p.num_parts(), 2);
+    assert_eq!(p.residual(), vec![3]);
+    assert_eq!(Partitioning::compute_with_residual(&[isize::MIN, -1]).err(), Some(Error::Overflow));
+}
+
+#[test]
+fn test_order() {
+    // Several equally-good partitionings; this pins down which one we pick, and in what order.
+    let values = vec![1, -1, 1, -1, 2, -2, -1, 1];
+    let p = Partitioning::compute(&values).unwrap();
+    assert_eq!(p.parts().collect::<Vec<_>>(), vec![&[0, 6][..], &[1, 7], &[2, 3], &[4, 5]]);
+    let maxes: Vec<_> = MZSP::compute(&values).unwrap().map(|x| x.max().unwrap()).collect();
+    assert_eq!(maxes, vec![7, 6, 5, 3]);
+}
+
+#[test]
+fn test_clone() {
+    let mut parts = MZSP::compute(&[10, -10, 15, -15, 3, -1, -2]).unwrap();
+    let first = parts.peek();
+    assert_eq!(parts.next(), first);
+    let rest = parts.clone();
+    assert_eq!(parts.peek(), rest.peek());
+    assert_eq!(rest.len(), 2);
+    assert_eq!(parts.collect::<Vec<_>>(), rest.collect::<Vec<_>>());
+    assert_eq!(MZSP::compute(&[]).unwrap().peek(), None);
+}
+
+#[test]
+fn test_by_size() {
+    let values = vec![3, -1, -2, 100, -100, 50, -21, -29, 7, -7];
+    let p = Partitioning::compute(&values).unwrap();
+    let sizes = |order| p.parts_by_size(order).map(|x| x.to_vec()).collect::<Vec<_>>();
+    assert_eq!(sizes(SizeOrder::SmallestFirst), vec![vec![3, 4], vec![8, 9], vec![0, 1, 2], vec![5, 6, 7]]);
+    assert_eq!(sizes(SizeOrder::LargestFirst), vec![vec![0, 1, 2], vec![5, 6, 7], vec![3, 4], vec![8, 9]]);
+    let sizes: Vec<_> = MZSP::compute(&values).unwrap().by_size(SizeOrder::SmallestFirst).map(|x| x.size()).collect();
+    assert_eq!(sizes, vec![2, 2, 3, 3]);
+}
+
+#[cfg(feature = "introspect")]
+#[test]
+fn test_introspect() {
+    let values = vec![10, -10, 15, -15, 3, -1, -2];
+    let solution = MZSP::compute(&values).unwrap();
+    // Following the witnesses down from the top gives the partitioning
+    let mut set = BitSet64::full_set(values.len() as u64);
+    let mut witnesses = vec![];
+    while let Some(entry) = solution.memo_entry(set).filter(|x| x.parts > 0) {
+        witnesses.push(entry.witness);
+        set = set.minus(entry.witness);
+    }
+    assert_eq!(witnesses, solution.clone().collect::<Vec<_>>());
+    let entries = solution.memo_entries();
+    assert!(entries.windows(2).all(|w| w[0].set.0 < w[1].set.0));
+    assert!(entries.iter().all(|x| solution.memo_entry(x.set) == Some(*x)));
+    assert_eq!(solution.memo_entry(BitSet64::singleton(0)), None);
+}
+
+#[test]
+fn test_solver() {
+    fn send_sync<T: Send + Sync>(_: &T) {}
+    let values = vec![10, -10, 15, -15, 3, -1, -2];
+    let solver = Solver::compute(&values).unwrap();
+    send_sync(&solver);
+    assert_eq!(solver.num_parts(), 3);
+    assert_eq!(solver.parts().collect::<Vec<_>>(), MZSP::compute(&values).unwrap().collect::<Vec<_>>());
+    // After taking out the first part, the remainder has been solved too
+    let first = solver.parts().next().unwrap();
+    let rest = BitSet64::full_set(7).minus(first);
+    assert_eq!(solver.parts_of(rest).unwrap().collect::<Vec<_>>(), solver.parts().skip(1).collect::<Vec<_>>());
+    let mut parts = MZSP::compute(&values).unwrap();
+    parts.next();
+    assert_eq!(Solver::from(parts).num_parts(), 2);
+    assert_eq!(Solver::compute(&[]).unwrap().parts().len(), 0);
+}
+
+#[test]
+fn test_max_part_size() {
+    let values = vec![10, -10, 15, -15, 3, -1, -2];
+    let capped = |k| Partitioning::compute_with(&values, Options { max_part_size: Some(k), ..Options::default() });
+    assert_eq!(capped(3).unwrap().num_parts(), 3);
+    assert_eq!(capped(2).err(), Some(Error::Infeasible));
+    let values = vec![1, 1, 1, 1, -4];
+    let capped = |k| Partitioning::compute_with(&values, Options { max_part_size: Some(k), ..Options::default() });
+    assert_eq!(capped(5).unwrap().num_parts(), 1);
+    assert_eq!(capped(4).err(), Some(Error::Infeasible));
+    // Parts of size 3 are possible, but only if the other part is of size 4
+    let values = vec![5, -5, 3, -1, -2, 2, -2];
+    let capped = |k| Partitioning::compute_with(&values, Options { max_part_size: Some(k), ..Options::default() });
+    assert_eq!(capped(3).unwrap().num_parts(), 3);
+    assert_eq!(capped(2).err(), Some(Error::Infeasible));
+}
+
+#[test]
+fn test_minimize_largest_part() {
+    // Two parts either way, but they can be sized 5 and 2, or 3 and 4
+    let values = vec![-3, 1, 1, -3, 1, 4, -1];
+    let options = Options { minimize_largest_part: true, ..Options::default() };
+    let parts = |p: Partitioning| p.parts().map(|x| x.to_vec()).collect::<Vec<_>>();
+    assert_eq!(parts(Partitioning::compute(&values).unwrap()), vec![vec![0, 2, 3, 4, 5], vec![1, 6]]);
+    assert_eq!(parts(Partitioning::compute_with(&values, options).unwrap()), vec![vec![0, 5, 6], vec![1, 2, 3, 4]]);
+}
+
+#[test]
+fn test_weighted() {
+    let values = vec![10, -10, 15, -15, 3, -1, -2];
+    let unweighted = Partitioning::compute(&values).unwrap();
+    let constant = Partitioning::compute_weighted(&values, Options::default(), |_| 1.0).unwrap();
+    assert_eq!(constant, unweighted);
+    // Many partitionings are tied for the most parts
+    let tied = vec![1, -1, 1, -1, 2, -2, -1, 1];
+    assert_eq!(Partitioning::compute_weighted(&tied, Options::default(), |_| 1.0).unwrap(), Partitioning::compute(&tied).unwrap());
+
+    // Keeping 0 and 2 together is worth more than the extra part
+    let together = |set: BitSet64| if set.contains(0) && set.contains(2) { 5.0 } else { 1.0 };
+    let p = Partitioning::compute_weighted(&values, Options::default(), together).unwrap();
+    assert_eq!(p.num_parts(), 2);
+    assert_eq!(p.part_of(0), p.part_of(2));
+
+    // Weights and constraints together
+    let p = Partitioning::compute_weighted(&values, Options { max_part_size: Some(3), ..Options::default() }, together).unwrap();
+    assert_eq!(p, unweighted);
+}
+
+#[test]
+fn test_tolerance() {
+    let values = vec![10.004, -10.0, 15.0, -14.998, 3.0, -1.0, -2.002];
+    assert_eq!(Partitioning::compute_with_tolerance(&values, 0.01).unwrap().num_parts(), 3);
+    assert_eq!(Partitioning::compute_with_tolerance(&values, 0.0).unwrap().num_parts(), 0);
+    let values = vec![0.1, 0.2, -0.3];  // Not exactly zero-sum in floating point!
+    assert_eq!(Partitioning::compute_with_tolerance(&values, 1e-9).unwrap().num_parts(), 1);
+}
+
+#[test]
+fn test_try_compute() {
+    assert_eq!(MZSP::try_compute(vec![]).unwrap().len(), 0);
+    assert_eq!(MZSP::try_compute(vec![3, -1, -2, 7, -7]).unwrap().len(), 2);
+    assert_eq!(MZSP::try_compute(vec![1; 64]).err(), Some(Error::TooManyElements { len: 64 }));
+    assert_eq!(MZSP::try_compute(vec![i64::MAX, 1]).err(), Some(Error::NotZeroSum { sum: 1 << 63 }));
+    let options = Options { max_part_size: Some(2), ..Options::default() };
+    assert_eq!(MZSP::try_compute_with(vec![3, -1, -2], options).err(), Some(Error::Infeasible));
+    assert_eq!(MZSP::try_compute(vec![i64::MAX, 1, i64::MIN]).err(), Some(Error::Overflow));
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(MZSP::compute(&[1; 64]).err(), Some(Error::TooManyElements { len: 64 }));
+    assert_eq!(exists_partitioning(&[0; 64], 1), Err(Error::TooManyElements { len: 64 }));
+    assert_eq!(Partitioning::compute(&[isize::MAX, 1, isize::MIN]).err(), Some(Error::Overflow));
+    assert_eq!(Partitioning::compute(&[isize::MAX, isize::MIN + 1]).unwrap().num_parts(), 1);
+    let cancel = AtomicBool::new(false);
+    let values = vec![10, -10, 15, -15, 3, -1, -2];
+    assert_eq!(Partitioning::compute_cancellable(&values, Options::default(), &cancel), Partitioning::compute(&values));
+    cancel.store(true, Ordering::Relaxed);
+    assert_eq!(Partitioning::compute_cancellable(&values, Options::default(), &cancel).err(), Some(Error::Cancelled));
+    // Cancelled part-way through, once some of the search is done
+    let cancel = AtomicBool::new(false);
+    let options = Options { minimize_largest_part: true, ..Options::default() };
+    let values = vec![10, -10, 15, -15, 3, -1, -2, 4, -4, 6, -6, 1, -1];
+    let stop = |p: Progress| if p.done > 0 { cancel.store(true, Ordering::Relaxed) };
+    assert_eq!(Partitioning::compute_monitored(&values, options, Some(&cancel), &stop).err(), Some(Error::Cancelled));
+}
+
+#[test]
+fn test_exists_partitioning() {
+    let cases = [vec![], vec![1, -1, 1, -1, 2, -2, -1, 1], vec![3, 4, -7, 1, 1, -2, 6, -3, -3],
+        vec![2, 2, 2, -3, -3], vec![-3, 1, 1, -3, 1, 4, -1], vec![4, -2, 0, 1]];
+    for values in &cases {
+        let n = Partitioning::compute(values).unwrap().num_parts();
+        for k in 0..values.len() + 2 {
+            assert_eq!(exists_partitioning(values, k), Ok(k <= n), "{:?}, k = {}", values, k);
+        }
+    }
+}
+
+#[test]
+fn test_bounds() {
+    assert_eq!(bounds(&[]), (0, 0));
+    assert_eq!(bounds(&[0, 0, 3, -3]), (3, 3));
+    assert_eq!(bounds(&[1, 2, -3, 4, -4, 5]), (2, 2));  // 5 is left over
+    assert_eq!(bounds(&[5, 5, -10]), (1, 1));
+    for values in &[vec![1, -1, 1, -1, 2, -2, -1, 1], vec![3, 4, -7, 1, 1, -2, 6, -3, -3], vec![2, 2, 2, -3, -3]] {
+        let (lower, upper) = bounds(values);
+        let n = Partitioning::compute(values).unwrap().num_parts();
+        assert!(lower <= n && n <= upper, "{:?}: {} not in {}..={}", values, n, lower, upper);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let values = vec![10, -10, 15, -15, 3, -1, -2];
+    let json = serde_json::to_string(&MZSP::compute(&values).unwrap()).unwrap();
+    let parts: Vec<_> = serde_json::from_str::<MZSP>(&json).unwrap().collect();
+    assert_eq!(parts, MZSP::compute(&values).unwrap().collect::<Vec<_>>());
+    assert_eq!(parts.len(), 3);
+
+    let partitioning = Partitioning::compute(&values).unwrap();
+    let json = serde_json::to_string(&partitioning).unwrap();
+    assert_eq!(serde_json::from_str::<Partitioning>(&json).unwrap(), partitioning);
+}
@@ -0,0 +1,103 @@
+/*!
+Payment accounts: the ways someone pays and gets paid (eg. a bank account, PayPal, or cash).
+
+Each person's accounts are listed in the `accounts` section of the config file, or of a ledger's
+header, with a handle for each (eg. an IBAN, or an email address):
+
+```json
+{ "accounts": { "alice": { "bank": "DE89 3704 0044 0532 0130 00", "paypal": "alice@example.com", "cash": "" } } }
+```
+
+A transfer can say which accounts it went through, with `from_account` and `to_account`.  The
+accounts don't affect anyone's balance: a person's balance is the same whichever of their accounts
+they used.  `repay accounts` shows how much went through each one.
+*/
+
+use ledger::Transfer;
+use std::collections::BTreeMap;
+
+/// Each person's accounts, and their handles.  (By the names which are used, so after any aliases.)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Accounts {
+    pub people: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Accounts {
+    pub fn is_empty(&self) -> bool {
+        self.people.is_empty()
+    }
+
+    /// Add some more accounts.  It's an error if one of them has a different handle already.
+    pub fn add(&mut self, other: &Accounts) -> Result<(), String> {
+        for (who, accounts) in &other.people {
+            let ours = self.people.entry(who.clone()).or_default();
+            for (name, handle) in accounts {
+                match ours.get(name) {
+                    Some(x) if x != handle => return Err(format!("{}'s {} account is both \"{}\" and \"{}\"", who, name, x, handle)),
+                    _ => { ours.insert(name.clone(), handle.clone()); }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A problem with the accounts which a transfer says it went through: one which isn't any of
+    /// the person's listed accounts.  (Anyone whose accounts aren't listed can use any.)
+    pub fn check(&self, t: &Transfer<String>) -> Option<String> {
+        for (who, account) in [(&t.from, &t.from_account), (&t.to, &t.to_account)] {
+            let (account, theirs) = match (account, self.people.get(who)) {
+                (Some(account), Some(theirs)) if !theirs.is_empty() => (account, theirs),
+                _ => continue,
+            };
+            if !theirs.contains_key(account) {
+                let names: Vec<&str> = theirs.keys().map(String::as_str).collect();
+                return Some(format!("{} has no account called \"{}\" (they have: {})", who, account, names.join(", ")));
+            }
+        }
+        None
+    }
+}
+
+/// How much went into and out of one account.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flow {
+    pub paid: isize,
+    pub received: isize,
+}
+
+/// How much went through each person's accounts, in each currency: (person, currency) => account
+/// => flow.  Transfers which don't say which account they used count under `None`.
+pub type Flows = BTreeMap<(String, Option<String>), BTreeMap<Option<String>, Flow>>;
+
+/// Add up how much went through each account.
+pub fn flows<I: IntoIterator<Item=Transfer<String>>>(transfers: I) -> Flows {
+    let mut flows = Flows::new();
+    for t in transfers {
+        let from = flows.entry((t.from, t.currency.clone())).or_default().entry(t.from_account).or_default();
+        from.paid += t.amt;
+        let to = flows.entry((t.to, t.currency)).or_default().entry(t.to_account).or_default();
+        to.received += t.amt;
+    }
+    flows
+}
+
+#[test]
+fn test_accounts() {
+    let t = |from: &str, to: &str, amt, from_account: Option<&str>, to_account: Option<&str>| Transfer {
+        from_account: from_account.map(String::from),
+        to_account: to_account.map(String::from),
+        ..Transfer::new(from.to_string(), to.to_string(), amt)
+    };
+    let mut accounts: Accounts = ::serde_json::from_str(r#"{"ann": {"bank": "DE89", "paypal": "ann@example.com"}}"#).unwrap();
+    assert_eq!(accounts.check(&t("bob", "ann", 5, Some("cash"), Some("paypal"))), None);
+    assert!(accounts.check(&t("ann", "bob", 5, Some("venmo"), None)).unwrap().contains("bank, paypal"));
+    let other: Accounts = ::serde_json::from_str(r#"{"ann": {"bank": "GB29"}}"#).unwrap();
+    assert!(accounts.add(&other).is_err());
+
+    let flows = flows(vec![t("ann", "bob", 5, Some("bank"), None), t("ann", "bob", 3, Some("paypal"), None), t("bob", "ann", 2, None, Some("bank"))]);
+    let ann = &flows[&("ann".to_string(), None)];
+    assert_eq!(ann[&Some("bank".to_string())], Flow { paid: 5, received: 2 });
+    assert_eq!(ann[&Some("paypal".to_string())], Flow { paid: 3, received: 0 });
+    assert_eq!(flows[&("bob".to_string(), None)][&None], Flow { paid: 2, received: 8 });
+}
@@ -0,0 +1,313 @@
+/*!
+Exports from other bill-splitting apps: Tricount, and Settle Up.
+
+Tricount's CSV export has a row per expense, with columns like these:
+
+```text
+Title,Amount,Currency,Paid by,Impacted to Ann,Impacted to Bob,Date,Category
+Dinner,30.00,EUR,Ann,15.00,15.00,2024-05-01,Food
+```
+
+The "Impacted to" (or, in older exports, "Paid for") columns are how much of the amount was each
+person's.  Money transfers between members are rows like any other, impacting just the recipient.
+
+Settle Up's CSV export has a row per transaction too:
+
+```text
+Who paid,Amount,Currency,For whom,Split amounts,Purpose,Category,Date & time,Type
+Ann,30.00,EUR,Ann;Bob,15.00;15.00,Dinner,Food,2024-05-01 19:30,expense
+```
+
+Several people can pay, with `;` between them and between their amounts.  Settle Up can also back
+up a group as JSON, with its `members` and `transactions`; there, each item of a transaction is
+split between the members it's for in proportion to their weights, and the members who paid paid
+in proportion to theirs.
+
+Either way, each expense becomes the transfers which change everyone's balances by what they paid
+and what they owe, with the expense's title, date, currency, and category.  Amounts are in major
+units (see `locale`).
+*/
+
+use date::{Date, Time, Timestamp};
+use expense::split;
+use ledger::{ReadOptions, Transfer};
+use plan::construct_plan;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+use units::Money;
+
+/// An expense (or a transfer between members): how much each person paid, and how much was theirs.
+struct Expense {
+    /// Each person's share, less what they paid.
+    shares: BTreeMap<String, isize>,
+    template: Transfer<String>,
+}
+
+impl Expense {
+    fn new(template: Transfer<String>) -> Expense {
+        Expense { shares: BTreeMap::new(), template }
+    }
+
+    fn paid(&mut self, who: &str, amt: isize) {
+        *self.shares.entry(who.to_string()).or_insert(0) -= amt;
+    }
+
+    fn owes(&mut self, who: &str, amt: isize) {
+        *self.shares.entry(who.to_string()).or_insert(0) += amt;
+    }
+
+    fn transfers(self, what: &str) -> Result<Vec<Transfer<String>>, String> {
+        let total: isize = self.shares.values().sum();
+        if total != 0 {
+            return Err(format!("{}: the shares don't add up to what was paid (they're off by {})", what, total));
+        }
+        let template = self.template;
+        // `construct_plan` finds transfers which cancel out the balances it's given, so give it
+        // the opposite of what we want
+        Ok(construct_plan(self.shares.into_iter().filter(|&(_, x)| x != 0).map(|(who, x)| (who, -x)).collect())
+            .into_iter()
+            .map(|mut t| {
+                t.normalise();
+                Transfer { from: t.from, to: t.to, amt: t.amt, ..template.clone() }
+            })
+            .collect())
+    }
+}
+
+/// A CSV table, with a header row.
+struct Table {
+    names: Vec<String>,
+    rows: Vec<(u64, Vec<String>)>,
+}
+
+impl Table {
+    fn read<R: Read>(mut reader: R) -> Result<Table, String> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).map_err(|e| e.to_string())?;
+        let header = text.trim_start_matches('\u{feff}').lines().next().unwrap_or_default();
+        let delimiter = [b',', b';', b'\t'].iter().cloned().max_by_key(|&d| header.bytes().filter(|&b| b == d).count()).unwrap_or(b',');
+        let mut reader = ::csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .trim(::csv::Trim::All)
+            .from_reader(text.trim_start_matches('\u{feff}').as_bytes());
+        let names = reader.headers().map_err(|e| e.to_string())?.iter().map(String::from).collect();
+        let mut rows = vec![];
+        for row in reader.records() {
+            let row = row.map_err(|e| e.to_string())?;
+            if row.iter().all(|x| x.is_empty()) { continue; }
+            rows.push((row.position().map_or(0, |p| p.line()), row.iter().map(String::from).collect()));
+        }
+        Ok(Table { names, rows })
+    }
+
+    /// The first of these columns which the table has.
+    fn col(&self, names: &[&str]) -> Option<usize> {
+        names.iter().find_map(|name| self.names.iter().position(|x| x.eq_ignore_ascii_case(name)))
+    }
+}
+
+fn amount(s: &str, currency: Option<&str>, options: &ReadOptions, line: u64) -> Result<isize, String> {
+    options.locale.parse_minor(s, options.exponent(currency)).map_err(|e| format!("Line {}: {}", line, e))
+}
+
+/// Dates like `2024-05-01`, `2024-05-01 19:30`, or `01/05/2024` (day first).
+fn date(s: &str, line: u64) -> Result<Option<Timestamp>, String> {
+    let s = s.trim();
+    if s.is_empty() { return Ok(None); }
+    if let Ok(x) = s.parse() { return Ok(Some(x)); }
+    let invalid = || format!("Line {}: invalid date '{}'", line, s);
+    let mut words = s.split_whitespace();
+    let day: Vec<&str> = words.next().unwrap_or_default().split(['-', '/', '.']).collect();
+    let nums: Vec<u32> = day.iter().map(|x| x.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+    let date = match nums[..] {
+        [y, m, d] if day[0].len() == 4 => Date::new(y as i32, m as u8, d as u8),
+        [d, m, y] if day[2].len() == 4 => Date::new(y as i32, m as u8, d as u8),
+        _ => None,
+    }.ok_or_else(invalid)?;
+    // The apps don't say what time zone their times are in, so they're taken as UTC
+    let time = match words.next() {
+        Some(time) => {
+            let hms: Vec<u32> = time.split(':').map(|x| x.parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+            match hms[..] {
+                [h, m] | [h, m, _] if h < 24 && m < 60 => Some(Time { secs: h * 3600 + m * 60 + hms.get(2).cloned().unwrap_or(0), nanos: 0, offset_secs: 0 }),
+                _ => return Err(invalid()),
+            }
+        }
+        None => None,
+    };
+    Ok(Some(Timestamp { date, time }))
+}
+
+/// Read Tricount's CSV export.
+pub fn read_tricount<R: Read>(reader: R, options: &ReadOptions) -> Result<Vec<Transfer<String>>, String> {
+    let table = Table::read(reader)?;
+    let col = |names: &[&str]| table.col(names);
+    let (title, amt, payer) = match (col(&["Title", "Name", "Description"]), col(&["Amount"]), col(&["Paid by"])) {
+        (Some(title), Some(amt), Some(payer)) => (title, amt, payer),
+        _ => return Err("This doesn't look like a Tricount export (it needs 'Title', 'Amount', and 'Paid by' columns)".into()),
+    };
+    let (currency, when, category) = (col(&["Currency"]), col(&["Date", "Date & time"]), col(&["Category"]));
+    let prefix = if table.names.iter().any(|x| x.starts_with("Impacted to ")) { "Impacted to " } else { "Paid for " };
+    let people: Vec<(usize, &str)> = table.names.iter().enumerate()
+        .filter_map(|(i, x)| x.strip_prefix(prefix).map(|name| (i, name.trim()))).collect();
+    if people.is_empty() {
+        return Err(format!("The Tricount export has no '{}...' columns, which say who each expense was for", prefix));
+    }
+    let mut transfers = vec![];
+    for (line, row) in &table.rows {
+        let field = |i: usize| row.get(i).map_or("", |x| x.as_str());
+        let currency = currency.map(|i| field(i).to_string()).filter(|x| !x.is_empty());
+        let mut expense = Expense::new(Transfer {
+            memo: Some(field(title).to_string()).filter(|x| !x.is_empty()),
+            date: match when { Some(i) => date(field(i), *line)?, None => None },
+            category: category.map(|i| field(i).to_string()).filter(|x| !x.is_empty()),
+            currency: currency.clone(),
+            ..Transfer::new(String::new(), String::new(), 0)
+        });
+        expense.paid(field(payer), amount(field(amt), currency.as_deref(), options, *line)?);
+        for &(i, who) in &people {
+            if !field(i).is_empty() {
+                expense.owes(who, amount(field(i), currency.as_deref(), options, *line)?);
+            }
+        }
+        transfers.extend(expense.transfers(&format!("Line {}", line))?);
+    }
+    Ok(transfers)
+}
+
+/// Read Settle Up's CSV export, or its JSON backup of a group.
+pub fn read_settle_up<R: Read>(mut reader: R, options: &ReadOptions) -> Result<Vec<Transfer<String>>, String> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text).map_err(|e| e.to_string())?;
+    if text.trim_start_matches('\u{feff}').trim_start().starts_with('{') {
+        return read_settle_up_json(&text, options);
+    }
+    let table = Table::read(text.as_bytes())?;
+    let col = |names: &[&str]| table.col(names);
+    let (payers, amt, whom) = match (col(&["Who paid"]), col(&["Amount"]), col(&["For whom"])) {
+        (Some(payers), Some(amt), Some(whom)) => (payers, amt, whom),
+        _ => return Err("This doesn't look like a Settle Up export (it needs 'Who paid', 'Amount', and 'For whom' columns)".into()),
+    };
+    let shares = col(&["Split amounts"]);
+    let (currency, purpose, category, when) = (col(&["Currency"]), col(&["Purpose"]), col(&["Category"]), col(&["Date & time", "Date time", "Date"]));
+    let list = |x: &str| -> Vec<String> { x.split(';').map(str::trim).filter(|x| !x.is_empty()).map(String::from).collect() };
+    let mut transfers = vec![];
+    for (line, row) in &table.rows {
+        let field = |i: usize| row.get(i).map_or("", |x| x.as_str());
+        let currency = currency.map(|i| field(i).to_string()).filter(|x| !x.is_empty());
+        let parse = |xs: Vec<String>| xs.iter().map(|x| amount(x, currency.as_deref(), options, *line)).collect::<Result<Vec<_>, _>>();
+        let mut expense = Expense::new(Transfer {
+            memo: purpose.map(|i| field(i).to_string()).filter(|x| !x.is_empty()),
+            date: match when { Some(i) => date(field(i), *line)?, None => None },
+            category: category.map(|i| field(i).to_string()).filter(|x| !x.is_empty()),
+            currency: currency.clone(),
+            ..Transfer::new(String::new(), String::new(), 0)
+        });
+        let (payers, paid) = (list(field(payers)), parse(list(field(amt)))?);
+        let total: isize = paid.iter().sum();
+        match (payers.len(), paid.len()) {
+            (_, 1) => for (who, x) in payers.iter().zip(split(paid[0], &vec![1; payers.len()])) { expense.paid(who, x) },
+            (n, m) if n == m => for (who, &x) in payers.iter().zip(&paid) { expense.paid(who, x) },
+            _ => return Err(format!("Line {}: {} people paid, but there are {} amounts", line, payers.len(), paid.len())),
+        }
+        let whom = list(field(whom));
+        let owed = match shares.map(|i| list(field(i))) {
+            Some(shares) if !shares.is_empty() => parse(shares)?,
+            _ => split(total, &vec![1; whom.len()]),
+        };
+        if owed.len() != whom.len() {
+            return Err(format!("Line {}: it's for {} people, but there are {} split amounts", line, whom.len(), owed.len()));
+        }
+        for (who, x) in whom.iter().zip(owed) { expense.owes(who, x); }
+        transfers.extend(expense.transfers(&format!("Line {}", line))?);
+    }
+    Ok(transfers)
+}
+
+/// A Settle Up group, as JSON: `{"members": {id: {"name": ...}}, "transactions": {id: {...}}}`.
+fn read_settle_up_json(text: &str, options: &ReadOptions) -> Result<Vec<Transfer<String>>, String> {
+    let group: Value = ::serde_json::from_str(text.trim_start_matches('\u{feff}')).map_err(|e| e.to_string())?;
+    let members = group.get("members").and_then(Value::as_object);
+    let transactions = group.get("transactions").and_then(Value::as_object);
+    let (members, transactions) = match (members, transactions) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return Err("This doesn't look like a Settle Up group (it needs 'members' and 'transactions')".into()),
+    };
+    let name = |id: &Value| -> Result<String, String> {
+        let id = id.as_str().unwrap_or_default();
+        members.get(id).and_then(|m| m.get("name")).and_then(Value::as_str).map(String::from)
+            .ok_or_else(|| format!("There's no member with id '{}'", id))
+    };
+    // Amounts and weights are strings (or sometimes numbers)
+    let text = |x: Option<&Value>| x.map_or(String::new(), |x| x.as_str().map_or_else(|| x.to_string(), String::from));
+    let weights = |people: &[Value], what: &str| -> Result<(Vec<String>, Vec<u32>), String> {
+        let mut names = vec![];
+        let mut weights = vec![];
+        for x in people {
+            names.push(name(&x["memberId"])?);
+            let weight = text(x.get("weight"));
+            let w = weight.parse::<Money>().ok().and_then(|m| m.rescale(4)).filter(|m| m.minor > 0)
+                .ok_or_else(|| format!("{}: invalid weight '{}'", what, weight))?;
+            weights.push(w.minor as u32);
+        }
+        Ok((names, weights))
+    };
+    let mut transactions: Vec<(&String, &Value)> = transactions.iter().collect();
+    transactions.sort_by_key(|(_, t)| t.get("dateTime").and_then(Value::as_i64));
+    let mut transfers = vec![];
+    for (id, t) in transactions {
+        let what = format!("Transaction {}", id);
+        let currency = t.get("currencyCode").and_then(Value::as_str).map(String::from);
+        let date = t.get("dateTime").and_then(Value::as_i64).map(|ms| Timestamp::from(Date::from_days(ms.div_euclid(86_400_000))));
+        let mut expense = Expense::new(Transfer {
+            memo: t.get("purpose").and_then(Value::as_str).filter(|x| !x.is_empty()).map(String::from),
+            category: t.get("category").and_then(Value::as_str).filter(|x| !x.is_empty()).map(String::from),
+            currency: currency.clone(),
+            date,
+            ..Transfer::new(String::new(), String::new(), 0)
+        });
+        let mut total = 0;
+        for item in t.get("items").and_then(Value::as_array).map_or(&[][..], |x| x.as_slice()) {
+            let amt = options.locale.parse_minor(&text(item.get("amount")), options.exponent(currency.as_deref()))
+                .map_err(|e| format!("{}: {}", what, e))?;
+            let (names, weights) = weights(item.get("forWhom").and_then(Value::as_array).map_or(&[][..], |x| x.as_slice()), &what)?;
+            if names.is_empty() { return Err(format!("{}: an item isn't for anyone", what)); }
+            for (who, x) in names.iter().zip(split(amt, &weights)) { expense.owes(who, x); }
+            total += amt;
+        }
+        let (names, weights) = weights(t.get("whoPaid").and_then(Value::as_array).map_or(&[][..], |x| x.as_slice()), &what)?;
+        if names.is_empty() { return Err(format!("{}: nobody paid", what)); }
+        for (who, x) in names.iter().zip(split(total, &weights)) { expense.paid(who, x); }
+        transfers.extend(expense.transfers(&what)?);
+    }
+    Ok(transfers)
+}
+
+#[test]
+fn test_read() {
+    use ledger::balances;
+    let options = ReadOptions::default();
+    let tricount = "Title,Amount,Currency,Exchange rate,Paid by,Impacted to Ann,Impacted to Bob,Date,Category\n\
+                    Dinner,30.00,EUR,1,Ann,10.00,20.00,2024-05-01,Food\nRefund,5.00,EUR,1,Bob,5.00,,01/06/2024,\n";
+    let transfers = read_tricount(tricount.as_bytes(), &options).unwrap();
+    assert_eq!(balances(transfers.clone()).1, vec![("Ann".into(), -1500), ("Bob".into(), 1500)]);
+    assert_eq!((transfers[0].memo.as_deref(), transfers[0].category.as_deref()), (Some("Dinner"), Some("Food")));
+    assert_eq!(transfers[1].date.unwrap().to_string(), "2024-06-01");
+    assert!(read_tricount("Title,Amount,Paid by,Impacted to Ann\nX,3,Bob,2\n".as_bytes(), &options).unwrap_err().contains("off by -100"));
+
+    let settle_up = "Who paid,Amount,Currency,For whom,Split amounts,Purpose,Category,Date & time,Type\n\
+                     Ann,30.00,EUR,Ann;Bob;Cara,,Dinner,Food,2024-05-01 19:30,expense\nAnn;Bob,10;20,EUR,Cara,30.00,Tickets,,,expense\n";
+    let transfers = read_settle_up(settle_up.as_bytes(), &options).unwrap();
+    assert_eq!(balances(transfers.clone()).1, vec![("Ann".into(), -3000), ("Bob".into(), -1000), ("Cara".into(), 4000)]);
+    assert_eq!(transfers[0].date.unwrap().to_string(), "2024-05-01T19:30:00Z");
+
+    let group = r#"{"members": {"m1": {"name": "Ann"}, "m2": {"name": "Bob"}},
+        "transactions": {"t1": {"purpose": "Taxi", "dateTime": 1714521600000, "currencyCode": "EUR",
+            "whoPaid": [{"memberId": "m1", "weight": "1"}],
+            "items": [{"amount": "9.00", "forWhom": [{"memberId": "m1", "weight": "1"}, {"memberId": "m2", "weight": "2"}]}]}}}"#;
+    let transfers = read_settle_up(group.as_bytes(), &options).unwrap();
+    assert_eq!(transfers.iter().map(|t| (t.from.as_str(), t.to.as_str(), t.amt)).collect::<Vec<_>>(), vec![("Ann", "Bob", 600)]);
+    assert_eq!(transfers[0].date.unwrap().to_string(), "2024-05-01");
+}
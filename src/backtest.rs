@@ -0,0 +1,172 @@
+/*!
+Replaying a ledger to see how a settlement policy would have worked out.
+
+The ledger is read in order, and every so often everyone settles up, using some strategy.  Each
+settlement brings every balance back to zero, so it only has to deal with the transfers since the
+last one.  Comparing the totals for different policies (eg. settling often vs. settling once at
+the end) shows what each would have cost, in repayments and in money moved.
+*/
+
+use date::Date;
+use export::DebtGraph;
+use ledger::{self, Transfer};
+use netting;
+use plan;
+use std::str::FromStr;
+
+/// How to settle up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// A repayment plan, as computed by `plan::plan_with`.
+    Plan,
+    /// Net out each pair's debts, and nothing more (see `netting`).
+    NetPairs,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Strategy, String> {
+        match s {
+            "plan" => Ok(Strategy::Plan),
+            "net-pairs" => Ok(Strategy::NetPairs),
+            _ => Err(format!("Unknown strategy '{}' (expected 'plan' or 'net-pairs')", s)),
+        }
+    }
+}
+
+/// How often to settle up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    /// After every this many transfers (and at the end).
+    Transfers(usize),
+    /// At the end of each calendar period, going by the transfers' dates (and at the end).
+    /// Undated transfers count towards the current period.
+    Period(Period),
+    /// Only once, at the end.
+    End,
+}
+
+/// A calendar period.  Weeks start on Monday.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    /// Numbers the periods, in order.
+    fn index(self, date: Date) -> i64 {
+        match self {
+            Period::Day => date.days(),
+            // 1970-01-01 was a Thursday
+            Period::Week => (date.days() + 3).div_euclid(7),
+            Period::Month => i64::from(date.year()) * 12 + i64::from(date.month()),
+            Period::Year => i64::from(date.year()),
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Interval, String> {
+        match s {
+            "end" => Ok(Interval::End),
+            "day" => Ok(Interval::Period(Period::Day)),
+            "week" => Ok(Interval::Period(Period::Week)),
+            "month" => Ok(Interval::Period(Period::Month)),
+            "year" => Ok(Interval::Period(Period::Year)),
+            _ => match s.parse() {
+                Ok(n) if n > 0 => Ok(Interval::Transfers(n)),
+                _ => Err(format!("Invalid interval '{}' (expected 'end', 'day', 'week', 'month', 'year', or a number of transfers)", s)),
+            },
+        }
+    }
+}
+
+/// One simulated settlement.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Settlement {
+    /// The number of transfers read (in total) when it happened.
+    pub after: usize,
+    pub repayments: usize,
+    /// The total amount repaid.
+    pub flow: isize,
+}
+
+/// Replay the transfers, settling up at the given interval.  There's always a settlement at the
+/// end, unless there's nothing to settle.  Fails if the exact solver can't make one of the plans.
+pub fn backtest<I>(transfers: I, every: Interval, strategy: Strategy, options: &plan::Options) -> Result<Vec<Settlement>, ::mzsp::Error>
+    where I: IntoIterator<Item=Transfer<String>>
+{
+    let mut settlements = vec![];
+    let mut batch = vec![];
+    let mut n = 0;
+    let mut period = None;
+    for t in transfers {
+        if let (Interval::Period(p), Some(date)) = (every, t.date) {
+            let next = p.index(date.date);
+            if period.is_some_and(|x| x != next) && !batch.is_empty() {
+                settlements.push(settle(::std::mem::take(&mut batch), n, strategy, options)?);
+            }
+            period = Some(next);
+        }
+        batch.push(t);
+        n += 1;
+        if every == Interval::Transfers(batch.len()) {
+            settlements.push(settle(::std::mem::take(&mut batch), n, strategy, options)?);
+        }
+    }
+    if !batch.is_empty() {
+        settlements.push(settle(batch, n, strategy, options)?);
+    }
+    Ok(settlements)
+}
+
+fn settle(batch: Vec<Transfer<String>>, after: usize, strategy: Strategy, options: &plan::Options) -> Result<Settlement, ::mzsp::Error> {
+    let repayments: Vec<Transfer<String>> = match strategy {
+        Strategy::Plan => {
+            let mut repayments = vec![];
+            // Each currency is settled separately
+            for balances in ledger::balances_by_currency(batch).1.into_values() {
+                let plan = plan::plan_with(balances, options)?;
+                for w in &plan.warnings { debug!("After {} transfers: {}", after, w); }
+                repayments.extend(plan.transfers);
+            }
+            repayments
+        }
+        Strategy::NetPairs => netting::net_pairs(&DebtGraph::from_transfers(batch)).transfers,
+    };
+    Ok(Settlement { after, repayments: repayments.len(), flow: repayments.iter().map(|t| t.amt).sum() })
+}
+
+#[test]
+fn test_backtest() {
+    let t = |from: &str, to: &str, amt| Transfer::new(from.to_string(), to.to_string(), amt);
+    // Alice lends Bob 10, and then Bob lends it back
+    let ledger = vec![t("alice", "bob", 10), t("carol", "dave", 5), t("bob", "alice", 10), t("dave", "carol", 3)];
+    let options = plan::Options::default();
+    assert_eq!(backtest(ledger.clone(), Interval::End, Strategy::Plan, &options).unwrap(),
+        vec![Settlement { after: 4, repayments: 1, flow: 2 }]);
+    assert_eq!(backtest(ledger.clone(), Interval::Transfers(2), Strategy::Plan, &options).unwrap(), vec![
+        Settlement { after: 2, repayments: 2, flow: 15 },
+        Settlement { after: 4, repayments: 2, flow: 13 },
+    ]);
+    assert_eq!(backtest(ledger, Interval::Transfers(3), Strategy::NetPairs, &options).unwrap(), vec![
+        Settlement { after: 3, repayments: 1, flow: 5 },
+        Settlement { after: 4, repayments: 1, flow: 3 },
+    ]);
+    assert_eq!("10".parse(), Ok(Interval::Transfers(10)));
+    assert_eq!("month".parse(), Ok(Interval::Period(Period::Month)));
+
+    let dated = |date: &str, t: Transfer<String>| Transfer { date: Some(date.parse().unwrap()), ..t };
+    let ledger = vec![
+        dated("2024-04-30", t("alice", "bob", 10)), dated("2024-05-01", t("bob", "alice", 10)),
+        t("carol", "dave", 5), dated("2024-05-31", t("dave", "carol", 3)),
+    ];
+    assert_eq!(backtest(ledger, Interval::Period(Period::Month), Strategy::Plan, &options).unwrap(), vec![
+        Settlement { after: 1, repayments: 1, flow: 10 },
+        Settlement { after: 4, repayments: 2, flow: 12 },
+    ]);
+}
@@ -0,0 +1,238 @@
+/*!
+Importing bank statements (`repay import --bank=PROFILE`).
+
+A bank's CSV export has a row per payment in or out of someone's account.  A profile says which
+columns are which, and which payments were to or from other people in the ledger:
+
+```json
+{
+  "holder": "alice",
+  "skip_lines": 4,
+  "date": "Booking date",
+  "date_order": "dmy",
+  "description": "Payee",
+  "amount": "Amount (EUR)",
+  "currency": "EUR",
+  "rules": [
+    { "pattern": "(?i)^bob( smith)?$", "counterparty": "bob" },
+    { "pattern": "(?i)paypal .*carol", "counterparty": "carol", "memo": "paypal" }
+  ]
+}
+```
+
+The amount is in major units, written as `locale` says.  Money going out is negative, unless
+`negate` is set (some banks write it the other way round); or there may be separate `debit` and
+`credit` columns, both positive.  `date_order` is `ymd` (the default), `dmy`, or `mdy`, and dates
+can be separated by `-`, `/`, or `.`.  The columns are separated by commas, semicolons, or tabs,
+whichever the header row has most of.
+
+Each row is matched against the rules' patterns (regexes on the description, the first match
+wins).  A payment out to the counterparty is a transfer from the holder to them, and a payment in
+is a transfer from them to the holder.  The memo is the rule's, or else the description.  Rows
+which don't match any rule are ordinary spending, and aren't imported.
+*/
+
+use date::{Date, Timestamp};
+use ledger::{ReadOptions, Transfer};
+use regex::Regex;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Whose account the statement is for.
+    pub holder: String,
+    /// Lines to skip before the header row.
+    #[serde(default)]
+    pub skip_lines: usize,
+    /// The names of the columns.
+    pub date: Option<String>,
+    pub description: String,
+    pub amount: Option<String>,
+    pub debit: Option<String>,
+    pub credit: Option<String>,
+    /// Money going out is positive in the `amount` column.
+    #[serde(default)]
+    pub negate: bool,
+    #[serde(default)]
+    pub date_order: DateOrder,
+    /// The currency the amounts are in.
+    pub currency: Option<String>,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateOrder {
+    #[default]
+    Ymd,
+    Dmy,
+    Mdy,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// A regex, which matches the description.
+    pub pattern: String,
+    pub counterparty: String,
+    pub memo: Option<String>,
+    pub category: Option<String>,
+}
+
+/// A row of a statement.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub line: u64,
+    pub date: Option<Timestamp>,
+    pub description: String,
+    /// In minor units: negative if the money went out.
+    pub amt: isize,
+    pub currency: Option<String>,
+    /// Who the money went to or came from, if it was someone in the ledger.
+    pub counterparty: Option<String>,
+    pub memo: Option<String>,
+    pub category: Option<String>,
+}
+
+impl Entry {
+    /// The transfer between the holder and the counterparty (or `counterparty`, if it's given).
+    pub fn transfer(&self, holder: &str, counterparty: Option<&str>) -> Option<Transfer<String>> {
+        let other = counterparty.or(self.counterparty.as_deref())?.to_string();
+        let (from, to) = if self.amt < 0 { (holder.to_string(), other) } else { (other, holder.to_string()) };
+        Some(Transfer {
+            date: self.date,
+            currency: self.currency.clone(),
+            memo: self.memo.clone().or_else(|| Some(self.description.clone())),
+            category: self.category.clone(),
+            ..Transfer::new(from, to, self.amt.abs())
+        })
+    }
+}
+
+impl Profile {
+    pub fn load(path: &Path) -> Result<Profile, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let profile: Profile = serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if profile.amount.is_none() && (profile.debit.is_none() || profile.credit.is_none()) {
+            return Err(format!("{}: say which column is the 'amount', or which are the 'debit' and 'credit'", path.display()));
+        }
+        Ok(profile)
+    }
+
+    /// Read a statement.  Rows with nothing in them, or with no amount, are skipped.
+    pub fn read<R: Read>(&self, mut reader: R, options: &ReadOptions) -> Result<Vec<Entry>, String> {
+        let rules = self.rules.iter()
+            .map(|r| Regex::new(&r.pattern).map(|re| (re, r)).map_err(|e| format!("Invalid pattern '{}': {}", r.pattern, e)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut text = String::new();
+        reader.read_to_string(&mut text).map_err(|e| e.to_string())?;
+        let start = text.split_inclusive('\n').take(self.skip_lines).map(str::len).sum::<usize>();
+        let text = &text[start..];
+        let header = text.lines().next().unwrap_or_default();
+        let delimiter = [b',', b';', b'\t'].iter().cloned().max_by_key(|&d| header.bytes().filter(|&b| b == d).count()).unwrap_or(b',');
+        let mut reader = ::csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .trim(::csv::Trim::All)
+            .from_reader(text.as_bytes());
+        let names: Vec<String> = reader.headers().map_err(|e| e.to_string())?.iter().map(String::from).collect();
+        let col = |name: &Option<String>| -> Result<Option<usize>, String> {
+            match *name {
+                Some(ref name) => names.iter().position(|x| x == name).map(Some)
+                    .ok_or_else(|| format!("The statement has no '{}' column (found: {})", name, names.join(", "))),
+                None => Ok(None),
+            }
+        };
+        let description = col(&Some(self.description.clone()))?.unwrap_or_default();
+        let (date, amount, debit, credit) = (col(&self.date)?, col(&self.amount)?, col(&self.debit)?, col(&self.credit)?);
+        let exponent = options.exponent(self.currency.as_deref());
+        let mut entries = vec![];
+        for row in reader.records() {
+            let row = row.map_err(|e| e.to_string())?;
+            let line = row.position().map_or(0, |p| p.line()) + self.skip_lines as u64;
+            if row.iter().all(|x| x.is_empty()) { continue; }
+            let field = |i: usize| row.get(i).unwrap_or_default();
+            let parse = |i: usize| -> Result<Option<isize>, String> {
+                match field(i) {
+                    "" => Ok(None),
+                    x => options.locale.parse_minor(x, exponent).map(Some).map_err(|e| format!("Line {}: {}", line, e)),
+                }
+            };
+            let amt = match (amount, debit, credit) {
+                (Some(i), _, _) => parse(i)?.map(|x| if self.negate { -x } else { x }),
+                (None, Some(d), Some(c)) => match (parse(d)?, parse(c)?) {
+                    (None, None) => None,
+                    (d, c) => Some(c.unwrap_or(0) - d.unwrap_or(0).abs()),
+                },
+                _ => unreachable!("checked by Profile::load"),
+            };
+            let amt = match amt {
+                Some(x) if x != 0 => x,
+                _ => continue,
+            };
+            let date = match date.map(field).filter(|x| !x.is_empty()) {
+                Some(x) => Some(self.parse_date(x).map_err(|e| format!("Line {}: {}", line, e))?),
+                None => None,
+            };
+            let description = field(description).to_string();
+            let rule = rules.iter().find(|(re, _)| re.is_match(&description)).map(|&(_, r)| r);
+            entries.push(Entry {
+                line,
+                date,
+                amt,
+                currency: self.currency.clone(),
+                counterparty: rule.map(|r| r.counterparty.clone()),
+                memo: rule.and_then(|r| r.memo.clone()),
+                category: rule.and_then(|r| r.category.clone()),
+                description,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn parse_date(&self, s: &str) -> Result<Timestamp, String> {
+        if self.date_order == DateOrder::Ymd {
+            if let Ok(x) = s.parse() { return Ok(x); }
+        }
+        let parts: Vec<&str> = s.split(['-', '/', '.']).map(str::trim).collect();
+        let invalid = || format!("Invalid date '{}' (expected {})", s, match self.date_order {
+            DateOrder::Ymd => "eg. 2024-05-31",
+            DateOrder::Dmy => "eg. 31.05.2024",
+            DateOrder::Mdy => "eg. 05/31/2024",
+        });
+        if parts.len() != 3 { return Err(invalid()); }
+        let (y, m, d) = match self.date_order {
+            DateOrder::Ymd => (parts[0], parts[1], parts[2]),
+            DateOrder::Dmy => (parts[2], parts[1], parts[0]),
+            DateOrder::Mdy => (parts[2], parts[0], parts[1]),
+        };
+        let year: i32 = y.parse().map_err(|_| invalid())?;
+        // Two-digit years are this century
+        let year = if y.len() == 2 { 2000 + year } else { year };
+        let date = Date::new(year, m.parse().map_err(|_| invalid())?, d.parse().map_err(|_| invalid())?).ok_or_else(invalid)?;
+        Ok(date.into())
+    }
+}
+
+#[test]
+fn test_read() {
+    let profile: Profile = serde_json::from_str(r#"{
+        "holder": "alice", "skip_lines": 1, "date": "Date", "date_order": "dmy", "description": "Payee",
+        "debit": "Out", "credit": "In", "currency": "EUR",
+        "rules": [{ "pattern": "(?i)^bob", "counterparty": "bob" }, { "pattern": "PAYPAL.*carol", "counterparty": "carol", "memo": "paypal" }]
+    }"#).unwrap();
+    let statement = "Account 1234\nDate;Payee;Out;In\n01.05.2024;Bob Smith;12,50;\n02.05.2024;Supermarket;30,00;\n03.05.2024;PAYPAL *carol;;1.000,00\n";
+    let options = ReadOptions { locale: "de".parse().unwrap(), ..ReadOptions::default() };
+    let entries = profile.read(statement.as_bytes(), &options).unwrap();
+    assert_eq!(entries.iter().map(|e| (e.line, e.amt, e.counterparty.as_deref())).collect::<Vec<_>>(),
+        vec![(3, -1250, Some("bob")), (4, -3000, None), (5, 100_000, Some("carol"))]);
+    let t = |e: &Entry| e.transfer("alice", None).map(|t| (t.from, t.to, t.amt, t.memo.unwrap(), t.date.unwrap().to_string()));
+    assert_eq!(t(&entries[0]), Some(("alice".into(), "bob".into(), 1250, "Bob Smith".into(), "2024-05-01".into())));
+    assert_eq!(t(&entries[1]), None);
+    assert_eq!(t(&entries[2]), Some(("carol".into(), "alice".into(), 100_000, "paypal".into(), "2024-05-03".into())));
+    assert_eq!(entries[1].transfer("alice", Some("dave")).unwrap().to, "dave");
+    assert!(profile.read("Account\nDate;Payee;Out;In\n31.02.2024;Bob;1;\n".as_bytes(), &options).unwrap_err().starts_with("Line 3"));
+}
@@ -0,0 +1,136 @@
+/*!
+An on-disk cache of solved partitionings, so that running repay again on the same balances doesn't
+mean solving the same problem again.
+
+Entries are keyed by the multiset of balances (ie. the sorted values) and the solver's options, so
+they don't depend on who has which balance.  Each entry is a small JSON file in the cache
+directory, and can be deleted at any time.
+*/
+
+use bitset64::BitSet64;
+use ledger::fnv1a;
+use mzsp::{Options, Partitioning};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many entries this process has started to store, for naming their temporary files.
+static STORES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// The sorted values, in case of hash collisions.
+    values: Vec<isize>,
+    /// Indices into `values`.
+    parts: Vec<Vec<usize>>,
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Cache {
+        Cache { dir: dir.into() }
+    }
+
+    /// The partitioning of `values`: from the cache if it's there, and otherwise computed by
+    /// `solve` (which is given the values in sorted order) and then cached.  Problems with the cache
+    /// are logged, but otherwise ignored.  `solve` should respect `options`; if it fails, nothing is
+    /// cached.
+    pub fn partitioning<F, E>(&self, values: &[isize], options: Options, solve: F) -> Result<Partitioning, E>
+        where F: FnOnce(&[isize]) -> Result<Partitioning, E>
+    {
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by_key(|&i| values[i]);
+        let sorted: Vec<isize> = order.iter().map(|&i| values[i]).collect();
+        let mut key = sorted.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",");
+        if options != Options::default() { key += &format!(";{:?}", options); }
+        let path = self.dir.join(format!("{:016x}.json", fnv1a(&[key.as_bytes()])));
+
+        let parts = match load(&path, &sorted) {
+            Some(parts) => {
+                debug!("Using the cached partitioning in {}", path.display());
+                parts
+            }
+            None => {
+                let parts: Vec<Vec<usize>> = solve(&sorted)?.parts().map(|x| x.to_vec()).collect();
+                let entry = Entry { values: sorted, parts };
+                if let Err(e) = self.store(&path, &entry) {
+                    warn!("Couldn't write to the cache ({}): {}", path.display(), e);
+                }
+                entry.parts
+            }
+        };
+        Ok(Partitioning::from_sets(values.len(), parts.iter().map(|part|
+            part.iter().fold(BitSet64::empty_set(), |set, &i| set.insert(order[i] as u64))
+        )))
+    }
+
+    fn store(&self, path: &Path, entry: &Entry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write the whole entry before it appears, so a concurrent run never sees half of one.  The
+        // temporary file is this store's alone, even if other threads are storing the same entry.
+        let n = STORES.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}.{}.tmp", ::std::process::id(), n));
+        let written = File::create(&tmp).and_then(|file| Ok(::serde_json::to_writer(file, entry)?));
+        match written.and_then(|()| fs::rename(&tmp, path)) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The cached parts for `sorted`, if there are any (and they make sense).
+fn load(path: &Path, sorted: &[isize]) -> Option<Vec<Vec<usize>>> {
+    let file = File::open(path).ok()?;
+    let entry: Entry = match ::serde_json::from_reader(file) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("Ignoring a corrupt cache entry ({}): {}", path.display(), e);
+            return None;
+        }
+    };
+    let mut seen = vec![false; sorted.len()];
+    let valid = entry.values == sorted && entry.parts.iter().all(|part|
+        part.iter().all(|&i| i < seen.len() && !::std::mem::replace(&mut seen[i], true))
+            && part.iter().map(|&i| sorted[i]).sum::<isize>() == 0
+    );
+    if valid { Some(entry.parts) } else { None }
+}
+
+#[test]
+fn test_cache() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-cache-{}", ::std::process::id()));
+    let cache = Cache::new(&dir);
+    let values = [3, -1, 2, -3, -1];
+    let first = cache.partitioning(&values, Options::default(), Partitioning::compute).unwrap();
+    assert_eq!(first.num_parts(), 2);
+    let second = cache.partitioning(&values, Options::default(), |_| -> Result<_, ()> { panic!("should be cached") }).unwrap();
+    assert_eq!(first, second);
+    // The same multiset, in a different order
+    let permuted = cache.partitioning(&[-1, -3, 2, 3, -1], Options::default(), |_| -> Result<_, ()> { panic!("should be cached") }).unwrap();
+    assert_eq!(permuted.part_of(1), permuted.part_of(3));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_concurrent_stores() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-cache-threads-{}", ::std::process::id()));
+    let cache = Cache::new(&dir);
+    let path = dir.join("entry.json");
+    let entry = Entry { values: vec![-1, 1], parts: vec![vec![0, 1]] };
+    // Several threads store the same entry at once, as when two currencies have the same balances
+    ::std::thread::scope(|s| {
+        let stores: Vec<_> = (0..8).map(|_| s.spawn(|| for _ in 0..20 { cache.store(&path, &entry).unwrap() })).collect();
+        for x in stores { x.join().unwrap(); }
+    });
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    assert_eq!(load(&path, &[-1, 1]), Some(vec![vec![0, 1]]));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,88 @@
+/*!
+A hash chain through a JSON ledger, so that changes to it can be detected.
+
+Each record in a chained ledger has a `chain` field: the SHA-256 of the previous record's `chain`
+(empty, for the first record), a newline, and the record itself without its `chain` field (as
+compact JSON, with the fields in the order they're written).  So changing, removing, or inserting
+a record breaks the chain from there on, which `repay verify-chain` reports.  Cutting records off
+the end doesn't break the chain, but it does lose the last hash: `repay verify-chain --head=HASH`
+checks that the chain still reaches a hash which you noted down earlier.
+
+`repay chain` adds the hashes to a ledger's records.  After that, the records which repay appends
+are chained too.  The header isn't part of the chain, and nor are the ledgers it includes.
+*/
+
+use ledger::{self, ParseError};
+use serde_json::Value;
+use sha256;
+use std::io::BufRead;
+
+/// The field which holds a record's hash.
+pub const FIELD: &str = "chain";
+
+/// The hash of a record, which comes after the one whose hash is `prev`.
+pub fn link(prev: Option<&str>, record: &Value) -> String {
+    let mut record = record.clone();
+    if let Some(x) = record.as_object_mut() { x.remove(FIELD); }
+    sha256::hex_digest(format!("{}\n{}", prev.unwrap_or_default(), record).as_bytes())
+}
+
+/// The hash of the last record in a ledger, if it's chained.
+pub fn head(ledger: &[u8]) -> Option<String> {
+    let (_, last) = ledger::json_values(ledger, "-").filter_map(Result::ok).last()?;
+    last.get(FIELD)?.as_str().map(String::from)
+}
+
+/// Check a ledger's chain, and return the hashes of its records.
+pub fn verify<R: BufRead>(reader: R, source: &str) -> Result<Vec<String>, ParseError> {
+    let mut hashes: Vec<String> = vec![];
+    for x in ledger::json_values(reader, source) {
+        let (line, x) = x?;
+        if x.get("repay_version").is_some() { continue; }
+        let bad = |message: &str| ParseError { line: Some(line), text: Some(x.to_string()), ..ParseError::new(source, message.into()) };
+        let hash = link(hashes.last().map(String::as_str), &x);
+        match x.get(FIELD) {
+            Some(Value::String(theirs)) if *theirs == hash => hashes.push(hash),
+            Some(_) => return Err(bad("The hash doesn't match, so this record (or the one before it) has been changed")),
+            None => return Err(bad("This record isn't chained")),
+        }
+    }
+    Ok(hashes)
+}
+
+/// A ledger's records with hashes, one per line.  (Any hashes they already had are replaced.)
+pub fn seal<R: BufRead>(reader: R, source: &str) -> Result<String, ParseError> {
+    let mut out = String::new();
+    let mut prev: Option<String> = None;
+    for x in ledger::json_values(reader, source) {
+        let (_, mut x) = x?;
+        if x.get("repay_version").is_none() {
+            let hash = link(prev.as_deref(), &x);
+            if let Some(x) = x.as_object_mut() {
+                x.remove(FIELD);
+                x.insert(FIELD.into(), hash.clone().into());
+            }
+            prev = Some(hash);
+        }
+        out += &x.to_string();
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_chain() {
+    let ledger = "{\"repay_version\":2}\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":10}\n{\"from\":\"bob\",\"to\":\"cara\",\"amt\":3}\n";
+    let sealed = seal(ledger.as_bytes(), "-").unwrap();
+    let hashes = verify(sealed.as_bytes(), "-").unwrap();
+    assert_eq!(hashes.len(), 2);
+    assert_eq!(head(sealed.as_bytes()), hashes.last().cloned());
+    assert_eq!(seal(sealed.as_bytes(), "-").unwrap(), sealed);
+    let tampered = sealed.replace("\"amt\":10", "\"amt\":1");
+    assert_eq!(verify(tampered.as_bytes(), "-").unwrap_err().line, Some(2));
+    let dropped: String = sealed.lines().enumerate().filter(|&(i, _)| i != 1).map(|(_, l)| format!("{}\n", l)).collect();
+    assert_eq!(verify(dropped.as_bytes(), "-").unwrap_err().line, Some(2));
+    let added = format!("{}{{\"from\":\"cara\",\"to\":\"ann\",\"amt\":1}}\n", sealed);
+    assert!(verify(added.as_bytes(), "-").unwrap_err().message.contains("isn't chained"));
+    assert_eq!(head(ledger.as_bytes()), None);
+}
@@ -0,0 +1,186 @@
+/*!
+The (optional) config file, given with `--config`.  It's a single JSON object; all sections are
+optional.
+
+```json
+{
+  "display": {
+    "unit": "major",
+    "amounts": "decimal",
+    "output": "table",
+    "color": "never",
+    "currencies": { "XAU": { "exponent": 3, "symbol": "oz" } }
+  },
+  "exclude_categories": ["personal", "reimbursed-by-employer"],
+  "groups": { "household": ["alice", "bob"], "ski-trip": ["alice", "carol", "dave"] },
+  "accounts": { "alice": { "bank": "DE89 3704 0044 0532 0130 00", "paypal": "alice@example.com" } },
+  "names": { "case_fold": true, "whitespace": true, "compatibility": true, "aliases": { "Bob": ["Robert S"] } },
+  "cache_dir": "/home/alice/.cache/repay",
+  "short_chains": true,
+  "timeout_secs": 60,
+  "history_file": "/home/alice/.local/share/repay/history.jsonl",
+  "csv_columns": ["date", "from", "to", "amt"],
+  "account_prefix": "assets:receivable:",
+  "validation": "strict",
+  "rates_file": "/home/alice/rates.json",
+  "settle_in": "EUR",
+  "locale": "de-DE",
+  "currency_symbol": "CHF",
+  "interest": { "rate": 5, "compounding": "monthly", "grace_days": 30 }
+}
+```
+*/
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use accounts::Accounts;
+use cache::Cache;
+use date::Date;
+use history::History;
+use interest::Interest;
+use ledger::{Condition, Filter, ReadOptions, Validation};
+use locale::Locale;
+use names::{NameConfig, Names};
+use plan::{self, Mode};
+use rates::Rates;
+use units::DisplayConfig;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub display: DisplayConfig,
+    /// Transfers in these categories never affect anyone's balance.
+    pub exclude_categories: Vec<String>,
+    /// Groups of people, for `--group`.  (By the names which are used, so after any aliases.)
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Everyone's payment accounts (see `accounts`).
+    pub accounts: Accounts,
+    /// How to decide whether two names refer to the same person.
+    pub names: NameConfig,
+    /// Where to keep solved problems, so they don't need solving again.  (No caching if unset.)
+    pub cache_dir: Option<PathBuf>,
+    /// See `plan::Options::short_chains`.
+    pub short_chains: bool,
+    /// See `plan::Options::timeout`, in seconds.
+    pub timeout_secs: Option<u64>,
+    /// Where to record how long each plan took to compute, for `repay recommend`.  (Nothing is
+    /// recorded if unset.)
+    pub history_file: Option<PathBuf>,
+    /// See `ledger::ReadOptions::csv_columns`.
+    pub csv_columns: Option<Vec<String>>,
+    /// See `ledger::ReadOptions::account_prefix`.
+    pub account_prefix: Option<String>,
+    /// Skip records which can't be read, with a warning, instead of giving up.  (In a JSON ledger
+    /// this skips the one record; in other formats, the whole file.)
+    pub skip_bad_records: bool,
+    /// See `ledger::ReadOptions::validation`: one of "strict", "warn", or "lenient".
+    pub validation: Validation,
+    /// Exchange rates (see `rates`), for converting everything into `settle_in`.
+    pub rates_file: Option<PathBuf>,
+    /// Settle all the balances in this currency, with a single plan.  (Defaults to the rates'
+    /// base currency, if there's a rates file; otherwise each currency is settled separately.)
+    pub settle_in: Option<String>,
+    /// How amounts in major units are written (see `locale`).
+    pub locale: Locale,
+    /// A currency symbol to ignore in amounts, besides the usual ones.
+    pub currency_symbol: Option<String>,
+    /// Charge interest on debts which are left outstanding (see `interest`).
+    pub interest: Option<Interest>,
+    /// See `ledger::Filter`.  (These can only be set with flags.)
+    #[serde(skip)]
+    pub since: Option<Date>,
+    #[serde(skip)]
+    pub until: Option<Date>,
+    #[serde(skip)]
+    pub conditions: Vec<Condition>,
+    /// The currency of transfers which don't say, if the ledgers' headers give one.
+    #[serde(skip)]
+    pub base_currency: Option<String>,
+    /// Say why each repayment is in the plan (see `explain`).  (Only with `--explain`.)
+    #[serde(skip)]
+    pub explain: bool,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let config: Config = serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        config.names.check().map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Which transfers should count towards the balances.
+    pub fn filter(&self) -> Filter {
+        Filter {
+            exclude_categories: self.exclude_categories.clone(),
+            since: self.since,
+            until: self.until,
+            conditions: self.conditions.clone(),
+        }
+    }
+
+    /// Leaves out the transfers in excluded categories, and nothing else.  This is what a
+    /// checkpoint's balances are of (see `ledger::Checkpoint`).
+    pub fn exclusions(&self) -> Filter {
+        Filter { exclude_categories: self.exclude_categories.clone(), ..Filter::default() }
+    }
+
+    /// The solver cache, if there is one.
+    pub fn cache(&self) -> Option<Cache> {
+        self.cache_dir.as_ref().map(Cache::new)
+    }
+
+    /// The run history, if the user wants one.
+    pub fn history(&self) -> Option<History> {
+        self.history_file.as_ref().map(History::new)
+    }
+
+    /// How to compute plans, using the given solver.
+    pub fn plan_options(&self, mode: Mode) -> plan::Options {
+        plan::Options {
+            mode,
+            short_chains: self.short_chains,
+            cache: self.cache(),
+            timeout: self.timeout_secs.map(Duration::from_secs),
+            progress: None,
+            avoid: Default::default(),
+        }
+    }
+
+    /// How to read ledgers.
+    pub fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            csv_columns: self.csv_columns.clone(),
+            account_prefix: self.account_prefix.clone(),
+            exponent: self.display.currency(None).exponent,
+            exponents: self.display.exponents(),
+            locale: self.locale(),
+            validation: self.validation,
+        }
+    }
+
+    /// The exchange rates, and the currency to settle in, if everything is to be settled in one.
+    pub fn rates(&self) -> Result<Option<(Rates, String)>, String> {
+        let rates = match self.rates_file {
+            Some(ref path) => Rates::load(path)?,
+            None => Rates::default(),
+        };
+        match self.settle_in.clone().or_else(|| rates.base.clone()) {
+            Some(currency) => Ok(Some((rates, currency))),
+            None if self.rates_file.is_some() => Err("The rates file has no base currency, so use --settle-in to say which currency to settle in".into()),
+            None => Ok(None),
+        }
+    }
+
+    /// How to read amounts.
+    pub fn locale(&self) -> Locale {
+        self.locale.clone().with_symbol(self.currency_symbol.clone())
+    }
+
+    /// A fresh name-matcher.
+    pub fn names(&self) -> Names {
+        Names::new(self.names.clone())
+    }
+}
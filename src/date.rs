@@ -0,0 +1,242 @@
+/*!
+Dates, and points in time, for saying when a transfer happened.
+
+A transfer's date is written either as a calendar date (`2024-05-01`) or as an RFC 3339 timestamp
+(`2024-05-01T19:30:00+02:00`).  There's no time zone database here: a timestamp keeps its UTC
+offset, and a plain date has no time of day at all.
+*/
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A day in the (proleptic Gregorian) calendar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: i32,
+    month: u8,
+    day: u8,
+}
+
+impl Date {
+    /// `None` if there's no such day.
+    pub fn new(year: i32, month: u8, day: u8) -> Option<Date> {
+        if (1..=12).contains(&month) && day >= 1 && day <= days_in_month(year, month) {
+            Some(Date { year, month, day })
+        } else {
+            None
+        }
+    }
+
+    pub fn year(self) -> i32 { self.year }
+    pub fn month(self) -> u8 { self.month }
+    pub fn day(self) -> u8 { self.day }
+
+    /// The number of days since 1970-01-01.
+    pub fn days(self) -> i64 {
+        // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let y = i64::from(self.year) - if self.month <= 2 { 1 } else { 0 };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let m = i64::from(self.month);
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146_097 + doe - 719_468
+    }
+
+    /// Today's date, in UTC.
+    pub fn today() -> Date {
+        let secs = ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Date::from_days(secs as i64 / 86_400)
+    }
+
+    /// The inverse of `days`.
+    pub fn from_days(days: i64) -> Date {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        Date { year, month, day }
+    }
+}
+
+fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+    /// `YYYY-MM-DD`
+    fn from_str(s: &str) -> Result<Date, String> {
+        let invalid = || format!("Invalid date '{}' (expected eg. '2024-05-01')", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2
+            || !parts.iter().all(|p| p.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return Err(invalid());
+        }
+        Date::new(parts[0].parse().unwrap(), parts[1].parse().unwrap(), parts[2].parse().unwrap()).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// When something happened: a date, and perhaps a time of day.
+///
+/// Timestamps are ordered by the moment they refer to (a plain date counts as midnight UTC), with
+/// ties broken by how they're written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Timestamp {
+    /// The local date.
+    pub date: Date,
+    pub time: Option<Time>,
+}
+
+/// A time of day, with its offset from UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    /// Since local midnight.  (A leap second is 86400.)
+    pub secs: u32,
+    pub nanos: u32,
+    /// East of UTC.
+    pub offset_secs: i32,
+}
+
+impl Timestamp {
+    /// Seconds since 1970-01-01T00:00:00Z.
+    pub fn unix_secs(&self) -> i64 {
+        let midnight = self.date.days() * 86_400;
+        match self.time {
+            None => midnight,
+            Some(t) => midnight + i64::from(t.secs) - i64::from(t.offset_secs),
+        }
+    }
+}
+
+impl From<Date> for Timestamp {
+    fn from(date: Date) -> Timestamp {
+        Timestamp { date, time: None }
+    }
+}
+
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Timestamp) -> Ordering {
+        let nanos = |t: &Timestamp| t.time.map_or(0, |t| t.nanos);
+        (self.unix_secs(), nanos(self), self.date, self.time).cmp(&(other.unix_secs(), nanos(other), other.date, other.time))
+    }
+}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Timestamp) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+    /// `YYYY-MM-DD`, or RFC 3339 (eg. `2024-05-01T19:30:00Z`, `2024-05-01 19:30:00.5+02:00`)
+    fn from_str(s: &str) -> Result<Timestamp, String> {
+        let invalid = || format!("Invalid date '{}' (expected eg. '2024-05-01' or '2024-05-01T19:30:00Z')", s);
+        if s.len() == 10 { return Ok(s.parse::<Date>()?.into()); }
+        if s.len() < 20 || !s.is_ascii() || !matches!(s.as_bytes()[10], b'T' | b't' | b' ') {
+            return Err(invalid());
+        }
+        let date: Date = s[..10].parse().map_err(|_| invalid())?;
+        let rest = &s[11..];
+        let num = |x: &str| if x.len() == 2 && x.bytes().all(|b| b.is_ascii_digit()) { x.parse::<u32>().ok() } else { None };
+        let (h, m, sec) = match (num(&rest[0..2]), &rest[2..3], num(&rest[3..5]), &rest[5..6], num(&rest[6..8])) {
+            (Some(h), ":", Some(m), ":", Some(sec)) if h < 24 && m < 60 && sec <= 60 => (h, m, sec),
+            _ => return Err(invalid()),
+        };
+        let mut rest = &rest[8..];
+        let mut nanos = 0;
+        if let Some(frac) = rest.strip_prefix('.') {
+            let digits = frac.bytes().take_while(|b| b.is_ascii_digit()).count();
+            if digits == 0 { return Err(invalid()); }
+            let padded = format!("{:0<9}", &frac[..digits.min(9)]);
+            nanos = padded.parse().map_err(|_| invalid())?;
+            rest = &frac[digits..];
+        }
+        let offset_secs = match rest {
+            "Z" | "z" => 0,
+            _ if rest.len() == 6 && (rest.starts_with('+') || rest.starts_with('-')) && &rest[3..4] == ":" => {
+                let (oh, om) = match (num(&rest[1..3]), num(&rest[4..6])) {
+                    (Some(oh), Some(om)) if oh < 24 && om < 60 => (oh, om),
+                    _ => return Err(invalid()),
+                };
+                let offset = (oh * 3600 + om * 60) as i32;
+                if rest.starts_with('-') { -offset } else { offset }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(Timestamp { date, time: Some(Time { secs: h * 3600 + m * 60 + sec, nanos, offset_secs }) })
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.date)?;
+        let t = match self.time {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let (h, m, sec) = if t.secs >= 86_400 { (23, 59, 60) } else { (t.secs / 3600, t.secs / 60 % 60, t.secs % 60) };
+        write!(f, "T{:02}:{:02}:{:02}", h, m, sec)?;
+        if t.nanos > 0 {
+            write!(f, ".{}", format!("{:09}", t.nanos).trim_end_matches('0'))?;
+        }
+        match t.offset_secs {
+            0 => write!(f, "Z"),
+            x => write!(f, "{}{:02}:{:02}", if x < 0 { '-' } else { '+' }, x.abs() / 3600, x.abs() / 60 % 60),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Timestamp, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn test_date() {
+    let d: Date = "2024-02-29".parse().unwrap();
+    assert_eq!(Date::from_days(d.days()), d);
+    assert_eq!("1970-01-01".parse::<Date>().unwrap().days(), 0);
+    assert_eq!(Date::new(2024, 3, 1).unwrap().days() - d.days(), 1);
+    assert!("2023-02-29".parse::<Date>().is_err());
+    assert!("2024-5-1".parse::<Date>().is_err());
+
+    for s in &["2024-05-01", "2024-05-01T19:30:00Z", "2024-05-01T19:30:00.25+02:00", "1999-12-31T23:59:60-05:30"] {
+        assert_eq!(s.parse::<Timestamp>().unwrap().to_string(), *s);
+    }
+    let t = |s: &str| s.parse::<Timestamp>().unwrap();
+    assert_eq!(t("2024-05-01 19:30:00z"), t("2024-05-01T19:30:00Z"));
+    assert!(t("2024-05-01T19:30:00+02:00") < t("2024-05-01T18:30:00Z"));
+    assert!(t("2024-05-01") < t("2024-05-01T00:00:01Z"));
+    assert!("2024-05-01T25:00:00Z".parse::<Timestamp>().is_err());
+    assert!("2024-05-01T19:30:00".parse::<Timestamp>().is_err());
+}
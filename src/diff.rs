@@ -0,0 +1,63 @@
+/*!
+Comparing a plan with an earlier one, for `--diff`: which repayments are new, which have gone, and
+which are for a different amount.
+
+Repayments are matched up by who pays whom, in which currency.  (Ids are no use for this, since a
+repayment's id depends on its amount.)  If the earlier plan has several repayments from the same
+person to the same person, they count as one.
+*/
+
+use ledger::Transfer;
+use std::collections::BTreeMap;
+
+/// How a repayment differs between two plans.
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// It's only in the new plan.
+    Added(Transfer<String>),
+    /// It's only in the old plan.
+    Removed(Transfer<String>),
+    /// It's in both, but it was for `old` before.
+    Changed { old: isize, new: Transfer<String> },
+}
+
+/// The differences between the plans, in order of payer, then payee, then currency.  Repayments
+/// which haven't changed are left out.
+pub fn diff(old: &[Transfer<String>], new: &[Transfer<String>]) -> Vec<Change> {
+    type Key = (String, String, Option<String>);
+    let key = |t: &Transfer<String>| (t.from.clone(), t.to.clone(), t.currency.clone());
+    let mut before: BTreeMap<Key, Transfer<String>> = BTreeMap::new();
+    for t in old {
+        before.entry(key(t)).and_modify(|x| x.amt += t.amt).or_insert_with(|| t.clone());
+    }
+    let mut changes: BTreeMap<Key, Change> = BTreeMap::new();
+    for t in new {
+        match before.remove(&key(t)) {
+            None => { changes.insert(key(t), Change::Added(t.clone())); }
+            Some(x) if x.amt != t.amt => { changes.insert(key(t), Change::Changed { old: x.amt, new: t.clone() }); }
+            Some(_) => (),
+        }
+    }
+    for (k, t) in before {
+        changes.insert(k, Change::Removed(t));
+    }
+    changes.into_values().collect()
+}
+
+#[test]
+fn test_diff() {
+    let t = |from: &str, to: &str, amt| Transfer::new(from.to_string(), to.to_string(), amt);
+    let old = vec![t("bob", "ann", 1000), t("cat", "ann", 500), t("dan", "eve", 300), t("dan", "eve", 200)];
+    let new = vec![t("bob", "ann", 1000), t("cat", "ann", 700), t("eve", "ann", 50)];
+    let summary: Vec<_> = diff(&old, &new).into_iter().map(|c| match c {
+        Change::Added(t) => ("added", t.from, t.to, 0, t.amt),
+        Change::Removed(t) => ("removed", t.from, t.to, t.amt, 0),
+        Change::Changed { old, new } => ("changed", new.from, new.to, old, new.amt),
+    }).collect();
+    let s = |x: &str| x.to_string();
+    assert_eq!(summary, vec![
+        ("changed", s("cat"), s("ann"), 500, 700),
+        ("removed", s("dan"), s("eve"), 500, 0),
+        ("added", s("eve"), s("ann"), 0, 50),
+    ]);
+}
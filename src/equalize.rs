@@ -0,0 +1,64 @@
+/*!
+Equalization: "everyone should end up having paid the same amount".
+
+Here the ledger is read as a list of expenses.  Each transfer counts as money spent by `from`; if
+`to` is one of the participants too, it's money given back to them, and counts against what they
+spent.  Everyone's fair share is the mean spend, and their balance is how far they are from it.
+*/
+
+use ledger::Transfer;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The deviation of each participant from the mean spend.  Positive means they spent less than
+/// their share.
+///
+/// The participants are everyone who has paid for something, plus `members` (who may not have).
+/// If `categories` is non-empty, only expenses in those categories count.
+///
+/// Shares are in whole minor units, so the remainder of the division goes to the first
+/// participants (in alphabetical order), one unit each.
+pub fn deviations<I>(transfers: I, members: &[String], categories: &[String]) -> Vec<(String, isize)>
+    where I: IntoIterator<Item=Transfer<String>>
+{
+    let mut paid = BTreeMap::new();
+    let mut received = BTreeMap::new();
+    let mut people: BTreeSet<String> = members.iter().cloned().collect();
+    for t in transfers {
+        if !categories.is_empty() && !t.category.as_ref().is_some_and(|c| categories.contains(c)) {
+            continue;
+        }
+        people.insert(t.from.clone());
+        *paid.entry(t.from).or_insert(0) += t.amt;
+        *received.entry(t.to).or_insert(0) += t.amt;
+    }
+
+    let spent: Vec<(String, isize)> = people.into_iter().map(|p| {
+        let x = paid.get(&p).cloned().unwrap_or(0) - received.get(&p).cloned().unwrap_or(0);
+        (p, x)
+    }).collect();
+    if spent.is_empty() { return vec![]; }
+    let total: isize = spent.iter().map(|x| x.1).sum();
+    let n = spent.len() as isize;
+    let (share, remainder) = (total.div_euclid(n), total.rem_euclid(n));
+    spent.into_iter().enumerate()
+        .map(|(i, (p, x))| (p, share + if (i as isize) < remainder { 1 } else { 0 } - x))
+        .filter(|x| x.1 != 0)
+        .collect()
+}
+
+#[test]
+fn test_deviations() {
+    let expense = |from: &str, amt, cat: &str| Transfer {
+        category: Some(cat.to_string()),
+        ..Transfer::new(from.to_string(), "shop".to_string(), amt)
+    };
+    let ledger = vec![expense("ann", 3000, "food"), expense("bob", 1000, "food"), expense("ann", 500, "fuel")];
+    let s = |x: &str| x.to_string();
+    assert_eq!(deviations(ledger.clone(), &[s("cara")], &[]),
+        vec![(s("ann"), -2000), (s("bob"), 500), (s("cara"), 1500)]);
+    assert_eq!(deviations(ledger.clone(), &[], &[s("food")]),
+        vec![(s("ann"), -1000), (s("bob"), 1000)]);
+    assert_eq!(deviations(ledger.clone(), &[s("cara"), s("dan")], &[s("fuel")]),
+        vec![(s("ann"), -333), (s("cara"), 167), (s("dan"), 166)]);
+    assert_eq!(deviations(ledger, &[], &[s("nothing")]), vec![]);
+}
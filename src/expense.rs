@@ -0,0 +1,261 @@
+/*!
+Shared expenses: one record for something which one person paid for, and several people shared.
+
+```json
+{"payer": "ann", "participants": ["ann", "bob", "cara"], "amt": 3000, "memo": "dinner"}
+```
+
+This can go in a JSON ledger alongside ordinary transfers, and is read as one transfer from the
+payer to each of the other participants, for their share.  (The payer only pays a share if they're
+a participant.)  The other fields are the same as a transfer's, and are copied onto each of the
+transfers.
+
+By default the amount is split equally, but a participant can be given as an object instead of a
+name, to change their share:
+
+```json
+{"payer": "ann", "amt": 10000, "participants": [
+  {"name": "ann", "shares": 2}, "bob", {"name": "cara", "amt": 1500}
+]}
+```
+
+- `shares`: the participant counts as this many people.  (Plain names count as 1.)
+- `amt`: the participant pays exactly this much, and the rest is split between the others.
+- `percent`: the participant pays this percentage.  If anyone has one, everyone must, and they
+  must add up to 100.
+
+Something paid for by several people has a list of `payers` instead of a `payer`:
+
+```json
+{"payers": [{"name": "ann", "amt": 60000}, {"name": "bob", "amt": 40000}],
+ "participants": ["ann", "bob", "cara", "dan"], "amt": 100000, "memo": "deposit"}
+```
+
+What the payers paid must add up to the amount.  The expense is read as whatever transfers between
+the payers and the participants leave everyone's balance as it should be.
+
+The shares are in whole minor units, and always add up to the amount.  If it doesn't divide
+exactly, whoever's share was rounded down the most pays one unit more (with ties going to the
+participants listed first).
+*/
+
+use date::Timestamp;
+use ledger::Transfer;
+use plan::construct_plan;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Expense {
+    /// Whoever paid.  (Either this or `payers` must be given.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payer: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub payers: Vec<Payer>,
+    /// Everyone who shares the cost, with no repeats.
+    pub participants: Vec<Participant>,
+    /// The total paid.
+    pub amt: isize,
+    /// Given to each of the transfers, so that the expense can be reversed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, alias = "ts", skip_serializing_if = "Option::is_none")]
+    pub date: Option<Timestamp>,
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+/// One of several people who paid for an expense.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Payer {
+    pub name: String,
+    /// How much they paid.
+    pub amt: isize,
+}
+
+/// Someone who shares the cost of an expense.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Participant {
+    /// An equal share.
+    Name(String),
+    Share(Share),
+}
+
+/// A participant's share, if it's not an equal one.  At most one of the fields may be set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Share {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shares: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amt: Option<isize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+}
+
+impl Participant {
+    pub fn name(&self) -> &str {
+        match self {
+            Participant::Name(name) => name,
+            Participant::Share(share) => &share.name,
+        }
+    }
+}
+
+impl Expense {
+    /// What each participant pays, in the order they're listed.
+    pub fn shares(&self) -> Result<Vec<(&str, isize)>, String> {
+        if self.participants.is_empty() {
+            return Err("An expense needs at least one participant".into());
+        }
+        let names: Vec<&str> = self.participants.iter().map(Participant::name).collect();
+        for (i, who) in names.iter().enumerate() {
+            if names[..i].contains(who) {
+                return Err(format!("{} is listed as a participant more than once", who));
+            }
+        }
+        let share = |p: &Participant| match p {
+            Participant::Name(_) => (None, None, None),
+            Participant::Share(s) => (s.shares, s.amt, s.percent),
+        };
+        for p in &self.participants {
+            match share(p) {
+                (Some(0), _, _) => return Err(format!("{}'s shares must be positive", p.name())),
+                (Some(_), Some(_), _) | (Some(_), _, Some(_)) | (_, Some(_), Some(_)) =>
+                    return Err(format!("{} can only have one of 'shares', 'amt', and 'percent'", p.name())),
+                _ => (),
+            }
+        }
+
+        if self.participants.iter().any(|p| share(p).2.is_some()) {
+            // Percentages, to two decimal places
+            let mut weights = vec![];
+            for p in &self.participants {
+                match share(p).2 {
+                    Some(pc) if pc > 0. && (pc * 100.).fract().abs() < 1e-6 => weights.push((pc * 100.).round() as u32),
+                    Some(pc) => return Err(format!("Invalid percentage for {}: {}", p.name(), pc)),
+                    None => return Err(format!("If anyone has a percentage, everyone must ({} doesn't)", p.name())),
+                }
+            }
+            if weights.iter().sum::<u32>() != 10_000 {
+                return Err(format!("The percentages add up to {}, not 100", weights.iter().sum::<u32>() as f64 / 100.));
+            }
+            return Ok(names.into_iter().zip(split(self.amt, &weights)).collect());
+        }
+
+        // Fixed amounts come off the top, and the rest is split by shares
+        let fixed: isize = self.participants.iter().filter_map(|p| share(p).1).sum();
+        let rest = self.amt - fixed;
+        let weights: Vec<u32> = self.participants.iter().filter(|p| share(p).1.is_none())
+            .map(|p| share(p).0.unwrap_or(1)).collect();
+        if weights.is_empty() && rest != 0 {
+            return Err(format!("The participants' amounts add up to {}, not {}", fixed, self.amt));
+        }
+        let mut split = if weights.is_empty() { vec![] } else { split(rest, &weights) }.into_iter();
+        Ok(self.participants.iter().map(|p| (p.name(), share(p).1.unwrap_or_else(|| split.next().unwrap()))).collect())
+    }
+
+    /// The transfers from the payer to the other participants.  (With several payers, the fewest
+    /// transfers from the payers which settle everyone's shares.)
+    pub fn transfers(&self) -> Result<Vec<Transfer<String>>, String> {
+        let shares = self.shares()?;
+        let transfers: Vec<Transfer<String>> = match (&self.payer, self.payers.is_empty()) {
+            (Some(payer), true) => shares.into_iter()
+                .filter(|&(who, amt)| who != payer && amt != 0)
+                .map(|(who, amt)| Transfer::new(payer.clone(), who.to_string(), amt))
+                .collect(),
+            (None, false) => {
+                // How much everyone's balance changes by: up by their share, and down by what they paid
+                let mut deltas: BTreeMap<String, isize> = BTreeMap::new();
+                for (i, p) in self.payers.iter().enumerate() {
+                    if self.payers[..i].iter().any(|q| q.name == p.name) {
+                        return Err(format!("{} is listed as a payer more than once", p.name));
+                    }
+                    *deltas.entry(p.name.clone()).or_insert(0) -= p.amt;
+                }
+                let paid: isize = self.payers.iter().map(|p| p.amt).sum();
+                if paid != self.amt {
+                    return Err(format!("The payers paid {} in total, not {}", paid, self.amt));
+                }
+                for (who, amt) in shares {
+                    *deltas.entry(who.to_string()).or_insert(0) += amt;
+                }
+                // `construct_plan` cancels out the balances it's given, so give it the opposite
+                let mut transfers = construct_plan(deltas.into_iter().filter(|&(_, x)| x != 0).map(|(who, x)| (who, -x)).collect());
+                for t in &mut transfers { t.normalise(); }
+                transfers
+            }
+            _ => return Err("An expense needs either a 'payer' or some 'payers', but not both".into()),
+        };
+        Ok(transfers.into_iter().map(|t| Transfer {
+            id: self.id.clone(),
+            currency: self.currency.clone(),
+            category: self.category.clone(),
+            memo: self.memo.clone(),
+            tags: self.tags.clone(),
+            date: self.date,
+            extra: self.extra.clone(),
+            ..t
+        }).collect())
+    }
+}
+
+/// Split an amount in proportion to some (non-zero) weights, in whole minor units.  The units left
+/// over after rounding down go to the shares which were rounded down the most (the first of them,
+/// if there's a tie), so the shares always add up to `amount`.
+pub fn split(amount: isize, weights: &[u32]) -> Vec<isize> {
+    let total: isize = weights.iter().map(|&w| w as isize).sum();
+    let (sign, amount) = (amount.signum(), amount.abs());
+    let mut shares: Vec<isize> = weights.iter().map(|&w| amount * w as isize / total).collect();
+    let remainder = amount - shares.iter().sum::<isize>();
+    let mut order: Vec<usize> = (0..weights.len()).collect();
+    order.sort_by_key(|&i| ::std::cmp::Reverse(amount * weights[i] as isize % total));
+    for &i in order.iter().take(remainder as usize) { shares[i] += 1; }
+    shares.into_iter().map(|x| x * sign).collect()
+}
+
+#[test]
+fn test_transfers() {
+    let expense: Expense = ::serde_json::from_str(
+        r#"{"payer": "ann", "participants": ["bob", "ann", "cara"], "amt": 1000, "memo": "dinner"}"#).unwrap();
+    let transfers = expense.transfers().unwrap();
+    let got: Vec<_> = transfers.iter().map(|t| (t.from.as_str(), t.to.as_str(), t.amt)).collect();
+    assert_eq!(got, vec![("ann", "bob", 334), ("ann", "cara", 333)]);
+    assert_eq!(transfers[0].memo.as_deref(), Some("dinner"));
+    assert_eq!(split(-7, &[1, 1, 1]), vec![-3, -2, -2]);
+    assert_eq!(split(10, &[1, 3]), vec![3, 7]);
+    let name = |x: &str| Participant::Name(x.into());
+    let twice = Expense { participants: vec![name("bob"), name("bob")], ..expense.clone() };
+    assert!(twice.transfers().is_err());
+
+    let weighted = |participants: &str| Expense {
+        participants: ::serde_json::from_str(participants).unwrap(), ..expense.clone()
+    }.shares().map(|x| x.into_iter().map(|(_, amt)| amt).collect::<Vec<_>>());
+    assert_eq!(weighted(r#"[{"name": "ann", "shares": 2}, "bob", {"name": "cara", "amt": 100}]"#), Ok(vec![600, 300, 100]));
+    assert_eq!(weighted(r#"[{"name": "ann", "percent": 33.33}, {"name": "bob", "percent": 66.67}]"#), Ok(vec![333, 667]));
+    assert_eq!(weighted(r#"[{"name": "ann", "shares": 1}, {"name": "bob", "shares": 2}]"#), Ok(vec![333, 667]));
+    assert!(weighted(r#"[{"name": "ann", "percent": 50}, "bob"]"#).is_err());
+    assert!(weighted(r#"[{"name": "ann", "percent": 50}, {"name": "bob", "percent": 40}]"#).is_err());
+    assert!(weighted(r#"[{"name": "ann", "amt": 500}, {"name": "bob", "amt": 400}]"#).is_err());
+    assert!(weighted(r#"[{"name": "ann", "amt": 500, "shares": 2}, "bob"]"#).is_err());
+
+    let deposit: Expense = ::serde_json::from_str(r#"{
+        "payers": [{"name": "ann", "amt": 600}, {"name": "bob", "amt": 400}],
+        "participants": ["ann", "bob", "cara", "dan"], "amt": 1000
+    }"#).unwrap();
+    let transfers = deposit.transfers().unwrap();
+    let (_, balances) = ::ledger::balances(transfers);
+    assert_eq!(balances, vec![("ann".into(), -350), ("bob".into(), -150), ("cara".into(), 250), ("dan".into(), 250)]);
+    assert!(Expense { amt: 900, ..deposit.clone() }.transfers().is_err());
+    assert!(Expense { payer: Some("ann".into()), ..deposit }.transfers().is_err());
+}
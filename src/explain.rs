@@ -0,0 +1,82 @@
+/*!
+Explaining a plan (`--explain`): which group of people each repayment settles, and which of their
+debts it stands in for.
+
+A plan splits everyone into groups whose balances cancel out, and settles each group on its own;
+so a group is everyone connected by the repayments.  Within a group, a repayment from P to Q
+doesn't pay off any particular debt, but it's there because of what P owes and what Q is owed.  So
+(as a best guess) a repayment is explained by the debts in the group which P owes or Q is owed.
+*/
+
+use export::DebtGraph;
+use ledger::Transfer;
+use std::collections::BTreeMap;
+
+/// Why a repayment is in the plan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    /// The group it's part of, numbered from 1 in the order they first appear in the plan.
+    pub group: usize,
+    /// Everyone in the group, in order.
+    pub people: Vec<String>,
+    /// The debts it stands in for, as (debtor, creditor, amount), largest first.
+    pub debts: Vec<(String, String, isize)>,
+}
+
+/// Explain each repayment in the plan, using the debts between people in each currency.
+pub fn explain(plan: &[Transfer<String>], debts: &BTreeMap<Option<String>, DebtGraph>) -> Vec<Explanation> {
+    // Each (currency, person) is in a group; join the groups of the payer and payee
+    let mut parent: Vec<usize> = vec![];
+    let mut index: BTreeMap<(Option<String>, &str), usize> = BTreeMap::new();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for t in plan {
+        let mut node = |who| *index.entry((t.currency.clone(), who)).or_insert_with(|| {
+            parent.push(parent.len());
+            parent.len() - 1
+        });
+        let (a, b) = (node(t.from.as_str()), node(t.to.as_str()));
+        let (a, b) = (root(&mut parent, a), root(&mut parent, b));
+        parent[a.max(b)] = a.min(b);
+    }
+    let mut members: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (&(_, who), &i) in &index {
+        let r = root(&mut parent, i);
+        members.entry(r).or_default().push(who.to_string());
+    }
+    let mut numbers: BTreeMap<usize, usize> = BTreeMap::new();
+    let empty = DebtGraph::default();
+    plan.iter().map(|t| {
+        let r = root(&mut parent, index[&(t.currency.clone(), t.from.as_str())]);
+        let n = numbers.len() + 1;
+        let group = *numbers.entry(r).or_insert(n);
+        let people = members[&r].clone();
+        let graph = debts.get(&t.currency).unwrap_or(&empty);
+        let mut debts: Vec<(String, String, isize)> = graph.edges.iter()
+            .filter(|&((debtor, creditor), _)| *debtor == t.from || *creditor == t.to)
+            .filter(|&((debtor, creditor), _)| people.contains(debtor) && people.contains(creditor))
+            .map(|((debtor, creditor), &amt)| (debtor.clone(), creditor.clone(), amt))
+            .collect();
+        debts.sort_by_key(|x| -x.2);
+        Explanation { group, people, debts }
+    }).collect()
+}
+
+#[test]
+fn test_explain() {
+    let t = |from: &str, to: &str, amt| Transfer::new(from.to_string(), to.to_string(), amt);
+    // ann paid for bob and cat; dan paid for eve
+    let ledger = vec![t("ann", "bob", 1000), t("ann", "cat", 500), t("dan", "eve", 300), t("bob", "cat", 200)];
+    let debts: BTreeMap<_, _> = vec![(None, DebtGraph::from_transfers(ledger))].into_iter().collect();
+    let plan = vec![t("eve", "dan", 300), t("bob", "ann", 800), t("cat", "ann", 700)];
+    let explained = explain(&plan, &debts);
+    assert_eq!(explained.iter().map(|e| e.group).collect::<Vec<_>>(), vec![1, 2, 2]);
+    assert_eq!(explained[1].people, vec!["ann", "bob", "cat"]);
+    assert_eq!(explained[1].debts, vec![("bob".to_string(), "ann".to_string(), 1000), ("cat".to_string(), "ann".to_string(), 500)]);
+    assert_eq!(explained[0].debts, vec![("eve".to_string(), "dan".to_string(), 300)]);
+}
@@ -0,0 +1,197 @@
+/*!
+Exporting the debt network as a graph, for visualizing.
+
+This is the *gross* network, before any netting: there's an edge from A to B if A has received
+money from B, weighted by the total.  Debts in both directions are kept, so a graph can have edges
+from A to B and from B to A.  (The plan is what you get after netting everything out.)
+*/
+
+use ledger::Transfer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use units::DisplayConfig;
+
+/// Who owes whom how much, aggregated per pair.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DebtGraph {
+    /// Keyed by (debtor, creditor).  Every amount is positive.
+    pub edges: BTreeMap<(String, String), isize>,
+}
+
+impl DebtGraph {
+    pub fn from_transfers<I: IntoIterator<Item=Transfer<String>>>(transfers: I) -> DebtGraph {
+        let mut graph = DebtGraph::default();
+        for t in transfers { graph.add(&t); }
+        graph
+    }
+
+    /// Add the debt created by a single transfer.
+    pub fn add(&mut self, t: &Transfer<String>) {
+        if t.amt == 0 || t.from == t.to { return; }
+        let (debtor, creditor) = if t.amt > 0 { (&t.to, &t.from) } else { (&t.from, &t.to) };
+        *self.edges.entry((debtor.clone(), creditor.clone())).or_insert(0) += t.amt.abs();
+    }
+
+    /// Everyone who appears in the graph, in order.
+    pub fn people(&self) -> BTreeSet<&str> {
+        self.edges.keys().flat_map(|(a, b)| vec![a.as_str(), b.as_str()]).collect()
+    }
+}
+
+/// A file format for graphs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz
+    Dot,
+    GraphML,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<GraphFormat, String> {
+        match s {
+            "dot" | "graphviz" => Ok(GraphFormat::Dot),
+            "graphml" => Ok(GraphFormat::GraphML),
+            _ => Err(format!("Unknown graph format '{}' (expected 'dot' or 'graphml')", s)),
+        }
+    }
+}
+
+impl fmt::Display for GraphFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::GraphML => "graphml",
+        })
+    }
+}
+
+/// Write the graph in the given format.  Edges are labelled with their amounts, as formatted by
+/// `display`; GraphML files also have the amount in minor units, as an integer.
+pub fn write_graph<W: Write>(mut w: W, graph: &DebtGraph, format: GraphFormat, display: &DisplayConfig) -> io::Result<()> {
+    match format {
+        GraphFormat::Dot => {
+            writeln!(w, "digraph debts {{")?;
+            for who in graph.people() {
+                writeln!(w, "  \"{}\";", dot_escape(who))?;
+            }
+            for ((debtor, creditor), &amt) in &graph.edges {
+                writeln!(w, "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                    dot_escape(debtor), dot_escape(creditor), dot_escape(&display.format(amt, None)))?;
+            }
+            writeln!(w, "}}")
+        }
+        GraphFormat::GraphML => {
+            writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+            writeln!(w, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+            writeln!(w, "  <key id=\"amt\" for=\"edge\" attr.name=\"amt\" attr.type=\"long\"/>")?;
+            writeln!(w, "  <key id=\"label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>")?;
+            writeln!(w, "  <graph id=\"debts\" edgedefault=\"directed\">")?;
+            for who in graph.people() {
+                writeln!(w, "    <node id=\"{}\"/>", xml_escape(who))?;
+            }
+            for ((debtor, creditor), &amt) in &graph.edges {
+                writeln!(w, "    <edge source=\"{}\" target=\"{}\"><data key=\"amt\">{}</data><data key=\"label\">{}</data></edge>",
+                    xml_escape(debtor), xml_escape(creditor), amt, xml_escape(&display.format(amt, None)))?;
+            }
+            writeln!(w, "  </graph>")?;
+            writeln!(w, "</graphml>")
+        }
+    }
+}
+
+/// A plan as a Graphviz graph, with an edge from each payer to each payee, labelled with the
+/// amount.  The thicker the edge, the bigger the repayment.  With `debts`, the debt network which
+/// the plan settles is drawn next to it, so you can see what it simplified.
+pub fn plan_dot(plan: &[Transfer<String>], debts: Option<&DebtGraph>, display: &DisplayConfig) -> Vec<String> {
+    let label = |amt, ccy: Option<&str>| match ccy {
+        Some(ccy) => format!("{} {}", display.format(amt, Some(ccy)), ccy),
+        None => display.format(amt, None),
+    };
+    // Between 1 and 5 points
+    let width = |amt: isize, max: isize| 1.0 + 4.0 * amt as f64 / max.max(1) as f64;
+    let max = plan.iter().map(|t| t.amt).max().unwrap_or(0);
+    let mut people: Vec<&str> = plan.iter().flat_map(|t| vec![t.from.as_str(), t.to.as_str()]).collect();
+    people.sort_unstable();
+    people.dedup();
+    let (indent, prefix) = if debts.is_some() { ("    ", "plan/") } else { ("  ", "") };
+    let mut lines = vec!["digraph plan {".to_string()];
+    if debts.is_some() {
+        lines.push("  subgraph cluster_plan {".into());
+        lines.push("    label=\"Repayments\";".into());
+    }
+    for who in &people {
+        lines.push(format!("{}\"{}{}\" [label=\"{}\"];", indent, prefix, dot_escape(who), dot_escape(who)));
+    }
+    for t in plan {
+        lines.push(format!("{}\"{}{}\" -> \"{}{}\" [label=\"{}\", penwidth={:.1}];", indent, prefix, dot_escape(&t.from),
+            prefix, dot_escape(&t.to), dot_escape(&label(t.amt, t.currency.as_deref())), width(t.amt, max)));
+    }
+    if let Some(debts) = debts {
+        lines.push("  }".into());
+        lines.push("  subgraph cluster_debts {".into());
+        lines.push("    label=\"Debts\";".into());
+        let max = debts.edges.values().cloned().max().unwrap_or(0);
+        for who in debts.people() {
+            lines.push(format!("    \"debts/{}\" [label=\"{}\"];", dot_escape(who), dot_escape(who)));
+        }
+        for ((debtor, creditor), &amt) in &debts.edges {
+            lines.push(format!("    \"debts/{}\" -> \"debts/{}\" [label=\"{}\", penwidth={:.1}, style=dashed];",
+                dot_escape(debtor), dot_escape(creditor), dot_escape(&label(amt, None)), width(amt, max)));
+        }
+        lines.push("  }".into());
+    }
+    lines.push("}".into());
+    lines
+}
+
+/// For use inside a double-quoted dot string.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// For use in XML text or attributes.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+#[test]
+fn test_debt_graph() {
+    let graph = DebtGraph::from_transfers(vec![
+        Transfer::new("alice".to_string(), "bob".to_string(), 10),
+        Transfer::new("alice".to_string(), "bob".to_string(), 5),
+        Transfer::new("bob".to_string(), "alice".to_string(), 3),
+        Transfer::new("carol".to_string(), "bob".to_string(), -4),
+        Transfer::new("dave".to_string(), "dave".to_string(), 7),
+    ]);
+    let edges: Vec<_> = graph.edges.iter().map(|((a, b), &x)| (a.as_str(), b.as_str(), x)).collect();
+    assert_eq!(edges, vec![("alice", "bob", 3), ("bob", "alice", 15), ("carol", "bob", 4)]);
+
+    let mut dot = vec![];
+    write_graph(&mut dot, &graph, GraphFormat::Dot, &DisplayConfig::default()).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert!(dot.starts_with("digraph debts {\n  \"alice\";\n"));
+    assert!(dot.contains("  \"bob\" -> \"alice\" [label=\"15\"];\n"));
+    assert_eq!(xml_escape("<Tom & \"Jerry\">"), "&lt;Tom &amp; &quot;Jerry&quot;&gt;");
+}
+
+#[test]
+fn test_plan_dot() {
+    let plan = vec![Transfer::new("bob".to_string(), "ann".to_string(), 1000), Transfer::new("cat".to_string(), "ann".to_string(), 250)];
+    let dot = plan_dot(&plan, None, &DisplayConfig::default());
+    assert_eq!(dot, vec![
+        "digraph plan {",
+        "  \"ann\" [label=\"ann\"];",
+        "  \"bob\" [label=\"bob\"];",
+        "  \"cat\" [label=\"cat\"];",
+        "  \"bob\" -> \"ann\" [label=\"1000\", penwidth=5.0];",
+        "  \"cat\" -> \"ann\" [label=\"250\", penwidth=2.0];",
+        "}",
+    ]);
+    let debts = DebtGraph::from_transfers(vec![Transfer::new("ann".to_string(), "bob".to_string(), 1000)]);
+    let dot = plan_dot(&plan, Some(&debts), &DisplayConfig::default());
+    assert!(dot.contains(&"    \"plan/bob\" -> \"plan/ann\" [label=\"1000\", penwidth=5.0];".to_string()));
+    assert!(dot.contains(&"    \"debts/bob\" -> \"debts/ann\" [label=\"1000\", penwidth=5.0, style=dashed];".to_string()));
+}
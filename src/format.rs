@@ -0,0 +1,270 @@
+/*!
+Ledger file formats, and guessing which one a file is in.
+
+When `--format` isn't given, we look at the first few KB of the file and ask each format how
+plausible it looks.  The most confident guess wins, and we say why we picked it.  If the contents
+aren't conclusive, the file's extension (eg. `.csv`, `.journal`) breaks the tie.
+*/
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A ledger file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per transfer, usually one per line.
+    Json,
+    /// A table with one row per share of a transaction (see `long`).
+    Long,
+    /// A table with one row per transfer, and named columns (see `sheet`).
+    Csv,
+    /// An hledger or ledger-cli journal (see `journal`).
+    Journal,
+    /// Tricount's CSV export (see `apps`).  These can be read, but not written.
+    Tricount,
+    /// Settle Up's CSV export, or its JSON backup of a group (see `apps`).
+    SettleUp,
+}
+
+pub const ALL_FORMATS: &[Format] = &[Format::Json, Format::Long, Format::Csv, Format::Journal, Format::Tricount, Format::SettleUp];
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "json" | "jsonl" | "ndjson" => Ok(Format::Json),
+            "long" => Ok(Format::Long),
+            "csv" => Ok(Format::Csv),
+            "journal" | "hledger" | "ledger" => Ok(Format::Journal),
+            "tricount" => Ok(Format::Tricount),
+            "settle-up" | "settleup" => Ok(Format::SettleUp),
+            _ => Err(format!("Unknown format '{}' (expected one of: {})", s,
+                ALL_FORMATS.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Format::Json => f.write_str("json"),
+            Format::Long => f.write_str("long"),
+            Format::Csv => f.write_str("csv"),
+            Format::Journal => f.write_str("journal"),
+            Format::Tricount => f.write_str("tricount"),
+            Format::SettleUp => f.write_str("settle-up"),
+        }
+    }
+}
+
+/// How sure we are about a guess.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// The result of sniffing a file.
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub format: Format,
+    pub confidence: Confidence,
+    pub reason: String,
+}
+
+/// How much of the file to look at.
+const SNIFF_LEN: u64 = 8 * 1024;
+
+impl Format {
+    /// The format which files with this extension are usually in.
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        match ext.to_lowercase().as_str() {
+            "json" | "jsonl" | "ndjson" => Some(Format::Json),
+            "csv" | "tsv" => Some(Format::Csv),
+            "journal" | "hledger" | "ledger" | "j" => Some(Format::Journal),
+            _ => None,
+        }
+    }
+}
+
+/// Guess the format of the given file, from its contents and its name.
+pub fn detect(path: &Path) -> ::std::io::Result<Detection> {
+    let mut sample = vec![];
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut sample)?;
+    // The sample might end in the middle of a character, so don't insist on valid UTF-8
+    let sample = String::from_utf8_lossy(&sample);
+    Ok(detect_named(path.extension().and_then(|x| x.to_str()), &sample))
+}
+
+/// Guess the format of the beginning of a file, whose name has the given extension.  The contents
+/// win if they're clear, but otherwise the extension breaks the tie.
+pub fn detect_named(extension: Option<&str>, sample: &str) -> Detection {
+    let detection = detect_str(sample);
+    let format = match extension.and_then(Format::from_extension) {
+        Some(format) if format != detection.format => format,
+        _ => return detection,
+    };
+    if detection.confidence == Confidence::High { return detection; }
+    let (confidence, reason) = sniff(format, sample.trim_start_matches('\u{feff}'));
+    if confidence >= detection.confidence {
+        return Detection { format, confidence, reason };
+    }
+    Detection {
+        format,
+        confidence: Confidence::Medium,
+        reason: format!("the file name ends in .{} (although {})", extension.unwrap_or_default(), reason),
+    }
+}
+
+/// Guess the format of the beginning of a file.  A leading byte order mark is ignored.
+pub fn detect_str(sample: &str) -> Detection {
+    let sample = sample.trim_start_matches('\u{feff}');
+    ALL_FORMATS.iter()
+        .map(|&format| {
+            let (confidence, reason) = sniff(format, sample);
+            Detection { format, confidence, reason }
+        })
+        .max_by_key(|d| d.confidence)
+        .expect("at least one format")
+}
+
+/// How much does `sample` look like the given format, and why?
+fn sniff(format: Format, sample: &str) -> (Confidence, String) {
+    match format {
+        Format::Json => sniff_json(sample),
+        Format::Long => sniff_long(sample),
+        Format::Csv => sniff_csv(sample),
+        Format::Journal => sniff_journal(sample),
+        Format::Tricount => sniff_tricount(sample),
+        Format::SettleUp => sniff_settle_up(sample),
+    }
+}
+
+fn sniff_json(sample: &str) -> (Confidence, String) {
+    let first_line = match sample.lines().map(|l| l.trim()).find(|l| !l.is_empty()) {
+        Some(l) => l,
+        None => return (Confidence::Low, "the file is empty".into()),
+    };
+    if !first_line.starts_with('{') {
+        return (Confidence::Low, "the first line isn't a JSON object".into());
+    }
+    match ::serde_json::from_str::<::serde_json::Value>(first_line) {
+        Ok(ref x) if x.get("repay_version").is_some() =>
+            (Confidence::High, "the first line is a repay ledger header".into()),
+        Ok(ref x) if x.get("from").is_some() && x.get("to").is_some() =>
+            (Confidence::High, "the first line is a JSON object with 'from' and 'to' fields".into()),
+        Ok(_) => (Confidence::Medium, "the first line is a JSON object".into()),
+        Err(_) => (Confidence::Medium, "the first line starts with '{'".into()),
+    }
+}
+
+fn sniff_long(sample: &str) -> (Confidence, String) {
+    let first_line = match sample.lines().map(|l| l.trim()).find(|l| !l.is_empty()) {
+        Some(l) => l,
+        None => return (Confidence::Low, "the file is empty".into()),
+    };
+    let cols: Vec<String> = first_line.split(',').map(|c| c.trim().to_lowercase()).collect();
+    if cols.len() != 3 {
+        return (Confidence::Low, "the first line doesn't have three columns".into());
+    }
+    if (cols[0] == "txn" || cols[0] == "id") && cols[1] == "person" {
+        return (Confidence::High, format!("the first line is a header with '{}' and 'person' columns", cols[0]));
+    }
+    if cols[2].parse::<isize>().is_ok() {
+        (Confidence::Medium, "the first line has three columns, the last of which is a number".into())
+    } else {
+        (Confidence::Low, "the first line has three columns".into())
+    }
+}
+
+fn sniff_csv(sample: &str) -> (Confidence, String) {
+    let first_line = match sample.lines().map(|l| l.trim()).find(|l| !l.is_empty()) {
+        Some(l) => l,
+        None => return (Confidence::Low, "the file is empty".into()),
+    };
+    let delimiter = ::sheet::delimiter(first_line) as char;
+    let cols: Vec<String> = first_line.split(delimiter).map(|c| c.trim().to_lowercase()).collect();
+    let has = |name: &str| cols.iter().any(|c| c == name);
+    if has("from") && has("to") && (has("amt") || has("amount")) {
+        (Confidence::High, "the first line is a header with 'from', 'to', and 'amt' columns".into())
+    } else {
+        (Confidence::Low, "the first line isn't a header with 'from', 'to', and 'amt' columns".into())
+    }
+}
+
+fn sniff_journal(sample: &str) -> (Confidence, String) {
+    let is_date = |l: &str| {
+        let date: Vec<&str> = l.split_whitespace().next().unwrap_or_default().split(['-', '/', '.']).collect();
+        date.len() == 3 && date.iter().all(|x| !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit()))
+    };
+    let mut lines = sample.lines();
+    while let Some(line) = lines.next() {
+        if !is_date(line) { continue; }
+        return if lines.next().is_some_and(|l| l.starts_with([' ', '\t'])) {
+            (Confidence::High, "it has a line starting with a date, followed by an indented posting".into())
+        } else {
+            (Confidence::Medium, "it has a line starting with a date".into())
+        };
+    }
+    (Confidence::Low, "no line starts with a date".into())
+}
+
+/// The columns of the first line, if it's a header.
+fn header(sample: &str) -> Vec<String> {
+    let first_line = sample.lines().map(|l| l.trim()).find(|l| !l.is_empty()).unwrap_or_default();
+    let delimiter = ::sheet::delimiter(first_line) as char;
+    first_line.split(delimiter).map(|c| c.trim().trim_matches('"').to_lowercase()).collect()
+}
+
+fn sniff_tricount(sample: &str) -> (Confidence, String) {
+    let cols = header(sample);
+    let shares = cols.iter().any(|c| c.starts_with("impacted to ") || c.starts_with("paid for "));
+    if cols.iter().any(|c| c == "paid by") && shares {
+        (Confidence::High, "the first line is a header with 'Paid by' and 'Impacted to' columns, like a Tricount export".into())
+    } else {
+        (Confidence::Low, "the first line isn't a header like a Tricount export's".into())
+    }
+}
+
+fn sniff_settle_up(sample: &str) -> (Confidence, String) {
+    let cols = header(sample);
+    if cols.iter().any(|c| c == "who paid") && cols.iter().any(|c| c == "for whom") {
+        (Confidence::High, "the first line is a header with 'Who paid' and 'For whom' columns, like a Settle Up export".into())
+    } else if sample.trim_start().starts_with('{') && sample.contains("\"whoPaid\"") {
+        (Confidence::High, "it's a JSON object with 'whoPaid' fields, like a Settle Up group".into())
+    } else {
+        (Confidence::Low, "it doesn't look like a Settle Up export".into())
+    }
+}
+
+#[test]
+fn test_detect() {
+    let d = detect_str("\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\n");
+    assert_eq!((d.format, d.confidence), (Format::Json, Confidence::High));
+    let d = detect_str("\u{feff}{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\r\n");
+    assert_eq!((d.format, d.confidence), (Format::Json, Confidence::High));
+    let d = detect_str("from,to,amt\nann,bob,1\n");
+    assert_eq!((d.format, d.confidence), (Format::Csv, Confidence::High));
+    let d = detect_str("; comment\n2024-05-01 Dinner\n    people:ann  -30\n    people:bob\n");
+    assert_eq!((d.format, d.confidence), (Format::Journal, Confidence::High));
+    // A headerless table looks more like the long format, unless the name says otherwise
+    assert_eq!(detect_str("ann,bob,1\n").format, Format::Long);
+    let d = detect_named(Some("csv"), "ann,bob,1\n");
+    assert_eq!((d.format, d.confidence), (Format::Csv, Confidence::Medium));
+    let d = detect_named(Some("csv"), "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\n");
+    assert_eq!(d.format, Format::Json);
+    let d = detect_str("ann,bob\n");
+    assert_eq!(d.confidence, Confidence::Low);
+    let d = detect_str("txn,person,share\ndinner,ann,-3000\n");
+    assert_eq!((d.format, d.confidence), (Format::Long, Confidence::High));
+    let d = detect_named(Some("csv"), "Title,Amount,Currency,Paid by,Impacted to Ann,Impacted to Bob\nDinner,30,EUR,Ann,15,15\n");
+    assert_eq!((d.format, d.confidence), (Format::Tricount, Confidence::High));
+    let d = detect_str("Who paid;Amount;Currency;For whom;Split amounts\nAnn;30;EUR;Ann,Bob;15,15\n");
+    assert_eq!(d.format, Format::SettleUp);
+    let d = detect_named(Some("json"), "{\n  \"members\": {},\n  \"transactions\": {\"t1\": {\"whoPaid\": []}}\n}\n");
+    assert_eq!(d.format, Format::SettleUp);
+}
@@ -0,0 +1,226 @@
+/*!
+Writing gzip files (RFC 1951 and 1952), for archived ledgers (see `ledger::archive`).
+
+This only has to be good enough for text like a ledger's, which repeats itself a lot, so it keeps
+things simple: repeats are found with a hash of the next three bytes (as zlib does, but looking
+less hard), and every block uses the fixed Huffman codes.  The output can be read by `gzip -d`,
+`zcat`, and so on.
+*/
+
+use std::io::{self, Write};
+
+/// How far back a repeat can be.
+const WINDOW: usize = 32 * 1024;
+/// Compress this much at a time.
+const CHUNK: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash to try.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Compresses what's written to it, and writes it to `W` as a gzip file.  Call `finish` at the
+/// end, to write the last block and the trailer.
+pub struct Encoder<W: Write> {
+    out: W,
+    /// Bits which haven't made a whole byte yet (the first in the lowest bits).
+    bits: u64,
+    nbits: u32,
+    /// The last `WINDOW` bytes which have been compressed, followed by the ones which haven't.
+    buf: Vec<u8>,
+    /// The position in the input of `buf[0]`.
+    start: usize,
+    /// Where the uncompressed bytes start, in `buf`.
+    pending: usize,
+    /// For each hash, the position (plus one) of the last three bytes with that hash; and for each
+    /// position, the one before it with the same hash.
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    crc: u32,
+    size: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(mut out: W) -> io::Result<Encoder<W>> {
+        // No name or timestamp, and the OS is "unknown"
+        out.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255])?;
+        Ok(Encoder {
+            out, bits: 0, nbits: 0, buf: vec![], start: 0, pending: 0,
+            head: vec![0; 1 << HASH_BITS], prev: vec![0; WINDOW], crc: !0, size: 0,
+        })
+    }
+
+    /// Write the rest, and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress(true)?;
+        // The last block: empty
+        self.put(0b011, 3);
+        self.put_code(0, 7);
+        self.flush_bits()?;
+        if self.nbits > 0 {
+            self.put(0, 8 - self.nbits);
+            self.flush_bits()?;
+        }
+        let crc = !self.crc;
+        self.out.write_all(&crc.to_le_bytes())?;
+        self.out.write_all(&(self.size as u32).to_le_bytes())?;
+        Ok(self.out)
+    }
+
+    fn put(&mut self, value: u32, n: u32) {
+        self.bits |= u64::from(value) << self.nbits;
+        self.nbits += n;
+    }
+
+    /// Huffman codes are written starting from their highest bit.
+    fn put_code(&mut self, code: u32, n: u32) {
+        self.put(code.reverse_bits() >> (32 - n), n);
+    }
+
+    fn flush_bits(&mut self) -> io::Result<()> {
+        let mut bytes = [0; 8];
+        let mut n = 0;
+        while self.nbits >= 8 {
+            bytes[n] = self.bits as u8;
+            self.bits >>= 8;
+            self.nbits -= 8;
+            n += 1;
+        }
+        self.out.write_all(&bytes[..n])
+    }
+
+    fn literal(&mut self, x: u16) {
+        match x {
+            0..=143 => self.put_code(0x30 + u32::from(x), 8),
+            144..=255 => self.put_code(0x190 + u32::from(x) - 144, 9),
+            256..=279 => self.put_code(u32::from(x) - 256, 7),
+            _ => self.put_code(0xc0 + u32::from(x) - 280, 8),
+        }
+    }
+
+    fn repeat(&mut self, len: usize, dist: usize) {
+        let i = LENGTH_BASE.iter().rposition(|&b| usize::from(b) <= len).unwrap_or_default();
+        self.literal(257 + i as u16);
+        self.put((len - usize::from(LENGTH_BASE[i])) as u32, u32::from(LENGTH_EXTRA[i]));
+        let j = DIST_BASE.iter().rposition(|&b| usize::from(b) <= dist).unwrap_or_default();
+        self.put_code(j as u32, 5);
+        self.put((dist - usize::from(DIST_BASE[j])) as u32, u32::from(DIST_EXTRA[j]));
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let x = u32::from(self.buf[i]) << 16 | u32::from(self.buf[i + 1]) << 8 | u32::from(self.buf[i + 2]);
+        (x.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// The longest repeat of what's at `buf[i]`: its length and distance.
+    fn longest_match(&self, i: usize, end: usize) -> Option<(usize, usize)> {
+        let pos = self.start + i;
+        let mut candidate = self.head[self.hash(i)];
+        let mut best: Option<(usize, usize)> = None;
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 { break; }
+            let p = candidate - 1;
+            if p < self.start || pos - p > WINDOW { break; }
+            let j = p - self.start;
+            let len = self.buf[j..].iter().zip(&self.buf[i..end]).take(MAX_MATCH).take_while(|(a, b)| a == b).count();
+            if len >= MIN_MATCH && best.is_none_or(|(l, _)| len > l) {
+                best = Some((len, pos - p));
+                if len == MAX_MATCH { break; }
+            }
+            candidate = self.prev[p % WINDOW];
+        }
+        best
+    }
+
+    fn insert(&mut self, i: usize) {
+        let h = self.hash(i);
+        let pos = self.start + i;
+        self.prev[pos % WINDOW] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+    /// Compress the pending bytes as a block (not the last one), except for some at the end which a
+    /// repeat might run into, unless `all`.
+    fn compress(&mut self, all: bool) -> io::Result<()> {
+        let end = self.buf.len();
+        let stop = if all { end } else { end.saturating_sub(MAX_MATCH) };
+        if self.pending >= stop { return Ok(()); }
+        self.put(0b010, 3);
+        let mut i = self.pending;
+        while i < stop {
+            let found = if i + MIN_MATCH <= end { self.longest_match(i, end) } else { None };
+            match found {
+                Some((len, dist)) => {
+                    self.repeat(len, dist);
+                    for k in i..i + len {
+                        if k + MIN_MATCH <= end { self.insert(k); }
+                    }
+                    i += len;
+                }
+                None => {
+                    self.literal(u16::from(self.buf[i]));
+                    if i + MIN_MATCH <= end { self.insert(i); }
+                    i += 1;
+                }
+            }
+            self.flush_bits()?;
+        }
+        self.literal(256);
+        self.flush_bits()?;
+        self.pending = i;
+        // Forget what's too far back to be repeated
+        let keep_from = self.pending.saturating_sub(WINDOW);
+        self.buf.drain(..keep_from);
+        self.start += keep_from;
+        self.pending -= keep_from;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.crc = crc32(self.crc, data);
+        self.size += data.len() as u64;
+        self.buf.extend_from_slice(data);
+        if self.buf.len() - self.pending >= CHUNK { self.compress(false)?; }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Continue a CRC-32 (as used by gzip).  Start with `!0`, and invert the result.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Compress some bytes.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new(vec![]).expect("writing to a Vec");
+    encoder.write_all(data).expect("writing to a Vec");
+    encoder.finish().expect("writing to a Vec")
+}
+
+#[test]
+fn test_compress() {
+    assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+    // An empty file, as written by `gzip -n`, apart from the OS
+    assert_eq!(compress(b""), vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let text: String = (0..5000).map(|i| format!("{{\"from\":\"ann\",\"to\":\"bob\",\"amt\":{}}}\n", i % 97)).collect();
+    let gz = compress(text.as_bytes());
+    assert!(gz.len() * 10 < text.len(), "{} bytes, from {}", gz.len(), text.len());
+    assert_eq!(gz[gz.len() - 4..], (text.len() as u32).to_le_bytes());
+}
@@ -0,0 +1,170 @@
+/*!
+A record of which solver each run used and how long it took, so that `repay recommend` can suggest
+better flags.
+
+This is opt-in: nothing is recorded unless `history_file` is set in the config (or `--history` is
+given).  The history is a local file with one JSON object per line, and it never goes anywhere
+else.
+*/
+
+use plan::{Mode, Solver, AUTO_EXACT_LIMIT};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// One run of the solver.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    /// The number of unresolved balances.
+    pub people: usize,
+    /// The solver which the user asked for.
+    pub mode: Mode,
+    /// The solver which actually ran.
+    pub solver: Solver,
+    /// How long it took, in seconds.
+    pub secs: f64,
+    /// The number of repayments in the plan.
+    pub repayments: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new<P: Into<PathBuf>>(path: P) -> History {
+        History { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a run to the history, creating it if necessary.
+    pub fn record(&self, run: &Run) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() { fs::create_dir_all(dir)?; }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", ::serde_json::to_string(run)?)
+    }
+
+    /// Every run recorded so far.  Lines which don't make sense (eg. ones which mention a solver
+    /// this build doesn't have) are skipped.
+    pub fn runs(&self) -> io::Result<Vec<Run>> {
+        let file = match File::open(&self.path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            x => x?,
+        };
+        let mut runs = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match ::serde_json::from_str(&line) {
+                Ok(run) => runs.push(run),
+                Err(e) => debug!("Skipping a history entry ({}): {}", e, line),
+            }
+        }
+        Ok(runs)
+    }
+}
+
+/// Exact runs which took longer than this many seconds are too slow to recommend.
+pub const SLOW_SECS: f64 = 10.0;
+
+/// A suggestion about which flags to use.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recommendation {
+    /// Auto mode would use the approximate solver, but the exact one has been quick enough.
+    UseExact { people: usize, secs: f64 },
+    /// Auto mode would use the exact solver, but it's been slow.
+    UseApprox { people: usize, secs: f64 },
+    /// The defaults are fine.
+    KeepDefaults { people: usize },
+    /// There are no exact runs of a similar size to go on.
+    NoData { people: usize },
+}
+
+impl fmt::Display for Recommendation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Recommendation::UseExact { people, secs } =>
+                write!(f, "Your group of {} solves exactly in ~{}; consider -x", people, approx_secs(secs)),
+            Recommendation::UseApprox { people, secs } =>
+                write!(f, "Your group of {} takes ~{} to solve exactly; consider -a", people, approx_secs(secs)),
+            Recommendation::KeepDefaults { people } =>
+                write!(f, "Your group of {} is fine with the default flags", people),
+            Recommendation::NoData { people } =>
+                write!(f, "There's no record of solving a group of {} or more exactly.  \
+                    (Try -x once to find out how long it takes)", people),
+        }
+    }
+}
+
+/// Eg. `2s`, or `0.3s`.
+fn approx_secs(secs: f64) -> String {
+    if secs < 1.0 { format!("{:.1}s", secs) } else { format!("{:.0}s", secs) }
+}
+
+/// Which flags to suggest for a group of `people`, based on past runs.
+///
+/// The exact solver gets slower as the group gets bigger, so the slowest exact run of the smallest
+/// group at least as big is a pessimistic estimate of how long it'll take, and the slowest exact
+/// run of the biggest group no bigger is an optimistic one.
+pub fn recommend(runs: &[Run], people: usize) -> Recommendation {
+    if cfg!(not(feature = "approx")) {
+        // There's only one solver anyway
+        return Recommendation::KeepDefaults { people };
+    }
+    let exact = |n: usize| runs.iter()
+        .filter(|r| r.solver == Solver::Exact && r.people == n)
+        .map(|r| r.secs)
+        .fold(None, |acc: Option<f64>, x| Some(acc.map_or(x, |y| y.max(x))));
+    let nearest = |ns: &mut dyn Iterator<Item=usize>| ns.filter_map(&exact).next();
+    let pessimistic = nearest(&mut (people..=runs.iter().map(|r| r.people).max().unwrap_or(0)));
+    let optimistic = nearest(&mut (0..=people).rev());
+
+    if people <= AUTO_EXACT_LIMIT {
+        match optimistic {
+            Some(secs) if secs > SLOW_SECS => Recommendation::UseApprox { people, secs },
+            _ => Recommendation::KeepDefaults { people },
+        }
+    } else {
+        match (pessimistic, optimistic) {
+            (Some(secs), _) if secs <= SLOW_SECS => Recommendation::UseExact { people, secs },
+            (_, Some(secs)) if secs > SLOW_SECS => Recommendation::KeepDefaults { people },
+            _ => Recommendation::NoData { people },
+        }
+    }
+}
+
+#[cfg(feature = "approx")]
+#[test]
+fn test_recommend() {
+    let run = |people, solver, secs| Run { people, mode: Mode::Auto, solver, secs, repayments: 0 };
+    let runs = vec![
+        run(15, Solver::Exact, 0.1),
+        run(18, Solver::Exact, 12.0),
+        run(28, Solver::Approx, 0.1),
+        run(30, Solver::Exact, 2.0),
+        run(30, Solver::Exact, 1.5),
+    ];
+    assert_eq!(recommend(&runs, 15), Recommendation::KeepDefaults { people: 15 });
+    assert_eq!(recommend(&runs, 19), Recommendation::UseApprox { people: 19, secs: 12.0 });
+    assert_eq!(recommend(&runs, 28), Recommendation::UseExact { people: 28, secs: 2.0 });
+    assert_eq!(recommend(&runs, 31), Recommendation::NoData { people: 31 });
+    assert_eq!(recommend(&[run(22, Solver::Exact, 60.0)], 25), Recommendation::KeepDefaults { people: 25 });
+    assert_eq!(recommend(&[], 28), Recommendation::NoData { people: 28 });
+    assert_eq!(recommend(&runs, 28).to_string(), "Your group of 28 solves exactly in ~2s; consider -x");
+}
+
+#[test]
+fn test_history() {
+    let path = ::std::env::temp_dir().join(format!("repay-test-history-{}", ::std::process::id()));
+    let history = History::new(&path);
+    assert_eq!(history.runs().unwrap(), vec![]);
+    let run = Run { people: 3, mode: Mode::Exact, solver: Solver::Exact, secs: 0.25, repayments: 2 };
+    history.record(&run).unwrap();
+    history.record(&run).unwrap();
+    assert_eq!(history.runs().unwrap(), vec![run.clone(), run]);
+    fs::remove_file(path).unwrap();
+}
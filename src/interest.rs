@@ -0,0 +1,191 @@
+/*!
+Interest on long-running debts (the `interest` section of the config file).
+
+```json
+{
+  "rate": 5,
+  "compounding": "monthly",
+  "grace_days": 30,
+  "people": { "bob": { "rate": 0 }, "carol": { "grace_days": 90 } }
+}
+```
+
+Interest accrues on what one person owes another, as worked out from the transfers between those
+two alone.  Each debt starts accruing once it's been outstanding for `grace_days`, at `rate`
+percent a year (or the debtor's own terms, under `people`), and repayments pay off the oldest debts
+first.  The interest is charged as a transfer with the memo "interest": whenever the debtor repays
+something, at the end of each compounding period (`daily`, `monthly`, `yearly`, or `never`), and at
+the end of the last day counted (today, or `--until`).  Interest which has been charged accrues
+interest of its own, unless the compounding is `never`.
+
+Undated transfers never accrue interest, and count as coming before the dated ones.
+*/
+
+use date::Date;
+use ledger::Transfer;
+use std::collections::{BTreeMap, VecDeque};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Interest {
+    /// Percent a year.
+    pub rate: f64,
+    pub compounding: Compounding,
+    /// How long a debt can be outstanding before it accrues interest.
+    pub grace_days: u32,
+    /// Different terms for what these people owe.
+    pub people: BTreeMap<String, Terms>,
+}
+
+/// Overrides for some of the default terms.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Terms {
+    pub rate: Option<f64>,
+    pub compounding: Option<Compounding>,
+    pub grace_days: Option<u32>,
+}
+
+/// How often accrued interest is charged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compounding {
+    Daily,
+    #[default]
+    Monthly,
+    Yearly,
+    /// Only when the debtor repays something, for simple interest.
+    Never,
+}
+
+/// What `debtor` owes `creditor`.
+struct Debt {
+    debtor: String,
+    creditor: String,
+    currency: Option<String>,
+    /// Amounts owed, oldest first, with the day they start accruing interest (if ever).
+    lots: VecDeque<(isize, Option<i64>)>,
+    /// Interest which hasn't been charged yet, in minor units.
+    accrued: f64,
+}
+
+impl Interest {
+    /// The terms for what `debtor` owes: (percent a year, compounding, grace days).
+    fn terms(&self, debtor: &str) -> (f64, Compounding, u32) {
+        let terms = self.people.get(debtor).cloned().unwrap_or_default();
+        (terms.rate.unwrap_or(self.rate), terms.compounding.unwrap_or(self.compounding), terms.grace_days.unwrap_or(self.grace_days))
+    }
+
+    /// The interest charged on the debts in `transfers`, up to the end of `until`.
+    pub fn accrue(&self, transfers: &[Transfer<String>], until: Date) -> Vec<Transfer<String>> {
+        let mut transfers: Vec<&Transfer<String>> = transfers.iter().collect();
+        transfers.sort_by_key(|t| t.date.map(|d| d.date.days()));
+        let mut debts = BTreeMap::new();
+        let mut charges = vec![];
+        let mut next = transfers.into_iter().peekable();
+        while let Some(t) = next.next_if(|t| t.date.is_none()) {
+            self.apply(&mut debts, t, None, &mut charges);
+        }
+        let first = match next.peek().and_then(|t| t.date) {
+            Some(d) => d.date.days(),
+            None => return charges,
+        };
+        for day in first..=until.days() {
+            while let Some(t) = next.next_if(|t| t.date.map(|d| d.date.days()) == Some(day)) {
+                self.apply(&mut debts, t, Some(day), &mut charges);
+            }
+            let tomorrow = Date::from_days(day + 1);
+            for debt in debts.values_mut() {
+                let (rate, compounding, _) = self.terms(&debt.debtor);
+                for &(amt, since) in &debt.lots {
+                    if since.is_some_and(|since| since <= day) {
+                        debt.accrued += amt as f64 * rate / 100. / 365.;
+                    }
+                }
+                let period_end = match compounding {
+                    Compounding::Daily => true,
+                    Compounding::Monthly => tomorrow.day() == 1,
+                    Compounding::Yearly => tomorrow.day() == 1 && tomorrow.month() == 1,
+                    Compounding::Never => false,
+                };
+                if period_end || day == until.days() {
+                    self.charge(debt, day, &mut charges);
+                }
+            }
+        }
+        charges
+    }
+
+    fn apply(&self, debts: &mut BTreeMap<(String, String, Option<String>), Debt>, t: &Transfer<String>, day: Option<i64>, charges: &mut Vec<Transfer<String>>) {
+        // The recipient of a transfer owes the sender
+        let (creditor, debtor, amt) = if t.amt > 0 { (&t.from, &t.to, t.amt) } else { (&t.to, &t.from, -t.amt) };
+        if creditor == debtor || amt == 0 { return; }
+        let key = if creditor < debtor { (creditor.clone(), debtor.clone(), t.currency.clone()) } else { (debtor.clone(), creditor.clone(), t.currency.clone()) };
+        let debt = debts.entry(key).or_insert_with(|| Debt {
+            debtor: debtor.clone(), creditor: creditor.clone(), currency: t.currency.clone(), lots: VecDeque::new(), accrued: 0.,
+        });
+        let grace = |debtor: &str| i64::from(self.terms(debtor).2);
+        if debt.lots.is_empty() {
+            debt.debtor = debtor.clone();
+            debt.creditor = creditor.clone();
+        }
+        if debt.debtor == *debtor {
+            debt.lots.push_back((amt, day.map(|day| day + grace(debtor))));
+            return;
+        }
+        // A repayment, which first pays off the interest so far
+        if let Some(day) = day { self.charge(debt, day, charges); }
+        let mut left = amt;
+        while let Some(lot) = debt.lots.front_mut() {
+            let x = lot.0.min(left);
+            lot.0 -= x;
+            left -= x;
+            if lot.0 > 0 { break; }
+            debt.lots.pop_front();
+        }
+        if left > 0 {
+            debt.debtor = debtor.clone();
+            debt.creditor = creditor.clone();
+            debt.accrued = 0.;
+            debt.lots.push_back((left, day.map(|day| day + grace(debtor))));
+        }
+    }
+
+    /// Charge the interest accrued on `debt` so far.
+    fn charge(&self, debt: &mut Debt, day: i64, charges: &mut Vec<Transfer<String>>) {
+        let amt = debt.accrued.round() as isize;
+        if amt <= 0 { return; }
+        debt.accrued -= amt as f64;
+        let compounds = self.terms(&debt.debtor).1 != Compounding::Never;
+        debt.lots.push_back((amt, if compounds { Some(day + 1) } else { None }));
+        charges.push(Transfer {
+            currency: debt.currency.clone(),
+            memo: Some("interest".into()),
+            date: Some(Date::from_days(day).into()),
+            ..Transfer::new(debt.creditor.clone(), debt.debtor.clone(), amt)
+        });
+    }
+}
+
+#[test]
+fn test_accrue() {
+    let t = |from: &str, to: &str, amt: isize, date: &str| Transfer { date: date.parse().ok(), ..Transfer::new(from.to_string(), to.to_string(), amt) };
+    let charges = |interest: &Interest, transfers: &[Transfer<String>], until: &str| interest.accrue(transfers, until.parse().unwrap())
+        .into_iter().map(|t| (t.from, t.to, t.amt, t.date.unwrap().date.to_string())).collect::<Vec<_>>();
+    let c = |from: &str, to: &str, amt: isize, date: &str| (from.to_string(), to.to_string(), amt, date.to_string());
+    let simple = Interest { rate: 10., compounding: Compounding::Never, ..Interest::default() };
+    let loan = vec![t("ann", "bob", 100_000, "2024-01-01"), t("bob", "ann", 50_000, "2024-07-01")];
+    // 182 days on 1000.00, then 184 days on 500.00
+    assert_eq!(charges(&simple, &loan, "2024-12-31"), vec![c("ann", "bob", 4986, "2024-07-01"), c("ann", "bob", 2521, "2024-12-31")]);
+    // Repaying more than's owed turns the debt around
+    let loan = vec![t("ann", "bob", 100_000, "2024-01-01"), t("bob", "ann", 150_000, "2024-01-31")];
+    assert_eq!(charges(&simple, &loan, "2024-03-01"), vec![c("ann", "bob", 822, "2024-01-31"), c("bob", "ann", 418, "2024-03-01")]);
+    // Monthly, after a 30-day grace period
+    let monthly = Interest { rate: 12., grace_days: 30, ..Interest::default() };
+    let loan = vec![t("ann", "bob", 100_000, "2024-01-01")];
+    assert_eq!(charges(&monthly, &loan, "2024-03-15"), vec![c("ann", "bob", 33, "2024-01-31"), c("ann", "bob", 954, "2024-02-29"), c("ann", "bob", 498, "2024-03-15")]);
+    let mut friendly = monthly.clone();
+    friendly.people.insert("bob".into(), Terms { rate: Some(0.), ..Terms::default() });
+    assert_eq!(charges(&friendly, &loan, "2024-03-15"), vec![]);
+    assert_eq!(charges(&monthly, &[Transfer::new("ann".into(), "bob".into(), 100_000)], "2024-03-15"), vec![]);
+}
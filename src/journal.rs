@@ -0,0 +1,216 @@
+/*!
+Plaintext-accounting journals, as written for hledger or ledger-cli.
+
+```text
+2024-05-01 Dinner
+    people:ann      -30.00
+    people:bob       15.00
+    people:carol
+```
+
+Only accounts under a given prefix (eg. `people:`) are read, and each one is a person: the part of
+the name after the prefix, up to the next `:`.  A posting to a person's account is how much the
+transaction changes their balance, as in the long format: negative for whoever paid, positive for
+whoever benefited.  The postings to people in each transaction must sum to zero; transactions which
+also move money to or from other accounts (eg. `expenses:food`) are skipped, with a warning.
+
+One posting per transaction may leave out its amount, which balances the rest.  Commodity symbols
+are ignored, so all amounts are assumed to be in the same currency; thousands separators must be
+commas.  Each transfer gets the transaction's (primary) date.  Directives, periodic transactions, and comments are skipped.
+*/
+
+use date::Date;
+use ledger::{ReadOptions, Transfer};
+use plan::construct_plan;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use units::Money;
+
+/// Read a journal, and turn the postings between people in each of its transactions into
+/// transfers.  `exponent` is the number of decimal places in the currency (see
+/// `units::CurrencyFormat`).
+pub fn read<R: Read>(reader: R, account_prefix: &str, exponent: u32) -> Result<Vec<Transfer<String>>, String> {
+    let mut transfers = vec![];
+    let mut txn: Option<Txn> = None;
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let lineno = i + 1;
+        let content = strip_comment(&line);
+        if content.trim().is_empty() {
+            if line.trim().is_empty() { finish(txn.take(), &mut transfers)?; }
+            continue;
+        }
+        if line.starts_with(|c: char| c.is_whitespace()) {
+            // A posting, or a comment/tag continuation of the transaction
+            if let Some(ref mut txn) = txn {
+                txn.add_posting(content.trim(), lineno, account_prefix, exponent)?;
+            }
+            continue;
+        }
+        finish(txn.take(), &mut transfers)?;
+        if line.starts_with(|c: char| c.is_ascii_digit()) {
+            txn = Some(Txn::new(content, lineno)?);
+        }
+        // Anything else at the start of a line is a directive (`account`, `P`, `~`, ...)
+    }
+    finish(txn, &mut transfers)?;
+    Ok(transfers)
+}
+
+/// Some transfers as journal transactions, one each.  Undated ones are dated today, and those
+/// without a memo are described as "repay".  The id, category, and tags go in a comment.
+pub fn entries(transfers: &[Transfer<String>], account_prefix: &str, options: &ReadOptions) -> Vec<String> {
+    let mut lines = vec![];
+    for t in transfers {
+        let date = t.date.map_or_else(Date::today, |d| d.date);
+        let mut description = t.memo.clone().unwrap_or_else(|| "repay".into());
+        let notes: Vec<String> = t.id.iter().map(|x| format!("id: {}", x))
+            .chain(t.category.iter().map(|c| format!("category: {}", c)))
+            .chain(t.tags.iter().map(|x| format!("{}:", x)))
+            .collect();
+        if !notes.is_empty() { description = format!("{}  ; {}", description, notes.join(", ")); }
+        let amt = Money::new(t.amt, options.exponent(t.currency.as_deref()));
+        let currency = t.currency.as_ref().map_or(String::new(), |c| format!(" {}", c));
+        lines.push(String::new());
+        lines.push(format!("{} {}", date, description));
+        lines.push(format!("    {}{}    {}{}", account_prefix, t.to, amt, currency));
+        lines.push(format!("    {}{}", account_prefix, t.from));
+    }
+    lines
+}
+
+/// A transaction being read.
+struct Txn {
+    description: String,
+    date: Date,
+    line: usize,
+    /// The people posted to, and the totals.
+    shares: BTreeMap<String, isize>,
+    /// The total of all the amounts, including postings to other accounts.
+    total: isize,
+    /// A person whose posting has no amount.
+    elided: Option<String>,
+    /// Whether some posting to another account has no amount.
+    elided_other: bool,
+}
+
+impl Txn {
+    fn new(header: &str, line: usize) -> Result<Txn, String> {
+        // The primary date: eg. 2024-05-01, 2024/5/1, or 2024.05.01 (a secondary date follows an '=')
+        let word = header.split_whitespace().next().unwrap_or_default();
+        let primary = word.split('=').next().unwrap_or_default();
+        let parts: Vec<Option<u32>> = primary.split(['-', '/', '.']).map(|x| x.parse().ok()).collect();
+        let date = match parts[..] {
+            [Some(y), Some(m), Some(d)] if m <= 12 && d <= 31 => Date::new(y as i32, m as u8, d as u8),
+            _ => None,
+        }.ok_or_else(|| format!("Line {}: invalid date '{}' (expected eg. '2024-05-01')", line, primary))?;
+        // Skip the date, and any status mark or code
+        let description = header.split_whitespace().skip(1)
+            .skip_while(|w| *w == "*" || *w == "!" || w.starts_with('('))
+            .collect::<Vec<_>>().join(" ");
+        Ok(Txn { description, date, line, shares: BTreeMap::new(), total: 0, elided: None, elided_other: false })
+    }
+
+    fn add_posting(&mut self, posting: &str, line: usize, account_prefix: &str, exponent: u32) -> Result<(), String> {
+        // The account name ends at a tab or two spaces
+        let (account, amount) = match posting.find('\t').into_iter().chain(posting.find("  ")).min() {
+            Some(i) => (posting[..i].trim(), posting[i..].trim()),
+            None => (posting, ""),
+        };
+        let account = account.trim_matches(['(', ')', '[', ']']);
+        let person = match account.strip_prefix(account_prefix) {
+            Some(rest) => Some(rest.split(':').next().unwrap_or_default().to_string()).filter(|p| !p.is_empty()),
+            None => None,
+        };
+        if amount.is_empty() {
+            if self.elided.is_some() || self.elided_other {
+                return Err(format!("Line {}: only one posting per transaction can leave out its amount", line));
+            }
+            match person {
+                Some(person) => self.elided = Some(person),
+                None => self.elided_other = true,
+            }
+            return Ok(());
+        }
+        let amount = parse_amount(amount, exponent)
+            .ok_or_else(|| format!("Line {}: invalid amount '{}' (at most {} decimal places)", line, amount, exponent))?;
+        self.total += amount;
+        if let Some(person) = person {
+            *self.shares.entry(person).or_insert(0) += amount;
+        }
+        Ok(())
+    }
+}
+
+/// Turn a finished transaction into transfers.
+fn finish(txn: Option<Txn>, transfers: &mut Vec<Transfer<String>>) -> Result<(), String> {
+    let mut txn = match txn {
+        Some(txn) => txn,
+        None => return Ok(()),
+    };
+    if let Some(person) = txn.elided.take() {
+        *txn.shares.entry(person).or_insert(0) -= txn.total;
+    }
+    if txn.shares.values().all(|&x| x == 0) { return Ok(()); }
+    let total: isize = txn.shares.values().sum();
+    if total != 0 {
+        warn!("Line {}: skipping '{}', since its postings to people sum to {}, not zero",
+            txn.line, txn.description, total);
+        return Ok(());
+    }
+    // As in the long format, `construct_plan` cancels out the balances it's given, so give it the
+    // opposite of what we want
+    for mut t in construct_plan(txn.shares.into_iter().map(|(who, x)| (who, -x)).collect()) {
+        t.normalise();
+        if !txn.description.is_empty() { t.memo = Some(txn.description.clone()); }
+        t.date = Some(txn.date.into());
+        transfers.push(t);
+    }
+    Ok(())
+}
+
+/// Everything before a `;` comment.  (Lines starting with `#` or `*` are comments too.)
+fn strip_comment(line: &str) -> &str {
+    if line.starts_with('#') || line.starts_with('*') || line.starts_with('%') { return ""; }
+    match line.find(';') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+/// Eg. `$-1,234.5`, with `exponent` = 2, is -123450.  Commodity symbols are ignored, as is a price
+/// (`@ ...`) or balance assertion (`= ...`).
+fn parse_amount(s: &str, exponent: u32) -> Option<isize> {
+    let s = s.split(['@', '=']).next().unwrap_or_default();
+    let number: String = s.chars().filter(|&c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+').collect();
+    Money::parse_minor(&number, exponent).ok()
+}
+
+#[test]
+fn test_read() {
+    use ledger::balances;
+    let journal = "\
+; Shared expenses
+account people:ann
+
+2024-05-01 * Dinner  ; tag:x
+    people:ann          $-30.00
+    people:bob           $15.00
+    people:carol
+
+2024/05/02 Groceries
+    expenses:food         20
+    assets:cash:ann      -20
+
+2024-05-03 (42) Taxi
+    people:bob:travel   EUR 9
+    people:ann          EUR -9 @ 1.1 USD
+";
+    let transfers = read(journal.as_bytes(), "people:", 2).unwrap();
+    assert_eq!(balances(transfers.clone()).1, vec![("ann".into(), -3900), ("bob".into(), 2400), ("carol".into(), 1500)]);
+    assert_eq!(transfers[0].memo.as_deref(), Some("Dinner"));
+    assert_eq!(transfers.last().unwrap().date.unwrap().to_string(), "2024-05-03");
+    assert_eq!(parse_amount("-1,234.5 EUR", 2), Some(-123450));
+    assert!(read("2024-05-01 x\n  people:ann  1.234\n".as_bytes(), "people:", 2).unwrap_err().starts_with("Line 2"));
+    assert!(read("2024-02-30 x\n".as_bytes(), "people:", 2).unwrap_err().starts_with("Line 1"));
+}
@@ -0,0 +1,1548 @@
+/*!
+The ledger: a file of JSON-encoded `Transfer`s, usually one per line, optionally preceded by a
+`Header`.  A record can also be a shared expense (see `expense`), which is read as the transfers
+it implies; `{"reverses": "<id>"}`, which undoes an earlier transfer or expense with that id; or
+`{"assert": {"who": "bob", "balance": -4200}}`, which checks someone's balance so far; or a
+`Checkpoint` of everyone's balances.  Transfers can also be read from files in other formats (see
+`format`).
+
+A JSON ledger is read a record at a time (see `LedgerReader`), so reading one takes the same memory
+however long it is.  What's kept is everyone's balances (for assertions and checkpoints), and the
+transfers which are reversed later on: before a ledger is read, a quick pass over it finds which
+ids its reversals refer to.  (If it includes other ledgers, every transfer with an id is kept.)
+The other formats are small exports, and are read all at once.
+*/
+
+use accounts::Accounts;
+use date::{Date, Timestamp};
+use expense::Expense;
+use format::{detect, Format};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use locale::Locale;
+use plan::construct_plan;
+use serde::Serialize;
+use units::CurrencyFormat;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transfer<T> {
+    pub from: T,
+    pub to: T,
+    /// In the currency's minor units (eg. cents).  A JSON ledger may instead give it as a string in
+    /// major units (eg. `"12.34"`), which is read exactly; see `ReadOptions::exponent` and
+    /// `ReadOptions::locale`.
+    pub amt: isize,
+    /// Identifies a planned repayment, or a transfer in the ledger (so that it can be reversed).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Marks this transfer as the payment of the planned repayment with the given id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirms: Option<String>,
+    /// For a partial payment, the amount of the planned repayment it was made against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub planned: Option<isize>,
+    /// Marks this transfer as undoing the one with the given id (see `{"reverses": id}` records).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverses: Option<String>,
+    /// Which currency `amt` is in, eg. "EUR".  (If unset, the ledger's base currency; see `Header`.)
+    /// Balances in different currencies are kept apart, and settled separately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// What the money was for (eg. "food").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// What it was for, in words (eg. "dinner at Luigi's").  On a planned repayment, a summary of
+    /// the transfers it settles (see `memo`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Free-form labels (eg. "trip:alps"), for picking out a sub-ledger with `Filter::conditions`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// When it happened.  (Also accepted as `ts`.)
+    #[serde(default, alias = "ts", skip_serializing_if = "Option::is_none")]
+    pub date: Option<Timestamp>,
+    /// Which of the sender's and the recipient's accounts the money went through (eg. "paypal"; see
+    /// `accounts`).  They don't affect anyone's balance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_account: Option<String>,
+    /// Any other fields (eg. a receipt, or a note from the payer).  We don't look at them, but
+    /// they're kept when the transfer is written out again.
+    #[serde(flatten)]
+    pub extra: ::serde_json::Map<String, ::serde_json::Value>,
+}
+
+impl<T> Transfer<T> {
+    pub fn new(from: T, to: T, amt: isize) -> Transfer<T> {
+        Transfer { from, to, amt, currency: None, id: None, confirms: None, planned: None, reverses: None, category: None, memo: None, tags: vec![], date: None, from_account: None, to_account: None, extra: Default::default() }
+    }
+
+    pub fn normalise(&mut self) {
+        if self.amt < 0 {
+            ::std::mem::swap(&mut self.from, &mut self.to);
+            ::std::mem::swap(&mut self.from_account, &mut self.to_account);
+            self.amt = -self.amt;
+        }
+    }
+}
+
+impl Transfer<String> {
+    /// A short id derived from the sender, recipient, and amount.  A given ledger always produces
+    /// the same plan, so this is stable from one run to the next (as long as the ledger doesn't
+    /// change).
+    pub fn digest(&self) -> String {
+        let amt = self.amt.to_string();
+        let hash = fnv1a(&[self.from.as_bytes(), b"\0", self.to.as_bytes(), b"\0", amt.as_bytes()]);
+        format!("{:08x}", hash >> 32)
+    }
+}
+
+/// 64-bit FNV-1a of the concatenated chunks.
+pub(crate) fn fnv1a(chunks: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for bytes in chunks {
+        for &b in bytes.iter() {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Stream the records out of the ledger, each of which is either a transfer or the reason it
+/// couldn't be read (with where it is).  Whoever reads them decides what to do about the bad ones.
+///
+/// A JSON ledger can include another ledger with a record like `{"include": "2023.jsonl"}`.  The
+/// path is relative to the including ledger, and the included ledger's format is guessed.  Its
+/// transfers are read at that point, as if they'd been written there.
+pub fn records(path: &Path, format: Format, options: &ReadOptions) -> Records {
+    read_file(path, format, options.clone(), vec![], State::default())
+}
+
+/// Reads the records of some ledgers, one after another.  Each ledger is opened once the one
+/// before it is finished, and read as its records are needed.
+///
+/// ```no_run
+/// use repay::format::Format;
+/// use repay::ledger::{balances, LedgerReader, ReadOptions};
+/// use std::path::Path;
+///
+/// let reader = LedgerReader::new(&ReadOptions::default())
+///     .ledger(Path::new("2023.jsonl"), Format::Json)
+///     .ledger(Path::new("2024.jsonl"), Format::Json);
+/// let (n, balances) = balances(reader.map(Result::unwrap));
+/// ```
+pub struct LedgerReader {
+    ledgers: VecDeque<(PathBuf, Format)>,
+    options: ReadOptions,
+    current: Option<Records>,
+}
+
+impl LedgerReader {
+    pub fn new(options: &ReadOptions) -> LedgerReader {
+        LedgerReader { ledgers: VecDeque::new(), options: options.clone(), current: None }
+    }
+
+    /// Read this ledger after the others.  (Each ledger is separate: one can't reverse another's
+    /// transfers, for instance, unless it includes it.)
+    pub fn ledger(mut self, path: &Path, format: Format) -> LedgerReader {
+        self.ledgers.push_back((path.to_path_buf(), format));
+        self
+    }
+}
+
+impl Iterator for LedgerReader {
+    type Item = Result<Transfer<String>, ParseError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(x) = self.current.as_mut().and_then(Iterator::next) { return Some(x); }
+            let (path, format) = self.ledgers.pop_front()?;
+            self.current = Some(records(&path, format, &self.options));
+        }
+    }
+}
+
+/// Reads the records of some ledgers like `LedgerReader`, but reads ahead: up to `jobs` ledgers are
+/// read at once, each on its own thread.  Their records still come out in the same order, and each
+/// thread stops once it's `buffer` records ahead, so at most `jobs * buffer` records are waiting.
+#[cfg(feature = "parallel")]
+pub struct ReadAhead {
+    ledgers: VecDeque<(PathBuf, Format)>,
+    options: ReadOptions,
+    jobs: usize,
+    buffer: usize,
+    reading: VecDeque<::std::sync::mpsc::Receiver<Result<Transfer<String>, ParseError>>>,
+}
+
+#[cfg(feature = "parallel")]
+impl LedgerReader {
+    pub fn read_ahead(self, jobs: usize, buffer: usize) -> ReadAhead {
+        assert!(self.current.is_none(), "the reader has already started");
+        ReadAhead { ledgers: self.ledgers, options: self.options, jobs: jobs.max(1), buffer, reading: VecDeque::new() }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl Iterator for ReadAhead {
+    type Item = Result<Transfer<String>, ParseError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while self.reading.len() < self.jobs {
+                let (path, format) = match self.ledgers.pop_front() { Some(x) => x, None => break };
+                let (tx, rx) = ::std::sync::mpsc::sync_channel(self.buffer);
+                let options = self.options.clone();
+                ::std::thread::spawn(move || {
+                    for record in records(&path, format, &options) {
+                        // Nobody wants the rest
+                        if tx.send(record).is_err() { return; }
+                    }
+                });
+                self.reading.push_back(rx);
+            }
+            match self.reading.front()?.recv() {
+                Ok(x) => return Some(x),
+                // That ledger is finished
+                Err(_) => { self.reading.pop_front(); }
+            }
+        }
+    }
+}
+
+/// The records of a ledger.
+pub type Records = Box<dyn Iterator<Item=Result<Transfer<String>, ParseError>>>;
+
+/// Why a record couldn't be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// The ledger's path, or "-" for one which was already open.
+    pub source: String,
+    /// Where the problem is, counting from 1.  (Not all formats know.)
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The offending line.
+    pub text: Option<String>,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(source: &str, message: String) -> ParseError {
+        ParseError { source: source.into(), line: None, column: None, text: None, message }
+    }
+
+    /// For the formats which report errors as "Line N: ...".
+    fn from_message(source: &str, message: String) -> ParseError {
+        let line = message.strip_prefix("Line ")
+            .and_then(|rest| rest.find(": ").map(|i| (&rest[..i], &rest[i + 2..])))
+            .and_then(|(n, msg)| n.parse().ok().map(|n| (n, msg.to_string())));
+        match line {
+            Some((n, message)) => ParseError { line: Some(n), ..ParseError::new(source, message) },
+            None => ParseError::new(source, message),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)?;
+        if let Some(line) = self.line { write!(f, ":{}", line)?; }
+        if let Some(column) = self.column { write!(f, ":{}", column)?; }
+        write!(f, ": {}", self.message)?;
+        if let Some(ref text) = self.text { write!(f, "\n    {}", text)?; }
+        Ok(())
+    }
+}
+
+/// `stack` is the chain of ledgers which included this one.
+fn read_file(path: &Path, format: Format, options: ReadOptions, mut stack: Vec<PathBuf>, seen: State) -> Records {
+    let source = path.display().to_string();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Box::new(::std::iter::once(Err(ParseError::new(&source, e.to_string())))),
+    };
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+        return Box::new(::std::iter::once(Err(ParseError::new(&source, "the ledger includes itself".into()))));
+    }
+    stack.push(canonical);
+    let dir = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+    // Everything before the last checkpoint is redundant, so don't bother reading it
+    let skip_before = match format {
+        Format::Json => match scan(&file).and_then(|scan| (&file).seek(SeekFrom::Start(0)).map(|_| scan)) {
+            Ok(scan) => {
+                if stack.len() == 1 { seen.borrow_mut().wanted = scan.reversed; }
+                scan.last_checkpoint
+            }
+            Err(e) => return Box::new(::std::iter::once(Err(ParseError::new(&source, e.to_string())))),
+        },
+        // Only JSON ledgers have reversals
+        _ if stack.len() == 1 => { seen.borrow_mut().wanted = Some(HashSet::new()); 0 }
+        _ => 0,
+    };
+    read(file, source, format, options, Context { dir, stack, seen, skip_before })
+}
+
+/// What a quick look through a JSON ledger finds, before it's read properly.
+struct Scan {
+    /// The line of the last checkpoint record (or 0, if there isn't one).
+    last_checkpoint: usize,
+    /// The ids of the transfers which it reverses, unless it's hard to tell: because it includes
+    /// other ledgers (which might reverse anything), or has a reversal spanning several lines.
+    reversed: Option<HashSet<String>>,
+}
+
+/// Look through a JSON ledger for checkpoints, reversals, and includes.  Only the lines which
+/// might be one of those are parsed.
+fn scan(file: &File) -> io::Result<Scan> {
+    let mut scan = Scan { last_checkpoint: 0, reversed: Some(HashSet::new()) };
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let has = |field: &str| line.contains(&format!("\"{}\"", field));
+        let (checkpoint, reversal) = (has("checkpoint"), has("reverses") || has("include"));
+        if !checkpoint && !reversal { continue; }
+        let record = Some(&line).filter(|l| l.trim_start().starts_with('{'))
+            .and_then(|l| ::serde_json::from_str::<::serde_json::Value>(l).ok());
+        let record = match record {
+            Some(x) => x,
+            None => {
+                if reversal { scan.reversed = None; }
+                continue;
+            }
+        };
+        if record.get("checkpoint").is_some() { scan.last_checkpoint = i + 1; }
+        if record.get("include").is_some() { scan.reversed = None; }
+        if let (Some(id), Some(reversed)) = (record.get("reverses").and_then(|x| x.as_str()), scan.reversed.as_mut()) {
+            reversed.insert(id.to_string());
+        }
+    }
+    Ok(scan)
+}
+
+/// The (optional) first record of a JSON ledger, which says which dialect it's written in, eg.
+/// `{"repay_version": 2, "base_currency": "EUR", "aliases": {"Bob": ["Robert S"]}}`.  Other fields
+/// are ignored, so that newer versions can add some without breaking older readers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub repay_version: u32,
+    /// The currency which the ledger's amounts are in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_currency: Option<String>,
+    /// Other names for the people in the ledger (see `names`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, Vec<String>>,
+    /// The people's payment accounts (see `accounts`).
+    #[serde(default, skip_serializing_if = "Accounts::is_empty")]
+    pub accounts: Accounts,
+    /// How the ledger's currencies are written, eg. `{"JPY": {"exponent": 0, "symbol": "¥"}}`
+    /// (see `units::CurrencyFormat`).  These replace the config's.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub currencies: BTreeMap<String, CurrencyFormat>,
+}
+
+/// The newest ledger version we can read.  A ledger without a header is version 1, which is the
+/// same dialect as version 2.
+pub const LEDGER_VERSION: u32 = 2;
+
+/// The header of a JSON ledger, if it has one.
+pub fn header(path: &Path) -> Result<Option<Header>, ParseError> {
+    let source = path.display().to_string();
+    let file = File::open(path).map_err(|e| ParseError::new(&source, e.to_string()))?;
+    let reader = skip_bom(BufReader::new(file)).map_err(|e| ParseError::new(&source, e.to_string()))?;
+    match JsonRecords::new(reader, source.clone()).next() {
+        Some(Ok((line, ref x))) if x.get("repay_version").is_some() => ::serde_json::from_value(x.clone())
+            .map(Some)
+            .map_err(|e| ParseError { line: Some(line), ..ParseError::new(&source, e.to_string()) }),
+        Some(Err(e)) => Err(e),
+        _ => Ok(None),
+    }
+}
+
+/// Options which only matter for some formats.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// The columns of a CSV ledger.  (If unset, the file's header row names them.)
+    pub csv_columns: Option<Vec<String>>,
+    /// In a journal, the accounts under this prefix are people's.  (Required for journals.)
+    pub account_prefix: Option<String>,
+    /// How many decimal places amounts written in major units have (see `units::CurrencyFormat`),
+    /// unless their currency is in `exponents`.  (Journals are always in major units.)
+    pub exponent: u32,
+    pub exponents: BTreeMap<String, u32>,
+    /// How amounts in major units are written.
+    pub locale: Locale,
+    /// What to do with transfers from someone to themselves, or of nothing.
+    pub validation: Validation,
+}
+
+/// What to do with a transfer which doesn't do anything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Validation {
+    /// It's a bad record (`--strict`).
+    Strict,
+    /// Drop it, with a warning.
+    #[default]
+    Warn,
+    /// Drop it quietly (`--lenient`).
+    Lenient,
+    /// Keep it (for `repay lint`, which reports them).
+    Keep,
+}
+
+/// Drop the transfers which don't do anything, or complain about them.
+fn validate(transfers: Vec<Transfer<String>>, validation: Validation, at: &str) -> Result<Vec<Transfer<String>>, String> {
+    let mut valid = Vec::with_capacity(transfers.len());
+    for t in transfers {
+        let why = if t.from == t.to {
+            format!("from {} to themselves", t.from)
+        } else if t.amt == 0 {
+            format!("of 0 from {} to {}", t.from, t.to)
+        } else {
+            valid.push(t);
+            continue;
+        };
+        match validation {
+            Validation::Strict => return Err(format!("A transfer {} doesn't do anything  (Use '--lenient' to drop transfers like this)", why)),
+            Validation::Warn => warn!("{}: Dropping a transfer {}", at, why),
+            Validation::Lenient => debug!("{}: Dropping a transfer {}", at, why),
+            Validation::Keep => valid.push(t),
+        }
+    }
+    Ok(valid)
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            csv_columns: None,
+            account_prefix: None,
+            exponent: CurrencyFormat::default().exponent,
+            exponents: BTreeMap::new(),
+            locale: Locale::default(),
+            validation: Validation::default(),
+        }
+    }
+}
+
+impl ReadOptions {
+    /// The number of decimal places in a currency.
+    pub fn exponent(&self, currency: Option<&str>) -> u32 {
+        currency.and_then(|c| self.exponents.get(c)).cloned().unwrap_or(self.exponent)
+    }
+}
+
+/// Like `records`, for a ledger which has already been opened.  A leading byte order mark is
+/// skipped, and lines may end with either LF or CRLF.  Included ledgers are relative to the current
+/// directory.
+pub fn read_records<R: Read + 'static>(reader: R, format: Format, options: &ReadOptions) -> Records {
+    read(reader, "-".into(), format, options.clone(), Context { dir: PathBuf::new(), stack: vec![], seen: State::default(), skip_before: 0 })
+}
+
+/// What's been read so far (in this ledger, or the ones it includes and which came before): the
+/// transfers with ids, so that they can be reversed, and everyone's balances, so that they can be
+/// checked.
+type State = Rc<RefCell<Seen>>;
+
+#[derive(Default)]
+struct Seen {
+    transfers: HashMap<String, Vec<Transfer<String>>>,
+    /// The ids which might be reversed, if we know.  Only the transfers with these ids are kept in
+    /// `transfers`, so that a ledger whose transfers all have ids can be read in constant memory.
+    wanted: Option<HashSet<String>>,
+    reversed: HashSet<String>,
+    /// Each person's balance in each currency, and the line of the last record which changed it.
+    balances: HashMap<(String, Option<String>), (isize, Option<usize>)>,
+}
+
+/// A record like `{"assert": {"who": "bob", "balance": -4200}}`, which checks that someone's
+/// balance is what it should be, given the records before it.  As in a plan, the balance is
+/// positive if they owe money.  `currency` picks which of their balances to check, and `date` is
+/// just for the reader.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Assertion {
+    #[serde(rename = "assert")]
+    expected: Balance,
+    #[serde(default, alias = "ts")]
+    #[allow(dead_code)]
+    date: Option<Timestamp>,
+}
+
+/// Someone's balance in some currency.  (Positive if they owe money.)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Balance {
+    pub who: String,
+    pub balance: isize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+}
+
+/// A record of everyone's balances at some point, like `{"checkpoint": [{"who": "ann", "balance":
+/// -4200}, {"who": "bob", "balance": 4200}], "date": "2024-06-01"}`.  Everyone's balances are
+/// set to these (and anyone not listed has nothing to settle), so everything before the last
+/// checkpoint can be archived, and isn't even read.  Transfers from before a checkpoint can't be
+/// reversed after it.
+///
+/// A checkpoint is read as the transfers which take everyone's balances from what they were to
+/// what it says.  The balances are of everything in the ledger (and the ledgers it includes),
+/// except for the transfers in excluded categories, which never count; filters like `--since` or
+/// `--filter` can't see through a checkpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checkpoint {
+    pub checkpoint: Vec<Balance>,
+    #[serde(default, alias = "ts", skip_serializing_if = "Option::is_none")]
+    pub date: Option<Timestamp>,
+}
+
+impl Checkpoint {
+    /// A checkpoint of some balances.
+    pub fn new(balances: &CurrencyBalances, date: Option<Timestamp>) -> Checkpoint {
+        let checkpoint = balances.iter()
+            .flat_map(|(ccy, xs)| xs.iter().map(move |(who, x)| Balance { who: who.clone(), balance: *x, currency: ccy.clone() }))
+            .collect();
+        Checkpoint { checkpoint, date }
+    }
+}
+
+/// A record like `{"reverses": "<id>"}`, which undoes an earlier transfer (or all the transfers
+/// which came from an expense) with that id.  It may have a date of its own, and a memo saying why.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Reversal {
+    reverses: String,
+    #[serde(default)]
+    memo: Option<String>,
+    #[serde(default, alias = "ts")]
+    date: Option<Timestamp>,
+}
+
+impl Seen {
+    fn remember(&mut self, transfers: &[Transfer<String>], line: Option<usize>) {
+        for t in transfers {
+            if let Some(ref id) = t.id {
+                if self.wanted.as_ref().is_none_or(|wanted| wanted.contains(id)) {
+                    self.transfers.entry(id.clone()).or_default().push(t.clone());
+                }
+            }
+            for (who, amt) in [(&t.from, -t.amt), (&t.to, t.amt)] {
+                let x = self.balances.entry((who.clone(), t.currency.clone())).or_insert((0, None));
+                *x = (x.0 + amt, line);
+            }
+        }
+    }
+
+    fn check(&self, assertion: Assertion) -> Result<(), String> {
+        let e = assertion.expected;
+        let (balance, line) = self.balances.get(&(e.who.clone(), e.currency.clone())).cloned().unwrap_or((0, None));
+        if balance == e.balance { return Ok(()); }
+        let diff = balance - e.balance;
+        let mut msg = format!("Balance assertion failed: {}'s balance is {}, not {} ({} {} than asserted)",
+            e.who, balance, e.balance, diff.abs(), if diff > 0 { "more" } else { "less" });
+        if let Some(ref currency) = e.currency { msg += &format!(" in {}", currency); }
+        match line {
+            Some(line) => msg += &format!(".  It was last changed on line {}", line),
+            None if balance == 0 => msg += &format!(".  Nothing before this involves {}", e.who),
+            None => (),
+        }
+        Err(msg)
+    }
+
+    /// The transfers which take everyone's balances to the checkpoint's.
+    fn restore(&mut self, checkpoint: Checkpoint) -> Result<Vec<Transfer<String>>, String> {
+        let mut deltas: BTreeMap<Option<String>, BTreeMap<String, isize>> = BTreeMap::new();
+        for ((who, ccy), &(x, _)) in &self.balances {
+            *deltas.entry(ccy.clone()).or_default().entry(who.clone()).or_insert(0) -= x;
+        }
+        for b in &checkpoint.checkpoint {
+            *deltas.entry(b.currency.clone()).or_default().entry(b.who.clone()).or_insert(0) += b.balance;
+        }
+        let mut totals: BTreeMap<&Option<String>, isize> = BTreeMap::new();
+        for b in &checkpoint.checkpoint { *totals.entry(&b.currency).or_insert(0) += b.balance; }
+        if let Some((ccy, total)) = totals.into_iter().find(|&(_, x)| x != 0) {
+            let ccy = ccy.as_ref().map_or(String::new(), |c| format!(" in {}", c));
+            return Err(format!("The checkpoint's balances{} add up to {}, not zero", ccy, total));
+        }
+        self.transfers.clear();
+        self.reversed.clear();
+        let mut transfers = vec![];
+        for (ccy, deltas) in deltas {
+            // `construct_plan` cancels out the balances it's given, so give it the opposite
+            for mut t in construct_plan(deltas.into_iter().filter(|&(_, x)| x != 0).map(|(who, x)| (who, -x)).collect()) {
+                t.normalise();
+                transfers.push(Transfer { currency: ccy.clone(), memo: Some("checkpoint".into()), date: checkpoint.date, ..t });
+            }
+        }
+        Ok(transfers)
+    }
+
+    /// The transfers which undo the reversed ones: the same, but the other way round.
+    fn reverse(&mut self, reversal: Reversal) -> Result<Vec<Transfer<String>>, String> {
+        let id = reversal.reverses.clone();
+        let originals = self.transfers.get(&id).ok_or_else(|| format!("There's no earlier transfer with id {}", id))?;
+        if !self.reversed.insert(id.clone()) {
+            return Err(format!("Transfer {} has already been reversed", id));
+        }
+        Ok(originals.iter().map(|t| Transfer {
+            from: t.to.clone(),
+            to: t.from.clone(),
+            from_account: t.to_account.clone(),
+            to_account: t.from_account.clone(),
+            id: None,
+            confirms: None,
+            planned: None,
+            memo: reversal.memo.clone().or_else(|| t.memo.clone()),
+            date: reversal.date.or(t.date),
+            reverses: Some(id.clone()),
+            ..t.clone()
+        }).collect())
+    }
+}
+
+/// Where a ledger is being read from.
+struct Context {
+    /// Where included ledgers are found.
+    dir: PathBuf,
+    /// The chain of ledgers which included this one.
+    stack: Vec<PathBuf>,
+    seen: State,
+    /// In a JSON ledger, skip the records before this line (except the header).
+    skip_before: usize,
+}
+
+fn read<R: Read + 'static>(reader: R, source: String, format: Format, options: ReadOptions, cx: Context) -> Records {
+    let Context { dir, stack, seen, skip_before } = cx;
+    let reader = match skip_bom(BufReader::new(reader)) {
+        Ok(reader) => reader,
+        Err(e) => return Box::new(::std::iter::once(Err(ParseError::new(&source, e.to_string())))),
+    };
+    let all = |x: Result<Vec<Transfer<String>>, String>, source: &str| -> Records {
+        match x {
+            Ok(transfers) => {
+                seen.borrow_mut().remember(&transfers, None);
+                match validate(transfers, options.validation, source) {
+                    Ok(transfers) => Box::new(transfers.into_iter().map(Ok)),
+                    Err(e) => Box::new(::std::iter::once(Err(ParseError::new(source, e)))),
+                }
+            }
+            Err(e) => Box::new(::std::iter::once(Err(ParseError::from_message(source, e)))),
+        }
+    };
+    match format {
+        Format::Json => Box::new(JsonRecords::new(reader, source.clone()).skip_before(skip_before).enumerate().flat_map(move |(i, x)| -> Records {
+            let (line, mut x) = match x {
+                Ok(x) => x,
+                Err(e) => return Box::new(::std::iter::once(Err(e))),
+            };
+            // The hash chain is only for `chain::verify`
+            if let Some(x) = x.as_object_mut() { x.remove(::chain::FIELD); }
+            let bad = |message: String| ParseError {
+                line: Some(line), text: Some(x.to_string()), ..ParseError::new(&source, message)
+            };
+            if x.get("repay_version").is_some() {
+                let header = match ::serde_json::from_value::<Header>(x.clone()) {
+                    _ if i > 0 => Err(bad("The header (with 'repay_version') must be the first record".into())),
+                    Ok(h) if h.repay_version > LEDGER_VERSION => Err(bad(format!(
+                        "This ledger is version {}, but this version of repay can only read up to version {}",
+                        h.repay_version, LEDGER_VERSION))),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(bad(e.to_string())),
+                };
+                return Box::new(header.err().map(Err).into_iter());
+            }
+            match x.get("include") {
+                Some(::serde_json::Value::String(include)) => {
+                    let path = dir.join(include);
+                    match detect(&path) {
+                        Ok(detection) => read_file(&path, detection.format, options.clone(), stack.clone(), seen.clone()),
+                        Err(e) => Box::new(::std::iter::once(Err(bad(format!("{}: {}", path.display(), e))))),
+                    }
+                }
+                Some(_) => Box::new(::std::iter::once(Err(bad("'include' should be a path".into())))),
+                None => {
+                    let transfers = if x.get("payer").is_some() || x.get("payers").is_some() {
+                        minor_units(x.clone(), &options)
+                            .and_then(|x| ::serde_json::from_value::<Expense>(x).map_err(|e| e.to_string()))
+                            .and_then(|x| x.transfers())
+                    } else if x.get("reverses").is_some() {
+                        ::serde_json::from_value::<Reversal>(x.clone()).map_err(|e| e.to_string())
+                            .and_then(|x| seen.borrow_mut().reverse(x))
+                    } else if x.get("checkpoint").is_some() {
+                        ::serde_json::from_value::<Checkpoint>(x.clone()).map_err(|e| e.to_string())
+                            .and_then(|x| seen.borrow_mut().restore(x))
+                    } else if x.get("assert").is_some() {
+                        let checked = minor_units(x.clone(), &options)
+                            .and_then(|x| ::serde_json::from_value::<Assertion>(x).map_err(|e| e.to_string()))
+                            .and_then(|x| seen.borrow().check(x));
+                        return Box::new(checked.err().map(|e| Err(bad(e))).into_iter());
+                    } else {
+                        minor_units(x.clone(), &options)
+                            .and_then(|x| ::serde_json::from_value(x).map_err(|e| e.to_string()))
+                            .map(|t| vec![t])
+                    };
+                    match transfers {
+                        Ok(transfers) => {
+                            seen.borrow_mut().remember(&transfers, Some(line));
+                            match validate(transfers, options.validation, &format!("{}:{}", source, line)) {
+                                Ok(transfers) => Box::new(transfers.into_iter().map(Ok)),
+                                Err(e) => Box::new(::std::iter::once(Err(bad(e)))),
+                            }
+                        }
+                        Err(e) => Box::new(::std::iter::once(Err(bad(e)))),
+                    }
+                }
+            }
+        })),
+        Format::Long => all(::long::read(reader), &source),
+        Format::Csv => all(::sheet::read(reader, &options), &source),
+        Format::Journal => match options.account_prefix {
+            Some(ref prefix) => all(::journal::read(reader, prefix, options.exponent), &source),
+            None => all(Err("Reading a journal needs an account prefix (eg. '--account-prefix=people:')".into()), &source),
+        },
+        Format::Tricount => all(::apps::read_tricount(reader, &options), &source),
+        Format::SettleUp => all(::apps::read_settle_up(reader, &options), &source),
+    }
+}
+
+/// Convert a JSON transfer's `amt`, if it's a decimal string, into minor units.  (Likewise the
+/// amounts of a shared expense's payers and participants, and an asserted balance.)
+fn minor_units(mut x: ::serde_json::Value, options: &ReadOptions) -> Result<::serde_json::Value, String> {
+    let currency = x.get("currency").or_else(|| x.get("assert").and_then(|a| a.get("currency")));
+    let exponent = options.exponent(currency.and_then(|c| c.as_str()));
+    let convert = |amt: &mut ::serde_json::Value| -> Result<(), String> {
+        if let ::serde_json::Value::String(s) = amt {
+            *amt = options.locale.parse_minor(s, exponent)?.into();
+        }
+        Ok(())
+    };
+    if let Some(amt) = x.get_mut("amt") { convert(amt)?; }
+    if let Some(balance) = x.get_mut("assert").and_then(|a| a.get_mut("balance")) { convert(balance)?; }
+    for list in &["payers", "participants"] {
+        if let Some(::serde_json::Value::Array(people)) = x.get_mut(*list) {
+            for amt in people.iter_mut().filter_map(|p| p.get_mut("amt")) { convert(amt)?; }
+        }
+    }
+    Ok(x)
+}
+
+/// The JSON values in a ledger, and the lines they start on.
+pub(crate) fn json_values<R: BufRead>(reader: R, source: &str) -> impl Iterator<Item=Result<(usize, ::serde_json::Value), ParseError>> {
+    JsonRecords::new(reader, source.to_string())
+}
+
+/// The JSON values in a file, and the lines they start on.  Values are usually one per line, but
+/// can span several; an unfinished value followed by a line starting with `{` is an error (rather
+/// than swallowing the rest of the file).
+struct JsonRecords<R> {
+    lines: ::std::iter::Enumerate<io::Lines<R>>,
+    source: String,
+    queue: ::std::collections::VecDeque<(usize, ::serde_json::Value)>,
+    /// An unfinished value, and the line it started on.
+    chunk: String,
+    start: usize,
+    /// A line which ended the previous chunk, and which starts the next.
+    held: Option<(usize, String)>,
+    /// Skip the lines before this one, apart from the first (which might be a header).
+    skip_before: usize,
+    first: bool,
+}
+
+impl<R: BufRead> JsonRecords<R> {
+    fn new(reader: R, source: String) -> JsonRecords<R> {
+        JsonRecords { lines: reader.lines().enumerate(), source, queue: Default::default(), chunk: String::new(), start: 0, held: None, skip_before: 0, first: true }
+    }
+
+    fn skip_before(self, line: usize) -> JsonRecords<R> {
+        JsonRecords { skip_before: line, ..self }
+    }
+
+    /// Give up on the current chunk.
+    fn bad_chunk(&mut self, e: &::serde_json::Error) -> ParseError {
+        let mut message = e.to_string();
+        if let Some(i) = message.rfind(" at line ") { message.truncate(i); }
+        // An unfinished value is reported where it starts
+        let (offset, column) = if e.is_eof() { (0, None) } else { (e.line().max(1) - 1, Some(e.column())) };
+        let text = self.chunk.lines().nth(offset).map(|l| l.trim_end().to_string());
+        self.chunk.clear();
+        ParseError { line: Some(self.start + offset), column, text, ..ParseError::new(&self.source, message) }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonRecords<R> {
+    type Item = Result<(usize, ::serde_json::Value), ParseError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(x) = self.queue.pop_front() { return Some(Ok(x)); }
+            let (i, line) = match self.held.take().map(Ok).or_else(|| self.lines.next().map(|(i, l)| l.map(|l| (i, l)))) {
+                Some(Ok(x)) => x,
+                Some(Err(e)) => return Some(Err(ParseError::new(&self.source, e.to_string()))),
+                None if self.chunk.trim().is_empty() => return None,
+                None => {
+                    let e = ::serde_json::from_str::<::serde_json::Value>(&self.chunk).unwrap_err();
+                    return Some(Err(self.bad_chunk(&e)));
+                }
+            };
+            if self.chunk.is_empty() {
+                if line.trim().is_empty() { continue; }
+                if i + 1 < self.skip_before && !(self.first && line.contains("repay_version")) {
+                    self.first = false;
+                    continue;
+                }
+                self.first = false;
+                self.start = i + 1;
+            } else if line.starts_with('{') {
+                // The previous value was never finished
+                let e = ::serde_json::from_str::<::serde_json::Value>(&self.chunk).unwrap_err();
+                self.held = Some((i, line));
+                return Some(Err(self.bad_chunk(&e)));
+            }
+            self.chunk.push_str(&line);
+            self.chunk.push('\n');
+            // Wait for the rest of an unfinished value
+            let mut values = vec![];
+            let mut unfinished = false;
+            for x in ::serde_json::Deserializer::from_str(&self.chunk).into_iter() {
+                match x {
+                    Ok(x) => values.push((self.start, x)),
+                    Err(ref e) if e.is_eof() => { unfinished = true; break; }
+                    Err(e) => return Some(Err(self.bad_chunk(&e))),
+                }
+            }
+            if !unfinished {
+                self.chunk.clear();
+                self.queue.extend(values);
+            }
+        }
+    }
+}
+
+/// The files matching a pattern, in order.  The last component of the pattern may contain `*` (any
+/// characters) and `?` (any one character); if it doesn't, the pattern is returned as-is.  (This
+/// is for shells which don't expand globs themselves.)
+pub fn glob(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let name = match pattern.file_name().and_then(|x| x.to_str()) {
+        Some(name) if name.contains(['*', '?']) => name,
+        _ => return Ok(vec![pattern.to_path_buf()]),
+    };
+    let dir = match pattern.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let mut paths = vec![];
+    for entry in ::std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_str().is_some_and(|x| wildcard_match(name.as_bytes(), x.as_bytes())) {
+            paths.push(pattern.with_file_name(entry.file_name()));
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => wildcard_match(rest, name) || (!name.is_empty() && wildcard_match(pattern, &name[1..])),
+        (Some((b'?', rest)), Some((_, name))) => wildcard_match(rest, name),
+        (Some((p, rest)), Some((c, name))) => p == c && wildcard_match(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+/// Skip a UTF-8 byte order mark, if there is one.  (Plenty of Windows programs write them.)
+fn skip_bom<R: BufRead>(mut reader: R) -> io::Result<R> {
+    if reader.fill_buf()?.starts_with(b"\xef\xbb\xbf") { reader.consume(3); }
+    Ok(reader)
+}
+
+/// Which transfers count towards the balances.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Transfers in these categories are kept in the ledger for the record, but are never settled.
+    pub exclude_categories: Vec<String>,
+    /// Only count transfers dated on or after this day.
+    pub since: Option<Date>,
+    /// Only count transfers dated on or before this day.
+    pub until: Option<Date>,
+    /// Only count transfers which meet all of these.
+    pub conditions: Vec<Condition>,
+}
+
+/// Something a transfer can be required to have, written like `tag=trip:alps`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Tag(String),
+    Category(String),
+    /// Both the sender and the recipient are in this group (see `--group`).
+    Members(BTreeSet<String>),
+}
+
+impl Condition {
+    pub fn accepts(&self, transfer: &Transfer<String>) -> bool {
+        match *self {
+            Condition::Tag(ref tag) => transfer.tags.contains(tag),
+            Condition::Category(ref cat) => transfer.category.as_ref() == Some(cat),
+            Condition::Members(ref xs) => xs.contains(&transfer.from) && xs.contains(&transfer.to),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Condition, String> {
+        match s.find('=').map(|i| (&s[..i], &s[i + 1..])) {
+            Some(("tag", x)) if !x.is_empty() => Ok(Condition::Tag(x.into())),
+            Some(("category", x)) if !x.is_empty() => Ok(Condition::Category(x.into())),
+            _ => Err(format!("Invalid filter '{}' (expected 'tag=TAG' or 'category=CATEGORY')", s)),
+        }
+    }
+}
+
+impl Filter {
+    /// Undated transfers are always inside the date window (if there is one).
+    pub fn accepts(&self, transfer: &Transfer<String>) -> bool {
+        let date = transfer.date.map(|d| d.date);
+        !transfer.category.as_ref().is_some_and(|c| self.exclude_categories.contains(c))
+            && self.since.is_none_or(|since| date.is_none_or(|d| d >= since))
+            && self.until.is_none_or(|until| date.is_none_or(|d| d <= until))
+            && self.conditions.iter().all(|c| c.accepts(transfer))
+    }
+
+    /// Whether it only counts transfers in some date window.
+    pub fn has_window(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+}
+
+/// Compute everyone's balances (starting from 0).  Returns the number of transfers, and the
+/// non-zero balances.
+pub fn balances<I: IntoIterator<Item=Transfer<String>>>(transfers: I) -> (usize, Vec<(String, isize)>) {
+    let mut n = 0;
+    let mut balances = BTreeMap::new();
+    for transfer in transfers {
+        *balances.entry(transfer.from).or_insert(0) -= transfer.amt;
+        *balances.entry(transfer.to).or_insert(0) += transfer.amt;
+        n += 1;
+    }
+    (n, balances.into_iter().filter(|&(_,x)| x != 0).collect())
+}
+
+/// Everyone's non-zero balances, in each currency.  (`None` is the ledger's base currency.)
+pub type CurrencyBalances = BTreeMap<Option<String>, Vec<(String, isize)>>;
+
+/// Like `balances`, but with separate balances for each currency.  Returns the number of
+/// transfers, and the balances.
+pub fn balances_by_currency<I>(transfers: I) -> (usize, CurrencyBalances)
+    where I: IntoIterator<Item=Transfer<String>>
+{
+    balances_by(transfers, |t| Some(t.currency.clone()))
+}
+
+/// Like `balances`, but with separate balances for each key.  A transfer counts towards every key
+/// which `keys` gives it, so it can count more than once, or not at all.  Returns the number of
+/// transfers (whether they counted or not), and the balances.
+pub fn balances_by<K, I, F, J>(transfers: I, mut keys: F) -> (usize, BTreeMap<K, Vec<(String, isize)>>)
+    where K: Ord, I: IntoIterator<Item=Transfer<String>>, F: FnMut(&Transfer<String>) -> J, J: IntoIterator<Item=K>
+{
+    let mut n = 0;
+    let mut balances: BTreeMap<K, BTreeMap<String, isize>> = BTreeMap::new();
+    for transfer in transfers {
+        for key in keys(&transfer) {
+            let xs = balances.entry(key).or_default();
+            *xs.entry(transfer.from.clone()).or_insert(0) -= transfer.amt;
+            *xs.entry(transfer.to.clone()).or_insert(0) += transfer.amt;
+        }
+        n += 1;
+    }
+    let balances = balances.into_iter()
+        .map(|(key, xs)| (key, xs.into_iter().filter(|&(_,x)| x != 0).collect::<Vec<_>>()))
+        .filter(|(_, xs)| !xs.is_empty())
+        .collect();
+    (n, balances)
+}
+
+/// Append some transfers to the end of the ledger, one per line.  The ledger is created if it
+/// doesn't exist.  If the ledger's lines end in CRLF, so will the new ones.
+pub fn append(path: &Path, transfers: &[Transfer<String>]) -> io::Result<()> {
+    append_records(path, transfers)
+}
+
+/// Like `append`, for any kind of record (eg. a `Checkpoint`).
+/// If the ledger is chained (see `chain`), so are the new records.
+pub fn append_records<T: Serialize>(path: &Path, records: &[T]) -> io::Result<()> {
+    let mut values = vec![];
+    for t in records { values.push(::serde_json::to_value(t)?); }
+    append_lines(path, |existing| {
+        let mut head = ::chain::head(existing);
+        Ok(values.into_iter().map(|mut x| {
+            if let Some(ref prev) = head {
+                let hash = ::chain::link(Some(prev), &x);
+                if let Some(x) = x.as_object_mut() { x.insert(::chain::FIELD.into(), hash.clone().into()); }
+                head = Some(hash);
+            }
+            x.to_string()
+        }).collect())
+    })
+}
+
+/// Like `append`, for a ledger in any format: as JSON records, CSV rows (with the ledger's columns),
+/// rows of the long format, or journal transactions.  It's an error if the format has nowhere to
+/// put something about one of them (eg. a memo, in the long format).
+pub fn append_as(path: &Path, format: Format, transfers: &[Transfer<String>], options: &ReadOptions) -> Result<(), String> {
+    if format == Format::Json { return append(path, transfers).map_err(|e| e.to_string()); }
+    let existing = if path.exists() { ::std::fs::read_to_string(path).map_err(|e| e.to_string())? } else { String::new() };
+    let lines = render(format, transfers, &existing, options)?;
+    append_lines(path, |_| Ok(lines)).map_err(|e| e.to_string())
+}
+
+/// The lines to add to a ledger in the given format, whose contents so far are `existing`, for
+/// these transfers (see `append_as`).  A new CSV ledger starts with a header.
+pub fn render(format: Format, transfers: &[Transfer<String>], existing: &str, options: &ReadOptions) -> Result<Vec<String>, String> {
+    match format {
+        Format::Json => transfers.iter().map(|t| ::serde_json::to_string(t).map_err(|e| e.to_string())).collect(),
+        Format::Csv => ::sheet::rows(transfers, existing, options),
+        Format::Long => ::long::rows(transfers),
+        Format::Journal => match options.account_prefix {
+            Some(ref prefix) => {
+                let mut lines = ::journal::entries(transfers, prefix, options);
+                // Each transaction comes after a blank line, except at the start of the file
+                if existing.trim().is_empty() && !lines.is_empty() { lines.remove(0); }
+                Ok(lines)
+            }
+            None => Err("Writing a journal needs an account prefix (eg. '--account-prefix=people:')".into()),
+        },
+        Format::Tricount => Err("Tricount exports can only be read, not written to".into()),
+        Format::SettleUp => Err("Settle Up exports can only be read, not written to".into()),
+    }
+}
+
+/// The first field which `t` has, other than `from`, `to`, and `amt`, and which isn't in `fields`.
+pub(crate) fn unwritable(t: &Transfer<String>, fields: &[&str]) -> Option<String> {
+    let has = |field: &str| match field {
+        "id" => t.id.is_some(),
+        "confirms" => t.confirms.is_some(),
+        "planned" => t.planned.is_some(),
+        "reverses" => t.reverses.is_some(),
+        "currency" => t.currency.is_some(),
+        "category" => t.category.is_some(),
+        "memo" => t.memo.is_some(),
+        "tags" => !t.tags.is_empty(),
+        "date" => t.date.is_some(),
+        "from_account" => t.from_account.is_some(),
+        "to_account" => t.to_account.is_some(),
+        _ => false,
+    };
+    ["id", "confirms", "planned", "reverses", "currency", "category", "memo", "tags", "date", "from_account", "to_account"].iter()
+        .map(|x| x.to_string()).chain(t.extra.keys().cloned())
+        .find(|x| (has(x) || t.extra.contains_key(x)) && !fields.contains(&x.as_str()))
+}
+
+/// Append some lines to a file, which is created if it doesn't exist.  The first one starts on a
+/// new line, and they end in CRLF if the file's lines do.  They're written all at once, while
+/// holding the file's lock.  `lines` is given what's in the file so far.
+fn append_lines<F: FnOnce(&[u8]) -> io::Result<Vec<String>>>(path: &Path, lines: F) -> io::Result<()> {
+    let mut file = open_locked(path)?;
+    let mut existing = vec![];
+    file.read_to_end(&mut existing)?;
+    let lines = lines(&existing)?;
+    let newline: &[u8] = if existing.ends_with(b"\r\n") { b"\r\n" } else { b"\n" };
+    let mut buf = vec![];
+    // Make sure we start on a new line
+    if !existing.is_empty() && !existing.ends_with(b"\n") { buf.extend_from_slice(newline); }
+    for line in &lines {
+        buf.extend_from_slice(line.as_bytes());
+        buf.extend_from_slice(newline);
+    }
+    file.write_all(&buf)?;
+    file.sync_data()
+}
+
+/// Replace the contents of a ledger, atomically: they're written to a temporary file next to it,
+/// which is then renamed over it.  This holds the ledger's lock, so that nobody appends to the old
+/// one meanwhile.
+pub fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let _lock = open_locked(path)?;
+    swap_in(path, contents)
+}
+
+/// Write a file all at once, so that nobody sees it half-written: they see whatever was there
+/// before, or the new contents.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    swap_in(path, contents)
+}
+
+/// Replace the contents of a file, whose lock we hold.
+fn swap_in(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path.file_name().map_or("ledger".into(), |x| x.to_string_lossy());
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", name, ::std::process::id()));
+    let written = File::create(&tmp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    match written.and_then(|()| ::std::fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = ::std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// What `archive` did.
+#[derive(Clone, Debug)]
+pub struct Archived {
+    /// How many records were moved into the archive, and how many were kept.
+    pub archived: usize,
+    pub kept: usize,
+    /// The checkpoint which stands in for the archived records.
+    pub checkpoint: Checkpoint,
+}
+
+/// Move the old records of a JSON ledger into a gzipped archive, and put a checkpoint of the
+/// balances so far in their place, so that the ledger's balances are the same as before.
+///
+/// The ledger is split at the first record dated on or after `before`: everything above it is
+/// archived, and everything from there on is kept, whatever its date.  (If a kept record reverses
+/// an older transfer, the split moves up to keep that transfer too, since a reversal can't see
+/// through a checkpoint.)  The header stays, and the archive gets a copy of it.  If the ledger is
+/// chained (see `chain`), what's kept is chained afresh.  The archive must not exist yet.
+///
+/// The balances are the ones which `filter` lets through, so a checkpoint leaves out the
+/// transfers which it's given `Config::exclusions` for.
+pub fn archive(path: &Path, before: Date, archive: &Path, options: &ReadOptions, filter: &Filter) -> Result<Archived, String> {
+    let source = path.display().to_string();
+    let open = || File::open(path).map(BufReader::new).map_err(|e| format!("{}: {}", source, e));
+    let is_header = |x: &::serde_json::Value| x.get("repay_version").is_some();
+    // Where to split, and which transfers are reversed where
+    let mut header: Option<usize> = None;
+    let mut split: Option<usize> = None;
+    let mut starts = vec![];
+    let mut reversals: Vec<(usize, String)> = vec![];
+    let mut chained = false;
+    for x in json_values(open()?, &source) {
+        let (line, x) = x.map_err(|e| e.to_string())?;
+        if starts.is_empty() && is_header(&x) { header = Some(line); }
+        starts.push(line);
+        chained = x.get(::chain::FIELD).is_some();
+        let date = x.get("date").or_else(|| x.get("ts")).and_then(|d| d.as_str()).and_then(|d| d.parse::<Timestamp>().ok());
+        if split.is_none() && date.is_some_and(|d| d.date >= before) { split = Some(line); }
+        if let Some(id) = x.get("reverses").and_then(|x| x.as_str()) { reversals.push((line, id.to_string())); }
+    }
+    let wanted: HashSet<&str> = reversals.iter().map(|(_, id)| id.as_str()).collect();
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    for x in json_values(open()?, &source) {
+        let (line, x) = x.map_err(|e| e.to_string())?;
+        match x.get("id").and_then(|x| x.as_str()) {
+            Some(id) if wanted.contains(id) && x.get("reverses").is_none() => { defined.entry(id.to_string()).or_insert(line); }
+            _ => (),
+        }
+    }
+    let end = starts.last().map_or(1, |x| x + 1);
+    let mut split = split.unwrap_or(end);
+    loop {
+        let earliest = reversals.iter().filter(|(line, _)| *line >= split)
+            .filter_map(|(_, id)| defined.get(id)).cloned().filter(|&line| line < split).min();
+        match earliest {
+            Some(line) => split = line,
+            None => break,
+        }
+    }
+    // A record starts the split, so records end where the next one starts
+    let split = starts.iter().cloned().find(|&line| line >= split).unwrap_or(end);
+    let header_end = header.map(|h| starts.iter().cloned().find(|&line| line > h).unwrap_or(split));
+    let archived = starts.iter().filter(|&&line| line < split && Some(line) != header).count();
+    if archived == 0 {
+        return Err(format!("Nothing in {} comes before the first record from {} or later", source, before));
+    }
+
+    // Write the archive, and keep the rest
+    let file = OpenOptions::new().write(true).create_new(true).open(archive).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => format!("{} already exists.  (Archive to somewhere else with '--to')", archive.display()),
+        _ => format!("{}: {}", archive.display(), e),
+    })?;
+    let result = (|| {
+        let mut gz = ::gzip::Encoder::new(io::BufWriter::new(file)).map_err(|e| e.to_string())?;
+        let (mut head, mut kept) = (vec![], vec![]);
+        let mut offset = 0;
+        let mut reader = open()?;
+        let mut line = vec![];
+        for i in 1.. {
+            line.clear();
+            if reader.read_until(b'\n', &mut line).map_err(|e| e.to_string())? == 0 { break; }
+            if i < split {
+                gz.write_all(&line).map_err(|e| e.to_string())?;
+                offset += line.len() as u64;
+                if header_end.is_some_and(|end| i < end) { head.extend_from_slice(&line); }
+            } else {
+                kept.extend_from_slice(&line);
+            }
+        }
+        gz.finish().and_then(|mut w| { w.flush()?; w.get_ref().sync_all() }).map_err(|e| e.to_string())?;
+        Ok((head, kept, offset))
+    })();
+    let cleanup = |e: String| { let _ = ::std::fs::remove_file(archive); e };
+    let (head, kept, offset) = result.map_err(cleanup)?;
+
+    // The balances above the split, and the whole ledger's (which must stay the same)
+    let dir = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let balances_of = |reader: Box<dyn Read>| -> Result<CurrencyBalances, String> {
+        let seen = State::default();
+        let cx = Context { dir: dir.clone(), stack: vec![canonical.clone()], seen, skip_before: 0 };
+        let transfers = read(reader, source.clone(), Format::Json, options.clone(), cx).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+        Ok(balances_by_currency(transfers.into_iter().filter(|t| filter.accepts(t))).1)
+    };
+    let file = File::open(path).map_err(|e| cleanup(e.to_string()))?;
+    let old = balances_of(Box::new(file)).map_err(cleanup)?;
+    let file = File::open(path).map_err(|e| cleanup(e.to_string()))?;
+    let checkpoint = Checkpoint::new(&balances_of(Box::new(file.take(offset))).map_err(cleanup)?, Some(Date::from_days(before.days() - 1).into()));
+    let mut active = head;
+    active.extend_from_slice(::serde_json::to_string(&checkpoint).map_err(|e| cleanup(e.to_string()))?.as_bytes());
+    active.push(b'\n');
+    active.extend_from_slice(&kept);
+    if chained {
+        active = ::chain::seal(&active[..], &source).map_err(|e| cleanup(e.to_string()))?.into_bytes();
+    }
+    if balances_of(Box::new(io::Cursor::new(active.clone())))? != old {
+        return Err(cleanup(format!("Archiving would change the balances of {}, so it's been left alone", source)));
+    }
+
+    // Nobody may append to the ledger while we swap it
+    let lock = open_locked(path).map_err(|e| cleanup(e.to_string()))?;
+    if lock.metadata().map_err(|e| cleanup(e.to_string()))?.len() != offset + kept.len() as u64 {
+        return Err(cleanup(format!("{} changed while it was being archived", source)));
+    }
+    swap_in(path, &active).map_err(|e| cleanup(e.to_string()))?;
+    Ok(Archived { archived, kept: starts.iter().filter(|&&line| line >= split).count(), checkpoint })
+}
+
+/// Open a ledger for writing, creating it if need be, and take an exclusive (advisory) lock on
+/// it, waiting for whoever else has it.  The lock is released when the file is closed.
+fn open_locked(path: &Path) -> io::Result<File> {
+    loop {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        file.lock()?;
+        // If it was replaced while we were waiting, we've locked the old one
+        if same_file(&file, path)? { return Ok(file); }
+    }
+}
+
+#[cfg(unix)]
+fn same_file(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let ours = file.metadata()?;
+    match ::std::fs::metadata(path) {
+        Ok(theirs) => Ok(ours.dev() == theirs.dev() && ours.ino() == theirs.ino()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// (Elsewhere, an open file can't be renamed over.)
+#[cfg(not(unix))]
+fn same_file(_: &File, _: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[test]
+fn test_digest() {
+    let a = Transfer::new("ann".to_string(), "bob".to_string(), 500);
+    let b = Transfer::new("ann".to_string(), "bob".to_string(), 501);
+    assert_eq!(a.digest(), a.clone().digest());
+    assert_ne!(a.digest(), b.digest());
+    assert_eq!(a.digest().len(), 8);
+}
+
+#[test]
+fn test_read_transfers() {
+    let ledger = b"\xef\xbb\xbf{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\r\n{\"from\":\"bob\",\"to\":\"ann\",\"amt\":2}\r\n";
+    let transfers: Vec<_> = read_records(&ledger[..], Format::Json, &ReadOptions::default()).map(Result::unwrap).collect();
+    assert_eq!(transfers.len(), 2);
+    assert_eq!((transfers[0].from.as_str(), transfers[1].amt), ("ann", 2));
+}
+
+#[test]
+fn test_extra_fields() {
+    let line = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1,\"receipt\":\"r1.jpg\",\"note\":{\"by\":\"ann\"}}";
+    let t: Transfer<String> = ::serde_json::from_str(line).unwrap();
+    assert_eq!(t.extra.keys().collect::<Vec<_>>(), vec!["receipt", "note"]);
+    assert_eq!(::serde_json::to_string(&t).unwrap(), line);
+}
+
+#[test]
+fn test_decimal_amounts() {
+    let options = ReadOptions { exponents: vec![("JPY".to_string(), 0)].into_iter().collect(), ..ReadOptions::default() };
+    let ledger = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":\"12.34\"}\n\
+                  {\"from\":\"ann\",\"to\":\"bob\",\"amt\":\"500\",\"currency\":\"JPY\"}\n";
+    let amts: Vec<_> = read_records(ledger.as_bytes(), Format::Json, &options).map(Result::unwrap).map(|t| t.amt).collect();
+    assert_eq!(amts, vec![1234, 500]);
+    let bad = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":\"1.5\",\"currency\":\"JPY\"}\n";
+    let e = read_records(bad.as_bytes(), Format::Json, &options).next().unwrap().unwrap_err();
+    assert!(e.message.contains("decimal places"));
+}
+
+#[test]
+fn test_expense_records() {
+    let ledger = "{\"payer\":\"ann\",\"participants\":[\"ann\",\"bob\",\"cara\"],\"amt\":\"10.00\",\"memo\":\"dinner\"}\n\
+                  {\"from\":\"bob\",\"to\":\"ann\",\"amt\":100}\n\
+                  {\"payer\":\"bob\",\"participants\":[{\"name\":\"ann\",\"amt\":\"1.50\"},\"cara\"],\"amt\":\"2.00\"}\n";
+    let (_, balances) = balances(read_records(ledger.as_bytes(), Format::Json, &ReadOptions::default()).map(Result::unwrap));
+    assert_eq!(balances, vec![("ann".into(), -416), ("bob".into(), 33), ("cara".into(), 383)]);
+}
+
+#[test]
+fn test_reversals() {
+    let read = |ledger: &str| read_records(::std::io::Cursor::new(ledger.to_string()), Format::Json, &ReadOptions::default()).collect::<Vec<_>>();
+    let ledger = "{\"id\":\"rent\",\"from\":\"ann\",\"to\":\"bob\",\"amt\":10,\"category\":\"home\"}\n\
+                  {\"id\":\"dinner\",\"payer\":\"bob\",\"participants\":[\"ann\",\"cara\"],\"amt\":4}\n\
+                  {\"reverses\":\"dinner\",\"memo\":\"refunded\"}\n";
+    let transfers: Vec<Transfer<String>> = read(ledger).into_iter().map(Result::unwrap).collect();
+    assert_eq!(transfers.len(), 5);
+    assert_eq!((transfers[3].from.as_str(), transfers[3].to.as_str(), transfers[3].reverses.as_deref()), ("ann", "bob", Some("dinner")));
+    assert_eq!(balances(transfers).1, vec![("ann".into(), -10), ("bob".into(), 10)]);
+
+    let twice = format!("{}{{\"reverses\":\"dinner\"}}\n", ledger);
+    assert!(read(&twice)[5].as_ref().unwrap_err().message.contains("already"));
+    assert_eq!(read("{\"reverses\":\"rent\"}\n")[0].as_ref().unwrap_err().line, Some(1));
+}
+
+#[test]
+fn test_ledger_reader() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-reader-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let mut ledger = String::new();
+    for i in 0..1000 { ledger += &format!("{{\"id\":\"t{}\",\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}}\n", i); }
+    // Reversals found by the first pass, and one spanning several lines (which it can't find)
+    ::std::fs::write(dir.join("a.jsonl"), format!("{}{{\"reverses\":\"t10\"}}\n", ledger)).unwrap();
+    ::std::fs::write(dir.join("b.jsonl"), format!("{}{{\n  \"reverses\": \"t20\"\n}}\n", ledger)).unwrap();
+    let reader = LedgerReader::new(&ReadOptions::default()).ledger(&dir.join("a.jsonl"), Format::Json).ledger(&dir.join("b.jsonl"), Format::Json);
+    let transfers: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(balances(transfers).1, vec![("ann".into(), -1998), ("bob".into(), 1998)]);
+    // Each ledger is separate
+    let c = "{\"reverses\":\"t10\"}\n";
+    ::std::fs::write(dir.join("c.jsonl"), c).unwrap();
+    let mut reader = LedgerReader::new(&ReadOptions::default()).ledger(&dir.join("a.jsonl"), Format::Json).ledger(&dir.join("c.jsonl"), Format::Json);
+    assert!(reader.nth(1001).unwrap().unwrap_err().message.contains("no earlier transfer"));
+    // Reading ahead gives the same records, in the same order
+    #[cfg(feature = "parallel")]
+    {
+        let paths = ["a.jsonl", "c.jsonl", "b.jsonl"].iter().map(|x| dir.join(x)).collect::<Vec<_>>();
+        let reader = || paths.iter().fold(LedgerReader::new(&ReadOptions::default()), |r, p| r.ledger(p, Format::Json));
+        let debug = |records: &mut dyn Iterator<Item=Result<Transfer<String>, ParseError>>| records.map(|x| format!("{:?}", x)).collect::<Vec<_>>();
+        assert_eq!(debug(&mut reader().read_ahead(2, 10)), debug(&mut reader()));
+    }
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_balances_by() {
+    let t = |from: &str, to: &str, amt| Transfer::new(from.to_string(), to.to_string(), amt);
+    let transfers = vec![t("ann", "bob", 10), t("bob", "cara", 4), t("cara", "ann", 4), t("ann", "dan", 3)];
+    // Everyone but dan is in "trip", and ann and dan are in "flat"
+    let (n, balances) = balances_by(transfers, |t| ["trip", "flat"].iter().cloned().filter(|&g| match g {
+        "trip" => t.from != "dan" && t.to != "dan",
+        _ => [&t.from, &t.to].iter().all(|x| *x == "ann" || *x == "dan"),
+    }).collect::<Vec<_>>());
+    assert_eq!(n, 4);
+    assert_eq!(balances.len(), 2);
+    assert_eq!(balances["trip"], vec![("ann".into(), -6), ("bob".into(), 6)]);
+    assert_eq!(balances["flat"], vec![("ann".into(), -3), ("dan".into(), 3)]);
+}
+
+#[test]
+fn test_assertions() {
+    let read = |ledger: &str| read_records(::std::io::Cursor::new(ledger.to_string()), Format::Json, &ReadOptions::default())
+        .collect::<Vec<_>>();
+    let ledger = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":4200}\n\
+                  {\"assert\":{\"who\":\"bob\",\"balance\":\"42.00\"},\"date\":\"2024-05-01\"}\n\
+                  {\"from\":\"bob\",\"to\":\"cara\",\"amt\":200}\n\
+                  {\"assert\":{\"who\":\"bob\",\"balance\":4200}}\n\
+                  {\"assert\":{\"who\":\"dan\",\"balance\":0}}\n";
+    let records = read(ledger);
+    assert_eq!(records.len(), 3);
+    let e = records[2].as_ref().unwrap_err();
+    assert_eq!(e.line, Some(4));
+    assert_eq!(e.message, "Balance assertion failed: bob's balance is 4000, not 4200 (200 less than asserted).  It was last changed on line 3");
+}
+
+#[test]
+fn test_checkpoints() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-checkpoint-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ledger.jsonl");
+    // The first transfer is bad, but it's before the checkpoint, so it doesn't matter
+    ::std::fs::write(&path, "{\"repay_version\":2}\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":10}\n").unwrap();
+    let (_, before) = balances_by_currency(records(&path, Format::Json, &ReadOptions::default()).map(Result::unwrap));
+    ::std::fs::write(&path, "{\"repay_version\":2}\n{\"from\":\"ann\"}\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":10}\n").unwrap();
+    append_records(&path, &[Checkpoint::new(&before, Some("2024-06-01".parse().unwrap()))]).unwrap();
+    append(&path, &[Transfer::new("bob".to_string(), "cara".to_string(), 3)]).unwrap();
+    let read: Vec<_> = records(&path, Format::Json, &ReadOptions::default()).map(Result::unwrap).collect();
+    assert_eq!(read[0].memo.as_deref(), Some("checkpoint"));
+    assert_eq!(balances(read).1, vec![("ann".into(), -10), ("bob".into(), 7), ("cara".into(), 3)]);
+
+    // A checkpoint in the middle of a stream resets the balances
+    let ledger = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":10}\n{\"checkpoint\":[{\"who\":\"cara\",\"balance\":5},{\"who\":\"dan\",\"balance\":-5}]}\n";
+    assert_eq!(balances(read_records(ledger.as_bytes(), Format::Json, &ReadOptions::default()).map(Result::unwrap)).1, vec![("cara".into(), 5), ("dan".into(), -5)]);
+    let unbalanced = "{\"checkpoint\":[{\"who\":\"cara\",\"balance\":5}]}\n";
+    assert!(read_records(unbalanced.as_bytes(), Format::Json, &ReadOptions::default()).next().unwrap().is_err());
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_archive() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-archive-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ledger.jsonl");
+    let ledger = "{\"repay_version\":1}\n\
+                  {\"from\":\"ann\",\"to\":\"bob\",\"amt\":100,\"date\":\"2023-05-01\"}\n\
+                  {\"id\":\"r\",\"from\":\"bob\",\"to\":\"cara\",\"amt\":50,\"date\":\"2023-06-01\"}\n\
+                  {\"from\":\"cara\",\"to\":\"ann\",\"amt\":30,\"date\":\"2024-02-01\"}\n\
+                  {\"reverses\":\"r\"}\n";
+    ::std::fs::write(&path, ledger).unwrap();
+    let (_, before) = balances_by_currency(records(&path, Format::Json, &ReadOptions::default()).map(Result::unwrap));
+    let archived = archive(&path, "2024-01-01".parse().unwrap(), &dir.join("old.gz"), &ReadOptions::default(), &Filter::default()).unwrap();
+    // The reversed transfer is kept
+    assert_eq!((archived.archived, archived.kept), (1, 3));
+    assert_eq!(balances_by_currency(records(&path, Format::Json, &ReadOptions::default()).map(Result::unwrap)).1, before);
+    let kept = ::std::fs::read_to_string(&path).unwrap();
+    assert!(kept.starts_with("{\"repay_version\":1}\n{\"checkpoint\""), "{}", kept);
+    assert!(kept.ends_with(&ledger[ledger.find("{\"id\"").unwrap()..]));
+    assert!(archive(&path, "2024-01-01".parse().unwrap(), &dir.join("old.gz"), &ReadOptions::default(), &Filter::default()).unwrap_err().contains("already exists"));
+
+    // Excluded transfers don't count, before the checkpoint or after it
+    let ledger = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":100,\"category\":\"personal\",\"date\":\"2023-05-01\"}\n\
+                  {\"from\":\"bob\",\"to\":\"cara\",\"amt\":30,\"date\":\"2023-06-01\"}\n\
+                  {\"from\":\"cara\",\"to\":\"ann\",\"amt\":5,\"category\":\"personal\",\"date\":\"2024-02-01\"}\n";
+    ::std::fs::write(&path, ledger).unwrap();
+    let filter = Filter { exclude_categories: vec!["personal".into()], ..Filter::default() };
+    let counted = |path: &Path| balances_by_currency(records(path, Format::Json, &ReadOptions::default()).map(Result::unwrap).filter(|t| filter.accepts(t))).1;
+    let before = counted(&path);
+    let archived = archive(&path, "2024-01-01".parse().unwrap(), &dir.join("old2.gz"), &ReadOptions::default(), &filter).unwrap();
+    assert_eq!((archived.archived, archived.kept), (2, 1));
+    assert_eq!(archived.checkpoint.checkpoint.len(), 2);
+    assert_eq!(counted(&path), before);
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_concurrent_writes() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-lock-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ledger.jsonl");
+    replace(&path, b"{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}").unwrap();
+    let writers: Vec<_> = (0..8).map(|i| {
+        let path = path.clone();
+        ::std::thread::spawn(move || for _ in 0..25 {
+            append(&path, &[Transfer::new(format!("p{}", i), "bob".to_string(), 1), Transfer::new("bob".to_string(), format!("p{}", i), 1)]).unwrap();
+        })
+    }).collect();
+    for w in writers { w.join().unwrap(); }
+    assert_eq!(records(&path, Format::Json, &ReadOptions::default()).map(Result::unwrap).count(), 401);
+    assert_eq!(::std::fs::read_dir(&dir).unwrap().count(), 1);
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_date_field() {
+    let t: Transfer<String> = ::serde_json::from_str("{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1,\"ts\":\"2024-05-01T19:30:00Z\"}").unwrap();
+    assert_eq!(t.date.unwrap().to_string(), "2024-05-01T19:30:00Z");
+    assert!(::serde_json::from_str::<Transfer<String>>("{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1,\"date\":\"May 1\"}").is_err());
+    assert_eq!(::serde_json::to_string(&Transfer { date: t.date, ..Transfer::new("ann", "bob", 1) }).unwrap(),
+        "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1,\"date\":\"2024-05-01T19:30:00Z\"}");
+}
+
+#[test]
+fn test_filter() {
+    let t = |date: Option<&str>| Transfer { date: date.map(|d| d.parse().unwrap()), ..Transfer::new("ann".to_string(), "bob".to_string(), 1) };
+    let filter = Filter { since: "2024-05-01".parse().ok(), until: "2024-05-31".parse().ok(), ..Filter::default() };
+    assert!(filter.accepts(&t(Some("2024-05-01"))));
+    assert!(filter.accepts(&t(Some("2024-05-31T23:59:59+02:00"))));
+    assert!(!filter.accepts(&t(Some("2024-04-30T23:59:59Z"))));
+    assert!(!filter.accepts(&t(Some("2024-06-01"))));
+    assert!(filter.accepts(&t(None)));
+
+    let filter = Filter { conditions: vec!["tag=trip:alps".parse().unwrap()], ..Filter::default() };
+    assert!(filter.accepts(&Transfer { tags: vec!["food".into(), "trip:alps".into()], ..t(None) }));
+    assert!(!filter.accepts(&t(None)));
+    assert!("colour=red".parse::<Condition>().is_err());
+
+    let filter = Filter { conditions: vec![Condition::Members(vec!["ann".into(), "bob".into()].into_iter().collect())], ..Filter::default() };
+    assert!(filter.accepts(&t(None)));
+    assert!(!filter.accepts(&Transfer { to: "carol".into(), ..t(None) }));
+}
+
+#[test]
+fn test_balances_by_currency() {
+    let t = |from: &str, to: &str, amt, ccy: Option<&str>| Transfer {
+        currency: ccy.map(String::from),
+        ..Transfer::new(from.to_string(), to.to_string(), amt)
+    };
+    let (n, balances) = balances_by_currency(vec![
+        t("ann", "bob", 10, Some("EUR")), t("ann", "bob", 5, Some("GBP")), t("bob", "ann", 5, Some("GBP")), t("ann", "bob", 1, None),
+    ]);
+    assert_eq!(n, 4);
+    let s = |x: &str| Some(x.to_string());
+    assert_eq!(balances.into_iter().collect::<Vec<_>>(), vec![
+        (None, vec![("ann".into(), -1), ("bob".into(), 1)]),
+        (s("EUR"), vec![("ann".into(), -10), ("bob".into(), 10)]),
+    ]);
+}
+
+#[test]
+fn test_records() {
+    let ledger = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":x}\n{\"from\":\"ann\",\n  \"to\":\"bob\",\"amt\":1}\n{\"from\":\"ann\"\n{\"from\":\"bob\"}\n";
+    let records: Vec<_> = read_records(ledger.as_bytes(), Format::Json, &ReadOptions::default()).collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[0].as_ref().unwrap_err().to_string(), "-:1:32: expected value\n    {\"from\":\"ann\",\"to\":\"bob\",\"amt\":x}");
+    assert_eq!(records[1].as_ref().unwrap().amt, 1);
+    let e = records[2].as_ref().unwrap_err();
+    assert_eq!((e.line, e.column, e.message.as_str()), (Some(4), None, "EOF while parsing an object"));
+    let e = records[3].as_ref().unwrap_err();
+    assert_eq!((e.line, e.message.as_str()), (Some(5), "missing field `to`"));
+    let e = read_records("txn,person,share\nt1,ann,x\n".as_bytes(), Format::Long, &ReadOptions::default()).next().unwrap().unwrap_err();
+    assert_eq!(e.line, Some(2));
+}
+
+#[test]
+fn test_validation() {
+    let ledger = "{\"from\":\"ann\",\"to\":\"ann\",\"amt\":5}\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":0}\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":3}\n";
+    let read = |validation| read_records(ledger.as_bytes(), Format::Json, &ReadOptions { validation, ..ReadOptions::default() }).collect::<Vec<_>>();
+    assert_eq!(read(Validation::Warn).len(), 1);
+    assert_eq!(read(Validation::Lenient).len(), 1);
+    let strict = read(Validation::Strict);
+    assert_eq!(strict.iter().filter(|x| x.is_err()).count(), 2);
+    assert_eq!(strict[0].as_ref().unwrap_err().line, Some(1));
+}
+
+#[test]
+fn test_header() {
+    let ledger = "{\"repay_version\":2,\"base_currency\":\"EUR\"}\n{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\n";
+    let records: Vec<_> = read_records(ledger.as_bytes(), Format::Json, &ReadOptions::default()).collect();
+    assert_eq!(records.len(), 1);
+    assert!(records[0].is_ok());
+    let newer = "{\"repay_version\":3}\n";
+    let e = read_records(newer.as_bytes(), Format::Json, &ReadOptions::default()).next().unwrap().unwrap_err();
+    assert!(e.message.contains("version 3"));
+    let late = "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\n{\"repay_version\":2}\n";
+    assert!(read_records(late.as_bytes(), Format::Json, &ReadOptions::default()).nth(1).unwrap().is_err());
+}
+
+#[test]
+fn test_include() {
+    let dir = ::std::env::temp_dir().join(format!("repay-test-include-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(dir.join("trips")).unwrap();
+    ::std::fs::write(dir.join("2023.jsonl"), "{\"from\":\"ann\",\"to\":\"bob\",\"amt\":1}\n{\"include\":\"trips/paris.csv\"}\n").unwrap();
+    ::std::fs::write(dir.join("trips/paris.csv"), "from,to,amt\nbob,carol,2\n").unwrap();
+    ::std::fs::write(dir.join("2024.jsonl"), "{\"include\":\"2023.jsonl\"}\n{\"from\":\"carol\",\"to\":\"ann\",\"amt\":3}\n").unwrap();
+    let amts: Vec<_> = records(&dir.join("2024.jsonl"), Format::Json, &ReadOptions::default()).map(Result::unwrap).map(|t| t.amt).collect();
+    assert_eq!(amts, vec![1, 2, 3]);
+    assert_eq!(glob(&dir.join("20*.json?")).unwrap(), vec![dir.join("2023.jsonl"), dir.join("2024.jsonl")]);
+    ::std::fs::remove_dir_all(&dir).unwrap();
+}
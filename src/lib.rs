@@ -0,0 +1,69 @@
+/*!
+Work out who should pay whom to settle a ledger of debts, using as few repayments as possible.
+
+This is the library behind the `repay` binary.  The pipeline is: read a `ledger` (in some
+`format`, eg. `long`) into balances, then turn those balances into a `plan`.
+
+## Cargo features
+
+- `approx` (default): the approximate solver, for ledgers too big to solve exactly.  It's built
+  on the `mcmf` crate, which needs a C++ compiler.  Without it, every plan is computed exactly.
+- `bank`: importing bank statements (see `bank`), which needs the `regex` crate.
+- `cli` (default): the `repay` binary, and its command-line dependencies.  (Implies `bank`.)
+- `parallel`: let the exact solver use all cores (see mzsp's `rayon` feature), read several
+  ledgers at once (see `ledger::ReadAhead`), and have the `repay` binary settle each currency (and
+  each group, with `--by-group`) at the same time.
+
+If you only want to embed the planner, `default-features = false` gives you a pure-Rust library
+with a handful of small dependencies.
+*/
+
+extern crate bitset64;
+extern crate csv;
+#[macro_use] extern crate log;
+#[cfg(feature = "approx")] extern crate mcmf;
+extern crate mzsp;
+#[cfg(feature = "bank")] extern crate regex;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+#[cfg_attr(test, macro_use)] extern crate serde_json;
+extern crate unicode_normalization;
+
+pub mod accounts;
+pub mod apps;
+pub mod backtest;
+#[cfg(feature = "bank")]
+pub mod bank;
+pub mod cache;
+pub mod chain;
+pub mod config;
+pub mod date;
+pub mod diff;
+pub mod equalize;
+pub mod expense;
+pub mod explain;
+pub mod export;
+pub mod format;
+pub mod gzip;
+pub mod history;
+pub mod interest;
+pub mod journal;
+pub mod ledger;
+pub mod lint;
+pub mod locale;
+pub mod long;
+pub mod memo;
+pub mod names;
+pub mod netting;
+pub mod output;
+pub mod plan;
+pub mod quick;
+pub mod rates;
+pub mod sha256;
+pub mod sheet;
+pub mod sql;
+pub mod statement;
+pub mod table;
+pub mod testing;
+pub mod units;
+pub mod verify;
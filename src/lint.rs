@@ -0,0 +1,146 @@
+/*!
+Looking for records which are probably mistakes, for `repay lint`.
+
+None of these make the ledger unreadable, but each is worth a look: a transfer which was entered
+twice, one from someone to themselves or for nothing, one to someone who isn't on the roster, one
+dated in the future, or one which is far bigger than the rest.  Each finding has a severity, and
+says which record it's about (counting transfers from 1, in the order they're read).
+*/
+
+use date::Date;
+use ledger::Transfer;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// How much a finding matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Info => f.write_str("info"),
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// Something suspicious.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Which check found it (eg. "duplicate").
+    pub check: &'static str,
+    /// The record it's about, if it's about one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub message: String,
+}
+
+/// What to check against.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Everyone who should be in the ledger.  (Anyone goes, if unset.)
+    pub roster: Option<BTreeSet<String>>,
+    /// Transfers dated after this are in the future.
+    pub today: Date,
+    /// Whether there are exchange rates, for settling several currencies at once.
+    pub rates: bool,
+}
+
+/// Amounts this many times the median are outliers...
+const OUTLIER_FACTOR: isize = 50;
+/// ...as long as there are enough of them to have a meaningful median.
+const OUTLIER_MIN_TRANSFERS: usize = 10;
+
+/// Check the transfers.  The findings are in the order of the records they're about, with the
+/// ones about the whole ledger at the end.
+pub fn lint<I: IntoIterator<Item=Transfer<String>>>(transfers: I, options: &Options) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut seen = HashMap::new();
+    // Each currency's amounts, and the records they're in; and each record's id
+    let mut amounts: BTreeMap<Option<String>, Vec<(isize, usize)>> = BTreeMap::new();
+    let mut ids = vec![];
+    for (i, t) in transfers.into_iter().enumerate() {
+        let record = i + 1;
+        let mut found = |severity, check, message| findings.push(Finding { severity, check, record: Some(record), id: t.id.clone(), message });
+        if t.from == t.to {
+            found(Severity::Warning, "self-transfer", format!("A transfer from {} to themselves", t.from));
+        }
+        if t.amt == 0 {
+            found(Severity::Warning, "zero-amount", format!("A transfer of 0 from {} to {}", t.from, t.to));
+        }
+        if let Some(ref roster) = options.roster {
+            for who in [&t.from, &t.to] {
+                if !roster.contains(who) {
+                    found(Severity::Error, "unknown-person", format!("{} isn't on the roster", who));
+                }
+            }
+        }
+        if let Some(d) = t.date.filter(|d| d.date > options.today) {
+            found(Severity::Warning, "future-date", format!("Dated {}, which is in the future", d));
+        }
+        // Reversals of the same record would look alike, but only one of them can take effect
+        if t.reverses.is_none() {
+            let key = (t.from.clone(), t.to.clone(), t.amt, t.currency.clone(), t.date, t.memo.clone());
+            match seen.get(&key) {
+                Some(&earlier) => found(Severity::Warning, "duplicate", format!("The same as record {}", earlier)),
+                None => { seen.insert(key, record); }
+            }
+        }
+        amounts.entry(t.currency.clone()).or_default().push((t.amt, record));
+        ids.push(t.id);
+    }
+    for (ccy, xs) in &amounts {
+        if xs.len() < OUTLIER_MIN_TRANSFERS { continue; }
+        let mut sorted: Vec<isize> = xs.iter().map(|x| x.0.abs()).collect();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2];
+        if median == 0 { continue; }
+        for &(amt, record) in xs.iter().filter(|x| x.0.abs() > median * OUTLIER_FACTOR) {
+            findings.push(Finding {
+                severity: Severity::Warning, check: "outlier", record: Some(record), id: ids[record - 1].clone(),
+                message: format!("{} is over {} times the usual amount{} ({})", amt, OUTLIER_FACTOR,
+                    ccy.as_ref().map_or(String::new(), |c| format!(" in {}", c)), median),
+            });
+        }
+    }
+    findings.sort_by_key(|f| f.record);
+    if amounts.len() > 1 && !options.rates {
+        let ccys: Vec<&str> = amounts.keys().map(|c| c.as_deref().unwrap_or("(no currency)")).collect();
+        findings.push(Finding {
+            severity: Severity::Info, check: "mixed-currencies", record: None, id: None,
+            message: format!("The ledger has transfers in {}, with no exchange rates, so each currency will be settled separately", ccys.join(", ")),
+        });
+    }
+    findings
+}
+
+#[test]
+fn test_lint() {
+    let t = |from: &str, to: &str, amt| Transfer::new(from.to_string(), to.to_string(), amt);
+    let options = Options {
+        roster: Some(["ann", "bob", "cat"].iter().map(|x| x.to_string()).collect()),
+        today: Date::new(2024, 6, 1).unwrap(),
+        rates: false,
+    };
+    let mut transfers: Vec<_> = (0..10).map(|i| t("ann", "bob", 100 + i)).collect();
+    transfers.push(t("ann", "ann", 5));
+    transfers.push(t("bob", "dan", 0));
+    transfers.push(Transfer { date: Some("2024-07-01".parse().unwrap()), ..t("cat", "bob", 10000) });
+    transfers.push(t("ann", "bob", 101));
+    transfers.push(Transfer { currency: Some("USD".into()), ..t("ann", "bob", 5) });
+    let findings: Vec<(usize, &str)> = lint(transfers, &options).iter().map(|f| (f.record.unwrap_or(0), f.check)).collect();
+    assert_eq!(findings, vec![
+        (11, "self-transfer"), (12, "zero-amount"), (12, "unknown-person"), (13, "future-date"), (13, "outlier"),
+        (14, "duplicate"), (0, "mixed-currencies"),
+    ]);
+}
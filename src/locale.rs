@@ -0,0 +1,178 @@
+/*!
+Reading amounts the way people write them, eg. `$1,234.56`, `1.234,56 €`, or `EUR 12`.
+
+A `Locale` says which character is the decimal separator.  It's chosen by language (`--locale`,
+eg. `de` or `fr-FR`): languages which use a decimal comma get one, and everything else (`en`, `ja`,
+...) gets a decimal point.  Digits may be grouped with whichever of `.`/`,` isn't the decimal
+separator, or with spaces or apostrophes.  Currency symbols (the common ones, and the one given with
+`--currency-symbol`) and three-letter currency codes are ignored, before or after the number.  A
+sign can go before or after the symbol, and an amount in parentheses is negative.
+
+This only applies to amounts written in major units.  Plain integers in a ledger are still minor
+units.
+*/
+
+use serde::de::{self, Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use units::Money;
+
+/// Symbols which are always ignored.
+const SYMBOLS: &[&str] = &["$", "€", "£", "¥", "₹", "₩", "₽", "₺", "₪", "₫", "₱", "₴", "₦", "฿", "₣", "kr", "zł", "Kč", "Fr."];
+
+/// Languages which write `1.234,56` (or `1 234,56`).
+const DECIMAL_COMMA: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fo", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// How amounts are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Locale {
+    /// As given, eg. `de-DE`.
+    name: String,
+    /// Either `.` or `,`.
+    pub decimal: char,
+    /// What groups the digits when amounts are displayed, eg. `,` in `1,234.56`.  (Any of the usual
+    /// ones is accepted when reading.)
+    pub group: char,
+    /// Another currency symbol to ignore, like `CHF`.
+    pub symbol: Option<String>,
+}
+
+impl Default for Locale {
+    fn default() -> Locale {
+        Locale { name: "en".into(), decimal: '.', group: ',', symbol: None }
+    }
+}
+
+impl Locale {
+    /// Also ignore this currency symbol.
+    pub fn with_symbol(self, symbol: Option<String>) -> Locale {
+        Locale { symbol, ..self }
+    }
+
+    /// Parse an amount in major units.
+    pub fn parse(&self, s: &str) -> Result<Money, String> {
+        let invalid = || format!("Invalid amount '{}' (expected eg. '{}')", s, if self.decimal == ',' { "1.234,56" } else { "1,234.56" });
+        let mut rest = s.trim();
+        let mut negative = false;
+        if let Some(inner) = rest.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+            negative = true;
+            rest = inner.trim();
+        }
+        // Peel off any signs and currency symbols, from both ends
+        loop {
+            let before = rest;
+            for sign in &["-", "−", "+"] {
+                if let Some(x) = rest.strip_prefix(sign).or_else(|| rest.strip_suffix(sign)) {
+                    if negative && *sign != "+" { return Err(invalid()); }
+                    negative |= *sign != "+";
+                    rest = x;
+                }
+            }
+            for symbol in SYMBOLS.iter().cloned().chain(self.symbol.as_deref()) {
+                rest = rest.strip_prefix(symbol).or_else(|| rest.strip_suffix(symbol)).unwrap_or(rest);
+            }
+            let is_code = |x: &str| x.len() == 3 && x.bytes().all(|b| b.is_ascii_uppercase());
+            if rest.len() > 3 && rest.is_char_boundary(3) && is_code(&rest[..3]) && !rest[3..].starts_with(|c: char| c.is_alphabetic()) {
+                rest = &rest[3..];
+            } else if rest.len() > 3 && rest.is_char_boundary(rest.len() - 3) && is_code(&rest[rest.len() - 3..]) {
+                rest = &rest[..rest.len() - 3];
+            }
+            rest = rest.trim_matches(is_space);
+            if rest == before { break; }
+        }
+        // The integer part may be grouped: 1,234,567 or 1 234 567
+        let (int, frac) = match rest.rfind(self.decimal) {
+            Some(i) => (&rest[..i], Some(&rest[i + self.decimal.len_utf8()..])),
+            None => (rest, None),
+        };
+        let groups: Vec<&str> = int.split(|c: char| c != self.decimal && (c == '.' || c == ',' || c == '\'' || c == '’' || is_space(c))).collect();
+        let digits = |x: &str| !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit());
+        if !groups.iter().all(|g| digits(g)) || groups[1..].iter().any(|g| g.len() != 3) || (groups.len() > 1 && groups[0].len() > 3) {
+            return Err(invalid());
+        }
+        let mut number = groups.concat();
+        if let Some(frac) = frac {
+            if !digits(frac) { return Err(invalid()); }
+            number = format!("{}.{}", number, frac);
+        }
+        let money: Money = number.parse().map_err(|_| invalid())?;
+        Ok(if negative { Money::new(-money.minor, money.exponent) } else { money })
+    }
+
+    /// Parse an amount in major units, and give it in the minor units of a currency with
+    /// `exponent` decimal places.
+    pub fn parse_minor(&self, s: &str, exponent: u32) -> Result<isize, String> {
+        self.parse(s)?.rescale(exponent).map(|x| x.minor)
+            .ok_or_else(|| format!("Invalid amount '{}' (at most {} decimal places)", s, exponent))
+    }
+}
+
+fn is_space(c: char) -> bool {
+    c == ' ' || c == '\u{a0}' || c == '\u{202f}'
+}
+
+impl FromStr for Locale {
+    type Err = String;
+    /// A language, perhaps with a region: eg. `de`, `fr-FR`, `pt_BR.UTF-8`.
+    fn from_str(s: &str) -> Result<Locale, String> {
+        let lower = s.to_ascii_lowercase();
+        let mut parts = lower.split(['-', '_', '.']);
+        let lang = parts.next().unwrap_or_default();
+        let region = parts.next().unwrap_or_default();
+        if !(lang.len() == 2 || lang.len() == 3 || lang == "c" || lang == "posix") || !lang.bytes().all(|b| b.is_ascii_lowercase()) {
+            return Err(format!("Unknown locale '{}' (expected eg. 'en' or 'de-DE')", s));
+        }
+        // The Swiss write 1'234.56, even in German and French
+        let swiss = region == "ch" || region == "li";
+        let comma = DECIMAL_COMMA.contains(&lang) && !swiss;
+        let group = match lang {
+            _ if swiss => '\'',
+            "fr" => '\u{202f}',
+            _ if comma => '.',
+            _ => ',',
+        };
+        Ok(Locale { name: s.to_string(), decimal: if comma { ',' } else { '.' }, group, symbol: None })
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Locale {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Locale, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn test_parse() {
+    let en = Locale::default();
+    let de: Locale = "de-DE".parse().unwrap();
+    let fr: Locale = "fr_FR.UTF-8".parse().unwrap();
+    let p = |l: &Locale, s: &str| l.parse_minor(s, 2);
+    assert_eq!(p(&en, "$1,234.56"), Ok(123456));
+    assert_eq!(p(&en, "-$5"), Ok(-500));
+    assert_eq!(p(&en, "$-5"), Ok(-500));
+    assert_eq!(p(&en, "(12.00)"), Ok(-1200));
+    assert_eq!(p(&en, "12.5 USD"), Ok(1250));
+    assert_eq!(p(&de, "1.234,56"), Ok(123456));
+    assert_eq!(p(&de, "€12"), Ok(1200));
+    assert_eq!(p(&de, "-12,50 €"), Ok(-1250));
+    assert_eq!(p(&fr, "1\u{202f}234,5"), Ok(123450));
+    assert_eq!("de-CH".parse::<Locale>().unwrap().decimal, '.');
+    assert_eq!(p(&"de-CH".parse().unwrap(), "CHF 1'234.50"), Ok(123450));
+    assert_eq!(p(&en.clone().with_symbol(Some("R$".into())), "R$ 3.10"), Ok(310));
+    for bad in &["1,23.45", "12,34", "1.2.3", "$", "--5", "12 apples", "ñañ5"] {
+        assert!(en.parse(bad).is_err(), "{}", bad);
+    }
+    assert!(p(&de, "1.234").is_ok());
+    assert!(p(&de, "1,234").is_err());
+    assert!("english".parse::<Locale>().is_err());
+}
@@ -0,0 +1,91 @@
+/*!
+The "long" format: a table with one row per share of a transaction, which is the shape that a lot
+of database exports come in.
+
+```text
+txn,person,share
+dinner,ann,-3000
+dinner,bob,1500
+dinner,carol,1500
+```
+
+A share is how much the transaction changes the person's balance, in minor units: negative for
+whoever paid, positive for whoever benefited.  The rows of a transaction don't need to be next to
+each other, but its shares must sum to zero.  The header row is optional.
+*/
+
+use ledger::Transfer;
+use plan::construct_plan;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// Read a long-format table, and turn each of its transactions into transfers.
+pub fn read<R: Read>(reader: R) -> Result<Vec<Transfer<String>>, String> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(::csv::Trim::All)
+        .from_reader(reader);
+    // Transactions in the order they first appear, and the shares in each
+    let mut txns: Vec<(String, BTreeMap<String, isize>)> = vec![];
+    let mut index: BTreeMap<String, usize> = BTreeMap::new();
+    for (i, row) in reader.records().enumerate() {
+        let row = row.map_err(|e| e.to_string())?;
+        let line = row.position().map_or(i as u64 + 1, |p| p.line());
+        if row.len() != 3 {
+            return Err(format!("Line {}: expected 3 columns (txn, person, share), found {}", line, row.len()));
+        }
+        let share = match row[2].parse::<isize>() {
+            Ok(x) => x,
+            Err(_) if i == 0 => continue,  // A header
+            Err(_) => return Err(format!("Line {}: invalid share '{}'", line, &row[2])),
+        };
+        let idx = *index.entry(row[0].to_string()).or_insert_with(|| {
+            txns.push((row[0].to_string(), BTreeMap::new()));
+            txns.len() - 1
+        });
+        *txns[idx].1.entry(row[1].to_string()).or_insert(0) += share;
+    }
+
+    let mut transfers = vec![];
+    for (txn, shares) in txns {
+        let total: isize = shares.values().sum();
+        if total != 0 {
+            return Err(format!("The shares of transaction '{}' sum to {}, not zero", txn, total));
+        }
+        // `construct_plan` finds transfers which cancel out the balances it's given, so give it
+        // the opposite of what we want
+        for mut t in construct_plan(shares.into_iter().map(|(who, x)| (who, -x)).collect()) {
+            t.normalise();
+            transfers.push(t);
+        }
+    }
+    Ok(transfers)
+}
+
+/// Some transfers as rows of a long-format table, one transaction each.
+pub fn rows(transfers: &[Transfer<String>]) -> Result<Vec<String>, String> {
+    let mut lines = vec![];
+    for t in transfers {
+        if let Some(field) = ::ledger::unwritable(t, &["id"]) {
+            return Err(format!("The long format has nowhere to put the {}", field));
+        }
+        let txn = t.id.clone().unwrap_or_else(|| t.digest());
+        let mut writer = ::csv::Writer::from_writer(vec![]);
+        for (who, share) in &[(&t.from, -t.amt), (&t.to, t.amt)] {
+            writer.write_record([txn.as_str(), who.as_str(), &share.to_string()]).map_err(|e| e.to_string())?;
+        }
+        let text = String::from_utf8(writer.into_inner().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        lines.extend(text.lines().map(String::from));
+    }
+    Ok(lines)
+}
+
+#[test]
+fn test_read() {
+    use ledger::balances;
+    let table = "txn,person,share\ndinner,ann,-3000\ntaxi,bob,-900\ndinner,bob,1500\ntaxi,ann,900\ndinner,carol,1500\n";
+    let transfers = read(table.as_bytes()).unwrap();
+    assert_eq!(balances(transfers).1, vec![("ann".into(), -2100), ("bob".into(), 600), ("carol".into(), 1500)]);
+    assert!(read("t1,ann,-5\nt1,bob,4\n".as_bytes()).unwrap_err().contains("sum to -1"));
+    assert!(read("t1,ann,-5\nt1,bob,x\n".as_bytes()).unwrap_err().starts_with("Line 2"));
+}
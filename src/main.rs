@@ -1,188 +1,2174 @@
-extern crate bitset64;
 extern crate clap;
 extern crate env_logger;
 #[macro_use] extern crate log;
-extern crate mcmf;
 extern crate mzsp;
-extern crate serde;
-#[macro_use] extern crate serde_derive;
+#[cfg(feature = "parallel")] extern crate rayon;
+extern crate repay;
 extern crate serde_json;
 
-use mcmf::*;
-use mzsp::MZSP;
-use std::collections::BTreeMap;
-use std::fs::File;
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use repay::accounts;
+use repay::backtest::{self, Interval, Strategy};
+use repay::bank;
+use repay::chain;
+use repay::config::Config;
+use repay::date::{Date, Time, Timestamp};
+use repay::diff::{self, Change};
+use repay::format::{self, Confidence, Format};
+use repay::history::{self, Run};
+use repay::ledger::{self, Transfer};
+use repay::lint::{self, Severity};
+use repay::memo::Memos;
+use repay::names::Names;
+use repay::netting::{self, PairwiseNetting};
+use repay::output::{self, Color, OutputFormat};
+use repay::plan::{self, Mode, Plan};
+use repay::quick;
+use repay::rates::Rates;
+use repay::equalize;
+use repay::explain;
+use repay::export::{self, DebtGraph, GraphFormat};
+use repay::statement;
+use repay::table::{Align, Table};
+use repay::units::Amounts;
+use repay::verify;
+#[cfg(feature = "parallel")] use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// With `--output`, what's been printed so far, and where it'll go at the end.
+    static OUTPUT: RefCell<Option<(PathBuf, Vec<u8>)>> = const { RefCell::new(None) };
+}
+
+/// How `repay` exits, if something goes wrong.  (It exits with 0 if all went well.)  Scripts can
+/// tell what happened from this, without reading the message.
+#[derive(Clone, Copy, Debug)]
+enum Exit {
+    /// Something else went wrong, eg. a file couldn't be read or written.
+    Failure = 1,
+    /// The ledger, the config, or the flags don't make sense.
+    BadInput = 2,
+    /// There's no plan which does what was asked, eg. because the balances can't be converted into
+    /// the same currency, or there are too many of them for the exact solver.
+    Infeasible = 3,
+    /// The exact solver ran out of time (see `--timeout`).
+    Timeout = 4,
+    /// A check found a problem: `repay lint` found errors, `repay verify` found that the plan
+    /// doesn't settle up, or `repay verify-chain` found that the ledger was tampered with.
+    CheckFailed = 5,
+}
+
+impl Exit {
+    fn name(self) -> &'static str {
+        match self {
+            Exit::Failure => "failure",
+            Exit::BadInput => "bad-input",
+            Exit::Infeasible => "infeasible",
+            Exit::Timeout => "timeout",
+            Exit::CheckFailed => "check-failed",
+        }
+    }
+}
+
+/// With `--error-format=json`, errors which make us exit are written as JSON objects.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Report an error and exit.  It takes an `Exit`, and then a message like `format!`.
+macro_rules! fail {
+    ($exit:expr, $($arg:tt)*) => { fail($exit, format!($($arg)*)) }
+}
+
+fn fail(exit: Exit, message: String) -> ! {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        let mut error = serde_json::Map::new();
+        error.insert("error".into(), exit.name().into());
+        error.insert("code".into(), (exit as i32).into());
+        error.insert("message".into(), message.into());
+        eprintln!("{}", serde_json::Value::from(error));
+    } else {
+        error!("{}", message);
+    }
+    ::std::process::exit(exit as i32);
+}
+
+/// Like `println!`, but writes to `--output` if it was given.
+macro_rules! out {
+    ($($arg:tt)*) => { print_line(format!($($arg)*)) }
+}
+
+fn print_line(line: String) {
+    OUTPUT.with(|output| match *output.borrow_mut() {
+        Some((_, ref mut buf)) => {
+            buf.extend_from_slice(line.as_bytes());
+            buf.push(b'\n');
+        }
+        None => println!("{}", line),
+    })
+}
+
+/// Write everything which was printed to `--output`, all at once, so it's never seen half-written.
+/// (Nothing is written if we exit with an error first.)
+fn write_output() {
+    if let Some((path, buf)) = OUTPUT.with(|output| output.borrow_mut().take()) {
+        ledger::write_atomically(&path, &buf).unwrap_or_else(|e| {
+            fail!(Exit::Failure, "Couldn't write to {}: {}", path.display(), e);
+        });
+    }
+}
+
+/// The command-line interface.
+fn app() -> App<'static, 'static> {
+    App::new("debtor").version("1.0")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::from_usage("<PATH>... 'The ledgers containing historical transactions (read in order, and combined)'"))
+        .arg(Arg::from_usage("-a, --approx 'Guarantee a fast solution (may be suboptimal)'").global(true))
+        .arg(Arg::from_usage("-x, --exact 'Guarantee an exact solution (may be slow)'").global(true)
+            .conflicts_with("approx"))
+        .arg(Arg::from_usage("-c, --config=[FILE] 'A config file (JSON)'").global(true))
+        .arg(Arg::from_usage("-f, --format=[FORMAT] 'The format of the ledger (default: guess)'").global(true))
+        .arg(Arg::from_usage("--csv-cols=[COLS] 'The columns of a CSV ledger, eg. from,to,amt,date (implies --format=csv; default: read the header)'").global(true))
+        .arg(Arg::from_usage("--account-prefix=[PREFIX] 'In a journal, the accounts which are people\'s (eg. people:)'").global(true))
+        .arg(Arg::from_usage("--skip-bad-records 'Skip records which can\'t be read (with a warning), instead of giving up'").global(true))
+        .arg(Arg::from_usage("--strict 'Treat transfers from someone to themselves, or of nothing, as bad records'").global(true))
+        .arg(Arg::from_usage("--lenient 'Drop transfers from someone to themselves, or of nothing, without a warning'")
+            .conflicts_with("strict").global(true))
+        .arg(Arg::from_usage("--since=[DATE] 'Only count transfers dated on or after DATE (eg. 2024-05-01)'").global(true))
+        .arg(Arg::from_usage("--until=[DATE] 'Only count transfers dated on or before DATE (alias: --as-of)'").global(true)
+            .alias("as-of"))
+        .arg(Arg::from_usage("--filter=[COND]... 'Only count transfers with this tag (tag=TAG) or category (category=CAT)'")
+            .number_of_values(1).global(true))
+        .arg(Arg::from_usage("--group=[NAME] 'Only count transfers between the members of this group (see the config file)'").global(true))
+        .arg(Arg::from_usage("--rates=[FILE] 'Exchange rates (JSON), for converting between currencies'").global(true))
+        .arg(Arg::from_usage("--settle-in=[CURRENCY] 'Convert every balance into CURRENCY, and settle them all together'").global(true))
+        .arg(Arg::from_usage("--locale=[LOCALE] 'How amounts are written, eg. de for 1.234,56 (default: en, for 1,234.56)'").global(true))
+        .arg(Arg::from_usage("--currency-symbol=[SYMBOL] 'Ignore this currency symbol in amounts (as well as the usual ones)'").global(true))
+        .arg(Arg::from_usage("--unit=[UNIT] 'Display amounts in major units if their currency is known (auto, the default), or always in minor units (cents) or major units (major)'").global(true))
+        .arg(Arg::from_usage("--amounts=[REPR] 'Write amounts in the plan as integers (minor), strings (decimal), or floats (float)'").global(true))
+        .arg(Arg::from_usage("--output-format=[FORMAT] 'Write the plan as JSON (one object per line), CSV, an aligned table, a Markdown table, or a Graphviz graph'")
+            .possible_values(&["json", "csv", "table", "markdown", "dot"]).global(true))
+        .arg(Arg::from_usage("--color=[WHEN] 'Colour tables: payers red, payees green, and big amounts bold (default: auto, if stdout is a terminal)'")
+            .possible_values(&["auto", "always", "never"]).global(true))
+        .arg(Arg::from_usage("-o, --output=[FILE] 'Write the plan (or whatever would go to stdout) to FILE, replacing it all at once when done'").global(true))
+        .arg(Arg::from_usage("--explain 'Say which group of people each repayment settles, and which of their debts it stands in for (in the JSON output)'").global(true))
+        .arg(Arg::from_usage("--by-group 'Settle each group (see the config file) separately, and say which group each repayment is for'")
+            .conflicts_with_all(&["group", "dot-debts", "diff", "interactive"]))
+        .arg(Arg::from_usage("--dot-debts 'With --output-format=dot, draw the debts which the plan settles next to it'"))
+        .arg(Arg::from_usage("--diff=[PLAN] 'Instead of the plan, show which repayments were added, removed, or changed since PLAN (as printed by an earlier run)'"))
+        .arg(Arg::from_usage("-i, --interactive 'Go through the plan one repayment at a time, accepting, skipping, or changing each (the rest is worked out again after a change)'"))
+        .arg(Arg::from_usage("--time 'At the end, say how long each phase took (parse, balance, partition, plan, output), and the peak memory use (in the --log-format)'"))
+        .arg(Arg::from_usage("--cache-dir=[DIR] 'Keep solved problems in DIR, and reuse them'").global(true))
+        .arg(Arg::from_usage("--short-chains 'Prefer plans where people settle up in small groups (exact mode only)'").global(true))
+        .arg(Arg::from_usage("--history=[FILE] 'Record which solver each run used, and how long it took, in FILE'").global(true))
+        .arg(Arg::from_usage("-q, --quiet 'Only print the plan (and any errors): no warnings, and no summary of the run at the end'").global(true))
+        .arg(Arg::from_usage("--error-format=[FORMAT] 'If repay has to give up, say why as text, or as a JSON object (with the kind of error, and the exit code)'")
+            .possible_values(&["text", "json"]).default_value("text").global(true))
+        .arg(Arg::from_usage("--timeout=[SECS] 'Give up if the exact solver takes longer than this (exiting with 4)'").global(true))
+        .arg(Arg::from_usage("--log-format=[FORMAT] 'Write log messages (and the summary) to stderr as text, or as one JSON object per line'")
+            .possible_values(&["text", "json"]).default_value("text").global(true))
+        .arg(Arg::from_usage("-v... 'Increase the level of verbosity'").global(true))
+        .subcommand(SubCommand::with_name("add")
+            .about("Add a transfer to the ledger (eg. --from ann --to bob --amt 12.50), or an expense described in words (eg. \"alice paid 84.30 for dinner split bob,carol\")")
+            .args_from_usage(
+                "<PATH>                 'The ledger to add to'
+                 [EXPENSE]              'What was paid: \"<payer> paid <amount> [for <what>] split <person>[:<weight>],...\" or \"<payer> paid <amount> to <person>\"'
+                 --from=[NAME]          'Who the transfer is from'
+                 --to=[NAME]            'Who the transfer is to'
+                 --amt=[AMOUNT]         'How much, in major units (eg. 12.50)'
+                 --currency=[CURRENCY]  'Which currency the amount is in'
+                 --memo=[MEMO]          'What the transfer was for'
+                 --category=[CATEGORY]  'The transfer\'s category'
+                 --tag=[TAG]...         'A tag for the transfer'
+                 --date=[DATE]          'When the transfer was made'
+                 --from-account=[NAME]  'Which of the sender\'s accounts it was paid from'
+                 --to-account=[NAME]    'Which of the recipient\'s accounts it was paid into'"))
+        .subcommand(SubCommand::with_name("accounts")
+            .about("Show how much each person paid and received through each of their accounts")
+            .args_from_usage(
+                "<PATH>                 'The ledger containing historical transactions'"))
+        .subcommand(SubCommand::with_name("archive")
+            .about("Move the ledger's old records into a compressed archive, leaving a checkpoint of the balances in their place")
+            .args_from_usage(
+                "<PATH>                 'The ledger to archive'
+                 --before=<DATE>        'Archive everything before the first record dated on or after DATE'
+                 --to=[ARCHIVE]         'Where to write the archive (default: eg. ledger.before-2024-01-01.jsonl.gz, next to the ledger)'"))
+        .subcommand(SubCommand::with_name("backtest")
+            .about("Replay the ledger, settling up every so often, and report what it would have taken")
+            .args_from_usage(
+                "<PATH>                 'The ledger containing historical transactions'")
+            .arg(Arg::from_usage("--every=[INTERVAL] 'Settle up after every INTERVAL transfers, at the end of every day/week/month/year, or only at the end'")
+                .default_value("end"))
+            .arg(Arg::from_usage("--strategy=[STRATEGY] 'How to settle up: with a repayment plan, or by netting each pair\'s debts'")
+                .possible_values(&["plan", "net-pairs"]).default_value("plan")))
+        .subcommand(SubCommand::with_name("balances")
+            .about("Show what everyone owes, or is owed (as JSON, or a table with --output-format=table)")
+            .args_from_usage(
+                "<PATH>...              'The ledgers to read'"))
+        .subcommand(SubCommand::with_name("chain")
+            .about("Add a running hash to each record in the ledger, so that changes to it can be detected with verify-chain")
+            .args_from_usage(
+                "<PATH>                 'The ledger to chain'"))
+        .subcommand(SubCommand::with_name("checkpoint")
+            .about("Append a snapshot of everyone's balances to the ledger, so that what comes before it can be archived")
+            .args_from_usage(
+                "<PATH>                 'The ledger to add the checkpoint to'
+                 --date=[DATE]          'The date of the checkpoint (default: today)'"))
+        .subcommand(SubCommand::with_name("confirm")
+            .about("Record that planned repayments were actually made")
+            .args_from_usage(
+                "<PATH>                 'The ledger to append the confirmations to'
+                 --transfer-id=[ID]...  'Confirm the planned repayment with this id'
+                 --all-from=[PERSON]    'Confirm every planned repayment made by PERSON'
+                 --amount=[AMT]         'Only this much of the repayment was made, in minor units or eg. 12.34 (requires a single --transfer-id)'"))
+        .subcommand(SubCommand::with_name("convert")
+            .about("Write the transfers in a ledger out in another format")
+            .args_from_usage(
+                "<PATH>                 'The ledger to convert (in the format given by --format, or guessed)'
+                 <OUTPUT>               'Where to write the new ledger (\'-\' for stdout)'
+                 --to=[FORMAT]          'The format to write: json, csv, long, journal, or sqlite (SQL statements; default: guess from OUTPUT\'s name)'"))
+        .subcommand(SubCommand::with_name("equalize")
+            .about("Plan repayments so that everyone ends up having paid the same amount")
+            .args_from_usage(
+                "<PATH>                 'A ledger of expenses (each transfer is money spent by \'from\')'
+                 --member=[PERSON]...   'Include PERSON, even if they haven\'t paid for anything'
+                 --category=[CAT]...    'Only count expenses in this category'"))
+        .subcommand(SubCommand::with_name("export")
+            .about("Write out the ledger's debt network (before any netting) as a graph")
+            .args_from_usage(
+                "<PATH>                 'The ledger to export'")
+            .arg(Arg::from_usage("--what=[WHAT] 'What to export'")
+                .possible_values(&["debt-graph"]).default_value("debt-graph"))
+            .arg(Arg::from_usage("--to=[FORMAT] 'The graph format to write (--format is the ledger\'s)'")
+                .possible_values(&["dot", "graphml"]).default_value("dot")))
+        .subcommand(SubCommand::with_name("import")
+            .about("Import transfers from another file (in the format given by --format) into the ledger")
+            .args_from_usage(
+                "<PATH>                 'The ledger to import into'
+                 <SOURCE>               'The file to import from (\'-\' to read rows pasted on stdin)'
+                 --preview              'Show what would be imported, and how it would change the balances (the default)'
+                 --bank=[PROFILE]       'SOURCE is a bank statement (CSV), to be read with this profile (JSON)'
+                 -i, --interactive      'Ask about each transfer before importing it'")
+            .arg(Arg::from_usage("--commit 'Append the imported transfers to the ledger'").conflicts_with("preview")))
+        .subcommand(SubCommand::with_name("lint")
+            .about("Look for records which are probably mistakes, and print what was found as JSON")
+            .args_from_usage(
+                "<PATH>                 'The ledger to check'
+                 --roster=[FILE]        'Everyone who should be in the ledger, one name per line (default: everyone in the config\'s groups)'"))
+        .subcommand(SubCommand::with_name("net-pairs")
+            .about("Plan repayments which only cancel out debts between each pair of people")
+            .args_from_usage(
+                "<PATH>                 'The ledger containing historical transactions'"))
+        .subcommand(SubCommand::with_name("settle")
+            .about("Compute the plan, and record its repayments in the ledger as made, so that everyone's balance is zero")
+            .args_from_usage(
+                "<PATH>                 'The ledger to settle'
+                 --date=[DATE]          'When the repayments were made (default: today)'
+                 -y, --yes              'Don\'t ask before appending the repayments'")
+            .arg(Arg::from_usage("--tag=[TAG] 'Tag the repayments with TAG'").default_value("settlement")))
+        .subcommand(SubCommand::with_name("statement")
+            .about("List every transfer to or from someone, with their running balance, and what they'll pay or receive under the plan")
+            .args_from_usage(
+                "<PATH>                 'The ledger containing historical transactions'
+                 <PERSON>               'Whose statement to show'"))
+        .subcommand(SubCommand::with_name("verify")
+            .about("Check that a plan (eg. one edited by hand) would settle everyone's balances, and report anyone it wouldn't")
+            .args_from_usage(
+                "<PATH>                 'The ledger containing historical transactions'
+                 <PLAN>                 'The plan to check: the JSON which repay printed (\'-\' for stdin)'"))
+        .subcommand(SubCommand::with_name("verify-chain")
+            .about("Check that nobody has changed a chained ledger, and print the last hash")
+            .args_from_usage(
+                "<PATH>                 'The ledger to check'
+                 --head=[HASH]          'A hash printed earlier, which the chain should still reach (so it hasn\'t been truncated)'"))
+        .subcommand(SubCommand::with_name("recommend")
+            .about("Suggest which solver to use for a ledger, based on the history of past runs (see --history)")
+            .args_from_usage(
+                "<PATH>                 'The ledger to make a suggestion for'"))
+        .subcommand(SubCommand::with_name("people")
+            .about("List everyone in the ledgers, one per line (after any aliases)")
+            .args_from_usage(
+                "<PATH>...              'The ledgers to read'"))
+        .subcommand(SubCommand::with_name("completions")
+            .about("Print a script which makes the shell complete repay's arguments, including people's names (from the ledger given earlier on the command line)")
+            .arg(Arg::from_usage("<SHELL> 'Which shell the script is for'")
+                .possible_values(&["bash", "zsh", "fish"])))
+}
 
 fn main() {
+    let started = Instant::now();
     // Parse the command-line arguments
-    let opts = clap::App::new("debtor").version("1.0")
-        .args_from_usage(
-            "<PATH>         'The ledger containing historical transactions'
-             -a, --approx   'Guarantee a fast solution (may be suboptimal)'
-             -x, --exact    'Guarantee an exact solution (may be slow)'
-             -v...          'Increase the level of verbosity'")
-        .get_matches();
+    let opts = app().get_matches();
 
     // Initialise the logger (prints to stderr)
     let log_level = match opts.occurrences_of("v") {
+        _ if opts.is_present("quiet") => log::LevelFilter::Error,
         0 => log::LevelFilter::Warn,
         1 => log::LevelFilter::Info,
         2 => log::LevelFilter::Debug,
-        3 | _ => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+    let json_logs = opts.value_of("log-format") == Some("json");
+    let mut logger = env_logger::Builder::new();
+    logger.filter(None, log_level);
+    if json_logs {
+        logger.format(|buf, record| {
+            let mut event = serde_json::Map::new();
+            event.insert("time".into(), now().to_string().into());
+            event.insert("level".into(), record.level().to_string().to_lowercase().into());
+            event.insert("message".into(), record.args().to_string().into());
+            writeln!(buf, "{}", serde_json::Value::from(event))
+        });
+    }
+    logger.init();
+    JSON_ERRORS.store(opts.value_of("error-format") == Some("json"), Ordering::Relaxed);
+    {
+        use std::io::IsTerminal;
+        PROGRESS.store(::std::io::stderr().is_terminal() && !opts.is_present("quiet") && !json_logs, Ordering::Relaxed);
+    }
+
+    // Load the config file, if any.  Flags take precedence over the config.
+    let mut config = match opts.value_of_os("config") {
+        None => Config::default(),
+        Some(path) => Config::load(Path::new(path)).unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "Couldn't load config: {}", e);
+        }),
+    };
+    if let Some(unit) = opts.value_of("unit") {
+        config.display.unit = unit.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        });
+    }
+    if let Some(dir) = opts.value_of_os("cache-dir") {
+        config.cache_dir = Some(dir.into());
+    }
+    if let Some(path) = opts.value_of_os("history") {
+        config.history_file = Some(path.into());
+    }
+    if let Some(cols) = opts.value_of("csv-cols") {
+        config.csv_columns = Some(cols.split(',').map(|c| c.trim().to_string()).collect());
+    }
+    if let Some(prefix) = opts.value_of("account-prefix") {
+        config.account_prefix = Some(prefix.into());
+    }
+    if let Some(locale) = opts.value_of("locale") {
+        config.locale = locale.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        });
+    }
+    if let Some(symbol) = opts.value_of("currency-symbol") {
+        config.currency_symbol = Some(symbol.into());
+    }
+    if let Some(path) = opts.value_of_os("rates") {
+        config.rates_file = Some(path.into());
+    }
+    if let Some(currency) = opts.value_of("settle-in") {
+        config.settle_in = Some(currency.into());
+    }
+    if opts.is_present("explain") {
+        config.explain = true;
+    }
+    if opts.is_present("skip-bad-records") {
+        config.skip_bad_records = true;
+    }
+    if opts.is_present("strict") {
+        config.validation = ledger::Validation::Strict;
+    }
+    if opts.is_present("lenient") {
+        config.validation = ledger::Validation::Lenient;
+    }
+    let date = |flag: &str| opts.value_of(flag).map(|x| x.parse::<Date>().unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "--{}: {}", flag, e);
+    }));
+    config.since = date("since");
+    config.until = date("until");
+    for cond in opts.values_of("filter").into_iter().flatten() {
+        config.conditions.push(cond.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        }));
+    }
+    if let Some(group) = opts.value_of("group") {
+        let members = config.groups.get(group).unwrap_or_else(|| {
+            let groups = config.groups.keys().map(|x| x.as_str()).collect::<Vec<_>>();
+            fail!(Exit::BadInput, "There's no group called {} (the groups are: {})", group, if groups.is_empty() { "none".into() } else { groups.join(", ") });
+        });
+        config.conditions.push(ledger::Condition::Members(members.iter().cloned().collect()));
+    }
+    if opts.is_present("short-chains") {
+        config.short_chains = true;
+    }
+    if let Some(secs) = opts.value_of("timeout") {
+        config.timeout_secs = Some(secs.parse().unwrap_or_else(|_| {
+            fail!(Exit::BadInput, "--timeout: {} isn't a whole number of seconds", secs);
+        }));
+    }
+    if let Some(amounts) = opts.value_of("amounts") {
+        config.display.amounts = amounts.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        });
+    }
+    if let Some(output) = opts.value_of("output-format") {
+        config.display.output = output.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        });
+    }
+    if let Some(color) = opts.value_of("color") {
+        config.display.color = color.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        });
+    }
+    if let Some(path) = opts.value_of_os("output") {
+        OUTPUT.with(|output| *output.borrow_mut() = Some((path.into(), vec![])));
+        // Whether to colour depends on where it's going
+        if config.display.color == Color::Auto { config.display.color = Color::Never; }
+    }
+    config.display.color = config.display.color.resolve();
+    config.display.locale = config.locale.clone();
+    if config.display.amounts == Amounts::Float {
+        warn!("Writing amounts as floats, which may not be exact.  (Use '--amounts=decimal' for exact decimals)");
+    }
+    let mode = Mode::from_flags(opts.is_present("exact"), opts.is_present("approx")).unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    });
+    let format = opts.value_of("format").map(|x| x.parse::<Format>().unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    })).or_else(|| if opts.is_present("csv-cols") { Some(Format::Csv) } else { None });
+
+    match opts.subcommand() {
+        ("accounts", Some(sub)) => accounts(&config, format, sub),
+        ("add", Some(sub)) => add(&config, format, sub),
+        ("archive", Some(sub)) => archive(&config, format, sub),
+        ("backtest", Some(sub)) => backtest(&config, mode, format, sub),
+        ("balances", Some(sub)) => balances(&config, format, sub),
+        ("chain", Some(sub)) => chain(&config, format, sub),
+        ("completions", Some(sub)) => completions(sub),
+        ("checkpoint", Some(sub)) => checkpoint(&config, format, sub),
+        ("confirm", Some(sub)) => confirm(&config, mode, format, sub),
+        ("convert", Some(sub)) => convert_ledger(&config, format, sub),
+        ("equalize", Some(sub)) => equalize(&config, mode, format, sub),
+        ("export", Some(sub)) => export(&config, format, sub),
+        ("import", Some(sub)) => import(&config, format, sub),
+        ("lint", Some(sub)) => lint(&config, format, sub),
+        ("net-pairs", Some(sub)) => net_pairs(&config, format, sub),
+        ("people", Some(sub)) => people(&config, format, sub),
+        ("recommend", Some(sub)) => recommend(&config, format, sub),
+        ("settle", Some(sub)) => settle(&config, mode, format, sub),
+        ("statement", Some(sub)) => statement(&config, mode, format, sub),
+        ("verify", Some(sub)) => verify(&config, format, sub),
+        ("verify-chain", Some(sub)) => verify_chain(&config, format, sub),
+        _ if opts.is_present("by-group") => {
+            let mut summary = Summary::default();
+            let paths = ledger_paths(&opts);
+            use_ledger_headers(&mut config, format, &paths);
+            settle_groups(&config, mode, format, &paths, &mut summary);
+            write_output();
+            if !opts.is_present("quiet") { summary.print(&config, json_logs); }
+            if opts.is_present("time") { summary.print_timings(started.elapsed(), json_logs); }
+        }
+        _ => {
+            let mut summary = Summary::default();
+            let paths = ledger_paths(&opts);
+            use_ledger_headers(&mut config, format, &paths);
+            let mut plan = compute_plan(&config, mode, format, &paths, &mut summary);
+            if opts.is_present("interactive") {
+                if paths.iter().any(|p| p == Path::new("-")) {
+                    fail!(Exit::BadInput, "--interactive reads the answers from stdin, so the ledger can't come from there too");
+                }
+                plan.transfers = review(&config, mode, plan.transfers);
+                summary.repayments = plan.transfers.len();
+                summary.flow.clear();
+                summary.largest.clear();
+                for t in &plan.transfers {
+                    *summary.flow.entry(t.currency.clone()).or_default() += t.amt;
+                    let largest = summary.largest.entry(t.currency.clone()).or_default();
+                    *largest = t.amt.max(*largest);
+                }
+                summary.involved = plan.transfers.iter().flat_map(|t| [&t.from, &t.to]).collect::<BTreeSet<_>>().len();
+            }
+            let ts = Instant::now();
+            if config.display.output == OutputFormat::Dot && opts.is_present("dot-debts") {
+                let mut debts = DebtGraph::default();
+                // The merges were reported when the plan was worked out
+                for t in (Counted { reported: true, ..read_counted(&config, format, &paths) }) { debts.add(&t); }
+                for line in export::plan_dot(&plan.transfers, Some(&debts), &config.display) { out!("{}", line); }
+            } else if let Some(old) = opts.value_of_os("diff") {
+                let people: Vec<&String> = plan.transfers.iter().flat_map(|t| [&t.from, &t.to]).collect();
+                let old = read_plan(&config, Path::new(old), people.into_iter());
+                print_diff(&config, &old, &plan.transfers);
+            } else {
+                print_plan(&config, &plan);
+            }
+            write_output();
+            summary.output = ts.elapsed();
+            if !opts.is_present("quiet") { summary.print(&config, json_logs); }
+            if opts.is_present("time") { summary.print_timings(started.elapsed(), json_logs); }
+        }
+    }
+    write_output();
+}
+
+/// Stream the transfers out of a ledger.  A bad record is fatal, unless the user asked to skip them.
+fn read_ledger(config: &Config, path: &Path, format: Format) -> Box<dyn Iterator<Item=Transfer<String>>> {
+    check_records(config, ledger::records(path, format, &config.read_options()))
+}
+
+/// The transfers which count (see `Config::filter`), with everyone's names spelt the same way.
+/// Once the last one has been read, says which names were merged.
+struct Counted<I> {
+    transfers: I,
+    names: Names,
+    filter: ledger::Filter,
+    /// Whether the merges have been reported already.
+    reported: bool,
+}
+
+impl<I: Iterator<Item=Transfer<String>>> Iterator for Counted<I> {
+    type Item = Transfer<String>;
+    fn next(&mut self) -> Option<Transfer<String>> {
+        for t in self.transfers.by_ref() {
+            let t = self.names.normalise(t);
+            if self.filter.accepts(&t) { return Some(t); }
+        }
+        if !self.reported {
+            report_merges(&self.names);
+            self.reported = true;
+        }
+        None
+    }
+}
+
+/// The transfers out of these which count (see `Counted`), spelling names as `names` does.
+fn counted<I: Iterator<Item=Transfer<String>>>(config: &Config, names: Names, transfers: I) -> Counted<I> {
+    Counted { transfers, names, filter: config.filter(), reported: false }
+}
+
+/// Read the transfers which count (see `Counted`) out of the ledgers, one after the other.
+fn read_counted<'a>(config: &'a Config, format: Option<Format>, ledger_paths: &[PathBuf])
+    -> Counted<impl Iterator<Item=Transfer<String>> + 'a>
+{
+    let ledger_paths: Vec<PathBuf> = ledger_paths.into();
+    let transfers = ledger_paths.into_iter()
+        .flat_map(move |path| read_ledger(config, &path, ledger_format(config, format, &path)));
+    counted(config, config.names(), transfers)
+}
+
+/// Say which names were merged: quietly if the config file said to, and with a warning if not.
+fn report_merges(names: &Names) {
+    for m in names.merges() {
+        if m.expected { info!("{}", m) } else { warn!("{}", m) }
+    }
+}
+
+fn check_records(config: &Config, records: ledger::Records) -> Box<dyn Iterator<Item=Transfer<String>>> {
+    let skip = config.skip_bad_records;
+    Box::new(records.filter_map(move |x| match x {
+        Ok(t) => Some(t),
+        Err(e) if skip => { warn!("Skipping a bad record: {}", e); None }
+        Err(e) => fail!(Exit::BadInput, "{}  (Use '--skip-bad-records' to skip records like this)", e),
+    }))
+}
+
+/// Read transfers from stdin, eg. rows pasted from a spreadsheet.  The format is guessed, unless
+/// it's given.
+fn read_pasted(config: &Config, format: Option<Format>) -> Box<dyn Iterator<Item=Transfer<String>>> {
+    let mut text = String::new();
+    ::std::io::stdin().read_to_string(&mut text).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't read stdin: {}", e);
+    });
+    let format = format.unwrap_or_else(|| {
+        let detection = format::detect_str(&text);
+        if detection.confidence == Confidence::Low {
+            warn!("Reading stdin as {}, but {}.  (Use '--format' to choose another format)", detection.format, detection.reason);
+        } else {
+            info!("Reading stdin as {}, since {}", detection.format, detection.reason);
+        }
+        detection.format
+    });
+    check_read_options(config, format, "stdin");
+    check_records(config, ledger::read_records(::std::io::Cursor::new(text.into_bytes()), format, &config.read_options()))
+}
+
+/// The format of the ledger: either the one given by the user, or our best guess.
+fn ledger_format(config: &Config, format: Option<Format>, ledger_path: &Path) -> Format {
+    let format = format.unwrap_or_else(|| guess_format(ledger_path));
+    check_read_options(config, format, &ledger_path.display().to_string());
+    format
+}
+
+/// Make sure we have whatever options the format needs.
+fn check_read_options(config: &Config, format: Format, source: &str) {
+    if format == Format::Journal && config.account_prefix.is_none() {
+        fail!(Exit::BadInput, "Which of the accounts in {} are people's?  (Use '--account-prefix', eg. '--account-prefix=people:')", source);
+    }
+}
+
+fn guess_format(ledger_path: &Path) -> Format {
+    let detection = format::detect(ledger_path).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't read {}: {}", ledger_path.display(), e);
+    });
+    if detection.confidence == Confidence::Low {
+        warn!("Reading {} as {}, but {}.  (Use '--format' to choose another format)",
+            ledger_path.display(), detection.format, detection.reason);
+    } else {
+        info!("Reading {} as {}, since {}", ledger_path.display(), detection.format, detection.reason);
+    }
+    detection.format
+}
+
+/// Print the plan to stdout, in the chosen output format (see `print_transfers`).
+fn print_plan(config: &Config, plan: &Plan) {
+    print_transfers(config, &plan.transfers);
+}
+
+/// Print planned repayments (or other transfers) to stdout, in the chosen output format.
+fn print_transfers(config: &Config, transfers: &[Transfer<String>]) {
+    for line in output::render(config.display.output, transfers, &config.display) {
+        out!("{}", line);
+    }
+}
+
+/// The ledgers named on the command line, with any globs expanded.
+fn ledger_paths(opts: &ArgMatches) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    for pattern in opts.values_of_os("PATH").unwrap() {
+        let matches = ledger::glob(Path::new(pattern)).unwrap_or_else(|e| {
+            fail!(Exit::Failure, "Couldn't expand {}: {}", Path::new(pattern).display(), e);
+        });
+        if matches.is_empty() {
+            fail!(Exit::BadInput, "No ledgers match {}", Path::new(pattern).display());
+        }
+        paths.extend(matches);
+    }
+    paths
+}
+
+/// If the ledgers' headers say which currency they're in, display amounts in that currency; and use
+/// any aliases they give.
+fn use_ledger_headers(config: &mut Config, format: Option<Format>, ledger_paths: &[PathBuf]) {
+    let mut currency: Option<(String, &Path)> = None;
+    // The ledger which gave each currency's format
+    let mut formats: BTreeMap<String, &Path> = BTreeMap::new();
+    for path in ledger_paths {
+        let guess = || format::detect(path).map(|d| d.format).unwrap_or(Format::Json);
+        if format.unwrap_or_else(guess) != Format::Json { continue; }
+        let header = ledger::header(path).unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        });
+        if let Some(ref header) = header {
+            config.names.add_aliases(&header.aliases).unwrap_or_else(|e| {
+                fail!(Exit::BadInput, "{}: {}", path.display(), e);
+            });
+            config.accounts.add(&header.accounts).unwrap_or_else(|e| {
+                fail!(Exit::BadInput, "{}: {}", path.display(), e);
+            });
+            for (code, fmt) in &header.currencies {
+                if let Some(other) = formats.get(code) {
+                    if config.display.currencies[code] != *fmt {
+                        fail!(Exit::BadInput, "{} and {} write {} differently", other.display(), path.display(), code);
+                    }
+                }
+                config.display.currencies.insert(code.clone(), fmt.clone());
+                formats.insert(code.clone(), path);
+            }
+        }
+        let theirs = match header.and_then(|h| h.base_currency) {
+            Some(x) => x,
+            None => continue,
+        };
+        match currency {
+            Some((ref ours, other)) if *ours != theirs => {
+                fail!(Exit::BadInput, "{} is in {}, but {} is in {}", other.display(), ours, path.display(), theirs);
+            }
+            _ => currency = Some((theirs, path)),
+        }
+    }
+    if let Some((currency, _)) = currency {
+        info!("The ledger is in {}", currency);
+        config.display.base_currency = Some(currency.clone());
+        config.base_currency = Some(currency);
+    }
+}
+
+/// What happened during a run, for the summary at the end.
+#[derive(Default)]
+struct Summary {
+    /// Everyone who appears in the ledger.
+    people: usize,
+    transfers: usize,
+    balances: usize,
+    /// Reading the ledgers, and computing the balances...
+    reading: Duration,
+    /// ...of which this much was spent parsing them.
+    parsing: Duration,
+    solver: Option<plan::Solver>,
+    /// Computing the plan...
+    solving: Duration,
+    /// ...of which this much was spent partitioning the balances (by the exact solver).
+    partitioning: Duration,
+    /// Writing out the plan.
+    output: Duration,
+    /// How many transfers were converted into the settlement currency, from each currency.
+    converted: BTreeMap<String, usize>,
+    repayments: usize,
+    /// The fewest repayments which any plan could have (see `Plan::at_least`).
+    at_least: usize,
+    /// The total repaid, in each currency.
+    flow: BTreeMap<Option<String>, isize>,
+    /// The largest single repayment, in each currency.
+    largest: BTreeMap<Option<String>, isize>,
+    /// How many people have a repayment to make or receive.
+    involved: usize,
+}
+
+impl Summary {
+    /// Print to stderr, so it doesn't get mixed up with the plan.  With `json`, it's a single JSON
+    /// object, like the log messages (with amounts as in the plan, and times in seconds).
+    fn print(&self, config: &Config, json: bool) {
+        if json {
+            let mut event = serde_json::Map::new();
+            event.insert("time".into(), now().to_string().into());
+            event.insert("level".into(), "info".into());
+            event.insert("event".into(), "summary".into());
+            event.insert("people".into(), self.people.into());
+            event.insert("transfers".into(), self.transfers.into());
+            event.insert("open_balances".into(), self.balances.into());
+            let converted: serde_json::Map<String, serde_json::Value> = self.converted.iter().map(|(ccy, &n)| (ccy.clone(), n.into())).collect();
+            event.insert("converted".into(), converted.into());
+            event.insert("solver".into(), self.solver.map(|s| s.to_string()).into());
+            event.insert("repayments".into(), self.repayments.into());
+            event.insert("at_least".into(), self.at_least.into());
+            let by_currency = |xs: &BTreeMap<Option<String>, isize>| -> serde_json::Map<String, serde_json::Value> {
+                xs.iter().map(|(ccy, &x)| (ccy.clone().unwrap_or_default(), config.display.json(x, ccy.as_deref()))).collect()
+            };
+            event.insert("flow".into(), by_currency(&self.flow).into());
+            event.insert("largest".into(), by_currency(&self.largest).into());
+            event.insert("involved".into(), self.involved.into());
+            event.insert("reading_secs".into(), self.reading.as_secs_f64().into());
+            event.insert("solving_secs".into(), self.solving.as_secs_f64().into());
+            eprintln!("{}", serde_json::Value::from(event));
+            return;
+        }
+        let secs = |d: Duration| format!("{}.{:0>3}s", d.as_secs(), d.subsec_millis());
+        eprintln!("Participants:   {}", self.people);
+        eprintln!("Transfers read: {}", self.transfers);
+        eprintln!("Open balances:  {}", self.balances);
+        if !self.converted.is_empty() {
+            let from = self.converted.iter().map(|(ccy, n)| format!("{} from {}", n, ccy)).collect::<Vec<_>>().join(", ");
+            eprintln!("Converted:      {} transfers ({})", self.converted.values().sum::<usize>(), from);
+        }
+        eprintln!("Solver:         {}", self.solver.map_or("none".to_string(), |s| s.to_string()));
+        let by_currency = |xs: &BTreeMap<Option<String>, isize>| if xs.is_empty() { config.display.format(0, None) } else {
+            xs.iter().map(|(ccy, &x)| amount(config, x, ccy.as_deref())).collect::<Vec<_>>().join(", ")
+        };
+        let fewest = if self.at_least == self.repayments { "the fewest possible".to_string() } else {
+            format!("no plan could have fewer than {}", self.at_least)
+        };
+        eprintln!("Repayments:     {} ({} in total; {})", self.repayments, by_currency(&self.flow), fewest);
+        eprintln!("Largest:        {}", by_currency(&self.largest));
+        eprintln!("Involved:       {} of the {} participants", self.involved, self.people);
+        eprintln!("Time:           {} reading, {} solving", secs(self.reading), secs(self.solving));
+    }
+
+    /// Print how long each phase took (for `--time`), and the peak memory use, to stderr.  `total` is
+    /// the whole run, which includes some things (like loading the config) which aren't in any phase.
+    fn print_timings(&self, total: Duration, json: bool) {
+        let phases = [
+            ("parse", self.parsing),
+            ("balance", self.reading.saturating_sub(self.parsing)),
+            ("partition", self.partitioning),
+            ("plan", self.solving.saturating_sub(self.partitioning)),
+            ("output", self.output),
+        ];
+        let memory = peak_memory();
+        if json {
+            let mut event = serde_json::Map::new();
+            event.insert("time".into(), now().to_string().into());
+            event.insert("level".into(), "info".into());
+            event.insert("event".into(), "timings".into());
+            let secs: serde_json::Map<String, serde_json::Value> = phases.iter().map(|&(phase, d)| (phase.to_string(), d.as_secs_f64().into())).collect();
+            event.insert("phases_secs".into(), secs.into());
+            event.insert("total_secs".into(), total.as_secs_f64().into());
+            event.insert("peak_memory_bytes".into(), memory.into());
+            eprintln!("{}", serde_json::Value::from(event));
+            return;
+        }
+        let secs = |d: Duration| format!("{}.{:0>6}s", d.as_secs(), d.subsec_micros());
+        for &(phase, d) in &phases {
+            eprintln!("{:<16}{}", format!("{}:", phase), secs(d));
+        }
+        eprintln!("{:<16}{}", "total:", secs(total));
+        match memory {
+            Some(bytes) => eprintln!("{:<16}{:.1} MiB", "peak memory:", bytes as f64 / (1 << 20) as f64),
+            None => eprintln!("{:<16}unknown", "peak memory:"),
+        }
+    }
+}
+
+/// The most memory this process has used at once (its peak resident set size), in bytes.  (Only on
+/// Linux, where it's in `/proc`.)
+fn peak_memory() -> Option<u64> {
+    let status = ::std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line["VmHWM:".len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// The time now, in UTC.
+fn now() -> Timestamp {
+    let since_epoch = ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let time = Time { secs: (secs % 86_400) as u32, nanos: since_epoch.subsec_nanos(), offset_secs: 0 };
+    Timestamp { date: Date::from_days((secs / 86_400) as i64), time: Some(time) }
+}
+
+/// An amount, with its currency code (if it has one).
+fn amount(config: &Config, amt: isize, currency: Option<&str>) -> String {
+    config.display.labelled(amt, currency)
+}
+
+/// Read the ledgers and compute a repayment plan for them.  Each currency is settled separately.
+fn compute_plan(config: &Config, mode: Mode, format: Option<Format>, ledger_paths: &[PathBuf], summary: &mut Summary) -> Plan {
+    let mut plan = None;
+    compute_plans(config, mode, format, ledger_paths, None, summary, |_, p| plan = Some(p));
+    plan.expect("everyone has a plan")
+}
+
+/// Read the ledgers and compute a repayment plan for each of the `groups` (or for everyone, without
+/// any).  Each group's currencies are settled separately, and `done` is given each group's plan as
+/// soon as all of them are, with its repayments tagged with the group's name.
+fn compute_plans(config: &Config, mode: Mode, format: Option<Format>, ledger_paths: &[PathBuf],
+    groups: Option<&BTreeMap<String, Vec<String>>>, summary: &mut Summary, mut done: impl FnMut(Option<&str>, Plan))
+{
+    // Step 1: Parse the ledgers and compute everyone's balances
+    let (balances, memos, debts) = read_group_balances(config, format, ledger_paths, groups, summary);
+
+    // Step 2: Figure out how to shift money around to make all the balances go back to 0
+    let names: Vec<Option<String>> = match groups {
+        Some(groups) => groups.keys().cloned().map(Some).collect(),
+        None => vec![None],
     };
-    env_logger::Builder::new().filter(None, log_level).init();
+    if groups.is_none() && balances.get(&None).is_some_and(|xs| xs.len() > 1) {
+        info!("Settling {} currencies separately", balances[&None].len());
+    }
+    // How many of each group's currencies are still being settled, and the plans for the rest
+    let mut left: BTreeMap<Option<String>, usize> = names.iter().map(|g| (g.clone(), balances.get(g).map_or(0, BTreeMap::len))).collect();
+    let mut plans: BTreeMap<Option<String>, Vec<(Option<String>, Plan)>> = BTreeMap::new();
+    let mut involved = BTreeSet::new();
+    let mut finish = |group: Option<String>, mut plans: Vec<(Option<String>, Plan)>, summary: &mut Summary| {
+        // However they finished, the currencies are in order
+        plans.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut plan: Option<Plan> = None;
+        for (currency, mut p) in plans {
+            *summary.flow.entry(currency.clone()).or_default() += p.transfers.iter().map(|t| t.amt).sum::<isize>();
+            let largest = summary.largest.entry(currency.clone()).or_default();
+            *largest = p.transfers.iter().map(|t| t.amt).fold(*largest, isize::max);
+            for t in &mut p.transfers {
+                t.currency = currency.clone();
+                t.memo = memos.describe(t);
+                if let Some(ref group) = group { t.extra.insert("group".into(), group.clone().into()); }
+            }
+            plan = Some(match plan {
+                None => p,
+                Some(mut plan) => {
+                    plan.transfers.extend(p.transfers);
+                    plan.warnings.extend(p.warnings);
+                    plan
+                }
+            });
+        }
+        let mut plan = plan.unwrap_or_else(|| solve(config, mode, vec![], summary));
+        match group {
+            Some(ref group) => info!("{}: {} repayments required", group, plan.transfers.len()),
+            None => info!("{} repayments required", plan.transfers.len()),
+        }
+        involved.extend(plan.transfers.iter().flat_map(|t| [t.from.clone(), t.to.clone()]));
+        if config.explain {
+            let explanations = explain::explain(&plan.transfers, debts.get(&group).unwrap_or(&BTreeMap::new()));
+            for (t, e) in plan.transfers.iter_mut().zip(explanations) {
+                let debts: Vec<serde_json::Value> = e.debts.iter().map(|(debtor, creditor, amt)| {
+                    let mut debt = serde_json::Map::new();
+                    debt.insert("debtor".into(), debtor.clone().into());
+                    debt.insert("creditor".into(), creditor.clone().into());
+                    debt.insert("amt".into(), config.display.json(*amt, t.currency.as_deref()));
+                    debt.into()
+                }).collect();
+                let mut explanation = serde_json::Map::new();
+                explanation.insert("group".into(), e.group.into());
+                explanation.insert("people".into(), e.people.into());
+                explanation.insert("debts".into(), debts.into());
+                t.extra.insert("explain".into(), explanation.into());
+            }
+        }
+        done(group.as_deref(), plan);
+    };
+    // The groups with nothing to settle are done already
+    for group in &names {
+        if left[group] == 0 { finish(group.clone(), vec![], summary); }
+    }
+    let jobs = balances.into_iter()
+        .flat_map(|(group, balances)| balances.into_iter().map(move |(currency, balances)| (group.clone(), currency, balances)))
+        .collect();
+    solve_jobs(config, mode, jobs, summary, |group, currency, plan, summary| {
+        plans.entry(group.clone()).or_default().push((currency, plan));
+        let left = left.get_mut(&group).expect("a job for a known group");
+        *left -= 1;
+        if *left == 0 {
+            let plans = plans.remove(&group).unwrap_or_default();
+            finish(group, plans, summary);
+        }
+    });
+    summary.involved = involved.len();
+}
 
-    // Step 1: Parse the ledger (JSON)
-    let ledger_path = opts.value_of("PATH").unwrap();
-    let ledger_file = File::open(ledger_path).unwrap();
-    let ledger_iter = serde_json::Deserializer::from_reader(ledger_file)
-        .into_iter().map(|x| x.expect("Deserialise line"));
+/// `repay --by-group`: a plan for each group.  As JSON, each group's repayments are printed as soon
+/// as its plan is ready; the other formats need them all at once.
+fn settle_groups(config: &Config, mode: Mode, format: Option<Format>, ledger_paths: &[PathBuf], summary: &mut Summary) {
+    if config.groups.is_empty() {
+        fail!(Exit::BadInput, "--by-group settles each group separately, but the config file doesn't define any groups");
+    }
+    let streaming = config.display.output == OutputFormat::Json;
+    let mut transfers = vec![];
+    let mut output = Duration::ZERO;
+    compute_plans(config, mode, format, ledger_paths, Some(&config.groups), summary, |_, plan| {
+        if streaming {
+            let ts = Instant::now();
+            print_plan(config, &plan);
+            output += ts.elapsed();
+        } else {
+            transfers.extend(plan.transfers);
+        }
+    });
+    let ts = Instant::now();
+    if !streaming { print_transfers(config, &transfers); }
+    summary.output = output + ts.elapsed();
+}
 
-    // Step 2: Compute everyone's balances (starting from 0)
-    let mut n = 0;
-    let mut balances = BTreeMap::new();
+/// Read the ledgers, one after the other, and compute everyone's balances.
+/// The balances in each currency are kept separate.  Also returns the transfers' memos, for
+/// explaining the plan.
+fn read_balances(config: &Config, format: Option<Format>, ledger_paths: &[PathBuf], summary: &mut Summary)
+    -> (ledger::CurrencyBalances, Memos, BTreeMap<Option<String>, DebtGraph>)
+{
+    let (mut balances, memos, mut debts) = read_group_balances(config, format, ledger_paths, None, summary);
+    (balances.remove(&None).unwrap_or_default(), memos, debts.remove(&None).unwrap_or_default())
+}
+
+/// Each group's debts, in each currency (for `--explain`).
+type GroupDebts = BTreeMap<Option<String>, BTreeMap<Option<String>, DebtGraph>>;
+
+/// Like `read_balances`, but with separate balances for each of the `groups` (see `--by-group`).  A
+/// transfer counts towards every group which has both its sender and its recipient.  Without any
+/// `groups`, everyone's balances are under `None`.  With `--features parallel`, several ledgers
+/// are read at once.
+fn read_group_balances(config: &Config, format: Option<Format>, ledger_paths: &[PathBuf],
+    groups: Option<&BTreeMap<String, Vec<String>>>, summary: &mut Summary)
+    -> (BTreeMap<Option<String>, ledger::CurrencyBalances>, Memos, GroupDebts)
+{
+    let formats: Vec<Format> = ledger_paths.iter().map(|path| ledger_format(config, format, path)).collect();
     let ts = ::std::time::Instant::now();
-    for transfer in ledger_iter {
-        let transfer: Transfer<String> = transfer;  // FIXME: dumb
-        {
-        let from = balances.entry(transfer.from).or_insert(0);
-        *from -= transfer.amt;
+    // Only needed for the report
+    let mut graph = DebtGraph::default();
+    let report = log_enabled!(log::Level::Info);
+    let mut people = BTreeSet::new();
+    let mut dates: Option<(Timestamp, Timestamp)> = None;
+    let mut undated = 0;
+    let mut memos = Memos::default();
+    // Only needed for `--explain`
+    let mut debts = GroupDebts::new();
+    // Which groups each transfer counts towards
+    let groups: Vec<(Option<String>, Option<ledger::Condition>)> = match groups {
+        Some(groups) => groups.iter()
+            .map(|(name, members)| (Some(name.clone()), Some(ledger::Condition::Members(members.iter().cloned().collect()))))
+            .collect(),
+        None => vec![(None, None)],
+    };
+    let in_groups = |t: &Transfer<String>| groups.iter()
+        .filter(|(_, members)| members.as_ref().is_none_or(|m| m.accepts(t)))
+        .map(|(name, _)| name.clone()).collect::<Vec<_>>();
+    let mut ungrouped = 0;
+    let rates = config.rates().unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    });
+    let mut converted = BTreeMap::new();
+    let mut bad_accounts = BTreeSet::new();
+    let reader = ledger_paths.iter().zip(formats)
+        .fold(ledger::LedgerReader::new(&config.read_options()), |reader, (path, format)| reader.ledger(path, format));
+    // Each ledger being read ahead has a thread of its own, which stops once it's this many records
+    // ahead of the balances
+    #[cfg(feature = "parallel")]
+    let mut reader = reader.read_ahead(rayon::current_num_threads(), 1024);
+    #[cfg(not(feature = "parallel"))]
+    let mut reader = reader;
+    // How long is spent in the reader itself, for `--time`
+    let parsing = Rc::new(Cell::new(Duration::ZERO));
+    let timed = {
+        let parsing = parsing.clone();
+        ::std::iter::from_fn(move || {
+            let ts = Instant::now();
+            let record = reader.next();
+            parsing.set(parsing.get() + ts.elapsed());
+            record
+        })
+    };
+    let transfers = counted(config, config.names(), check_records(config, Box::new(timed)))
+        .inspect(|t| if let Some(e) = config.accounts.check(t) {
+            if !bad_accounts.contains(&e) { warn!("{}", e); bad_accounts.insert(e); }
+        })
+        .map(|t| Transfer { currency: t.currency.clone().or_else(|| config.base_currency.clone()), ..t });
+    let transfers: Box<dyn Iterator<Item=Transfer<String>> + '_> = match config.interest {
+        Some(ref interest) => {
+            // Interest has to go through the transfers in date order, so this needs them all at once
+            let transfers: Vec<_> = transfers.collect();
+            let until = config.until.unwrap_or_else(Date::today);
+            let charges = interest.accrue(&transfers, until);
+            info!("Charged interest {} times, up to {}", charges.len(), until);
+            Box::new(transfers.into_iter().chain(charges))
         }
-        let to = balances.entry(transfer.to).or_insert(0);
-        *to += transfer.amt;
-        n += 1;
+        None => Box::new(transfers),
+    };
+    let (n, balances) = ledger::balances_by(transfers
+        .map(|t| match rates {
+            Some((ref rates, ref into)) => convert(config, rates, into, t, &mut converted),
+            None => t,
+        })
+        .inspect(|t| {
+            for who in [&t.from, &t.to] {
+                if !people.contains(who) { people.insert(who.clone()); }
+            }
+            if report { graph.add(t) }
+            let groups = in_groups(t);
+            if groups.is_empty() { ungrouped += 1; }
+            if config.explain {
+                for group in groups { debts.entry(group).or_default().entry(t.currency.clone()).or_default().add(t) }
+            }
+            memos.add(t);
+            match t.date {
+                Some(d) => dates = Some(dates.map_or((d, d), |(first, last)| (first.min(d), last.max(d)))),
+                None => undated += 1,
+            }
+        }), |t| in_groups(t).into_iter().map(|group| (group, t.currency.clone())).collect::<Vec<_>>());
+    let mut by_group: BTreeMap<Option<String>, ledger::CurrencyBalances> = BTreeMap::new();
+    for ((group, ccy), balances) in balances {
+        by_group.entry(group).or_default().insert(ccy, balances);
     }
-    let balances: Vec<(String, isize)> = balances.into_iter().filter(|&(_,x)| x != 0).collect();
+    let balances = by_group;
     let ts = ts.elapsed();
-    info!("Read {} entries from {} in {}.{:0>3}s", n, ledger_path, ts.as_secs(), ts.subsec_nanos()/1_000_000);
-    info!("{} unresolved balances, {} to repay", balances.len(), balances.iter().map(|&(_,x)|x.abs()).sum::<isize>());
+    let open = balances.values().flat_map(|xs| xs.values()).map(Vec::len).sum();
+    if let Some((_, ref into)) = rates {
+        for (ccy, n) in &converted { info!("Converted {} transfers from {} into {}", n, ccy, into); }
+    }
+    *summary = Summary { people: people.len(), transfers: n, balances: open, reading: ts, parsing: parsing.get(), converted, ..Summary::default() };
+    let sources = ledger_paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ");
+    info!("Read {} transfers from {} in {}.{:0>3}s", n, sources, ts.as_secs(), ts.subsec_millis());
+    if let Some((first, last)) = dates {
+        info!("The dated transfers are from {} to {}", first.date, last.date);
+    }
+    if config.filter().has_window() && undated > 0 {
+        warn!("{} transfers have no date, so they were counted regardless of --since/--until", undated);
+    }
+    if ungrouped > 0 {
+        warn!("{} transfers aren't between the members of any group, so they won't be settled", ungrouped);
+    }
+    if report { report_netting(config, &netting::net_pairs(&graph)); }
+    for (group, balances) in &balances {
+        let group = group.as_ref().map_or(String::new(), |g| format!("{}: ", g));
+        for (ccy, balances) in balances {
+            info!("{}{} unresolved balances, {} to repay", group, balances.len(),
+                amount(config, balances.iter().map(|&(_,x)|x.abs()).sum::<isize>(), ccy.as_deref()));
+        }
+    }
+    (balances, memos, debts)
+}
+
+/// Convert a transfer into the settlement currency.  (A transfer in no particular currency is
+/// assumed to be in it already.)
+fn convert(config: &Config, rates: &Rates, into: &str, t: Transfer<String>, converted: &mut BTreeMap<String, usize>) -> Transfer<String> {
+    let from = match t.currency {
+        Some(ref ccy) if ccy != into => ccy.clone(),
+        _ => return Transfer { currency: Some(into.to_string()), ..t },
+    };
+    let exponent = |ccy: &str| config.display.currency(Some(ccy)).exponent;
+    let amt = rates.convert(t.amt, (&from, exponent(&from)), (into, exponent(into)), t.date).unwrap_or_else(|e| {
+        fail!(Exit::Infeasible, "{} (converting {} -> {}: {})", e, t.from, t.to, amount(config, t.amt, Some(&from)));
+    });
+    *converted.entry(from).or_insert(0) += 1;
+    Transfer { amt, currency: Some(into.to_string()), ..t }
+}
+
+/// Whether to draw a progress bar while the exact solver runs: only if stderr is a terminal, and
+/// nobody asked for quiet (or for JSON).
+static PROGRESS: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// When the exact solver started, and when the progress bar was last drawn (if it has been).
+    static PROGRESS_TIMES: Cell<Option<(Instant, Option<Instant>)>> = const { Cell::new(None) };
+}
+
+/// Draw a progress bar for the exact solver on stderr, with a guess at how long it'll take.  It only
+/// appears once the solver has been running for a second, and it's erased when the solver finishes.
+fn draw_progress(p: mzsp::Progress) {
+    const WIDTH: usize = 30;
+    let now = Instant::now();
+    let (start, drawn) = match PROGRESS_TIMES.with(|times| times.get()) {
+        Some(x) if p.done != 0 => x,
+        _ => return PROGRESS_TIMES.with(|times| times.set(Some((now, None)))),
+    };
+    if p.done == p.total { return clear_progress(); }
+    if now - start < Duration::from_secs(1) || drawn.is_some_and(|t| now - t < Duration::from_millis(100)) { return; }
+    PROGRESS_TIMES.with(|times| times.set(Some((start, Some(now)))));
+    let fraction = p.done as f64 / p.total as f64;
+    let left = (now - start).mul_f64((1.0 - fraction) / fraction).as_secs();
+    let left = if left >= 60 { format!("{}m{:02}s", left / 60, left % 60) } else { format!("{}s", left) };
+    let filled = (fraction * WIDTH as f64) as usize;
+    eprint!("\r[{}{}] {:>3}%  {} of {} subsets, about {} to go\x1b[K", "#".repeat(filled), " ".repeat(WIDTH - filled),
+        (fraction * 100.0) as u32, p.done, p.total, left);
+}
+
+/// Erase the progress bar, if it was drawn.
+fn clear_progress() {
+    if let Some((_, Some(_))) = PROGRESS_TIMES.with(|times| times.take()) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// Compute a repayment plan for the balances, and record how it went (if the user wants a history).
+fn solve(config: &Config, mode: Mode, balances: Vec<(String, isize)>, summary: &mut Summary) -> Plan {
+    solve_with(config, config.plan_options(mode), balances, summary)
+}
+
+fn solve_with(config: &Config, mut options: plan::Options, balances: Vec<(String, isize)>, summary: &mut Summary) -> Plan {
+    let people = balances.len();
+    if PROGRESS.load(Ordering::Relaxed) { options.progress = Some(draw_progress); }
+    let (plan, ts) = run_solver(&options, balances);
+    summary.solving += ts;
+    record_run(config, options.mode, people, &plan, ts, summary);
+    plan
+}
+
+/// Some balances to plan: a group's (see `--by-group`), or everyone's, in one currency.
+type Job = (Option<String>, Option<String>, Vec<(String, isize)>);
+
+/// Plan each job's balances separately, one after the other.  `done` is given each plan (with its
+/// group and currency) as soon as it's ready.
+#[cfg(not(feature = "parallel"))]
+fn solve_jobs(config: &Config, mode: Mode, jobs: Vec<Job>, summary: &mut Summary,
+    mut done: impl FnMut(Option<String>, Option<String>, Plan, &mut Summary))
+{
+    for (group, currency, balances) in jobs {
+        let plan = solve(config, mode, balances, summary);
+        done(group, currency, plan, summary);
+    }
+}
+
+/// Plan each job's balances separately, all at the same time.  `done` is given each plan (with its
+/// group and currency) as soon as it's ready, so they come back in whatever order they finish in.
+/// There's no progress bar, since several solvers can't share one line of the terminal.
+#[cfg(feature = "parallel")]
+fn solve_jobs(config: &Config, mode: Mode, jobs: Vec<Job>, summary: &mut Summary,
+    mut done: impl FnMut(Option<String>, Option<String>, Plan, &mut Summary))
+{
+    if jobs.len() < 2 {
+        for (group, currency, balances) in jobs {
+            let plan = solve(config, mode, balances, summary);
+            done(group, currency, plan, summary);
+        }
+        return;
+    }
+    let ts = Instant::now();
+    let jobs: Vec<_> = jobs.into_iter().map(|(group, currency, balances)| (group, currency, balances, config.plan_options(mode))).collect();
+    let (tx, rx) = ::std::sync::mpsc::channel();
+    ::std::thread::scope(|scope| {
+        scope.spawn(move || jobs.into_par_iter().for_each_with(tx, |tx, (group, currency, balances, options)| {
+            let people = balances.len();
+            let (plan, ts) = run_solver(&options, balances);
+            // Only fails if the main thread has given up already
+            let _ = tx.send((group, currency, people, plan, ts));
+        }));
+        for (group, currency, people, plan, ts) in rx {
+            record_run(config, mode, people, &plan, ts, summary);
+            done(group, currency, plan, summary);
+        }
+    });
+    summary.solving += ts.elapsed();
+}
 
+/// Compute a repayment plan, and how long it took; or exit, if the exact solver can't.
+fn run_solver(options: &plan::Options, balances: Vec<(String, isize)>) -> (Plan, Duration) {
     let ts = ::std::time::Instant::now();
-    println!("{:?}", opts);
-    let plan = match (opts.is_present("exact"), opts.is_present("approx"), balances.len() <= 20) {
-        (true, true, _) => panic!("User specified exact mode *and* approximate mode!"),
-        (true, false, _) => compute_repayments_exact(balances),      // -x was specified
-        (false, true, _) => compute_repayments_approx(balances),     // -a was specified
-        (false, false, true) => compute_repayments_exact(balances),  // n is small
-        (false, false, false) => {                                   // n is big
-            warn!("The following solution may be approximate.  (Use '-x' to force exact mode)");
-            compute_repayments_approx(balances)
+    let plan = plan::plan_with(balances, options).unwrap_or_else(|e| {
+        clear_progress();
+        exact_failed(e)
+    });
+    let ts = ts.elapsed();
+    for w in &plan.warnings { warn!("{}", w); }
+    info!("Computed repayment plan in {}.{:0>3}s", ts.as_secs(), ts.subsec_millis());
+    (plan, ts)
+}
+
+/// Give up, since the exact solver couldn't make a plan.
+fn exact_failed(e: mzsp::Error) -> ! {
+    let exit = match e {
+        mzsp::Error::Cancelled => Exit::Timeout,
+        mzsp::Error::TooManyElements { .. } | mzsp::Error::Infeasible => Exit::Infeasible,
+        mzsp::Error::Overflow => Exit::BadInput,
+        _ => Exit::Failure,
+    };
+    fail!(exit, "Exact mode can't handle this ledger: {}.  {}", e, plan::hint(&e));
+}
+
+/// Count a plan (for `people` balances, which took `ts` to compute) in the summary, and in the
+/// history.
+fn record_run(config: &Config, mode: Mode, people: usize, plan: &Plan, ts: Duration, summary: &mut Summary) {
+    summary.solver = Some(plan.solver);
+    summary.partitioning += plan.partitioning;
+    summary.repayments += plan.transfers.len();
+    summary.at_least += plan.at_least;
+    if let Some(history) = config.history() {
+        let run = Run { people, mode, solver: plan.solver, secs: ts.as_secs_f64(), repayments: plan.transfers.len() };
+        if let Err(e) = history.record(&run) {
+            warn!("Couldn't record this run in {}: {}", history.path().display(), e);
         }
+    }
+}
+
+/// `repay accounts`: how much went through each of everyone's accounts.
+fn accounts(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    // For the handles
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &[ledger_path.into()]);
+    let config = &config;
+    let flows = accounts::flows(read_counted(config, format, &[ledger_path.into()])
+        .map(|t| Transfer { currency: t.currency.clone().or_else(|| config.base_currency.clone()), ..t }));
+    for ((who, ccy), accounts) in &flows {
+        let ccy = ccy.as_deref();
+        let net: isize = accounts.values().map(|f| f.paid - f.received).sum();
+        out!("{}{}: {} net", who, ccy.map_or(String::new(), |c| format!(" ({})", c)), amount(config, net, ccy));
+        for (account, f) in accounts {
+            let name = match account {
+                Some(account) => match config.accounts.people.get(who).and_then(|x| x.get(account)) {
+                    Some(handle) if !handle.is_empty() => format!("{} ({})", account, handle),
+                    _ => account.clone(),
+                },
+                None => "unspecified".into(),
+            };
+            out!("  {}: paid {}, received {}", name, amount(config, f.paid, ccy), amount(config, f.received, ccy));
+        }
+    }
+}
+
+/// `repay add`: parse a description of an expense, and append it to the ledger.
+fn add(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let format = if ledger_path.exists() {
+        ledger_format(config, format, ledger_path)
+    } else {
+        let ext = ledger_path.extension().and_then(|x| x.to_str());
+        format.or_else(|| ext.and_then(Format::from_extension)).unwrap_or(Format::Json)
     };
-    let ts = ts.elapsed();
-    info!("Computed repayment plan in {}.{:0>3}s", ts.as_secs(), ts.subsec_nanos()/1_000_000);
-    info!("{} repayments required", plan.len());
-    for mut p in plan {
-        p.normalise();
-        println!("{}", serde_json::to_string(&p).unwrap());
+    let flags = ["from", "to", "amt", "currency", "memo", "category", "tag", "date", "from-account", "to-account"];
+    let (transfers, payer) = match opts.value_of("EXPENSE") {
+        Some(_) if flags.iter().any(|x| opts.is_present(x)) => {
+            fail!(Exit::BadInput, "Either describe an expense, or give --from, --to, and --amt, but not both");
+        }
+        Some(expense) => {
+            let expense = quick::parse_with(expense, config.display.currency(None).exponent, &config.locale())
+                .unwrap_or_else(|e| {
+                    fail!(Exit::BadInput, "{}", e);
+                });
+            (expense.transfers(), Some(expense.payer))
+        }
+        None => (vec![transfer_from_flags(config, opts)], None),
+    };
+    // Spell everyone's names the way the ledger already does
+    let mut names = config.names();
+    if ledger_path.exists() {
+        for t in read_ledger(config, ledger_path, format) { names.normalise(t); }
+    }
+    let transfers: Vec<Transfer<String>> = transfers.into_iter().map(|t| names.normalise(t)).collect();
+    report_merges(&names);
+    if let Some(t) = transfers.iter().find(|t| t.from == t.to) {
+        fail!(Exit::BadInput, "A transfer from {} to themselves doesn't do anything", t.from);
+    }
+    if transfers.is_empty() {
+        warn!("Nothing to add: {} paid only for themself", payer.unwrap_or_default());
+        return;
+    }
+    ledger::append_as(ledger_path, format, &transfers, &config.read_options()).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", ledger_path.display(), e);
+    });
+    info!("Added {} transfers", transfers.len());
+    print_transfers(config, &transfers);
+}
+
+/// The transfer described by `repay add --from ... --to ... --amt ...`.
+fn transfer_from_flags(config: &Config, opts: &ArgMatches) -> Transfer<String> {
+    let required = |flag: &str| opts.value_of(flag).map(String::from).unwrap_or_else(|| {
+        fail!(Exit::BadInput, "Either describe an expense, or give --from, --to, and --amt");
+    });
+    let (from, to, amt) = (required("from"), required("to"), required("amt"));
+    if from.trim().is_empty() || to.trim().is_empty() {
+        fail!(Exit::BadInput, "Names can't be blank");
+    }
+    let currency = opts.value_of("currency").map(String::from);
+    let exponent = config.display.currency(currency.as_deref()).exponent;
+    let amt = match config.locale().parse_minor(&amt, exponent) {
+        Ok(x) if x > 0 => x,
+        Ok(_) => {
+            fail!(Exit::BadInput, "Invalid amount: {} (expected a positive amount)", amt);
+        }
+        Err(e) => {
+            fail!(Exit::BadInput, "{}", e);
+        }
+    };
+    let date = opts.value_of("date").map(|x| x.parse::<Timestamp>().unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "--date: {}", e);
+    }));
+    Transfer {
+        currency,
+        memo: opts.value_of("memo").map(String::from),
+        category: opts.value_of("category").map(String::from),
+        tags: opts.values_of("tag").into_iter().flatten().map(String::from).collect(),
+        date,
+        from_account: opts.value_of("from-account").map(String::from),
+        to_account: opts.value_of("to-account").map(String::from),
+        ..Transfer::new(from, to, amt)
+    }
+}
+
+/// `repay backtest`: simulate settling up periodically, and print what each settlement took.
+fn backtest(config: &Config, mode: Mode, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let every: Interval = opts.value_of("every").unwrap().parse().unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    });
+    let strategy: Strategy = opts.value_of("strategy").unwrap().parse().unwrap();
+    let settlements = backtest::backtest(read_counted(config, format, &[ledger_path.into()]), every, strategy, &config.plan_options(mode))
+        .unwrap_or_else(|e| exact_failed(e));
+    let mut table = Table::new(&[("settlement", Align::Left), ("after", Align::Right), ("repayments", Align::Right), ("repaid", Align::Right)]);
+    for (i, s) in settlements.iter().enumerate() {
+        table.row(vec![(i + 1).to_string(), format!("{} transfers", s.after), s.repayments.to_string(), config.display.format(s.flow, None)]);
+    }
+    table.total(vec!["total".into(), String::new(), settlements.iter().map(|s| s.repayments).sum::<usize>().to_string(),
+        config.display.format(settlements.iter().map(|s| s.flow).sum(), None)]);
+    for line in table.render(config.display.color == Color::Always) { out!("{}", line); }
+}
+
+/// `repay balances`: everyone's balance, in each currency.
+fn balances(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_paths = ledger_paths(opts);
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &ledger_paths);
+    let config = &config;
+    let (balances, _, _) = read_balances(config, format, &ledger_paths, &mut Summary::default());
+    match config.display.output {
+        OutputFormat::Json => {
+            for (ccy, balances) in &balances {
+                for (who, x) in balances {
+                    let mut json = serde_json::Map::new();
+                    json.insert("person".into(), who.clone().into());
+                    json.insert("balance".into(), config.display.json(*x, ccy.as_deref()));
+                    if let Some(ccy) = ccy { json.insert("currency".into(), ccy.clone().into()); }
+                    out!("{}", serde_json::Value::from(json));
+                }
+            }
+        }
+        OutputFormat::Table => {
+            let mut table = Table::new(&[("person", Align::Left), ("owes", Align::Right), ("is owed", Align::Right)]);
+            let mut totals = (vec![], vec![]);
+            for (ccy, xs) in &balances {
+                let ccy = ccy.as_deref();
+                if balances.len() > 1 { table.group(ccy.unwrap_or("(no currency)").to_string()); }
+                // Biggest debtors first, and biggest creditors last
+                let mut xs = xs.clone();
+                xs.sort_by_key(|&(ref who, x)| (-x, who.clone()));
+                for (who, x) in xs {
+                    let (owes, owed) = if x > 0 { (amount(config, x, ccy), String::new()) } else { (String::new(), amount(config, -x, ccy)) };
+                    table.row(vec![who, owes, owed]);
+                }
+            }
+            for (ccy, xs) in &balances {
+                totals.0.push(amount(config, xs.iter().map(|x| x.1.max(0)).sum(), ccy.as_deref()));
+                totals.1.push(amount(config, xs.iter().map(|x| (-x.1).max(0)).sum(), ccy.as_deref()));
+            }
+            if !balances.is_empty() { table.total(vec!["total".into(), totals.0.join(", "), totals.1.join(", ")]); }
+            for line in table.render(config.display.color == Color::Always) { out!("{}", line); }
+        }
+        x => fail!(Exit::BadInput, "Balances can be written as JSON or as a table, but not as {}", x),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Transfer<T> {
-    from: T,
-    to: T,
-    amt: isize,  // TODO: Change to f64, multiply by 100 for approx
+/// `repay chain`: add hashes to a ledger's records.
+fn chain(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let text = read_chained(config, format, ledger_path);
+    let sealed = chain::seal(text.as_bytes(), &ledger_path.display().to_string()).unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    });
+    ledger::replace(ledger_path, sealed.as_bytes()).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", ledger_path.display(), e);
+    });
+    match chain::head(sealed.as_bytes()) {
+        Some(head) => out!("{}", head),
+        None => warn!("{} has no records to chain", ledger_path.display()),
+    }
 }
 
-impl<T> Transfer<T> {
-    fn normalise(&mut self) {
-        if self.amt < 0 {
-            ::std::mem::swap(&mut self.from, &mut self.to);
-            self.amt = -self.amt;
+/// `repay statement`: everything which went into someone's balance, and what the plan has them do.
+fn statement(config: &Config, mode: Mode, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_paths = [PathBuf::from(opts.value_of_os("PATH").unwrap())];
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &ledger_paths);
+    let config = &config;
+    // The merges are reported when the plan is worked out
+    let mut counted = Counted { reported: true, ..read_counted(config, format, &ledger_paths) };
+    let transfers: Vec<Transfer<String>> = counted.by_ref()
+        .map(|t| Transfer { currency: t.currency.clone().or_else(|| config.base_currency.clone()), ..t })
+        .collect();
+    let person = counted.names.canonicalise(opts.value_of("PERSON").unwrap().to_string());
+    let lines = statement::statement(&person, transfers);
+    if lines.is_empty() {
+        fail!(Exit::BadInput, "{} doesn't appear in {}", person, ledger_paths[0].display());
+    }
+    out!("Statement for {} ({} transfers; a positive balance is what {} owes)", person, lines.len(), person);
+    for l in &lines {
+        let t = &l.transfer;
+        let ccy = t.currency.as_deref();
+        let what = if l.change < 0 { format!("paid {}", t.to) } else { format!("received from {}", t.from) };
+        let memo = t.memo.as_ref().map_or(String::new(), |m| format!(" ({})", m));
+        out!("  {:<10}  {}: {}{}, balance {}", t.date.map_or(String::new(), |d| d.date.to_string()),
+            what, amount(config, t.amt, ccy), memo, amount(config, l.balance, ccy));
+    }
+    for (ccy, balance) in statement::balances(&lines) {
+        let ccy = ccy.as_deref();
+        match balance {
+            0 => out!("{} is settled up", person),
+            x if x > 0 => out!("{} owes {}", person, amount(config, x, ccy)),
+            x => out!("{} is owed {}", person, amount(config, -x, ccy)),
+        }
+    }
+    let plan = compute_plan(config, mode, format, &ledger_paths, &mut Summary::default());
+    let repayments: Vec<&Transfer<String>> = plan.transfers.iter().filter(|t| t.from == person || t.to == person).collect();
+    if repayments.is_empty() { return; }
+    out!("Under the plan:");
+    for t in repayments {
+        let id = t.id.as_ref().map_or(String::new(), |x| format!(" ({})", x));
+        if t.from == person {
+            out!("  pay {} to {}{}", amount(config, t.amt, t.currency.as_deref()), t.to, id);
+        } else {
+            out!("  receive {} from {}{}", amount(config, t.amt, t.currency.as_deref()), t.from, id);
         }
     }
 }
 
-fn compute_repayments_exact(balances: Vec<(String, isize)>) -> Vec<Transfer<String>> {
-    if balances.len() >= 64 {
-        error!("Exact mode doesn't support ledgers with more than 64 unsettled \
-            balances.  Please use approximate mode instead.");
-        ::std::process::exit(1);
+/// `repay verify`: check that a plan would bring every balance to zero.
+fn verify(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_paths = [PathBuf::from(opts.value_of_os("PATH").unwrap())];
+    let plan_path = Path::new(opts.value_of_os("PLAN").unwrap());
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &ledger_paths);
+    let config = &config;
+    let (balances, _, _) = read_balances(config, format, &ledger_paths, &mut Summary::default());
+    let plan = read_plan(config, plan_path, balances.values().flatten().map(|x| &x.0));
+    let discrepancies = verify::verify(&balances, &plan);
+    if discrepancies.is_empty() {
+        info!("The plan's {} repayments settle everyone's balances", plan.len());
+        return;
+    }
+    for d in &discrepancies {
+        let left = amount(config, d.left.abs(), d.currency.as_deref());
+        if d.left > 0 {
+            out!("{} would still owe {}", d.person, left);
+        } else {
+            out!("{} would still be owed {}", d.person, left);
+        }
+    }
+    write_output();
+    fail!(Exit::CheckFailed, "The plan doesn't settle up: {} balances would be left over", discrepancies.len());
+}
+
+/// Read a plan which repay printed earlier (perhaps edited by hand), from a file or stdin (`-`).
+/// Everyone's names are spelled the same way as in `people`.
+fn read_plan<'a>(config: &Config, plan_path: &Path, people: impl Iterator<Item=&'a String>) -> Vec<Transfer<String>> {
+    let mut text = String::new();
+    let read = if plan_path == Path::new("-") {
+        ::std::io::stdin().read_to_string(&mut text).map(|_| ())
+    } else {
+        ::std::fs::read_to_string(plan_path).map(|x| text = x)
+    };
+    read.unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't read {}: {}", plan_path.display(), e);
+    });
+    let plan = verify::read_plan(&text, &config.display).unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}: {}", plan_path.display(), e);
+    });
+    let mut names = config.names();
+    for who in people { names.canonicalise(who.clone()); }
+    let plan = plan.into_iter()
+        .map(|t| names.normalise(t))
+        .map(|t| Transfer { currency: t.currency.clone().or_else(|| config.base_currency.clone()), ..t })
+        .collect();
+    report_merges(&names);
+    plan
+}
+
+/// Print how the plan differs from an earlier one (for `--diff`).
+fn print_diff(config: &Config, old: &[Transfer<String>], new: &[Transfer<String>]) {
+    let changes = diff::diff(old, new);
+    info!("{} of the {} repayments are unchanged", new.len() - changes.iter().filter(|c| !matches!(c, Change::Removed(_))).count(), new.len());
+    for c in &changes {
+        let (what, t, was) = match *c {
+            Change::Added(ref t) => ("added", t, None),
+            Change::Removed(ref t) => ("removed", t, None),
+            Change::Changed { old, ref new } => ("changed", new, Some(old)),
+        };
+        if config.display.output == OutputFormat::Json {
+            let mut json = serde_json::Map::new();
+            json.insert("change".into(), what.into());
+            json.insert("from".into(), t.from.clone().into());
+            json.insert("to".into(), t.to.clone().into());
+            json.insert("amt".into(), config.display.json(t.amt, t.currency.as_deref()));
+            if let Some(was) = was { json.insert("was".into(), config.display.json(was, t.currency.as_deref())); }
+            if let Some(ref ccy) = t.currency { json.insert("currency".into(), ccy.clone().into()); }
+            out!("{}", serde_json::Value::from(json));
+        } else {
+            let was = was.map_or(String::new(), |x| format!(" (was {})", amount(config, x, t.currency.as_deref())));
+            out!("{:<8} {} -> {}: {}{}", format!("{}:", what), t.from, t.to, amount(config, t.amt, t.currency.as_deref()), was);
+        }
+    }
+}
+
+/// `repay verify-chain`: check a ledger's hashes.
+fn verify_chain(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let text = read_chained(config, format, ledger_path);
+    let hashes = chain::verify(text.as_bytes(), &ledger_path.display().to_string()).unwrap_or_else(|e| {
+        fail!(Exit::CheckFailed, "{}", e);
+    });
+    if let Some(head) = opts.value_of("head") {
+        if head.len() < 8 || !hashes.iter().any(|x| x.starts_with(&head.to_lowercase())) {
+            fail!(Exit::CheckFailed, "The chain never reaches {}, so some records have been removed from the end", head);
+        }
+    }
+    info!("All {} records are intact", hashes.len());
+    match hashes.last() {
+        Some(head) => out!("{}", head),
+        None => warn!("{} has no records", ledger_path.display()),
+    }
+}
+
+/// The text of a JSON ledger, for `chain` and `verify-chain`.
+fn read_chained(config: &Config, format: Option<Format>, ledger_path: &Path) -> String {
+    if ledger_format(config, format, ledger_path) != Format::Json {
+        fail!(Exit::BadInput, "Only JSON ledgers can be chained");
+    }
+    ::std::fs::read_to_string(ledger_path).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't read {}: {}", ledger_path.display(), e);
+    })
+}
+
+/// `repay checkpoint`: append a record of everyone's balances to the ledger.
+fn checkpoint(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    if ledger_format(config, format, ledger_path) != Format::Json {
+        fail!(Exit::BadInput, "Checkpoints can only be added to JSON ledgers");
+    }
+    let date = match opts.value_of("date") {
+        Some(date) => date.parse::<Date>().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "--date: {}", e);
+        }),
+        None => Date::today(),
+    };
+    let exclusions = config.exclusions();
+    let (n, balances) = ledger::balances_by_currency(read_ledger(config, ledger_path, Format::Json).filter(|t| exclusions.accepts(t)));
+    let checkpoint = ledger::Checkpoint::new(&balances, Some(date.into()));
+    ledger::append_records(ledger_path, &[&checkpoint]).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", ledger_path.display(), e);
+    });
+    info!("Checkpointed {} balances, after {} transfers.  (Everything before the checkpoint can now be archived)",
+        checkpoint.checkpoint.len(), n);
+    out!("{}", serde_json::to_string(&checkpoint).unwrap());
+}
+
+/// `repay archive`: split the ledger at a date, and move what's before it into an archive.
+fn archive(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    if ledger_format(config, format, ledger_path) != Format::Json {
+        fail!(Exit::BadInput, "Only JSON ledgers can be archived");
     }
-    // Get the data into the right form (TODO: eliminate this)
-    let values: Vec<isize> = balances.iter().map(|x|x.1).collect();
+    let before = opts.value_of("before").unwrap().parse::<Date>().unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "--before: {}", e);
+    });
+    let archive_path = match opts.value_of_os("to") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let stem = ledger_path.file_stem().map_or("ledger".into(), |x| x.to_string_lossy());
+            let ext = ledger_path.extension().map_or(String::new(), |x| format!(".{}", x.to_string_lossy()));
+            ledger_path.with_file_name(format!("{}.before-{}{}.gz", stem, before, ext))
+        }
+    };
+    let archived = ledger::archive(ledger_path, before, &archive_path, &config.read_options(), &config.exclusions()).unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    });
+    info!("Moved {} records into {}, and kept {}", archived.archived, archive_path.display(), archived.kept);
+    out!("{}", serde_json::to_string(&archived.checkpoint).unwrap());
+}
 
-    // Compute the largest set of zero-sum paritions
-    let parts = MZSP::compute(&values);
-    info!("Divided into {} partitions", parts.len());
-    parts.flat_map(|partition| {
-        let balances: Vec<(String,isize)> = partition.elements()
-            .map(|idx| balances[idx as usize].clone())
-            .collect();
-        // For each partition, construct a plan.  We know that these partitions contain no zero-sum
-        // subsets, so `construct_plan` is optimal.
-        construct_plan(balances)
-    }).collect()
+/// A positive amount given on the command line: in minor units if it's a plain integer, and
+/// otherwise in major units (eg. `12.34`, or `12,34 €` with `--locale=de`).
+fn parse_amount(config: &Config, s: &str, currency: Option<&str>) -> isize {
+    let amt = s.parse::<isize>().or_else(|_| config.locale().parse_minor(s, config.display.currency(currency).exponent));
+    match amt {
+        Ok(x) if x > 0 => x,
+        Ok(_) => {
+            fail!(Exit::BadInput, "Invalid amount: {} (expected a positive amount)", s);
+        }
+        Err(e) => {
+            fail!(Exit::BadInput, "{}", e);
+        }
+    }
 }
 
-/// Given a zero-sum set of nodes, construct a graph which moves all the value from the positive
-/// nodes to the negative nodes.  This function is *O(n)*, but the graph will be maximally
-/// inefficient, in the sense that it will always contain exactly *n* edges.  If the given set of
-/// nodes contains zero-sum subsets then we can do better.
-// TODO: Use a priority search queue
-fn construct_plan<T: Clone>(mut balances: Vec<(T, isize)>) -> Vec<Transfer<T>> {
-    assert_eq!(balances.iter().map(|x|x.1).sum::<isize>(), 0, "balances must be zero-sum");
-    let mut ret = vec![];
-    loop {
-        // Take the node with the smallest absolute value;  this will be our "from" node.
-        balances.sort_unstable_by_key(|&(_, x)| -x.abs());
-        let (from_tag, from_val) = match balances.pop() { None => break, Some(x) => x };
-        if from_val == 0 { continue; }
-        // Find a node with the opposite sign (any will do);  this will be our "to" node.
-        let to = balances.iter_mut().find(|x| x.1.signum() != from_val.signum())
-            .expect("a node with opposite sign");  // The partition is zero-sum => it must exist
-        let to_tag = to.0.clone();
-        to.1 += from_val;  // Eliminate the "from" node with the "to" node.
-        // There's no need to remove zero-balance "to" nodes;  this will only occur for the very
-        // last node.
-        ret.push(Transfer { from: from_tag, to: to_tag, amt: from_val });
+/// `repay confirm`: append the selected planned repayments to the ledger, marked as confirmed.
+fn confirm(config: &Config, mode: Mode, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let ids: Vec<&str> = opts.values_of("transfer-id").map(|x| x.collect()).unwrap_or_default();
+    let all_from = opts.value_of("all-from");
+    if ids.is_empty() && all_from.is_none() {
+        fail!(Exit::BadInput, "Please specify which repayments to confirm (with --transfer-id or --all-from)");
+    }
+    let partial = opts.value_of("amount");
+    if partial.is_some() && (ids.len() != 1 || all_from.is_some()) {
+        fail!(Exit::BadInput, "--amount can only be used when confirming a single repayment");
+    }
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &[ledger_path.into()]);
+    let config = &config;
+
+    let plan = compute_plan(config, mode, format, &[ledger_path.to_path_buf()], &mut Summary::default()).transfers;
+    for id in &ids {
+        if !plan.iter().any(|p| p.id.as_deref() == Some(id)) {
+            fail!(Exit::BadInput, "There's no planned repayment with id {}", id);
+        }
+    }
+    let confirmations: Vec<Transfer<String>> = plan.into_iter()
+        .filter(|p| ids.contains(&p.id.as_deref().unwrap()) || Some(p.from.as_str()) == all_from)
+        .map(|p| match partial.map(|x| parse_amount(config, x, p.currency.as_deref())) {
+            // A partial payment: whatever is left over will show up in the next plan
+            Some(amt) if amt < p.amt => Transfer {
+                confirms: p.id,
+                planned: Some(p.amt),
+                currency: p.currency,
+                ..Transfer::new(p.from, p.to, amt)
+            },
+            Some(amt) if amt > p.amt => {
+                fail!(Exit::BadInput, "Repayment {} is only for {}", p.id.unwrap(), amount(config, p.amt, p.currency.as_deref()));
+            }
+            _ => Transfer { confirms: p.id, currency: p.currency, ..Transfer::new(p.from, p.to, p.amt) },
+        })
+        .collect();
+    if confirmations.is_empty() {
+        warn!("Nothing to confirm");
+        return;
     }
-    ret
+
+    let format = ledger_format(config, format, ledger_path);
+    ledger::append_as(ledger_path, format, &confirmations, &config.read_options()).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", ledger_path.display(), e);
+    });
+    info!("Confirmed {} repayments", confirmations.len());
+    print_transfers(config, &confirmations);
 }
 
-fn compute_repayments_approx(balances: Vec<(String, isize)>) -> Vec<Transfer<String>> {
-    // (Step 1.5: Set up a fully-connected graph with one node per person)
-    let mut graph = GraphBuilder::new();
-    for &(ref x,_) in balances.iter() {
-        for &(ref y,_) in balances.iter() {
-            if x != y {
-                graph.add_edge(x.clone(), y.clone(), Capacity(1_000_000_000), Cost(1));
+/// `repay settle`: append the plan to the ledger, as repayments which have been made.
+fn settle(config: &Config, mode: Mode, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let date: Timestamp = match opts.value_of("date") {
+        Some(x) => x.parse().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "--date: {}", e);
+        }),
+        None => Date::today().into(),
+    };
+    let tag = opts.value_of("tag").unwrap();
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &[ledger_path.into()]);
+    let config = &config;
+    let plan = compute_plan(config, mode, format, &[ledger_path.to_path_buf()], &mut Summary::default()).transfers;
+    if plan.is_empty() {
+        info!("Everyone is already settled up");
+        return;
+    }
+    let settlements: Vec<Transfer<String>> = plan.into_iter()
+        .map(|p| Transfer {
+            confirms: p.id,
+            currency: p.currency,
+            memo: p.memo,
+            date: Some(date),
+            tags: vec![tag.to_string()],
+            ..Transfer::new(p.from, p.to, p.amt)
+        })
+        .collect();
+    if !opts.is_present("yes") {
+        eprintln!("{} repayments to settle up:", settlements.len());
+        for t in &settlements {
+            eprintln!("  {} -> {}: {}", t.from, t.to, amount(config, t.amt, t.currency.as_deref()));
+        }
+        let question = format!("Record them in {} as made on {}? [y/N]", ledger_path.display(), date);
+        match ask(&question).as_deref() {
+            Some("y") | Some("Y") | Some("yes") => (),
+            _ => {
+                warn!("Nothing recorded");
+                return;
             }
         }
     }
+    let format = ledger_format(config, format, ledger_path);
+    ledger::append_as(ledger_path, format, &settlements, &config.read_options()).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", ledger_path.display(), e);
+    });
+    info!("Recorded {} repayments", settlements.len());
+    print_transfers(config, &settlements);
+}
 
-    // Step 2: Figure out how to shift money around to make all the balances go back to 0
-    for (client, balance) in balances {
-        if balance > 0 {
-            graph.add_edge(Vertex::Source, client, Capacity(balance.abs() as u32), Cost(0));
-        } else if balance < 0 {
-            graph.add_edge(client, Vertex::Sink, Capacity(balance.abs() as u32), Cost(0));
+/// `repay equalize`: plan repayments which even out everyone's spending.
+fn equalize(config: &Config, mode: Mode, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let categories: Vec<String> = opts.values_of("category").map(|x| x.map(String::from).collect()).unwrap_or_default();
+    let format = ledger_format(config, format, ledger_path);
+    let mut names = config.names();
+    let members: Vec<String> = opts.values_of("member")
+        .map(|x| x.map(|m| names.canonicalise(m.to_string())).collect()).unwrap_or_default();
+    let balances = equalize::deviations(counted(config, names, read_ledger(config, ledger_path, format)), &members, &categories);
+    info!("{} people have paid more or less than their share, {} to repay", balances.len(),
+        config.display.format(balances.iter().map(|&(_,x)|x.abs()).sum::<isize>(), None));
+    let plan = solve(config, mode, balances, &mut Summary::default());
+    print_plan(config, &plan);
+}
+
+/// `repay export`: write the gross debt network to stdout.
+fn export(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let graph_format: GraphFormat = opts.value_of("to").unwrap().parse().unwrap();
+    let graph = DebtGraph::from_transfers(read_counted(config, format, &[ledger_path.into()]));
+    info!("{} people, {} debts", graph.people().len(), graph.edges.len());
+    let mut text = vec![];
+    export::write_graph(&mut text, &graph, graph_format, &config.display).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write the graph: {}", e);
+    });
+    for line in String::from_utf8_lossy(&text).lines() { out!("{}", line); }
+}
+
+/// `repay convert`: read a ledger in one format, and write its transfers in another.  The output
+/// must be a new file.
+fn convert_ledger(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let output = Path::new(opts.value_of_os("OUTPUT").unwrap());
+    // `None` is SQL
+    let to = match opts.value_of("to") {
+        Some("sqlite") | Some("sql") => None,
+        Some(x) => Some(x.parse::<Format>().unwrap_or_else(|e| {
+            fail!(Exit::BadInput, "{}", e);
+        })),
+        None => match output.extension().and_then(|x| x.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("sql") => None,
+            ext => Some(ext.and_then(Format::from_extension).unwrap_or_else(|| {
+                fail!(Exit::BadInput, "Couldn't tell which format to write from the name {}.  (Use '--to' to say)", output.display());
+            })),
+        },
+    };
+    if output != Path::new("-") && output.exists() {
+        fail!(Exit::BadInput, "{} already exists.  (Convert to a new file, and rename it)", output.display());
+    }
+    let transfers: Vec<Transfer<String>> = read_ledger(config, ledger_path, ledger_format(config, format, ledger_path)).collect();
+    let lines = match to {
+        None => Ok(repay::sql::dump(&transfers)),
+        Some(to) => ledger::render(to, &transfers, "", &config.read_options()),
+    };
+    let mut text = lines.unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "Couldn't convert {}: {}", ledger_path.display(), e);
+    }).join("\n");
+    text.push('\n');
+    let written = if output == Path::new("-") {
+        out!("{}", &text[..text.len() - 1]);
+        Ok(())
+    } else {
+        ledger::replace(output, text.as_bytes())
+    };
+    written.unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", output.display(), e);
+    });
+    info!("Converted {} transfers", transfers.len());
+}
+
+/// `repay import`: read transfers from another file, and (with `--commit`) append them to the
+/// ledger.  Without `--commit`, just show what would happen.
+fn import(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let source_path = Path::new(opts.value_of_os("SOURCE").unwrap());
+    let to = ledger_format(config, format, ledger_path);
+    let mut config = config.clone();
+    if ledger_path.exists() { use_ledger_headers(&mut config, format, &[ledger_path.into()]); }
+    let config = &config;
+    // Spell everyone's names the way the ledger already does
+    let mut names = config.names();
+    if ledger_path.exists() {
+        for t in read_ledger(config, ledger_path, to) { names.normalise(t); }
+    }
+    let interactive = opts.is_present("interactive");
+    if interactive && source_path == Path::new("-") {
+        fail!(Exit::BadInput, "--interactive reads the answers from stdin, so the transfers can't come from there too");
+    }
+    let source: Vec<Transfer<String>> = if let Some(profile) = opts.value_of_os("bank") {
+        read_statement(config, Path::new(profile), source_path, interactive)
+    } else {
+        let source: Box<dyn Iterator<Item=Transfer<String>>> = if source_path == Path::new("-") {
+            read_pasted(config, format)
         } else {
-            error!("Got a zero node");
-        }
-    }
-    let (cost, paths) = graph.mcmf();
-    info!("Total flow: {}", cost);
-
-    // (Step 2.5: Wrangle these flows back into the shape of Tranfers)
-    let mut repayments = vec![];
-    for mut p in paths {
-        if p.flows.len() != 3 {
-            // Graph is strongly connected => all flows should have length 1
-            warn!("Maximum transfer amount exceeded.  Repaying via a different route...");
-        }
-        for Flow { a, b, amount, .. } in p.flows {
-            if let Vertex::Node(a) = a {
-                if let Vertex::Node(b) = b {
-                    repayments.push(Transfer {
-                        from: a,
-                        to: b,
-                        amt: amount as isize,
-                    });
+            read_ledger(config, source_path, ledger_format(config, format, source_path))
+        };
+        let mut reviewed = vec![];
+        for t in source {
+            if !interactive {
+                reviewed.push(t);
+                continue;
+            }
+            let question = format!("{} -> {}: {}{}.  Import it? [Y/n/q]", t.from, t.to, amount(config, t.amt, t.currency.as_deref()),
+                t.memo.as_ref().map_or(String::new(), |m| format!(" ({})", m)));
+            match ask(&question).as_deref() {
+                Some("") | Some("y") | Some("Y") => reviewed.push(t),
+                Some("n") | Some("N") => (),
+                _ => break,
+            }
+        }
+        reviewed
+    };
+    let imported: Vec<Transfer<String>> = source.into_iter().map(|t| names.normalise(t)).collect();
+    report_merges(&names);
+
+    if !opts.is_present("commit") {
+        out!("{} transfers to import from {}:", imported.len(), source_path.display());
+        for t in &imported {
+            out!("  {} -> {}: {}", t.from, t.to, amount(config, t.amt, t.currency.as_deref()));
+        }
+        let filter = config.filter();
+        let (_, deltas) = ledger::balances_by_currency(imported.iter().filter(|t| filter.accepts(t)).cloned());
+        out!("Changes to balances:");
+        for (ccy, deltas) in deltas {
+            for (who, delta) in deltas {
+                out!("  {}: {}{}", who, if delta > 0 { "+" } else { "" }, amount(config, delta, ccy.as_deref()));
+            }
+        }
+        out!("(Use '--commit' to append them to {})", ledger_path.display());
+        return;
+    }
+
+    ledger::append_as(ledger_path, to, &imported, &config.read_options()).unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't write to {}: {}", ledger_path.display(), e);
+    });
+    info!("Imported {} transfers", imported.len());
+    print_transfers(config, &imported);
+}
+
+/// The payments to and from people in the ledger, in a bank statement.  With `interactive`, the
+/// user decides which to import (and who the others were to or from).
+fn read_statement(config: &Config, profile_path: &Path, source_path: &Path, interactive: bool) -> Vec<Transfer<String>> {
+    let profile = bank::Profile::load(profile_path).unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}", e);
+    });
+    let reader: Box<dyn Read> = if source_path == Path::new("-") {
+        Box::new(::std::io::stdin())
+    } else {
+        Box::new(::std::fs::File::open(source_path).unwrap_or_else(|e| {
+            fail!(Exit::Failure, "Couldn't read {}: {}", source_path.display(), e);
+        }))
+    };
+    let entries = profile.read(reader, &config.read_options()).unwrap_or_else(|e| {
+        fail!(Exit::BadInput, "{}: {}", source_path.display(), e);
+    });
+    let mut transfers = vec![];
+    for e in &entries {
+        let t = e.transfer(&profile.holder, None);
+        if !interactive {
+            transfers.extend(t);
+            continue;
+        }
+        let date = e.date.map_or(String::new(), |d| format!("{} ", d.date));
+        let (proposal, default) = match t {
+            Some(ref t) => (format!("{} -> {}", t.from, t.to), "Y/n"),
+            None => ("not to or from anyone in the ledger".into(), "y/N"),
+        };
+        let question = format!("Line {}: {}{} {}, {}.  Import it? [{}/q, or who it was {}]",
+            e.line, date, e.description, amount(config, e.amt, e.currency.as_deref()), proposal, default,
+            if e.amt < 0 { "to" } else { "from" });
+        match ask(&question).as_deref() {
+            Some("") => transfers.extend(t),
+            Some("y") | Some("Y") if t.is_some() => transfers.extend(t),
+            Some("n") | Some("N") | Some("y") | Some("Y") => (),
+            Some("q") | Some("Q") | None => break,
+            Some(name) => transfers.extend(e.transfer(&profile.holder, Some(name))),
+        }
+    }
+    info!("{} of the {} payments in {} were to or from people in the ledger",
+        entries.iter().filter(|e| e.counterparty.is_some()).count(), entries.len(), source_path.display());
+    transfers
+}
+
+/// `--interactive`: go through the plan with the user, one repayment at a time.  If they skip one,
+/// or change how much it's for, the rest of the plan is worked out again: without that payer paying
+/// that payee, or with the new amount.  Quitting keeps the rest of the plan as it is.
+fn review(config: &Config, mode: Mode, mut proposed: Vec<Transfer<String>>) -> Vec<Transfer<String>> {
+    let mut reviewed: Vec<Transfer<String>> = vec![];
+    // As (currency, payer, payee)
+    let mut avoid: BTreeSet<(Option<String>, String, String)> = BTreeSet::new();
+    while !proposed.is_empty() {
+        let t = proposed.remove(0);
+        let ccy = t.currency.as_deref();
+        let question = format!("{} of {}: {} pays {} {}.  OK? [Y/n/q, or a different amount]", reviewed.len() + 1,
+            reviewed.len() + 1 + proposed.len(), t.from, t.to, amount(config, t.amt, ccy));
+        let paid = match ask(&question).as_deref() {
+            Some("") | Some("y") | Some("Y") => {
+                reviewed.push(t);
+                continue;
+            }
+            Some("q") | Some("Q") | None => {
+                reviewed.push(t);
+                break;
+            }
+            Some("n") | Some("N") => {
+                avoid.insert((t.currency.clone(), t.from.clone(), t.to.clone()));
+                0
+            }
+            // In the units it was shown in
+            Some(x) => {
+                let amt = match config.display.in_major_units(ccy) {
+                    true => config.locale().parse_minor(x, config.display.currency(ccy).exponent),
+                    false => x.parse::<isize>().map_err(|_| format!("Invalid amount '{}' (expected eg. 1234)", x)),
+                };
+                match amt.and_then(|amt| if amt > 0 { Ok(amt) } else { Err(format!("Invalid amount: {} (expected a positive amount)", x)) }) {
+                    Ok(amt) => amt,
+                    Err(e) => {
+                        warn!("{}", e);
+                        proposed.insert(0, t);
+                        continue;
+                    }
                 }
             }
+        };
+        // Settle whatever's left, in each currency
+        let mut balances: BTreeMap<Option<String>, BTreeMap<String, isize>> = BTreeMap::new();
+        for x in proposed.iter().chain(Some(&t)) {
+            let xs = balances.entry(x.currency.clone()).or_default();
+            *xs.entry(x.from.clone()).or_default() += x.amt;
+            *xs.entry(x.to.clone()).or_default() -= x.amt;
+        }
+        if paid > 0 {
+            let xs = balances.entry(t.currency.clone()).or_default();
+            *xs.entry(t.from.clone()).or_default() -= paid;
+            *xs.entry(t.to.clone()).or_default() += paid;
+            let mut edited = Transfer { amt: paid, ..t };
+            edited.id = Some(edited.digest());
+            reviewed.push(edited);
         }
+        proposed.clear();
+        for (currency, xs) in balances {
+            let mut options = config.plan_options(mode);
+            options.avoid = avoid.iter().filter(|x| x.0 == currency).map(|x| (x.1.clone(), x.2.clone())).collect();
+            let xs: Vec<(String, isize)> = xs.into_iter().filter(|x| x.1 != 0).collect();
+            let plan = solve_with(config, options, xs, &mut Summary::default());
+            proposed.extend(plan.transfers.into_iter().map(|t| Transfer { currency: currency.clone(), ..t }));
+        }
+    }
+    reviewed.extend(proposed);
+    reviewed
+}
+
+/// Ask the user something on stderr, and read a line of stdin.  (`None` at the end of the input.)
+fn ask(question: &str) -> Option<String> {
+    eprint!("{} ", question);
+    let mut answer = String::new();
+    match ::std::io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+/// `repay lint`: report suspicious records.
+fn lint(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &[ledger_path.into()]);
+    // Transfers which don't do anything are worth reporting, rather than dropping
+    config.validation = ledger::Validation::Keep;
+    let config = &config;
+    let format = ledger_format(config, format, ledger_path);
+    let mut names = config.names();
+    let roster: Vec<String> = match opts.value_of_os("roster") {
+        Some(path) => ::std::fs::read_to_string(path).unwrap_or_else(|e| {
+            fail!(Exit::Failure, "Couldn't read {}: {}", Path::new(path).display(), e);
+        }).lines().map(str::trim).filter(|x| !x.is_empty() && !x.starts_with('#')).map(String::from).collect(),
+        None => config.groups.values().flatten().cloned().collect(),
+    };
+    let roster = if roster.is_empty() { None } else { Some(roster.into_iter().map(|x| names.canonicalise(x)).collect()) };
+    let options = lint::Options { roster, today: Date::today(), rates: config.rates_file.is_some() };
+    let findings = lint::lint(read_ledger(config, ledger_path, format).map(|t| names.normalise(t)), &options);
+    report_merges(&names);
+    for f in &findings {
+        out!("{}", serde_json::to_string(f).unwrap());
     }
-    repayments
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    info!("{} findings, {} of them errors", findings.len(), errors);
+    if errors > 0 {
+        write_output();
+        fail!(Exit::CheckFailed, "{} of the findings are errors", errors);
+    }
+}
+
+/// How much pairwise netting took care of.
+fn report_netting(config: &Config, netting: &PairwiseNetting) {
+    info!("Netting each pair's debts cancels out {} of the {} owed, leaving {} repayments totalling {}",
+        config.display.format(netting.eliminated(), None), config.display.format(netting.gross, None),
+        netting.transfers.len(), config.display.format(netting.net(), None));
+}
+
+/// `repay net-pairs`: plan repayments using pairwise netting only.
+fn net_pairs(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_path = Path::new(opts.value_of_os("PATH").unwrap());
+    let graph = DebtGraph::from_transfers(read_counted(config, format, &[ledger_path.into()]));
+    let netting = netting::net_pairs(&graph);
+    report_netting(config, &netting);
+    print_transfers(config, &netting.transfers);
+}
+
+/// `repay people`: everyone in the ledgers.  (The shell completions use this for names.)
+fn people(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let ledger_paths = ledger_paths(opts);
+    let mut config = config.clone();
+    use_ledger_headers(&mut config, format, &ledger_paths);
+    let config = &config;
+    let mut names = config.names();
+    let mut people = BTreeSet::new();
+    for path in &ledger_paths {
+        for t in read_ledger(config, path, ledger_format(config, format, path)) {
+            let t = names.normalise(t);
+            people.insert(t.from);
+            people.insert(t.to);
+        }
+    }
+    for p in people { out!("{}", p); }
+}
+
+/// `repay completions`: clap's completion script, plus the completion of names.  The names come
+/// from `repay people`, run on the first file after the subcommand.
+fn completions(opts: &ArgMatches) {
+    let shell: Shell = opts.value_of("SHELL").unwrap().parse().unwrap();
+    let mut script = vec![];
+    app().gen_completions_to("repay", shell, &mut script);
+    let script = String::from_utf8_lossy(&script);
+    let script = match shell {
+        Shell::Bash => format!("{}\n{}", script.trim_end(), BASH_PEOPLE),
+        // clap's `_repay` is the whole script, so ours goes in its place
+        Shell::Zsh => script.replacen("\n_repay() {", "\n_repay_args() {", 1)
+            .replacen("\n_repay \"$@\"", &format!("\n{}\n_repay \"$@\"", ZSH_PEOPLE), 1),
+        _ => format!("{}\n{}", script.trim_end(), FISH_PEOPLE),
+    };
+    out!("{}", script.trim_end());
+}
+
+const BASH_PEOPLE: &str = r#"
+# People's names, from the ledger
+_repay_people() {
+    local i cmd ledger prev=${COMP_WORDS[COMP_CWORD-1]}
+    for (( i = 1; i < COMP_CWORD; i++ )); do
+        case "${COMP_WORDS[i]}" in
+            add|confirm|equalize|statement) [[ -z $cmd ]] && cmd=${COMP_WORDS[i]} ;;
+            *) [[ -n $cmd && -z $ledger && -f ${COMP_WORDS[i]} && ${COMP_WORDS[i-1]} != -c && ${COMP_WORDS[i-1]} != --config ]] && ledger=${COMP_WORDS[i]} ;;
+        esac
+    done
+    [[ -n $ledger ]] || return 1
+    case "$cmd:$prev" in
+        add:--from|add:--to|confirm:--all-from|equalize:--member) ;;
+        statement:*) [[ $prev == "$ledger" ]] || return 1 ;;
+        *) return 1 ;;
+    esac
+    local IFS=$'\n'
+    COMPREPLY=($(compgen -W "$(repay people "$ledger" 2>/dev/null)" -- "${COMP_WORDS[COMP_CWORD]}"))
+}
+
+_repay_with_people() {
+    _repay_people || _repay "$@"
+}
+
+complete -F _repay_with_people -o bashdefault -o default repay
+"#;
+
+const ZSH_PEOPLE: &str = r#"# People's names, from the ledger
+_repay_people() {
+    local i cmd ledger prev=$words[CURRENT-1]
+    for (( i = 2; i < CURRENT; i++ )); do
+        case $words[i] in
+            add|confirm|equalize|statement) [[ -z $cmd ]] && cmd=$words[i] ;;
+            *) [[ -n $cmd && -z $ledger && -f $words[i] && $words[i-1] != (-c|--config) ]] && ledger=$words[i] ;;
+        esac
+    done
+    [[ -n $ledger ]] || return 1
+    case "$cmd:$prev" in
+        add:--from|add:--to|confirm:--all-from|equalize:--member) ;;
+        statement:*) [[ $prev == $ledger ]] || return 1 ;;
+        *) return 1 ;;
+    esac
+    local -a people
+    people=(${(f)"$(repay people $ledger 2>/dev/null)"})
+    compadd -a people
+}
+
+_repay() {
+    _repay_people || _repay_args "$@"
+}
+"#;
+
+const FISH_PEOPLE: &str = r#"
+# People's names, from the ledger
+function __repay_people
+    set -l cmd
+    set -l prev
+    for word in (commandline -opc)[2..-1]
+        if test -z "$cmd"
+            contains -- $word add confirm equalize statement; and set cmd $word
+        else if test -f "$word"; and not contains -- "$prev" -c --config
+            repay people $word 2>/dev/null
+            return
+        end
+        set prev $word
+    end
+end
+
+function __repay_needs_person
+    set -l words (commandline -opc)
+    test "$words[-2]" = statement; and test -f "$words[-1]"
+end
+
+complete -c repay -n "__fish_seen_subcommand_from add" -l from -x -a "(__repay_people)"
+complete -c repay -n "__fish_seen_subcommand_from add" -l to -x -a "(__repay_people)"
+complete -c repay -n "__fish_seen_subcommand_from confirm" -l all-from -x -a "(__repay_people)"
+complete -c repay -n "__fish_seen_subcommand_from equalize" -l member -x -a "(__repay_people)"
+complete -c repay -n "__fish_seen_subcommand_from statement; and __repay_needs_person" -f -a "(__repay_people)"
+"#;
+
+/// `repay recommend`: suggest flags for the ledger, based on how long past runs took.
+fn recommend(config: &Config, format: Option<Format>, opts: &ArgMatches) {
+    let history = config.history().unwrap_or_else(|| {
+        fail!(Exit::BadInput, "There's no history to go on.  (Use '--history' or set 'history_file' in the config to start one)");
+    });
+    let runs = history.runs().unwrap_or_else(|e| {
+        fail!(Exit::Failure, "Couldn't read {}: {}", history.path().display(), e);
+    });
+    info!("{} runs recorded in {}", runs.len(), history.path().display());
+    let (balances, _, _) = read_balances(config, format, &[opts.value_of_os("PATH").unwrap().into()], &mut Summary::default());
+    // The largest problem which will have to be solved
+    let people = balances.values().map(Vec::len).max().unwrap_or(0);
+    out!("{}", history::recommend(&runs, people));
 }
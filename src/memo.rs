@@ -0,0 +1,93 @@
+/*!
+Explaining repayments with the memos of the transfers they settle.
+
+A plan nets everything out, so a repayment doesn't correspond to any one transfer.  As a rough
+guide, the memo on a repayment from P to Q lists the memos of the transfers between P and Q, or
+(if there aren't any) of the transfers which put P in debt.
+*/
+
+use ledger::Transfer;
+use std::collections::BTreeMap;
+
+/// How many memos to list on a repayment before giving up.
+const MAX_MEMOS: usize = 5;
+
+/// The memos in a ledger.  Only the first few for each pair of people are kept (which is all
+/// `describe` needs), so this stays small however long the ledger is.
+#[derive(Clone, Debug, Default)]
+pub struct Memos {
+    /// Keyed by (from, to, currency).
+    entries: BTreeMap<(String, String, Option<String>), Labels>,
+    count: usize,
+}
+
+/// The labels of the transfers from one person to another.
+#[derive(Clone, Debug, Default)]
+struct Labels {
+    /// The first few, in ledger order.  Each is numbered, so that the labels of several pairs can
+    /// be put back in order.
+    first: Vec<(usize, String)>,
+    /// How many there were.
+    count: usize,
+}
+
+impl Memos {
+    /// Record a transfer's memo, if it has one.
+    pub fn add(&mut self, t: &Transfer<String>) {
+        let memo = match t.memo {
+            Some(ref memo) if !memo.is_empty() => memo,
+            _ => return,
+        };
+        let entry = self.entries.entry((t.from.clone(), t.to.clone(), t.currency.clone())).or_default();
+        entry.count += 1;
+        self.count += 1;
+        if entry.first.len() == MAX_MEMOS { return; }
+        let label = match t.date {
+            Some(d) => format!("{} {:02}/{:02}", memo, d.date.day(), d.date.month()),
+            None => memo.clone(),
+        };
+        entry.first.push((self.count, label));
+    }
+
+    /// A memo for a planned repayment, like "settles: dinner 12/03, rent 01/04".  Only transfers
+    /// in the repayment's currency count.
+    pub fn describe(&self, repayment: &Transfer<String>) -> Option<String> {
+        let (p, q) = (&repayment.from, &repayment.to);
+        let entries = || self.entries.iter()
+            .filter(|((_, _, ccy), _)| *ccy == repayment.currency)
+            .map(|((from, to, _), entry)| (from, to, entry));
+        let mut found: Vec<_> = entries().filter(|&(from, to, _)| (from == p && to == q) || (from == q && to == p)).collect();
+        if found.is_empty() {
+            found = entries().filter(|&(_, to, _)| to == p).collect();
+        }
+        if found.is_empty() { return None; }
+        let total: usize = found.iter().map(|(_, _, entry)| entry.count).sum();
+        let mut labels: Vec<&(usize, String)> = found.iter().flat_map(|(_, _, entry)| &entry.first).collect();
+        labels.sort();
+        let mut memo = format!("settles: {}", labels.iter().take(MAX_MEMOS).map(|x| x.1.as_str()).collect::<Vec<_>>().join(", "));
+        if total > MAX_MEMOS {
+            memo += &format!(", and {} more", total - MAX_MEMOS);
+        }
+        Some(memo)
+    }
+}
+
+#[test]
+fn test_describe() {
+    let t = |from: &str, to: &str, memo: &str, date: Option<&str>| Transfer {
+        memo: Some(memo.to_string()),
+        date: date.map(|d| d.parse().unwrap()),
+        ..Transfer::new(from.to_string(), to.to_string(), 100)
+    };
+    let mut memos = Memos::default();
+    for x in &[t("carol", "bob", "dinner", Some("2024-03-12")), t("carol", "bob", "rent", Some("2024-04-01")),
+               t("ann", "dave", "taxi", None)] {
+        memos.add(x);
+    }
+    let repayment = |from: &str, to: &str| Transfer::new(from.to_string(), to.to_string(), 1);
+    assert_eq!(memos.describe(&repayment("bob", "carol")).unwrap(), "settles: dinner 12/03, rent 01/04");
+    assert_eq!(memos.describe(&repayment("dave", "carol")).unwrap(), "settles: taxi");
+    assert_eq!(memos.describe(&repayment("ann", "carol")), None);
+    for i in 0..10 { memos.add(&t(if i % 2 == 0 { "bob" } else { "carol" }, if i % 2 == 0 { "carol" } else { "bob" }, &i.to_string(), None)); }
+    assert_eq!(memos.describe(&repayment("bob", "carol")).unwrap(), "settles: dinner 12/03, rent 01/04, 0, 1, 2, and 7 more");
+}
@@ -0,0 +1,172 @@
+/*!
+Matching up people's names.
+
+The same person can be spelled in more than one way: "José" might be written with a precomposed
+"é", or with an "e" followed by a combining accent, and these look identical but compare unequal.
+So before aggregating, we put every name into Unicode NFC.  Optionally (in the config) we ignore
+case too (`case_fold`), so that "JOSÉ" and "josé" are the same person; leading, trailing, and
+repeated whitespace (`whitespace`), so that "anna " is "anna"; and the differences which NFKC
+removes (`compatibility`), so that "ａｎｎａ" typed with a full-width keyboard layout is "anna".
+And names which really are different can be declared the same person with `aliases` (in the
+config, or a ledger's header), eg. `{"Bob": ["bob", "Robert S"]}`.
+
+Whenever two different spellings get merged, we keep a record, so that the merges can be reported
+and checked.  The first spelling we see is the one which appears in the output.
+*/
+
+use ledger::Transfer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NameConfig {
+    /// Treat names which differ only in case as the same person.
+    pub case_fold: bool,
+    /// Ignore whitespace at either end, and treat every run of whitespace inside as one space.
+    pub whitespace: bool,
+    /// Use Unicode NFKC instead of NFC, so that eg. full-width and ordinary letters are the same.
+    pub compatibility: bool,
+    /// Other names for people, keyed by the name which should be used.
+    pub aliases: BTreeMap<String, Vec<String>>,
+}
+
+impl NameConfig {
+    /// Add some more aliases.
+    pub fn add_aliases(&mut self, aliases: &BTreeMap<String, Vec<String>>) -> Result<(), String> {
+        let mut config = self.clone();
+        for (name, xs) in aliases {
+            config.aliases.entry(name.clone()).or_default().extend(xs.iter().cloned());
+        }
+        config.check()?;
+        *self = config;
+        Ok(())
+    }
+
+    /// It's an error for a name to be an alias of two different people.
+    pub fn check(&self) -> Result<(), String> {
+        let names = Names::new(self.clone());
+        for (name, xs) in &self.aliases {
+            for x in xs {
+                let theirs = &names.aliases[&names.key(x)];
+                if names.key(theirs) != names.key(name) {
+                    return Err(format!("{:?} is an alias of both {:?} and {:?}", x, name, theirs));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps every spelling of a name to a single canonical one.
+#[derive(Clone, Debug)]
+pub struct Names {
+    config: NameConfig,
+    /// Keyed by normalised name.  The first spelling seen, and then all the others.
+    spellings: BTreeMap<String, (String, BTreeSet<String>)>,
+    /// Normalised alias => the name it stands for.
+    aliases: BTreeMap<String, String>,
+}
+
+/// Some spellings which were treated as the same person.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Merge {
+    /// The spelling which was used.
+    pub name: String,
+    /// The other spellings which were merged into it.
+    pub variants: Vec<String>,
+    /// Whether the variants were all declared as aliases.
+    pub expected: bool,
+}
+
+impl fmt::Display for Merge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Treating {} as the same person as {:?}",
+            self.variants.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>().join(", "), self.name)
+    }
+}
+
+impl Names {
+    pub fn new(config: NameConfig) -> Names {
+        let mut names = Names { config, spellings: BTreeMap::new(), aliases: BTreeMap::new() };
+        for (name, xs) in &names.config.aliases {
+            // The name given for someone in their aliases is the one which gets used
+            names.spellings.insert(names.key(name), (name.clone(), BTreeSet::new()));
+            for x in xs { names.aliases.insert(names.key(x), name.clone()); }
+        }
+        names
+    }
+
+    /// The form of `name` which is used for comparisons.
+    fn key(&self, name: &str) -> String {
+        let mut key: String = if self.config.compatibility { name.nfkc().collect() } else { name.nfc().collect() };
+        if self.config.whitespace { key = key.split_whitespace().collect::<Vec<_>>().join(" "); }
+        if self.config.case_fold { key = key.to_lowercase(); }
+        key
+    }
+
+    /// The canonical spelling of `name`.
+    pub fn canonicalise(&mut self, name: String) -> String {
+        let key = self.key(&name);
+        let key = match self.aliases.get(&key) {
+            Some(alias) => self.key(alias),
+            None => key,
+        };
+        let entry = self.spellings.entry(key).or_insert_with(|| (name.clone(), BTreeSet::new()));
+        if entry.0 != name { entry.1.insert(name); }
+        entry.0.clone()
+    }
+
+    /// Replace the sender's and recipient's names with their canonical spellings.
+    pub fn normalise(&mut self, mut transfer: Transfer<String>) -> Transfer<String> {
+        transfer.from = self.canonicalise(transfer.from);
+        transfer.to = self.canonicalise(transfer.to);
+        transfer
+    }
+
+    /// Every merge performed so far.
+    pub fn merges(&self) -> Vec<Merge> {
+        self.spellings.values()
+            .filter(|x| !x.1.is_empty())
+            .map(|x| Merge {
+                name: x.0.clone(),
+                variants: x.1.iter().cloned().collect(),
+                expected: x.1.iter().all(|v| self.aliases.contains_key(&self.key(v))),
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_names() {
+    let mut names = Names::new(NameConfig::default());
+    assert_eq!(names.canonicalise("Jos\u{e9}".into()), "Jos\u{e9}");
+    assert_eq!(names.canonicalise("Jose\u{301}".into()), "Jos\u{e9}");
+    assert_eq!(names.canonicalise("JOS\u{c9}".into()), "JOS\u{c9}");
+    assert_eq!(names.merges(), vec![Merge { name: "Jos\u{e9}".into(), variants: vec!["Jose\u{301}".into()], expected: false }]);
+
+    let mut names = Names::new(NameConfig { case_fold: true, ..NameConfig::default() });
+    assert_eq!(names.canonicalise("Jos\u{e9}".into()), "Jos\u{e9}");
+    assert_eq!(names.canonicalise("JOS\u{c9}".into()), "Jos\u{e9}");
+    assert_eq!(names.canonicalise("Ann".into()), "Ann");
+    assert_eq!(names.merges().len(), 1);
+
+    let mut names = Names::new(NameConfig { whitespace: true, compatibility: true, ..NameConfig::default() });
+    assert_eq!(names.canonicalise("anna".into()), "anna");
+    assert_eq!(names.canonicalise("anna ".into()), "anna");
+    assert_eq!(names.canonicalise("\u{ff41}\u{ff4e}\u{ff4e}\u{ff41}".into()), "anna");
+    assert_eq!(names.canonicalise("Anna  Maria".into()), "Anna  Maria");
+    assert_eq!(names.canonicalise("Anna Maria".into()), "Anna  Maria");
+    assert_eq!(names.merges()[0].variants, vec!["Anna Maria".to_string()]);
+
+    let mut config = NameConfig::default();
+    let aliases = |name: &str, xs: &[&str]| vec![(name.to_string(), xs.iter().map(|x| x.to_string()).collect())].into_iter().collect();
+    config.add_aliases(&aliases("Bob", &["bob", "Robert S"])).unwrap();
+    let mut names = Names::new(config.clone());
+    assert_eq!(names.canonicalise("Robert S".into()), "Bob");
+    assert_eq!(names.canonicalise("bob".into()), "Bob");
+    assert_eq!(names.canonicalise("Bob".into()), "Bob");
+    assert_eq!(names.merges(), vec![Merge { name: "Bob".into(), variants: vec!["Robert S".into(), "bob".into()], expected: true }]);
+    assert!(config.add_aliases(&aliases("Rob", &["Robert S"])).is_err());
+}
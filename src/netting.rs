@@ -0,0 +1,61 @@
+/*!
+Pairwise netting: cancelling out debts between each pair of people, and nothing more.
+
+If A owes B 10 and B owes A 3, then after netting A owes B 7.  Nobody's balance changes, so this
+makes no difference to the plan (which nets everything out globally); but it's a much simpler
+rule, and some groups would rather settle up that way.  It's also a useful measure of how much of
+the work the global plan does is just this.
+*/
+
+use export::DebtGraph;
+use ledger::Transfer;
+
+/// The result of netting each pair's debts.
+#[derive(Clone, Debug)]
+pub struct PairwiseNetting {
+    /// One repayment per pair of people who still owe each other something.  The debtor pays the
+    /// creditor.
+    pub transfers: Vec<Transfer<String>>,
+    /// The total of all debts, before netting.
+    pub gross: isize,
+}
+
+impl PairwiseNetting {
+    /// The total of all debts, after netting.
+    pub fn net(&self) -> isize {
+        self.transfers.iter().map(|t| t.amt).sum()
+    }
+
+    /// How much the netting cancelled out.
+    pub fn eliminated(&self) -> isize {
+        self.gross - self.net()
+    }
+}
+
+/// Net the debts between each pair of people.  Every transfer is given an id, as in a plan.
+pub fn net_pairs(graph: &DebtGraph) -> PairwiseNetting {
+    let mut transfers = vec![];
+    for ((debtor, creditor), &amt) in &graph.edges {
+        let back = graph.edges.get(&(creditor.clone(), debtor.clone())).cloned().unwrap_or(0);
+        if amt > back {
+            let mut t = Transfer::new(debtor.clone(), creditor.clone(), amt - back);
+            t.id = Some(t.digest());
+            transfers.push(t);
+        }
+    }
+    PairwiseNetting { transfers, gross: graph.edges.values().sum() }
+}
+
+#[test]
+fn test_net_pairs() {
+    let graph = DebtGraph::from_transfers(vec![
+        Transfer::new("alice".to_string(), "bob".to_string(), 10),
+        Transfer::new("bob".to_string(), "alice".to_string(), 3),
+        Transfer::new("carol".to_string(), "dave".to_string(), 4),
+        Transfer::new("dave".to_string(), "carol".to_string(), 4),
+    ]);
+    let netting = net_pairs(&graph);
+    let transfers: Vec<_> = netting.transfers.iter().map(|t| (t.from.as_str(), t.to.as_str(), t.amt)).collect();
+    assert_eq!(transfers, vec![("bob", "alice", 7)]);
+    assert_eq!((netting.gross, netting.net(), netting.eliminated()), (21, 7, 14));
+}
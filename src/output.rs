@@ -0,0 +1,282 @@
+/*!
+Writing out lists of transfers (eg. a plan) for people and programs to read.
+
+The default is one JSON object per line, which is what scripts want.  There's also CSV, an aligned
+table for the terminal (see `table`), a Markdown table for pasting into a chat or a wiki, and a
+Graphviz graph (see `export::plan_dot`).  The tables only have the columns which some transfer has a
+value for.  The terminal table groups the repayments by who settles up with whom, and ends with
+their total.
+*/
+
+use ledger::Transfer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use table::{Align, Table};
+use units::DisplayConfig;
+
+/// How lists of transfers are written (`--output-format`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// One JSON object per line, with amounts as given by `--amounts`.
+    #[default]
+    Json,
+    /// A header, then one row per transfer.  Amounts are as in the JSON.
+    Csv,
+    /// Aligned columns, with amounts as given by `--unit`.  Payers are red and payees green, if
+    /// there's colour.
+    Table,
+    /// A Markdown table, with amounts as given by `--unit`.
+    Markdown,
+    /// A Graphviz graph of who pays whom.
+    Dot,
+}
+
+pub const ALL_OUTPUT_FORMATS: &[OutputFormat] = &[OutputFormat::Json, OutputFormat::Csv, OutputFormat::Table, OutputFormat::Markdown, OutputFormat::Dot];
+
+impl FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "json" | "jsonl" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "dot" | "graphviz" => Ok(OutputFormat::Dot),
+            _ => Err(format!("Unknown output format '{}' (expected one of: {})", s,
+                ALL_OUTPUT_FORMATS.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutputFormat::Json => f.write_str("json"),
+            OutputFormat::Csv => f.write_str("csv"),
+            OutputFormat::Table => f.write_str("table"),
+            OutputFormat::Markdown => f.write_str("markdown"),
+            OutputFormat::Dot => f.write_str("dot"),
+        }
+    }
+}
+
+/// Whether to colour the output (`--color`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    /// Only if stdout is a terminal, and `NO_COLOR` isn't set.  (This is decided at startup, so by
+    /// the time anything is written it's `Always` or `Never`.)
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for Color {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Color, String> {
+        match s {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!("Unknown colour setting '{}' (expected 'auto', 'always', or 'never')", s)),
+        }
+    }
+}
+
+impl Color {
+    /// Decide what `Auto` means here.
+    pub fn resolve(self) -> Color {
+        use std::io::IsTerminal;
+        match self {
+            Color::Auto if ::std::io::stdout().is_terminal() && ::std::env::var_os("NO_COLOR").is_none() => Color::Always,
+            Color::Auto => Color::Never,
+            x => x,
+        }
+    }
+}
+
+const RED: &str = "31";
+const GREEN: &str = "32";
+const BOLD: &str = "1";
+const DIM: &str = "2";
+
+pub(crate) fn paint(code: &str, text: &str) -> String {
+    if code.is_empty() { return text.to_string(); }
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// Big amounts stand out, and small ones fade into the background.
+fn magnitude(amt: isize, max: isize) -> &'static str {
+    if amt.abs() * 2 >= max { BOLD } else if amt.abs() * 10 < max { DIM } else { "" }
+}
+
+/// The columns which a table can have, in order.
+const COLUMNS: &[&str] = &["group", "id", "date", "from", "to", "amt", "currency", "memo", "category", "tags", "confirms"];
+
+/// The transfers, as lines of text.
+pub fn render(format: OutputFormat, transfers: &[Transfer<String>], display: &DisplayConfig) -> Vec<String> {
+    if format == OutputFormat::Dot {
+        return ::export::plan_dot(transfers, None, display);
+    }
+    if format == OutputFormat::Json {
+        return transfers.iter().map(|t| {
+            let mut json = ::serde_json::to_value(t).unwrap();
+            json["amt"] = display.json(t.amt, t.currency.as_deref());
+            json.to_string()
+        }).collect();
+    }
+    let human = format != OutputFormat::Csv;
+    let cell = |t: &Transfer<String>, col: &str| -> String {
+        match col {
+            "group" => t.extra.get("group").and_then(|x| x.as_str()).unwrap_or_default().to_string(),
+            "id" => t.id.clone().unwrap_or_default(),
+            "date" => t.date.map(|d| d.to_string()).unwrap_or_default(),
+            "from" => t.from.clone(),
+            "to" => t.to.clone(),
+            "amt" if human => display.format(t.amt, t.currency.as_deref()),
+            "amt" => match display.json(t.amt, t.currency.as_deref()) {
+                ::serde_json::Value::String(s) => s,
+                x => x.to_string(),
+            },
+            "currency" => t.currency.clone().unwrap_or_default(),
+            "memo" => t.memo.clone().unwrap_or_default(),
+            "category" => t.category.clone().unwrap_or_default(),
+            "tags" => t.tags.join(";"),
+            "confirms" => t.confirms.clone().unwrap_or_default(),
+            _ => unreachable!(),
+        }
+    };
+    let columns: Vec<&str> = COLUMNS.iter().cloned()
+        .filter(|&c| ["from", "to", "amt"].contains(&c) || transfers.iter().any(|t| !cell(t, c).is_empty()))
+        // A table has a heading for each group instead
+        .filter(|&c| c != "group" || format != OutputFormat::Table)
+        .collect();
+    let rows: Vec<Vec<String>> = transfers.iter().map(|t| columns.iter().map(|c| cell(t, c)).collect()).collect();
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = ::csv::Writer::from_writer(vec![]);
+            writer.write_record(&columns).expect("writing to a Vec");
+            for row in &rows { writer.write_record(row).expect("writing to a Vec"); }
+            let text = String::from_utf8(writer.into_inner().expect("writing to a Vec")).expect("the CSV is UTF-8");
+            text.lines().map(String::from).collect()
+        }
+        OutputFormat::Markdown => {
+            let escape = |x: &str| x.replace('|', "\\|");
+            let mut lines = vec![
+                format!("| {} |", columns.join(" | ")),
+                format!("|{}|", columns.iter().map(|&c| if c == "amt" { "---:" } else { "---" }).collect::<Vec<_>>().join("|")),
+            ];
+            for row in &rows {
+                lines.push(format!("| {} |", row.iter().map(|x| escape(x)).collect::<Vec<_>>().join(" | ")));
+            }
+            lines
+        }
+        _ => {
+            let max = transfers.iter().map(|t| t.amt.abs()).max().unwrap_or(0);
+            let align = |c: &str| if c == "amt" { Align::Right } else { Align::Left };
+            let mut table = Table::new(&columns.iter().map(|&c| (c, align(c))).collect::<Vec<_>>());
+            // Each group of people who settle up among themselves, if there's more than one; or
+            // each group which the repayments say they're for (see `--by-group`)
+            let groups = ::explain::explain(transfers, &BTreeMap::new());
+            let tagged = transfers.iter().any(|t| t.extra.contains_key("group"));
+            let section = |i: usize| if tagged { (cell(&transfers[i], "group"), 0) } else { (String::new(), groups[i].group) };
+            let heading = |i: usize| if tagged {
+                let tag = cell(&transfers[i], "group");
+                let people: BTreeSet<&str> = transfers.iter().filter(|t| cell(t, "group") == tag)
+                    .flat_map(|t| [t.from.as_str(), t.to.as_str()]).collect();
+                format!("{}: {}", tag, people.into_iter().collect::<Vec<_>>().join(", "))
+            } else {
+                format!("group {}: {}", groups[i].group, groups[i].people.join(", "))
+            };
+            let mut order: Vec<usize> = (0..transfers.len()).collect();
+            order.sort_by_key(|&i| section(i));
+            let mut current = None;
+            for i in order {
+                let t = &transfers[i];
+                if (tagged || groups.iter().any(|x| x.group > 1)) && current != Some(section(i)) {
+                    table.group(heading(i));
+                    current = Some(section(i));
+                }
+                table.styled_row(rows[i].iter().zip(&columns).map(|(x, &c)| {
+                    let style = match c {
+                        "from" => RED,
+                        "to" => GREEN,
+                        "amt" => magnitude(t.amt, max),
+                        _ => "",
+                    };
+                    (x.clone(), style)
+                }).collect());
+            }
+            if !transfers.is_empty() {
+                let mut total: BTreeMap<Option<&str>, isize> = BTreeMap::new();
+                for t in transfers { *total.entry(t.currency.as_deref()).or_default() += t.amt; }
+                let total = match total.len() {
+                    1 => total.iter().map(|(&ccy, &amt)| display.format(amt, ccy)).collect(),
+                    _ => total.iter().map(|(&ccy, &amt)| display.labelled(amt, ccy)).collect::<Vec<_>>().join(", "),
+                };
+                table.total(columns.iter().map(|&c| match c {
+                    "to" => "total".to_string(),
+                    "amt" => total.clone(),
+                    _ => String::new(),
+                }).collect());
+            }
+            table.render(display.color == Color::Always)
+        }
+    }
+}
+
+#[test]
+fn test_render() {
+    let mut display = DisplayConfig::default();
+    let plan = vec![
+        Transfer { id: Some("a1".into()), ..Transfer::new("bob".to_string(), "ann".to_string(), 12345) },
+        Transfer { id: Some("b2".into()), memo: Some("a | b".into()), ..Transfer::new("cat".to_string(), "ann".to_string(), 50) },
+    ];
+    assert_eq!(render(OutputFormat::Json, &plan, &display)[0], r#"{"from":"bob","to":"ann","amt":12345,"id":"a1"}"#);
+    assert_eq!(render(OutputFormat::Csv, &plan, &display), vec!["id,from,to,amt,memo", "a1,bob,ann,12345,", "b2,cat,ann,50,a | b"]);
+    display.unit = ::units::Unit::Major;
+    assert_eq!(render(OutputFormat::Table, &plan, &display), vec![
+        "id  from  to        amt  memo",
+        "a1  bob   ann    123.45",
+        "b2  cat   ann      0.50  a | b",
+        "--  ----  -----  ------  -----",
+        "          total  123.95",
+    ]);
+    display.color = Color::Always;
+    assert_eq!(render(OutputFormat::Table, &plan, &display)[2], "b2  \x1b[31mcat \x1b[0m  \x1b[32mann  \x1b[0m  \x1b[2m  0.50\x1b[0m  a | b");
+    assert_eq!(render(OutputFormat::Markdown, &plan, &display), vec![
+        "| id | from | to | amt | memo |",
+        "|---|---|---|---:|---|",
+        "| a1 | bob | ann | 123.45 |  |",
+        "| b2 | cat | ann | 0.50 | a \\| b |",
+    ]);
+    // Settled in two groups
+    display.color = Color::Never;
+    let plan = vec![plan[0].clone(), Transfer::new("dan".to_string(), "eve".to_string(), 700), plan[1].clone()];
+    assert_eq!(render(OutputFormat::Table, &plan, &display)[1..7].to_vec(), vec![
+        "",
+        "group 1: ann, bob, cat",
+        "a1  bob   ann    123.45",
+        "b2  cat   ann      0.50  a | b",
+        "",
+        "group 2: dan, eve",
+    ]);
+    // Tagged with the groups they're for (see `--by-group`), which the table is split into instead
+    let tag = |t: &Transfer<String>, group: &str| {
+        let mut t = t.clone();
+        t.extra.insert("group".into(), group.into());
+        t
+    };
+    let plan = vec![tag(&plan[0], "home"), tag(&plan[1], "trip"), tag(&plan[2], "trip")];
+    assert_eq!(render(OutputFormat::Csv, &plan, &display)[1], "home,a1,bob,ann,12345,");
+    assert_eq!(render(OutputFormat::Table, &plan, &display)[1..6].to_vec(), vec![
+        "",
+        "home: ann, bob",
+        "a1  bob   ann    123.45",
+        "",
+        "trip: ann, cat, dan, eve",
+    ]);
+}
@@ -0,0 +1,482 @@
+/*!
+Computing repayment plans from balances.
+
+A balance is positive if the person has received more than they've given (ie. they owe money),
+and negative if they're owed money.  A plan is a list of transfers which brings every balance
+back to zero.
+*/
+
+use cache::Cache;
+use ledger::Transfer;
+#[cfg(feature = "approx")] use mcmf::*;
+use mzsp::{Partitioning, Progress, MZSP};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Which solver the user asked for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Exact if there are few enough balances, approximate otherwise.
+    #[default]
+    Auto,
+    Exact,
+    Approx,
+}
+
+impl Mode {
+    /// The mode selected by the `-x` and `-a` flags.  It's an error to give both.
+    pub fn from_flags(exact: bool, approx: bool) -> Result<Mode, String> {
+        match (exact, approx) {
+            (true, true) => Err("Exact mode and approximate mode can't both be used".into()),
+            (true, false) => Ok(Mode::Exact),
+            (false, true) => Ok(Mode::Approx),
+            (false, false) => Ok(Mode::Auto),
+        }
+    }
+}
+
+/// A solver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Solver {
+    /// Optimal, but exponential in the number of balances.  Can't handle more than
+    /// `mzsp::MAX_LEN`.
+    Exact,
+    /// Fast, but may produce more repayments than necessary.  Needs the `approx` feature.
+    #[cfg(feature = "approx")]
+    Approx,
+}
+
+impl fmt::Display for Solver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Solver::Exact => f.write_str("exact"),
+            #[cfg(feature = "approx")]
+            Solver::Approx => f.write_str("approx"),
+        }
+    }
+}
+
+/// In auto mode, use the exact solver for up to this many balances.
+pub const AUTO_EXACT_LIMIT: usize = 20;
+
+/// Decide which solver to run on `n` balances.  An explicit choice always wins; in auto mode, we
+/// use the exact solver if `n` is small enough, and warn if it isn't.  Without the `approx`
+/// feature, it's always the exact solver.
+pub fn choose_solver(mode: Mode, n: usize) -> (Solver, Option<Warning>) {
+    match mode {
+        Mode::Exact => (Solver::Exact, None),
+        Mode::Auto if n <= AUTO_EXACT_LIMIT => (Solver::Exact, None),
+        #[cfg(feature = "approx")]
+        Mode::Approx => (Solver::Approx, None),
+        #[cfg(feature = "approx")]
+        Mode::Auto => (Solver::Approx, Some(Warning::Approximate)),
+        #[cfg(not(feature = "approx"))]
+        Mode::Approx | Mode::Auto => (Solver::Exact, Some(Warning::NoApproxSolver)),
+    }
+}
+
+/// Something which the user should know about a plan.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "warning", rename_all = "kebab-case")]
+pub enum Warning {
+    /// There were too many balances for the exact solver, so we fell back to the approximate one.
+    Approximate,
+    /// The approximate solver was wanted, but this build doesn't have it.
+    NoApproxSolver,
+    /// Some repayments were too large for the approximate solver, so they go via someone else.
+    MaxTransferExceeded,
+    /// Someone with a zero balance was passed to the approximate solver.  (They're ignored.)
+    ZeroNode { who: String },
+    /// The approximate plan might not be optimal: `at_least` repayments is a lower bound (see
+    /// `mzsp::bounds`).
+    OptimalityGap { repayments: usize, at_least: usize },
+    /// `from` was to avoid paying `to` (see `Options::avoid`), but there's no way around it.
+    Unavoidable { from: String, to: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::Approximate =>
+                write!(f, "The following solution may be approximate.  (Use '-x' to force exact mode)"),
+            Warning::NoApproxSolver =>
+                write!(f, "Built without the approximate solver, so using the exact one.  (This may be slow)"),
+            Warning::MaxTransferExceeded =>
+                write!(f, "Maximum transfer amount exceeded.  Repaying via a different route..."),
+            Warning::ZeroNode { ref who } => write!(f, "Got a zero node: {}", who),
+            Warning::OptimalityGap { repayments, at_least } =>
+                write!(f, "The plan has {} repayments, but it might be possible with as few as {}",
+                    repayments, at_least),
+            Warning::Unavoidable { ref from, ref to } =>
+                write!(f, "There's no way to settle up without {} paying {}", from, to),
+        }
+    }
+}
+
+/// A repayment plan, and anything which went wrong while computing it.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    pub transfers: Vec<Transfer<String>>,
+    pub warnings: Vec<Warning>,
+    /// The solver which produced it.
+    pub solver: Solver,
+    /// How long the exact solver spent partitioning the balances.  (The rest of the time went on
+    /// turning the parts into repayments.)  Zero for the approximate solver.
+    pub partitioning: Duration,
+    /// The fewest repayments which a plan could have.  For the exact solver, this is the plan's own
+    /// number of repayments; for the approximate one, it's a lower bound (see `mzsp::bounds`).
+    pub at_least: usize,
+}
+
+/// How to compute a plan.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub mode: Mode,
+    /// Among the plans with the fewest repayments, prefer one where the largest group of people
+    /// settling up amongst themselves is as small as possible.  This keeps chains of repayments
+    /// short.  (Only the exact solver can do this.)
+    pub short_chains: bool,
+    /// The exact solver looks here before doing any work, and saves what it finds.
+    pub cache: Option<Cache>,
+    /// Give up on the exact solver if it takes longer than this.  (`plan_with` fails with
+    /// `mzsp::Error::Cancelled`.)
+    pub timeout: Option<Duration>,
+    /// The exact solver tells this how it's getting on, every so often (see
+    /// `mzsp::MZSP::compute_monitored`).
+    pub progress: Option<fn(Progress)>,
+    /// Pairs of people, as (payer, payee), where the payer shouldn't pay the payee directly.  The
+    /// plan goes around them if it can, even if that takes more repayments.
+    pub avoid: BTreeSet<(String, String)>,
+}
+
+/// Compute a repayment plan.  Every transfer in the plan is normalised (ie. has a positive
+/// amount) and is given an id (see `Transfer::digest`).  They're sorted by payer, then payee, then
+/// amount.  Fails if the exact solver can't handle the balances (see `hint`).
+pub fn plan(balances: Vec<(String, isize)>, mode: Mode) -> Result<Plan, ::mzsp::Error> {
+    plan_with(balances, &Options { mode, ..Options::default() })
+}
+
+/// Like `plan`, but with more options.  Also fails if the exact solver runs out of time.
+pub fn plan_with(balances: Vec<(String, isize)>, options: &Options) -> Result<Plan, ::mzsp::Error> {
+    let (solver, warning) = choose_solver(options.mode, balances.len());
+    #[cfg_attr(not(feature = "approx"), allow(unused_mut))]
+    let mut warnings: Vec<Warning> = warning.into_iter().collect();
+    let (mut transfers, partitioning, at_least) = match solver {
+        Solver::Exact => {
+            let ts = Instant::now();
+            let parts = partition(&balances, options)?;
+            let partitioning = ts.elapsed();
+            let transfers = settle_parts(&parts, &balances);
+            let at_least = transfers.len();
+            (transfers, partitioning, at_least)
+        }
+        #[cfg(feature = "approx")]
+        Solver::Approx => {
+            // Every zero-sum part saves a repayment, so `n - max_parts` is as good as it can get
+            let values: Vec<isize> = balances.iter().map(|x| x.1).collect();
+            let at_least = values.len() - ::mzsp::bounds(&values).1;
+            let transfers = compute_repayments_approx(balances, &mut warnings);
+            if transfers.len() <= at_least {
+                // It's optimal after all
+                warnings.retain(|w| *w != Warning::Approximate);
+            } else {
+                warnings.push(Warning::OptimalityGap { repayments: transfers.len(), at_least });
+            }
+            (transfers, Duration::ZERO, at_least)
+        }
+    };
+    for p in transfers.iter_mut() { p.normalise(); }
+    if transfers.iter().any(|t| options.avoid.contains(&(t.from.clone(), t.to.clone()))) {
+        transfers = reroute(transfers, &options.avoid, &mut warnings);
+    }
+    for p in transfers.iter_mut() { p.id = Some(p.digest()); }
+    // Whichever solver found it, the same plan is always written out the same way
+    transfers.sort_by(|a, b| (&a.from, &a.to, a.amt).cmp(&(&b.from, &b.to, b.amt)));
+    Ok(Plan { transfers, warnings, solver, partitioning, at_least })
+}
+
+pub fn compute_repayments_exact(balances: Vec<(String, isize)>, options: &Options) -> Result<Vec<Transfer<String>>, ::mzsp::Error> {
+    let parts = partition(&balances, options)?;
+    Ok(settle_parts(&parts, &balances))
+}
+
+/// Compute the largest set of zero-sum paritions.
+fn partition(balances: &[(String, isize)], options: &Options) -> Result<Partitioning, ::mzsp::Error> {
+    let values: Vec<isize> = balances.iter().map(|x| x.1).collect();
+    let mzsp_options = ::mzsp::Options { minimize_largest_part: options.short_chains, ..Default::default() };
+    let solve = |values: &[isize]| partition_exact(values, mzsp_options, options);
+    let parts = match options.cache {
+        Some(ref cache) => cache.partitioning(&values, mzsp_options, solve)?,
+        None => solve(&values)?,
+    };
+    info!("Divided into {} partitions", parts.num_parts());
+    Ok(parts)
+}
+
+fn settle_parts(parts: &Partitioning, balances: &[(String, isize)]) -> Vec<Transfer<String>> {
+    parts.parts().flat_map(|partition| {
+        let balances: Vec<(String,isize)> = partition.iter()
+            .map(|&idx| balances[idx].clone())
+            .collect();
+        // For each partition, construct a plan.  We know that these partitions contain no zero-sum
+        // subsets, so `construct_plan` is optimal.
+        construct_plan(balances)
+    }).collect()
+}
+
+fn partition_exact(values: &[isize], mzsp_options: ::mzsp::Options, options: &Options) -> Result<Partitioning, ::mzsp::Error> {
+    if options.timeout.is_none() && options.progress.is_none() {
+        return MZSP::try_compute_with(values.iter().map(|&x| x as i64), mzsp_options)
+            .map(|x| Partitioning::from_sets(values.len(), x));
+    }
+    // A watchdog cancels the search when the time's up, unless it's told that it finished first
+    // (by `done` being dropped)
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (done, finished) = mpsc::channel::<()>();
+    let watchdog = options.timeout.map(|timeout| {
+        let cancel = cancel.clone();
+        thread::spawn(move || if finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+            cancel.store(true, Ordering::Relaxed);
+        })
+    });
+    let report = |p| if let Some(progress) = options.progress { progress(p) };
+    let result = Partitioning::compute_monitored(values, mzsp_options, Some(&cancel), &report);
+    drop(done);
+    if let Some(watchdog) = watchdog { let _ = watchdog.join(); }
+    result
+}
+
+/// What the exact solver should say when it fails.
+pub fn hint(e: &::mzsp::Error) -> &'static str {
+    match *e {
+        ::mzsp::Error::TooManyElements { .. } => "Please use approximate mode instead.",
+        ::mzsp::Error::NotZeroSum { .. } => "The ledger's balances should always cancel out; this is a bug.",
+        ::mzsp::Error::Overflow => "Some of the amounts are implausibly large; check the ledger for typos.",
+        ::mzsp::Error::Cancelled => "It ran out of time; try approximate mode, or a longer timeout.",
+        _ => "Please report this as a bug.",
+    }
+}
+
+/// Given a zero-sum set of nodes, construct a graph which moves all the value from the positive
+/// nodes to the negative nodes.  This function is *O(n)*, but the graph will be maximally
+/// inefficient, in the sense that it will always contain exactly *n* edges.  If the given set of
+/// nodes contains zero-sum subsets then we can do better.
+// TODO: Use a priority search queue
+pub fn construct_plan<T: Clone>(mut balances: Vec<(T, isize)>) -> Vec<Transfer<T>> {
+    assert_eq!(balances.iter().map(|x|x.1).sum::<isize>(), 0, "balances must be zero-sum");
+    let mut ret = vec![];
+    loop {
+        // Take the node with the smallest absolute value;  this will be our "from" node.
+        balances.sort_unstable_by_key(|&(_, x)| -x.abs());
+        let (from_tag, from_val) = match balances.pop() { None => break, Some(x) => x };
+        if from_val == 0 { continue; }
+        // Find a node with the opposite sign (any will do);  this will be our "to" node.
+        let to = balances.iter_mut().find(|x| x.1.signum() != from_val.signum())
+            .expect("a node with opposite sign");  // The partition is zero-sum => it must exist
+        let to_tag = to.0.clone();
+        to.1 += from_val;  // Eliminate the "from" node with the "to" node.
+        // There's no need to remove zero-balance "to" nodes;  this will only occur for the very
+        // last node.
+        ret.push(Transfer::new(from_tag, to_tag, from_val));
+    }
+    ret
+}
+
+/// Change the plan so that no one pays someone they should avoid, if possible.  Each group of
+/// people who settle up amongst themselves is settled again, avoiding those repayments; and if
+/// that can't be done, it's settled along with the next group, and so on.  (Each time two groups
+/// are combined, it takes one more repayment.)
+fn reroute(transfers: Vec<Transfer<String>>, avoid: &BTreeSet<(String, String)>, warnings: &mut Vec<Warning>) -> Vec<Transfer<String>> {
+    let avoided = |t: &Transfer<String>| avoid.contains(&(t.from.clone(), t.to.clone()));
+    // Split the plan into groups, by who's connected to whom
+    let mut groups: Vec<Vec<Transfer<String>>> = vec![];
+    for t in transfers {
+        let touches = |g: &Vec<Transfer<String>>| g.iter().any(|x| [&x.from, &x.to].iter().any(|&w| *w == t.from || *w == t.to));
+        let (joined, rest): (Vec<_>, Vec<_>) = groups.into_iter().partition(touches);
+        let mut group: Vec<Transfer<String>> = joined.into_iter().flatten().collect();
+        group.push(t);
+        groups = rest;
+        groups.push(group);
+    }
+    let mut ret = vec![];
+    while let Some(i) = groups.iter().position(|g| g.iter().any(avoided)) {
+        let mut group = groups.swap_remove(i);
+        loop {
+            let mut balances: BTreeMap<String, isize> = BTreeMap::new();
+            for t in &group {
+                *balances.entry(t.from.clone()).or_default() += t.amt;
+                *balances.entry(t.to.clone()).or_default() -= t.amt;
+            }
+            if let Some(settled) = settle_avoiding(balances.into_iter().collect(), avoid) {
+                ret.extend(settled);
+                break;
+            }
+            match groups.pop() {
+                Some(other) => group.extend(other),
+                None => {
+                    for t in group.iter().filter(|t| avoided(t)) {
+                        warnings.push(Warning::Unavoidable { from: t.from.clone(), to: t.to.clone() });
+                    }
+                    ret.extend(group);
+                    break;
+                }
+            }
+        }
+    }
+    ret.extend(groups.into_iter().flatten());
+    ret
+}
+
+/// Like `construct_plan`, but without any of the repayments in `avoid`.  Someone's balance can be
+/// passed on to someone on the same side, who then pays (or is paid) the total.  The people with
+/// repayments to avoid are settled first, while there are still others to go through.  This
+/// doesn't always find a way round, even if there is one.
+fn settle_avoiding(mut balances: Vec<(String, isize)>, avoid: &BTreeSet<(String, String)>) -> Option<Vec<Transfer<String>>> {
+    let constrained: BTreeSet<&String> = avoid.iter().flat_map(|x| [&x.0, &x.1]).collect();
+    let mut ret = vec![];
+    loop {
+        balances.sort_by_key(|(who, x)| (constrained.contains(who), -x.abs()));
+        let (who, x) = match balances.pop() { None => break, Some(b) => b };
+        if x == 0 { continue; }
+        // Who'd pay whom, if `who` settled up with `other`
+        let pair = |other: &str| if x > 0 { (who.clone(), other.to_string()) } else { (other.to_string(), who.clone()) };
+        let allowed = |o: &(String, isize)| !avoid.contains(&pair(&o.0));
+        let i = balances.iter().position(|o| o.1.signum() != x.signum() && allowed(o))
+            .or_else(|| balances.iter().position(allowed))?;
+        balances[i].1 += x;
+        let (from, to) = pair(&balances[i].0);
+        ret.push(Transfer::new(from, to, x.abs()));
+    }
+    Some(ret)
+}
+
+#[cfg(feature = "approx")]
+pub fn compute_repayments_approx(balances: Vec<(String, isize)>, warnings: &mut Vec<Warning>)
+    -> Vec<Transfer<String>>
+{
+    // (Step 1.5: Set up a fully-connected graph with one node per person)
+    let mut graph = GraphBuilder::new();
+    for (x, _) in balances.iter() {
+        for (y, _) in balances.iter() {
+            if x != y {
+                graph.add_edge(x.clone(), y.clone(), Capacity(1_000_000_000), Cost(1));
+            }
+        }
+    }
+
+    // Step 2: Figure out how to shift money around to make all the balances go back to 0
+    for (client, balance) in balances {
+        if balance > 0 {
+            graph.add_edge(Vertex::Source, client, Capacity(balance.unsigned_abs() as u32), Cost(0));
+        } else if balance < 0 {
+            graph.add_edge(client, Vertex::Sink, Capacity(balance.unsigned_abs() as u32), Cost(0));
+        } else {
+            warnings.push(Warning::ZeroNode { who: client });
+        }
+    }
+    let (cost, paths) = graph.mcmf();
+    info!("Total flow: {}", cost);
+
+    // (Step 2.5: Wrangle these flows back into the shape of Tranfers)
+    let mut repayments = vec![];
+    for p in paths {
+        if p.flows.len() != 3 && !warnings.contains(&Warning::MaxTransferExceeded) {
+            // Graph is strongly connected => all flows should have length 1
+            warnings.push(Warning::MaxTransferExceeded);
+        }
+        for Flow { a, b, amount, .. } in p.flows {
+            if let Vertex::Node(a) = a {
+                if let Vertex::Node(b) = b {
+                    repayments.push(Transfer::new(a, b, amount as isize));
+                }
+            }
+        }
+    }
+    repayments
+}
+
+#[cfg(feature = "approx")]
+#[test]
+fn test_choose_solver() {
+    for &n in &[0, 1, AUTO_EXACT_LIMIT, AUTO_EXACT_LIMIT + 1, 63, 64, 1000] {
+        assert_eq!(choose_solver(Mode::Exact, n), (Solver::Exact, None));
+        assert_eq!(choose_solver(Mode::Approx, n), (Solver::Approx, None));
+        let expected = if n <= AUTO_EXACT_LIMIT {
+            (Solver::Exact, None)
+        } else {
+            (Solver::Approx, Some(Warning::Approximate))
+        };
+        assert_eq!(choose_solver(Mode::Auto, n), expected);
+    }
+    assert_eq!(Mode::from_flags(false, false), Ok(Mode::Auto));
+    assert_eq!(Mode::from_flags(true, false), Ok(Mode::Exact));
+    assert_eq!(Mode::from_flags(false, true), Ok(Mode::Approx));
+    assert!(Mode::from_flags(true, true).is_err());
+}
+
+#[cfg(feature = "approx")]
+#[test]
+fn test_optimality_gap() {
+    let balances = vec![("a".to_string(), 10), ("b".to_string(), -10), ("c".to_string(), 5), ("d".to_string(), -5)];
+    let plan = plan(balances, Mode::Approx).unwrap();
+    assert_eq!(plan.transfers.len(), 2);
+    assert_eq!(plan.warnings, vec![]);
+}
+
+#[cfg(not(feature = "approx"))]
+#[test]
+fn test_choose_solver_exact_only() {
+    assert_eq!(choose_solver(Mode::Exact, 1000), (Solver::Exact, None));
+    assert_eq!(choose_solver(Mode::Auto, AUTO_EXACT_LIMIT), (Solver::Exact, None));
+    assert_eq!(choose_solver(Mode::Auto, AUTO_EXACT_LIMIT + 1), (Solver::Exact, Some(Warning::NoApproxSolver)));
+    assert_eq!(choose_solver(Mode::Approx, 1), (Solver::Exact, Some(Warning::NoApproxSolver)));
+}
+
+#[test]
+fn test_order() {
+    let balances: Vec<(String, isize)> = vec![("d", -5), ("c", 5), ("b", -10), ("a", 10)].into_iter().map(|(x, y)| (x.to_string(), y)).collect();
+    let summarise = |plan: Plan| plan.transfers.into_iter().map(|t| (t.from, t.to, t.amt)).collect::<Vec<_>>();
+    let exact = summarise(plan(balances.clone(), Mode::Exact).unwrap());
+    assert_eq!(exact, vec![("a".to_string(), "b".to_string(), 10), ("c".to_string(), "d".to_string(), 5)]);
+    // The approximate solver's plan might be different, but it's in the same order
+    #[cfg(feature = "approx")]
+    {
+        let approx = summarise(plan(balances, Mode::Approx).unwrap());
+        assert!(approx.windows(2).all(|x| x[0] <= x[1]), "{:?}", approx);
+    }
+}
+
+#[test]
+fn test_avoid() {
+    let balances: Vec<(String, isize)> = vec![("a", 5), ("b", -3), ("c", -2), ("d", 4), ("e", -4)].into_iter().map(|(x, y)| (x.to_string(), y)).collect();
+    let summarise = |plan: &Plan| plan.transfers.iter().map(|t| (t.from.clone(), t.to.clone(), t.amt)).collect::<Vec<_>>();
+    let avoiding = |pairs: &[(&str, &str)]| Options { avoid: pairs.iter().map(|&(x, y)| (x.to_string(), y.to_string())).collect(), ..Options::default() };
+    let plan = plan_with(balances.clone(), &avoiding(&[("a", "b")])).unwrap();
+    let t = |from: &str, to: &str, amt| (from.to_string(), to.to_string(), amt);
+    assert_eq!(summarise(&plan), vec![t("a", "c", 5), t("c", "b", 3), t("d", "e", 4)]);
+    // d and e can't settle on their own, so they go through the others
+    let plan = plan_with(balances, &avoiding(&[("d", "e")])).unwrap();
+    assert_eq!(plan.transfers.len(), 4);
+    assert!(plan.warnings.is_empty());
+    assert!(!summarise(&plan).iter().any(|x| (x.0.as_str(), x.1.as_str()) == ("d", "e")));
+    let plan = plan_with(vec![("a".to_string(), 1), ("b".to_string(), -1)], &avoiding(&[("a", "b")])).unwrap();
+    assert_eq!(summarise(&plan), vec![t("a", "b", 1)]);
+    assert_eq!(plan.warnings, vec![Warning::Unavoidable { from: "a".into(), to: "b".into() }]);
+}
+
+#[test]
+fn test_timeout() {
+    // Big enough that the search can't finish before the watchdog's had a chance to cancel it
+    let mut balances: Vec<(String, isize)> = (1..24).map(|i| (format!("p{}", i), i * 1009 % 997 + 1)).collect();
+    let total: isize = balances.iter().map(|x| x.1).sum();
+    balances.push(("bank".to_string(), -total));
+    let options = Options { mode: Mode::Exact, timeout: Some(Duration::from_millis(0)), ..Options::default() };
+    assert_eq!(plan_with(balances, &options).err(), Some(::mzsp::Error::Cancelled));
+}
@@ -0,0 +1,150 @@
+/*!
+Quick-add: turning a one-line description of an expense into transfers.
+
+The description looks like one of these:
+
+```text
+alice paid 84.30 for dinner split bob,carol,dave
+alice paid 84.30 for dinner split alice,bob:2,carol
+alice paid 20 to bob
+```
+
+The amount is in major units (eg. euros, not cents).  When an expense is split, each participant
+gets a share in proportion to their weight (1 unless given with `:`), and the payer only pays for
+themself if they're listed.  A `for` clause is optional, and becomes the transfers' category.
+*/
+
+use expense::split;
+use ledger::Transfer;
+use locale::Locale;
+
+/// A parsed quick-add description.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expense {
+    pub payer: String,
+    /// In minor units.
+    pub amount: isize,
+    pub description: Option<String>,
+    pub split: Split,
+}
+
+/// Who the money was for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// Shared between these people, with these weights.
+    Shares(Vec<(String, u32)>),
+    /// Paid directly to one person.
+    To(String),
+}
+
+/// Parse a description.  `exponent` is the number of decimal places in the currency (see
+/// `units::CurrencyFormat`).
+pub fn parse(s: &str, exponent: u32) -> Result<Expense, String> {
+    parse_with(s, exponent, &Locale::default())
+}
+
+/// Like `parse`, with amounts written as in `locale` (eg. `84,30`).
+pub fn parse_with(s: &str, exponent: u32, locale: &Locale) -> Result<Expense, String> {
+    let mut tokens = s.split_whitespace().peekable();
+    let payer = tokens.next().ok_or("The description is empty")?.to_string();
+    if tokens.next() != Some("paid") {
+        return Err(format!("Expected '{} paid <amount> ...'", payer));
+    }
+    let amount = parse_amount(tokens.next().ok_or("Expected an amount after 'paid'")?, exponent, locale)?;
+    let mut description = None;
+    if tokens.peek() == Some(&"for") {
+        tokens.next();
+        let mut words = vec![];
+        while let Some(&word) = tokens.peek() {
+            if word == "split" || word == "to" { break; }
+            words.push(word);
+            tokens.next();
+        }
+        if words.is_empty() { return Err("Expected a description after 'for'".into()); }
+        description = Some(words.join(" "));
+    }
+    let split = match tokens.next() {
+        Some("to") => {
+            let to = tokens.next().ok_or("Expected someone after 'to'")?.to_string();
+            Split::To(to)
+        }
+        Some("split") => {
+            let list: String = tokens.by_ref().collect();
+            Split::Shares(parse_shares(&list)?)
+        }
+        Some(x) => return Err(format!("Expected 'for', 'split', or 'to', not '{}'", x)),
+        None => return Err("Who was it for?  (Expected 'split <people>' or 'to <person>')".into()),
+    };
+    if let Some(x) = tokens.next() {
+        return Err(format!("Unexpected '{}' at the end", x));
+    }
+    Ok(Expense { payer, amount, description, split })
+}
+
+/// Eg. `84.30`, with `exponent` = 2, is 8430.
+fn parse_amount(s: &str, exponent: u32, locale: &Locale) -> Result<isize, String> {
+    let amount = locale.parse_minor(s, exponent)?;
+    if amount < 0 { return Err(format!("Invalid amount '{}' (it can't be negative)", s)); }
+    if amount == 0 { return Err("The amount can't be zero".into()); }
+    Ok(amount)
+}
+
+/// Eg. `bob,carol:2`.
+fn parse_shares(s: &str) -> Result<Vec<(String, u32)>, String> {
+    let mut shares: Vec<(String, u32)> = vec![];
+    for item in s.split(',') {
+        let (name, weight) = match item.find(':') {
+            Some(i) => (&item[..i], item[i + 1..].parse::<u32>().ok().filter(|&w| w > 0)
+                .ok_or_else(|| format!("Invalid weight in '{}' (expected a positive integer)", item))?),
+            None => (item, 1),
+        };
+        if name.is_empty() { return Err(format!("Missing a name in '{}'", s)); }
+        if shares.iter().any(|x| x.0 == name) { return Err(format!("{} is listed twice", name)); }
+        shares.push((name.to_string(), weight));
+    }
+    Ok(shares)
+}
+
+impl Expense {
+    /// The transfers which record the expense: one from the payer to everyone else who had a
+    /// share.  Shares are in whole minor units, so the remainder goes to the first participants,
+    /// one unit each.
+    pub fn transfers(&self) -> Vec<Transfer<String>> {
+        let shares: Vec<(String, isize)> = match self.split {
+            Split::To(ref to) => vec![(to.clone(), self.amount)],
+            Split::Shares(ref shares) => {
+                let weights: Vec<u32> = shares.iter().map(|x| x.1).collect();
+                shares.iter().map(|x| x.0.clone()).zip(split(self.amount, &weights)).collect()
+            }
+        };
+        shares.into_iter()
+            .filter(|&(ref who, amt)| *who != self.payer && amt != 0)
+            .map(|(who, amt)| Transfer { category: self.description.clone(), ..Transfer::new(self.payer.clone(), who, amt) })
+            .collect()
+    }
+}
+
+#[test]
+fn test_parse() {
+    let e = parse("alice paid 84.30 for dinner out split bob, carol:2,alice", 2).unwrap();
+    assert_eq!(e, Expense {
+        payer: "alice".into(),
+        amount: 8430,
+        description: Some("dinner out".into()),
+        split: Split::Shares(vec![("bob".into(), 1), ("carol".into(), 2), ("alice".into(), 1)]),
+    });
+    let transfers: Vec<_> = e.transfers().into_iter().map(|t| (t.to, t.amt)).collect();
+    assert_eq!(transfers, vec![("bob".to_string(), 2108), ("carol".to_string(), 4215)]);
+
+    let e = parse("bob paid 5 to carol", 0).unwrap();
+    assert_eq!((e.amount, e.split), (5, Split::To("carol".into())));
+    assert_eq!(parse("alice paid 1.5 split bob", 2).unwrap().amount, 150);
+    assert_eq!(parse_with("alice paid €1.234,5 split bob", 2, &"de".parse().unwrap()).unwrap().amount, 123450);
+
+    assert!(parse("alice paid 1.234 split bob", 2).is_err());
+    assert!(parse("alice paid -1 split bob", 2).is_err());
+    assert!(parse("alice paid 10 for dinner", 2).is_err());
+    assert!(parse("alice paid 10 split bob,bob", 2).is_err());
+    assert!(parse("alice paid 10 split bob:0", 2).is_err());
+    assert!(parse("alice bought 10 split bob", 2).is_err());
+}
@@ -0,0 +1,105 @@
+/*!
+Exchange rates, for settling a ledger in several currencies with a single plan (`--rates` and
+`--settle-in`).
+
+```json
+{
+  "base": "EUR",
+  "rates": {
+    "GBP": 1.17,
+    "USD": [{ "date": "2024-01-01", "rate": 0.91 }, { "date": "2024-06-01", "rate": 0.93 }]
+  }
+}
+```
+
+A rate is what one unit of the currency is worth in the base currency, and can be converted into
+any other currency with a rate.  Rates may be dated: a dated transfer is converted at the latest
+rate on or before its date (or the earliest one, if the transfer is older than all of them), and an
+undated transfer at the latest rate.
+
+Each transfer is converted on its own, and rounded to the nearest minor unit (halves away from
+zero).  Since a transfer takes the same amount from one person as it gives to another, the
+converted balances still sum to zero.
+*/
+
+use date::Timestamp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rates {
+    /// The currency the rates are in.
+    pub base: Option<String>,
+    rates: BTreeMap<String, Rate>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum Rate {
+    Fixed(f64),
+    Dated(Vec<DatedRate>),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DatedRate {
+    date: Timestamp,
+    rate: f64,
+}
+
+impl Rates {
+    pub fn load(path: &Path) -> Result<Rates, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let rates: Rates = serde_json::from_reader(file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (currency, rate) in &rates.rates {
+            let ok = match rate {
+                Rate::Fixed(x) => *x > 0.,
+                Rate::Dated(xs) => !xs.is_empty() && xs.iter().all(|x| x.rate > 0.),
+            };
+            if !ok { return Err(format!("{}: the rates for {} must be positive", path.display(), currency)); }
+        }
+        Ok(rates)
+    }
+
+    /// What one unit of `currency` was worth in the base currency, `when` the transfer was made.
+    pub fn rate(&self, currency: &str, when: Option<Timestamp>) -> Option<f64> {
+        if self.base.as_deref() == Some(currency) { return Some(1.); }
+        match self.rates.get(currency)? {
+            Rate::Fixed(x) => Some(*x),
+            Rate::Dated(xs) => {
+                let latest = match when {
+                    Some(when) => xs.iter().filter(|x| x.date <= when).max_by_key(|x| x.date)
+                        .or_else(|| xs.iter().min_by_key(|x| x.date)),
+                    None => xs.iter().max_by_key(|x| x.date),
+                };
+                latest.map(|x| x.rate)
+            }
+        }
+    }
+
+    /// Convert an amount of minor units from one currency to another.  The exponents are the
+    /// currencies' numbers of decimal places.
+    pub fn convert(&self, amt: isize, from: (&str, u32), to: (&str, u32), when: Option<Timestamp>) -> Result<isize, String> {
+        let rate = |currency: &str| self.rate(currency, when)
+            .ok_or_else(|| format!("There's no exchange rate for {}", currency));
+        let rate = rate(from.0)? / rate(to.0)?;
+        let scale = 10f64.powi(to.1 as i32 - from.1 as i32);
+        Ok((amt as f64 * rate * scale).round() as isize)
+    }
+}
+
+#[test]
+fn test_convert() {
+    let rates: Rates = serde_json::from_str(r#"{
+        "base": "EUR",
+        "rates": { "GBP": 1.2, "JPY": [{ "date": "2024-06-01", "rate": 0.006 }, { "date": "2024-01-01", "rate": 0.005 }] }
+    }"#).unwrap();
+    let ts = |s: &str| Some(s.parse().unwrap());
+    assert_eq!(rates.convert(1000, ("GBP", 2), ("EUR", 2), None), Ok(1200));
+    assert_eq!(rates.convert(-1001, ("EUR", 2), ("GBP", 2), None), Ok(-834));
+    assert_eq!(rates.convert(1000, ("JPY", 0), ("EUR", 2), None), Ok(600));
+    assert_eq!(rates.convert(1000, ("JPY", 0), ("EUR", 2), ts("2024-05-31")), Ok(500));
+    assert_eq!(rates.convert(1000, ("JPY", 0), ("EUR", 2), ts("2023-01-01")), Ok(500));
+    assert!(rates.convert(1000, ("USD", 2), ("EUR", 2), None).unwrap_err().contains("USD"));
+}
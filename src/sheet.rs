@@ -0,0 +1,230 @@
+/*!
+The CSV format: one row per transfer, which is what you get if you export a spreadsheet.
+
+```text
+from,to,amt,date
+ann,bob,3000,2024-05-01
+bob,carol,1500,2024-05-02
+```
+
+The columns are named by the header row, or (for files without one) with `--csv-cols`.  The
+`from`, `to`, and `amt` columns are required, and `id`, `confirms`, `planned`, `reverses`,
+`currency`, `category`, `memo`, `date`, `tags`, `from_account`, and `to_account` columns are used if
+there are any.  (Separate tags with `;`.)  Any other columns are kept as extra fields, if they're
+named and non-empty.  Amounts are in minor units, as in the JSON format, unless they're written some
+other way than as a plain integer: `12.34` or `$1,234` is in major units (see `locale`).
+
+Columns may be separated by tabs instead of commas, which is what you get if you copy some rows out
+of a spreadsheet and paste them in.
+*/
+
+use ledger::{ReadOptions, Transfer};
+use std::io::Read;
+
+/// Read a CSV table of transfers.  `options.csv_columns` names the columns; if it's `None`, the
+/// first row is a header which does.  If it's given, a header row is still allowed (and skipped).
+pub fn read<R: Read>(mut reader: R, options: &ReadOptions) -> Result<Vec<Transfer<String>>, String> {
+    let columns = options.csv_columns.as_deref();
+    let mut text = String::new();
+    reader.read_to_string(&mut text).map_err(|e| e.to_string())?;
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(delimiter(&text))
+        .has_headers(false)
+        .flexible(true)
+        .trim(::csv::Trim::All)
+        .from_reader(text.as_bytes());
+    let mut records = reader.records();
+    let names: Vec<String> = match columns {
+        Some(columns) => columns.to_vec(),
+        None => match records.next() {
+            None => return Ok(vec![]),
+            Some(row) => row.map_err(|e| e.to_string())?.iter().map(String::from).collect(),
+        },
+    };
+    let col = |name: &str| names.iter().position(|x| x.eq_ignore_ascii_case(name));
+    let (from, to, amt) = match (col("from"), col("to"), col("amt").or_else(|| col("amount"))) {
+        (Some(from), Some(to), Some(amt)) => (from, to, amt),
+        _ => return Err(format!("The CSV columns must include 'from', 'to', and 'amt' (found: {}; use --csv-cols to name them)", names.join(", "))),
+    };
+    let category = col("category");
+    let date = col("date").or_else(|| col("ts"));
+    let tags = col("tags");
+    let memo = col("memo");
+    let currency = col("currency");
+    let id = col("id");
+    let (confirms, planned, reverses) = (col("confirms"), col("planned"), col("reverses"));
+    let (from_account, to_account) = (col("from_account"), col("to_account"));
+    let known = [Some(from), Some(to), Some(amt), category, date, tags, memo, currency, id, confirms, planned, reverses, from_account, to_account];
+    let others: Vec<(usize, &str)> = names.iter().enumerate()
+        .filter(|&(i, name)| !known.contains(&Some(i)) && !name.is_empty() && name != "_")
+        .map(|(i, name)| (i, name.as_str()))
+        .collect();
+
+    let mut transfers = vec![];
+    for (i, row) in records.enumerate() {
+        let row = row.map_err(|e| e.to_string())?;
+        let line = row.position().map_or(i as u64 + 1, |p| p.line());
+        if row.iter().all(|x| x.is_empty()) { continue; }
+        if row.len() != names.len() {
+            return Err(format!("Line {}: expected {} columns ({}), found {}", line, names.len(), names.join(", "), row.len()));
+        }
+        let currency = currency.map(|c| row[c].to_string()).filter(|c| !c.is_empty());
+        let amount = row[amt].parse::<isize>().or_else(|_| {
+            options.locale.parse_minor(&row[amt], options.exponent(currency.as_deref())).map_err(|_| ())
+        });
+        let amount = match amount {
+            Ok(x) => x,
+            Err(()) if i == 0 && columns.is_some() => continue,  // A header
+            Err(()) => return Err(format!("Line {}: invalid amount '{}'", line, &row[amt])),
+        };
+        let date = match date.map(|d| &row[d]).filter(|d| !d.is_empty()) {
+            Some(d) => Some(d.parse().map_err(|e| format!("Line {}: {}", line, e))?),
+            None => None,
+        };
+        let planned = match planned.map(|c| &row[c]).filter(|c| !c.is_empty()) {
+            Some(x) => Some(x.parse().map_err(|_| format!("Line {}: invalid amount '{}'", line, x))?),
+            None => None,
+        };
+        transfers.push(Transfer {
+            date,
+            id: id.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            confirms: confirms.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            planned,
+            reverses: reverses.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            from_account: from_account.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            to_account: to_account.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            memo: memo.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            currency,
+            tags: tags.map_or(vec![], |c| {
+                row[c].split(';').map(str::trim).filter(|x| !x.is_empty()).map(String::from).collect()
+            }),
+            category: category.map(|c| row[c].to_string()).filter(|c| !c.is_empty()),
+            extra: others.iter().filter(|&&(i, _)| !row[i].is_empty())
+                .map(|&(i, name)| (name.to_string(), row[i].into())).collect(),
+            ..Transfer::new(row[from].to_string(), row[to].to_string(), amount)
+        });
+    }
+    Ok(transfers)
+}
+
+/// Some transfers as rows of a CSV table, whose text so far is `existing`.  The rows have the
+/// table's columns, as named by `options.csv_columns` or the header row.  (If the table is empty,
+/// the rows come after a header naming the usual columns, and any others the transfers need.)
+pub fn rows(transfers: &[Transfer<String>], existing: &str, options: &ReadOptions) -> Result<Vec<String>, String> {
+    let delimiter = delimiter(existing);
+    let mut lines = vec![];
+    let header = existing.lines().map(str::trim).find(|l| !l.is_empty());
+    let names: Vec<String> = match (options.csv_columns.clone(), header) {
+        (Some(columns), _) => columns,
+        (None, Some(header)) => ::csv::ReaderBuilder::new().delimiter(delimiter).has_headers(false).trim(::csv::Trim::All)
+            .from_reader(header.as_bytes()).records().next().and_then(Result::ok)
+            .map_or(vec![], |row| row.iter().map(String::from).collect()),
+        (None, None) => {
+            let mut names: Vec<String> = ["from", "to", "amt", "currency", "date", "category", "memo", "tags"].iter().map(|x| x.to_string()).collect();
+            if transfers.iter().any(|t| t.id.is_some()) { names.insert(0, "id".into()); }
+            if transfers.iter().any(|t| t.from_account.is_some() || t.to_account.is_some()) {
+                names.extend(["from_account".to_string(), "to_account".to_string()]);
+            }
+            for t in transfers {
+                for name in t.extra.keys() {
+                    if !names.contains(name) { names.push(name.clone()); }
+                }
+            }
+            lines.push(names.join(","));
+            names
+        }
+    };
+    let col = |name: &str| names.iter().any(|x| x.eq_ignore_ascii_case(name));
+    for name in &["from", "to"] {
+        if !col(name) { return Err(format!("The CSV ledger has no '{}' column", name)); }
+    }
+    if !col("amt") && !col("amount") { return Err("The CSV ledger has no 'amt' column".into()); }
+    let fields: Vec<String> = names.iter().map(|x| x.to_ascii_lowercase()).collect();
+    let fields: Vec<&str> = fields.iter().map(|x| if x == "ts" { "date" } else { x.as_str() }).chain(names.iter().map(|x| x.as_str())).collect();
+    for t in transfers {
+        if let Some(field) = ::ledger::unwritable(t, &fields) {
+            return Err(format!("The CSV ledger has no '{}' column", field));
+        }
+        let row: Vec<String> = names.iter().map(|name| match name.to_ascii_lowercase().as_str() {
+            "from" => t.from.clone(),
+            "to" => t.to.clone(),
+            "id" => t.id.clone().unwrap_or_default(),
+            "confirms" => t.confirms.clone().unwrap_or_default(),
+            "planned" => t.planned.map(|x| x.to_string()).unwrap_or_default(),
+            "reverses" => t.reverses.clone().unwrap_or_default(),
+            "amt" | "amount" => t.amt.to_string(),
+            "currency" => t.currency.clone().unwrap_or_default(),
+            "date" | "ts" => t.date.map(|d| d.to_string()).unwrap_or_default(),
+            "category" => t.category.clone().unwrap_or_default(),
+            "memo" => t.memo.clone().unwrap_or_default(),
+            "tags" => t.tags.join(";"),
+            "from_account" => t.from_account.clone().unwrap_or_default(),
+            "to_account" => t.to_account.clone().unwrap_or_default(),
+            _ => t.extra.get(name).map(|x| x.as_str().map_or_else(|| x.to_string(), String::from)).unwrap_or_default(),
+        }).collect();
+        let mut writer = ::csv::WriterBuilder::new().delimiter(delimiter).from_writer(vec![]);
+        writer.write_record(&row).map_err(|e| e.to_string())?;
+        let row = String::from_utf8(writer.into_inner().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        lines.push(row.trim_end().to_string());
+    }
+    Ok(lines)
+}
+
+/// Tabs if the first line has any, otherwise commas.
+pub fn delimiter(text: &str) -> u8 {
+    match text.lines().find(|l| !l.trim().is_empty()) {
+        Some(line) if line.contains('\t') => b'\t',
+        _ => b',',
+    }
+}
+
+#[test]
+fn test_read() {
+    let table = "From,To,Amt,Date,Category,Note\nann,bob,3000,2024-05-01,food,\nbob,carol,1500,2024-05-02,,paid late\n";
+    let csv = |text: &str, columns: Option<&[String]>| read(text.as_bytes(), &ReadOptions {
+        csv_columns: columns.map(|c| c.to_vec()), ..ReadOptions::default()
+    });
+    let transfers = csv(table, None).unwrap();
+    assert_eq!(transfers[1].extra["Note"], "paid late");
+    let got: Vec<_> = transfers.iter().map(|t| (t.from.as_str(), t.to.as_str(), t.amt, t.category.as_deref())).collect();
+    assert_eq!(got, vec![("ann", "bob", 3000, Some("food")), ("bob", "carol", 1500, None)]);
+
+    let columns: Vec<String> = vec!["date".into(), "from".into(), "to".into(), "amt".into()];
+    let transfers = csv("2024-05-01,ann,bob,3000\n", Some(&columns)).unwrap();
+    assert_eq!((transfers[0].from.as_str(), transfers[0].amt), ("ann", 3000));
+    assert_eq!(transfers[0].date.unwrap().to_string(), "2024-05-01");
+    // A header is skipped, even when the columns are given
+    assert_eq!(csv("when,from,to,amt\n2024-05-01,ann,bob,3000\n", Some(&columns)).unwrap().len(), 1);
+
+    assert_eq!(csv("from,to,amt\nann,bob,30.5\n", None).unwrap()[0].amt, 3050);
+    assert_eq!(csv("from,to,amt\nann,bob,\"$1,030\"\n", None).unwrap()[0].amt, 103000);
+
+    // Pasted from a spreadsheet
+    let transfers = csv("from\tto\tamt\nann\tbob\t3000\n", None).unwrap();
+    assert_eq!((transfers[0].to.as_str(), transfers[0].amt), ("bob", 3000));
+
+    assert!(csv("ann,bob,3000\n", None).unwrap_err().contains("must include"));
+    assert!(csv("from,to,amt\nann,bob,x\n", None).unwrap_err().starts_with("Line 2"));
+    assert!(csv("from,to,amt,date\nann,bob,1,May\n", None).unwrap_err().starts_with("Line 2"));
+    assert!(csv("from,to,amt\nann,bob\n", None).unwrap_err().starts_with("Line 2"));
+}
+
+#[test]
+fn test_rows() {
+    let t = Transfer { memo: Some("dinner, mostly".into()), ..Transfer::new("ann".to_string(), "bob".to_string(), 3000) };
+    let options = ReadOptions::default();
+    assert_eq!(rows(::std::slice::from_ref(&t), "To\tFrom\tAmt\tMemo\n", &options).unwrap(), vec!["bob\tann\t3000\tdinner, mostly"]);
+    assert_eq!(rows(::std::slice::from_ref(&t), "", &options).unwrap(),
+        vec!["from,to,amt,currency,date,category,memo,tags", "ann,bob,3000,,,,\"dinner, mostly\","]);
+    assert!(rows(::std::slice::from_ref(&t), "from,to,amt\n", &options).unwrap_err().contains("'memo'"));
+    let t = Transfer { id: Some("t1".into()), ..t };
+    let lines = rows(&[t], "", &options).unwrap();
+    assert_eq!(lines[0], "id,from,to,amt,currency,date,category,memo,tags");
+    let read = read(lines.join("\n").as_bytes(), &options).unwrap();
+    assert_eq!((read[0].id.as_deref(), read[0].memo.as_deref(), read[0].extra.len()), (Some("t1"), Some("dinner, mostly"), 0));
+    let t = Transfer { confirms: Some("c0ffee".into()), planned: Some(5000), ..Transfer::new("bob".to_string(), "ann".to_string(), 3000) };
+    let lines = rows(&[t], "from,to,amt,confirms,planned\n", &options).unwrap();
+    assert_eq!(lines, vec!["bob,ann,3000,c0ffee,5000"]);
+    let back = ::sheet::read(format!("from,to,amt,confirms,planned\n{}", lines[0]).as_bytes(), &options).unwrap();
+    assert_eq!((back[0].confirms.as_deref(), back[0].planned, back[0].extra.len()), (Some("c0ffee"), Some(5000), 0));
+}
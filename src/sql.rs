@@ -0,0 +1,54 @@
+/*!
+Ledgers as SQL, for loading into SQLite (eg. `repay convert ledger.jsonl ledger.sql`, then
+`sqlite3 ledger.db < ledger.sql`).
+
+There's a row per transfer in a `transfers` table, with a column for each of its fields.  Amounts
+are in minor units, tags are separated by `;`, and any extra fields are a JSON object in `extra`.
+*/
+
+use ledger::Transfer;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS transfers (\"id\" TEXT, \"from\" TEXT NOT NULL, \"to\" TEXT NOT NULL, \
+    \"amt\" INTEGER NOT NULL, \"currency\" TEXT, \"date\" TEXT, \"category\" TEXT, \"memo\" TEXT, \"tags\" TEXT, \
+    \"confirms\" TEXT, \"planned\" INTEGER, \"reverses\" TEXT, \"extra\" TEXT);";
+
+/// A script which creates the table (if need be), and inserts the transfers into it.
+pub fn dump(transfers: &[Transfer<String>]) -> Vec<String> {
+    let mut lines = vec!["BEGIN;".to_string(), SCHEMA.to_string()];
+    for t in transfers {
+        let text = |x: Option<&str>| x.map_or("NULL".into(), quote);
+        let tags = if t.tags.is_empty() { None } else { Some(t.tags.join(";")) };
+        let extra = if t.extra.is_empty() { None } else { Some(::serde_json::Value::from(t.extra.clone()).to_string()) };
+        let values = [
+            text(t.id.as_deref()),
+            quote(&t.from),
+            quote(&t.to),
+            t.amt.to_string(),
+            text(t.currency.as_deref()),
+            text(t.date.map(|d| d.to_string()).as_deref()),
+            text(t.category.as_deref()),
+            text(t.memo.as_deref()),
+            text(tags.as_deref()),
+            text(t.confirms.as_deref()),
+            t.planned.map_or("NULL".into(), |x| x.to_string()),
+            text(t.reverses.as_deref()),
+            text(extra.as_deref()),
+        ];
+        lines.push(format!("INSERT INTO transfers VALUES ({});", values.join(", ")));
+    }
+    lines.push("COMMIT;".into());
+    lines
+}
+
+/// A string literal.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+#[test]
+fn test_dump() {
+    let t = Transfer { memo: Some("Luigi's".into()), tags: vec!["trip".into(), "food".into()], ..Transfer::new("ann".to_string(), "bob".to_string(), 3000) };
+    let lines = dump(&[t]);
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[2], "INSERT INTO transfers VALUES (NULL, 'ann', 'bob', 3000, NULL, NULL, NULL, 'Luigi''s', 'trip;food', NULL, NULL, NULL, NULL);");
+}
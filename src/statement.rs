@@ -0,0 +1,62 @@
+/*!
+One person's statement, for `repay statement`: every transfer they were part of, and how each one
+changed their balance.
+
+As with the plan, a balance is positive if the person owes money, and negative if they're owed
+some.  Each currency has its own balance.
+*/
+
+use ledger::Transfer;
+use std::collections::BTreeMap;
+
+/// A transfer to or from the person, and their balance after it.
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub transfer: Transfer<String>,
+    /// How much it changed their balance by.
+    pub change: isize,
+    /// Their balance in the transfer's currency, afterwards.
+    pub balance: isize,
+}
+
+/// The transfers involving `person`, in the order they're read.
+pub fn statement<I: IntoIterator<Item=Transfer<String>>>(person: &str, transfers: I) -> Vec<Line> {
+    let mut balances: BTreeMap<Option<String>, isize> = BTreeMap::new();
+    let mut lines = vec![];
+    for t in transfers {
+        let change = if t.from == person {
+            -t.amt
+        } else if t.to == person {
+            t.amt
+        } else {
+            continue;
+        };
+        let balance = balances.entry(t.currency.clone()).or_default();
+        *balance += change;
+        lines.push(Line { balance: *balance, change, transfer: t });
+    }
+    lines
+}
+
+/// Their balance in each currency, at the end.
+pub fn balances(lines: &[Line]) -> BTreeMap<Option<String>, isize> {
+    let mut balances = BTreeMap::new();
+    for l in lines {
+        balances.insert(l.transfer.currency.clone(), l.balance);
+    }
+    balances
+}
+
+#[test]
+fn test_statement() {
+    let t = |from: &str, to: &str, amt| Transfer::new(from.to_string(), to.to_string(), amt);
+    let transfers = vec![
+        t("ann", "bob", 3000),
+        t("cat", "ann", 500),
+        t("bob", "cat", 1000),
+        Transfer { currency: Some("USD".into()), ..t("ann", "bob", 200) },
+    ];
+    let lines = statement("bob", transfers);
+    assert_eq!(lines.iter().map(|l| (l.change, l.balance)).collect::<Vec<_>>(), vec![(3000, 3000), (-1000, 2000), (200, 200)]);
+    assert_eq!(balances(&lines).into_iter().collect::<Vec<_>>(), vec![(None, 2000), (Some("USD".into()), 200)]);
+}
@@ -0,0 +1,114 @@
+/*!
+Aligned tables for the terminal, as used for plans (`--output-format=table`), `repay balances`, and
+`repay backtest`.
+
+Each column is aligned to the left, or (for amounts) to the right.  The rows can be split into
+groups, each under a heading of its own, and the table can end with a totals row, below a rule.
+Widths are counted in chars, so `€` counts as one, and colours don't count at all.
+*/
+
+use output::paint;
+
+/// Which side of a column its cells line up on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A cell's text, and the ANSI style to paint it with if there's colour ("" for none).
+type Cell = (String, &'static str);
+
+/// A table, built up a row at a time.
+#[derive(Clone, Debug)]
+pub struct Table {
+    columns: Vec<(String, Align)>,
+    /// Each group's heading (if it has one), and its rows.
+    groups: Vec<(Option<String>, Vec<Vec<Cell>>)>,
+    total: Option<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(columns: &[(&str, Align)]) -> Table {
+        Table { columns: columns.iter().map(|&(c, a)| (c.to_string(), a)).collect(), groups: vec![], total: None }
+    }
+
+    /// Put the following rows under a heading.
+    pub fn group(&mut self, heading: String) {
+        self.groups.push((Some(heading), vec![]));
+    }
+
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.styled_row(cells.into_iter().map(|x| (x, "")).collect());
+    }
+
+    /// A row whose cells are painted when there's colour.
+    pub fn styled_row(&mut self, cells: Vec<Cell>) {
+        assert_eq!(cells.len(), self.columns.len(), "a row needs a cell for each column");
+        if self.groups.is_empty() { self.groups.push((None, vec![])); }
+        self.groups.last_mut().unwrap().1.push(cells);
+    }
+
+    /// The totals row, which goes at the end.
+    pub fn total(&mut self, cells: Vec<String>) {
+        assert_eq!(cells.len(), self.columns.len(), "a row needs a cell for each column");
+        self.total = Some(cells);
+    }
+
+    /// The table, as lines of text.
+    pub fn render(&self, color: bool) -> Vec<String> {
+        let rows = self.groups.iter().flat_map(|g| g.1.iter().map(|r| r.iter().map(|c| c.0.as_str()).collect::<Vec<_>>()));
+        let widths: Vec<usize> = self.columns.iter().map(|c| c.0.chars().count()).collect();
+        let widths = rows.chain(self.total.iter().map(|r| r.iter().map(String::as_str).collect()))
+            .fold(widths, |ws, r| ws.iter().zip(r).map(|(&w, x)| w.max(x.chars().count())).collect());
+        let line = |row: &mut dyn Iterator<Item=(&str, &'static str)>| -> String {
+            let cells: Vec<String> = row.zip(&widths).zip(&self.columns).enumerate()
+                .map(|(i, (((x, style), &w), &(_, align)))| {
+                    let cell = match align {
+                        Align::Right => format!("{:>w$}", x, w = w),
+                        Align::Left if i + 1 < widths.len() => format!("{:<w$}", x, w = w),
+                        Align::Left => x.to_string(),
+                    };
+                    if color { paint(style, &cell) } else { cell }
+                })
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        };
+        let mut lines = vec![line(&mut self.columns.iter().map(|c| (c.0.as_str(), "")))];
+        for (heading, rows) in &self.groups {
+            if let Some(heading) = heading {
+                lines.push(String::new());
+                lines.push(heading.clone());
+            }
+            lines.extend(rows.iter().map(|r| line(&mut r.iter().map(|c| (c.0.as_str(), c.1)))));
+        }
+        if let Some(ref total) = self.total {
+            let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+            lines.push(rule.join("  "));
+            lines.push(line(&mut total.iter().map(|x| (x.as_str(), ""))));
+        }
+        lines
+    }
+}
+
+#[test]
+fn test_table() {
+    let mut table = Table::new(&[("who", Align::Left), ("amt", Align::Right), ("memo", Align::Left)]);
+    table.group("ann and bob".into());
+    table.row(vec!["bob".into(), "€1,234.00".into(), "rent".into()]);
+    table.group("cat and dan".into());
+    table.styled_row(vec![("cat".into(), "31"), ("5.00".into(), ""), ("".into(), "")]);
+    table.total(vec!["total".into(), "€1,239.00".into(), "".into()]);
+    assert_eq!(table.render(false), vec![
+        "who          amt  memo",
+        "",
+        "ann and bob",
+        "bob    €1,234.00  rent",
+        "",
+        "cat and dan",
+        "cat         5.00",
+        "-----  ---------  ----",
+        "total  €1,239.00",
+    ]);
+    assert_eq!(table.render(true)[6], "\x1b[31mcat  \x1b[0m       5.00");
+}
@@ -0,0 +1,159 @@
+/*!
+Tools for testing code built on repay: random ledgers and balances, and checkers for the invariants
+which every plan should satisfy.  The checkers return a description of the problem on failure.
+
+```
+use repay::plan::{plan, Mode};
+use repay::testing::*;
+
+let mut rng = Rng::new(42);
+for _ in 0..10 {
+    let balances = random_balances(&mut rng, 8, 1000);
+    check_settles(&balances, &plan(balances.clone(), Mode::Exact).unwrap().transfers).unwrap();
+    #[cfg(feature = "approx")]
+    check_exact_le_approx(&balances).unwrap();
+}
+```
+*/
+
+use ledger::Transfer;
+use mzsp::Partitioning;
+use std::collections::BTreeMap;
+
+/// A small, seedable PRNG (xorshift64*).  It's not very good, but it's good enough for generating
+/// test cases, and the same seed always gives the same cases.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)  // The state mustn't be zero
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// A number in `lo..=hi`.
+    pub fn between(&mut self, lo: isize, hi: isize) -> isize {
+        lo + self.below((hi - lo + 1) as usize) as isize
+    }
+}
+
+/// The name of the ith generated person.
+pub fn person(i: usize) -> String {
+    format!("p{}", i)
+}
+
+/// `len` random transfers between `people` people (at least 2), with amounts in `1..=max_amt`.
+pub fn random_ledger(rng: &mut Rng, people: usize, len: usize, max_amt: isize) -> Vec<Transfer<String>> {
+    assert!(people >= 2, "transfers need two people");
+    (0..len).map(|_| {
+        let from = rng.below(people);
+        let to = (from + 1 + rng.below(people - 1)) % people;
+        Transfer::new(person(from), person(to), rng.between(1, max_amt))
+    }).collect()
+}
+
+/// Non-zero balances for `n` people (not 1) which sum to zero.  All but one are in
+/// `-max_amt..=max_amt`.
+pub fn random_balances(rng: &mut Rng, n: usize, max_amt: isize) -> Vec<(String, isize)> {
+    assert!(n != 1, "a single non-zero balance can't sum to zero");
+    if n == 0 { return vec![]; }
+    loop {
+        let mut balances: Vec<(String, isize)> = (0..n - 1).map(|i| {
+            let x = rng.between(1, max_amt);
+            (person(i), if rng.below(2) == 0 { x } else { -x })
+        }).collect();
+        let last = -balances.iter().map(|x| x.1).sum::<isize>();
+        if last != 0 {
+            balances.push((person(n - 1), last));
+            return balances;
+        }
+    }
+}
+
+/// Check that carrying out `plan` brings every balance to zero, and that every repayment is for
+/// a positive amount.
+pub fn check_settles(balances: &[(String, isize)], plan: &[Transfer<String>]) -> Result<(), String> {
+    let mut remaining: BTreeMap<&str, isize> = balances.iter().map(|x| (x.0.as_str(), x.1)).collect();
+    for t in plan {
+        if t.amt <= 0 {
+            return Err(format!("{} -> {} is for a non-positive amount ({})", t.from, t.to, t.amt));
+        }
+        *remaining.entry(&t.from).or_insert(0) -= t.amt;
+        *remaining.entry(&t.to).or_insert(0) += t.amt;
+    }
+    match remaining.into_iter().find(|x| x.1 != 0) {
+        None => Ok(()),
+        Some((who, x)) => Err(format!("{} is left with a balance of {}", who, x)),
+    }
+}
+
+/// Check that the exact solver uses no more repayments than the approximate one, and that both
+/// plans settle the balances.
+#[cfg(feature = "approx")]
+pub fn check_exact_le_approx(balances: &[(String, isize)]) -> Result<(), String> {
+    use plan::{plan, Mode};
+    let exact = plan(balances.to_vec(), Mode::Exact).unwrap().transfers;
+    let approx = plan(balances.to_vec(), Mode::Approx).unwrap().transfers;
+    check_settles(balances, &exact).map_err(|e| format!("Exact plan: {}", e))?;
+    check_settles(balances, &approx).map_err(|e| format!("Approximate plan: {}", e))?;
+    if exact.len() > approx.len() {
+        return Err(format!("The exact plan has {} repayments, but the approximate one has only {}",
+            exact.len(), approx.len()));
+    }
+    Ok(())
+}
+
+/// Check that every part of the partitioning sums to zero, and that (if the values sum to zero)
+/// every value is in some part.
+pub fn check_partitioning(values: &[isize], partitioning: &Partitioning) -> Result<(), String> {
+    for part in partitioning.parts() {
+        let sum: isize = part.iter().map(|&i| values[i]).sum();
+        if sum != 0 {
+            return Err(format!("Part {:?} sums to {}", part, sum));
+        }
+    }
+    let residual = partitioning.residual();
+    if values.iter().sum::<isize>() == 0 && !residual.is_empty() {
+        return Err(format!("The values sum to zero, but {:?} aren't in any part", residual));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_invariants() {
+    use ledger::balances;
+    use plan::{plan, Mode};
+    let mut rng = Rng::new(0);
+    for _ in 0..50 {
+        let people = 2 + rng.below(8);
+        let (_, balances) = balances(random_ledger(&mut rng, people, 20, 100));
+        check_settles(&balances, &plan(balances.clone(), Mode::Exact).unwrap().transfers).unwrap();
+        #[cfg(feature = "approx")]
+        check_exact_le_approx(&balances).unwrap();
+        let values: Vec<isize> = balances.iter().map(|x| x.1).collect();
+        check_partitioning(&values, &Partitioning::compute(&values).unwrap()).unwrap();
+    }
+}
+
+#[test]
+fn test_checkers() {
+    let balances = vec![(person(0), 10), (person(1), -10)];
+    assert!(check_settles(&balances, &[Transfer::new(person(0), person(1), 10)]).is_ok());
+    assert!(check_settles(&balances, &[Transfer::new(person(0), person(1), 5)]).is_err());
+    assert!(check_settles(&balances, &[]).is_err());
+    assert!(check_partitioning(&[1, -1, 2], &Partitioning::from_sets(3, vec![])).is_ok());
+    assert!(check_partitioning(&[1, -1], &Partitioning::from_sets(2, vec![])).is_err());
+    let mut rng = Rng::new(7);
+    assert_eq!(random_balances(&mut rng, 9, 50).iter().map(|x| x.1).sum::<isize>(), 0);
+}
@@ -0,0 +1,324 @@
+/*!
+Rendering amounts for humans.
+
+Amounts are always stored (in the ledger and in the plan) as integers in the currency's minor unit
+(eg. cents).  This module mostly affects how those integers are *displayed*, and how they're written
+in the plan's JSON output.  Amounts written as decimals are read with `Money`, which is exact.
+
+For people, an amount in a known currency is written the way it usually is: `$1,234.56`, `¥1,234`,
+or `1.234,56 €` with `--locale=de`.  Some common currencies are built in (see `KNOWN`), and others
+can be described in the config file or the ledger's header.
+*/
+
+use locale::Locale;
+use output::{Color, OutputFormat};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which unit amounts should be displayed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    /// Major units if the amount's currency is known (because the transfer or the ledger's header
+    /// says what it is), and the stored integer otherwise.
+    #[default]
+    Auto,
+    /// Print the stored integer as-is (eg. `1234`).
+    Cents,
+    /// Divide by the currency's exponent (eg. `12.34`).
+    Major,
+}
+impl FromStr for Unit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Unit, String> {
+        match s {
+            "auto" => Ok(Unit::Auto),
+            "cents" | "minor" => Ok(Unit::Cents),
+            "major" => Ok(Unit::Major),
+            _ => Err(format!("Unknown unit '{}' (expected 'auto', 'cents', or 'major')", s)),
+        }
+    }
+}
+
+/// How amounts are written in the plan's JSON output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Amounts {
+    /// Integers in minor units (eg. `1234`).  Exact, and what the ledger uses.
+    #[default]
+    Minor,
+    /// Strings in major units (eg. `"12.34"`).  Exact, and easy for a bank to read.
+    Decimal,
+    /// Numbers in major units (eg. `12.34`).  Handy for spreadsheets, but floats can't represent
+    /// most decimal fractions exactly, so only use this if whatever reads the plan will round.
+    Float,
+}
+impl FromStr for Amounts {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Amounts, String> {
+        match s {
+            "minor" | "cents" => Ok(Amounts::Minor),
+            "decimal" => Ok(Amounts::Decimal),
+            "float" => Ok(Amounts::Float),
+            _ => Err(format!("Unknown amount representation '{}' (expected 'minor', 'decimal', or 'float')", s)),
+        }
+    }
+}
+
+/// How to display a single currency.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CurrencyFormat {
+    /// The number of minor units in a major unit, as a power of ten.  (2 for EUR, 0 for JPY)
+    pub exponent: u32,
+    /// Printed with the amount in major mode, if set: before it, or after it in languages with a
+    /// decimal comma.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+}
+impl Default for CurrencyFormat {
+    fn default() -> CurrencyFormat {
+        CurrencyFormat { exponent: 2, symbol: None }
+    }
+}
+
+/// Some common currencies: their code, their ISO 4217 exponent, and their symbol.  A currency in
+/// the config (or a ledger's header) replaces the one here.
+pub const KNOWN: &[(&str, u32, &str)] = &[
+    ("AUD", 2, "A$"), ("BHD", 3, "BD"), ("BRL", 2, "R$"), ("CAD", 2, "C$"), ("CHF", 2, "CHF"),
+    ("CNY", 2, "¥"), ("CZK", 2, "Kč"), ("DKK", 2, "kr"), ("EUR", 2, "€"), ("GBP", 2, "£"),
+    ("HKD", 2, "HK$"), ("HUF", 2, "Ft"), ("IDR", 2, "Rp"), ("ILS", 2, "₪"), ("INR", 2, "₹"),
+    ("ISK", 0, "kr"), ("JPY", 0, "¥"), ("KRW", 0, "₩"), ("KWD", 3, "KD"), ("MXN", 2, "MX$"),
+    ("NOK", 2, "kr"), ("NZD", 2, "NZ$"), ("PLN", 2, "zł"), ("SEK", 2, "kr"), ("SGD", 2, "S$"),
+    ("THB", 2, "฿"), ("TRY", 2, "₺"), ("TWD", 2, "NT$"), ("UAH", 2, "₴"), ("USD", 2, "$"),
+    ("VND", 0, "₫"), ("ZAR", 2, "R"),
+];
+
+/// Display settings for amounts.  Currencies which aren't listed in `currencies` (or `KNOWN`) use
+/// `default`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    pub unit: Unit,
+    pub amounts: Amounts,
+    /// How plans (and other lists of transfers) are written.
+    pub output: OutputFormat,
+    /// Whether tables are coloured.
+    pub color: Color,
+    pub default: CurrencyFormat,
+    pub currencies: BTreeMap<String, CurrencyFormat>,
+    /// How to group digits, and which decimal separator to use.  (This is the top-level `locale`.)
+    #[serde(skip)]
+    pub locale: Locale,
+    /// The currency of amounts which don't say, if the ledgers' headers give one.
+    #[serde(skip)]
+    pub base_currency: Option<String>,
+}
+impl DisplayConfig {
+    /// The format to use for the given currency.  (`None` is the base currency, if there is one.)
+    pub fn currency(&self, currency: Option<&str>) -> CurrencyFormat {
+        let known = |code: &str| KNOWN.iter().find(|k| k.0 == code)
+            .map(|&(_, exponent, symbol)| CurrencyFormat { exponent, symbol: Some(symbol.into()) });
+        match currency.or(self.base_currency.as_deref()) {
+            Some(c) => self.currencies.get(c).cloned().or_else(|| known(c)).unwrap_or_else(|| self.default.clone()),
+            None => self.default.clone(),
+        }
+    }
+
+    /// The exponent of each currency with a format, for reading amounts in major units.
+    pub fn exponents(&self) -> BTreeMap<String, u32> {
+        KNOWN.iter().map(|&(c, e, _)| (c.to_string(), e))
+            .chain(self.currencies.iter().map(|(c, f)| (c.clone(), f.exponent)))
+            .collect()
+    }
+
+    /// Render an amount of minor units for humans.
+    pub fn format(&self, amt: isize, currency: Option<&str>) -> String {
+        if self.in_major_units(currency) {
+            human(amt, &self.currency(currency), &self.locale)
+        } else {
+            amt.to_string()
+        }
+    }
+
+    /// Like `format`, but also say which currency the amount is in: with its symbol if it's
+    /// written with one, or otherwise its code (eg. `12.34 XYZ`).
+    pub fn labelled(&self, amt: isize, currency: Option<&str>) -> String {
+        let text = self.format(amt, currency);
+        match currency {
+            Some(_) if self.in_major_units(currency) && self.currency(currency).symbol.is_some() => text,
+            Some(ccy) => format!("{} {}", text, ccy),
+            None => text,
+        }
+    }
+
+    /// Whether amounts in this currency are displayed in major units (see `unit`).
+    pub fn in_major_units(&self, currency: Option<&str>) -> bool {
+        match self.unit {
+            Unit::Auto => currency.or(self.base_currency.as_deref()).is_some(),
+            Unit::Cents => false,
+            Unit::Major => true,
+        }
+    }
+
+    /// An amount of minor units, as it should appear in the JSON plan.
+    pub fn json(&self, amt: isize, currency: Option<&str>) -> ::serde_json::Value {
+        let exponent = self.currency(currency).exponent;
+        match self.amounts {
+            Amounts::Minor => amt.into(),
+            Amounts::Decimal => major_units(amt, exponent).into(),
+            Amounts::Float => (amt as f64 / 10f64.powi(exponent as i32)).into(),
+        }
+    }
+}
+
+/// An exact decimal amount: `minor` units, of which there are 10^`exponent` in a major unit.  Eg.
+/// `"12.34"` is 1234 with exponent 2, and `"12.340"` is 12340 with exponent 3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Money {
+    pub minor: isize,
+    pub exponent: u32,
+}
+
+impl Money {
+    pub fn new(minor: isize, exponent: u32) -> Money {
+        Money { minor, exponent }
+    }
+
+    /// The same amount with `exponent` decimal places, if it can be written with that many (and
+    /// doesn't overflow).
+    pub fn rescale(self, exponent: u32) -> Option<Money> {
+        let minor = if exponent >= self.exponent {
+            self.minor.checked_mul(10isize.checked_pow(exponent - self.exponent)?)?
+        } else {
+            let scale = 10isize.checked_pow(self.exponent - exponent)?;
+            if self.minor % scale != 0 { return None; }
+            self.minor / scale
+        };
+        Some(Money { minor, exponent })
+    }
+
+    /// Parse a decimal in major units (eg. `"12.34"`), and give it in the minor units of a
+    /// currency with `exponent` decimal places (eg. 1234).
+    pub fn parse_minor(s: &str, exponent: u32) -> Result<isize, String> {
+        let money: Money = s.parse()?;
+        money.rescale(exponent).map(|x| x.minor)
+            .ok_or_else(|| format!("Invalid amount '{}' (at most {} decimal places)", s, exponent))
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+    /// Eg. `12.34`, `-5`, or `+0.5`.
+    fn from_str(s: &str) -> Result<Money, String> {
+        let invalid = || format!("Invalid amount '{}' (expected eg. '12.34')", s);
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (major, minor) = match unsigned.find('.') {
+            Some(i) => (&unsigned[..i], &unsigned[i + 1..]),
+            None => (unsigned, ""),
+        };
+        let digits = |x: &str| x.bytes().all(|b| b.is_ascii_digit());
+        if major.is_empty() || !digits(major) || !digits(minor) || (unsigned.contains('.') && minor.is_empty()) {
+            return Err(invalid());
+        }
+        let exponent = minor.len() as u32;
+        let minor = format!("{}{}", major, minor).parse::<isize>().map_err(|_| invalid())?;
+        Ok(Money { minor: if negative { -minor } else { minor }, exponent })
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&major_units(self.minor, self.exponent))
+    }
+}
+
+/// Eg. `-$1,234.56`, or `-1.234,56 €` in German.
+fn human(amt: isize, fmt: &CurrencyFormat, locale: &Locale) -> String {
+    let scale = 10usize.pow(fmt.exponent);
+    let major = (amt.unsigned_abs() / scale).to_string();
+    let mut number = String::new();
+    for (i, c) in major.chars().enumerate() {
+        if i > 0 && (major.len() - i).is_multiple_of(3) { number.push(locale.group); }
+        number.push(c);
+    }
+    if fmt.exponent > 0 {
+        number = format!("{}{}{:0>width$}", number, locale.decimal, amt.unsigned_abs() % scale, width = fmt.exponent as usize);
+    }
+    let sign = if amt < 0 { "-" } else { "" };
+    match fmt.symbol.as_deref() {
+        None => format!("{}{}", sign, number),
+        Some(symbol) if locale.decimal == ',' => format!("{}{} {}", sign, number, symbol),
+        // `CHF 12.00`, but `$12.00`
+        Some(symbol) if symbol.ends_with(char::is_alphabetic) => format!("{}{} {}", sign, symbol, number),
+        Some(symbol) => format!("{}{}{}", sign, symbol, number),
+    }
+}
+
+/// Eg. `-12.34`, for machines.
+fn major_units(amt: isize, exponent: u32) -> String {
+    let sign = if amt < 0 { "-" } else { "" };
+    let scale = 10usize.pow(exponent);
+    let (major, minor) = (amt.unsigned_abs() / scale, amt.unsigned_abs() % scale);
+    if exponent == 0 {
+        format!("{}{}", sign, major)
+    } else {
+        format!("{}{}.{:0>width$}", sign, major, minor, width = exponent as usize)
+    }
+}
+
+#[test]
+fn test_format() {
+    let mut cfg = DisplayConfig::default();
+    assert_eq!(cfg.format(-123456, None), "-123456");
+    assert_eq!(cfg.format(-123456, Some("USD")), "-$1,234.56");
+    assert_eq!(cfg.labelled(1234, Some("XYZ")), "12.34 XYZ");
+    cfg.base_currency = Some("JPY".into());
+    assert_eq!(cfg.labelled(1234567, None), "¥1,234,567");
+    cfg.base_currency = None;
+    cfg.unit = Unit::Major;
+    assert_eq!(cfg.format(-1234, None), "-12.34");
+    assert_eq!(cfg.format(5, None), "0.05");
+    cfg.currencies.insert("EUR".into(), CurrencyFormat { exponent: 3, symbol: None });
+    assert_eq!(cfg.format(1234, Some("EUR")), "1.234");
+    cfg.locale = "de".parse().unwrap();
+    assert_eq!(cfg.format(-123456, Some("GBP")), "-1.234,56 £");
+    cfg.locale = "de-CH".parse().unwrap();
+    assert_eq!(cfg.format(123456, Some("CHF")), "CHF 1'234.56");
+    cfg.unit = Unit::Cents;
+    assert_eq!(cfg.labelled(1234, Some("USD")), "1234 USD");
+}
+
+#[test]
+fn test_money() {
+    let m = |s: &str| s.parse::<Money>().unwrap();
+    assert_eq!(m("12.34"), Money::new(1234, 2));
+    assert_eq!(m("-0.050"), Money::new(-50, 3));
+    assert_eq!(m("+7"), Money::new(7, 0));
+    assert_eq!(m("-0.050").to_string(), "-0.050");
+    assert_eq!(m("12.340").rescale(2), Some(Money::new(1234, 2)));
+    assert_eq!(m("12.345").rescale(2), None);
+    assert_eq!(Money::parse_minor("12.3", 2), Ok(1230));
+    assert_eq!(Money::parse_minor("5", 0), Ok(5));
+    // Exactly, even where a float would be off
+    assert_eq!(Money::parse_minor("0.29", 2), Ok(29));
+    assert_eq!(Money::parse_minor("92233720368547758.07", 2), Ok(isize::MAX));
+    for bad in &["", ".5", "5.", "1,000", "1e3", "--1", "99999999999999999999"] {
+        assert!(bad.parse::<Money>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_json() {
+    let mut cfg = DisplayConfig::default();
+    assert_eq!(cfg.json(-1234, None), json!(-1234));
+    cfg.amounts = Amounts::Decimal;
+    assert_eq!(cfg.json(-1234, None), json!("-12.34"));
+    assert_eq!(cfg.json(1234, Some("JPY")), json!("1234"));
+    cfg.amounts = Amounts::Float;
+    assert_eq!(cfg.json(1250, None), json!(12.5));
+}
@@ -0,0 +1,103 @@
+/*!
+Checking a repayment plan against a ledger, for `repay verify`.
+
+A plan is what repay prints: one JSON object per line (or a JSON array of them), each with `from`,
+`to`, and `amt`.  It might have been edited by hand, so amounts can be in any of the forms which
+`--amounts` produces, and anything besides those three fields (and `currency`) is ignored.  A plan
+is right if, once every repayment in it has been made, nobody owes anybody anything.
+*/
+
+use ledger::{CurrencyBalances, Transfer};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use units::{DisplayConfig, Money};
+
+/// Read a plan.
+pub fn read_plan(text: &str, display: &DisplayConfig) -> Result<Vec<Transfer<String>>, String> {
+    if text.trim_start().starts_with('[') {
+        let items: Vec<Value> = ::serde_json::from_str(text).map_err(|e| e.to_string())?;
+        return items.iter().enumerate()
+            .map(|(i, x)| repayment(x, display).map_err(|e| format!("item {}: {}", i + 1, e)))
+            .collect();
+    }
+    let mut plan = vec![];
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() { continue; }
+        let x: Value = ::serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        plan.push(repayment(&x, display).map_err(|e| format!("line {}: {}", i + 1, e))?);
+    }
+    Ok(plan)
+}
+
+fn repayment(x: &Value, display: &DisplayConfig) -> Result<Transfer<String>, String> {
+    let name = |field: &str| match x.get(field) {
+        Some(Value::String(s)) if !s.trim().is_empty() => Ok(s.clone()),
+        Some(_) => Err(format!("'{}' should be a name", field)),
+        None => Err(format!("there's no '{}'", field)),
+    };
+    let (from, to) = (name("from")?, name("to")?);
+    let currency = match x.get("currency") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(_) => return Err("'currency' should be a string".into()),
+    };
+    let exponent = display.currency(currency.as_deref()).exponent;
+    let amt = match x.get("amt") {
+        Some(Value::Number(n)) => match n.as_i64() {
+            Some(n) => n as isize,
+            // Written with '--amounts=float'
+            None => (n.as_f64().unwrap_or_default() * 10f64.powi(exponent as i32)).round() as isize,
+        },
+        Some(Value::String(s)) => Money::parse_minor(s.trim(), exponent)?,
+        Some(_) => return Err("'amt' should be a number".into()),
+        None => return Err("there's no 'amt'".into()),
+    };
+    if amt <= 0 { return Err(format!("the amount should be positive, not {}", amt)); }
+    Ok(Transfer { currency, ..Transfer::new(from, to, amt) })
+}
+
+/// Someone whose balance the plan doesn't bring to zero.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub person: String,
+    pub currency: Option<String>,
+    /// Their balance after the plan: positive if they'd still owe money, negative if they'd still
+    /// be owed some.
+    pub left: isize,
+}
+
+/// Everyone whose balance isn't zero once the plan has been carried out.
+pub fn verify(balances: &CurrencyBalances, plan: &[Transfer<String>]) -> Vec<Discrepancy> {
+    let mut left: BTreeMap<(Option<String>, String), isize> = BTreeMap::new();
+    for (ccy, xs) in balances {
+        for (who, bal) in xs {
+            *left.entry((ccy.clone(), who.clone())).or_default() += bal;
+        }
+    }
+    for t in plan {
+        *left.entry((t.currency.clone(), t.from.clone())).or_default() -= t.amt;
+        *left.entry((t.currency.clone(), t.to.clone())).or_default() += t.amt;
+    }
+    left.into_iter()
+        .filter(|&(_, x)| x != 0)
+        .map(|((currency, person), left)| Discrepancy { person, currency, left })
+        .collect()
+}
+
+#[test]
+fn test_verify() {
+    let display = DisplayConfig::default();
+    let plan = read_plan("{\"from\":\"ann\",\"to\":\"bob\",\"amt\":500,\"id\":\"x\"}\n\n{\"from\":\"cat\",\"to\":\"bob\",\"amt\":\"2.50\"}\n", &display).unwrap();
+    assert_eq!(plan.iter().map(|t| t.amt).collect::<Vec<_>>(), vec![500, 250]);
+    assert_eq!(read_plan("[{\"from\":\"ann\",\"to\":\"bob\",\"amt\":0.1}]", &display).unwrap()[0].amt, 10);
+    assert!(read_plan("{\"from\":\"ann\",\"amt\":1}", &display).unwrap_err().contains("line 1"));
+
+    let balances: CurrencyBalances = vec![(None, vec![("ann".to_string(), 500), ("bob".to_string(), -750), ("cat".to_string(), 250)])]
+        .into_iter().collect();
+    assert_eq!(verify(&balances, &plan), vec![]);
+    let short = [Transfer::new("ann".to_string(), "bob".to_string(), 500)];
+    assert_eq!(verify(&balances, &short), vec![
+        Discrepancy { person: "bob".into(), currency: None, left: -250 },
+        Discrepancy { person: "cat".into(), currency: None, left: 250 },
+    ]);
+}